@@ -0,0 +1,34 @@
+//! Records the information that `--version` and `buildinfo` print
+//! (see src/buildinfo.rs), which cargo does not otherwise provide.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| String::from(commit.trim()))
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=XV6SH_GIT_COMMIT={}", commit);
+    let target = std::env::var("TARGET").unwrap_or_else(|_| String::from("unknown"));
+    println!("cargo:rustc-env=XV6SH_TARGET={}", target);
+    // note: rebuild when HEAD moves, either to another branch or because
+    // of a new commit on the current one
+    let head = Path::new(".git/HEAD");
+    if head.is_file() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Ok(content) = std::fs::read_to_string(head) {
+            if let Some(reference) = content.trim().strip_prefix("ref: ") {
+                let path = Path::new(".git").join(reference);
+                if path.is_file() {
+                    println!("cargo:rerun-if-changed={}", path.display());
+                }
+            }
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
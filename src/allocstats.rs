@@ -0,0 +1,69 @@
+//! Counts the memory that the shell allocates.
+//!
+//! When built with `--features alloc-stats`, the shell uses an allocator
+//! wrapping the system one that tracks how many bytes are allocated and
+//! the peak since the last reset, so that `--timings` can report how much
+//! memory each stage of the front-end (e.g., scanning and parsing) needs
+//! on large scripts. Counting costs a few atomic operations for each
+//! allocation, hence the feature is off by default.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The bytes currently allocated.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+/// The maximum of CURRENT since the last reset.
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of allocations since the last reset.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The allocator counting the allocations.
+pub struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        let next = System.realloc(ptr, layout, size);
+        if !next.is_null() {
+            CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(size);
+        }
+        next
+    }
+}
+
+/// Accounts for a new allocation of the given size.
+fn grow(size: usize) {
+    let current = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(current, Ordering::Relaxed);
+    COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Resets the peak and the number of allocations, returning the bytes
+/// currently allocated, which are the baseline of the next measure.
+pub fn reset() -> usize {
+    let current = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(current, Ordering::Relaxed);
+    COUNT.store(0, Ordering::Relaxed);
+    current
+}
+
+/// Returns the peak bytes allocated and the number of allocations
+/// since the last reset.
+pub fn peak() -> (usize, usize) {
+    (PEAK.load(Ordering::Relaxed), COUNT.load(Ordering::Relaxed))
+}
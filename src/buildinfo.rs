@@ -0,0 +1,101 @@
+//! Describes how the shell was built (i.e., `--version` and `buildinfo`).
+//!
+//! We print the version, the git commit, the enabled cargo features and
+//! the target triple either for humans or as a JSON object, such as:
+//!
+//! ```text
+//! {"name":"xv6sh","version":"0.1.0","commit":"c3dca6e...","features":["plugins"],"target":"x86_64-unknown-linux-gnu","capabilities":[...]}
+//! ```
+//!
+//! so that scripts can detect capabilities (e.g., `xv6sh --version
+//! --format json | jq .features`). The build script (see build.rs)
+//! provides the commit and the target triple.
+//!
+//! We also list the capabilities of the shell, which include the enabled
+//! features and which scripts may test using the `has` builtin (e.g., `has
+//! arithmetic`), so that they do not need to parse the version.
+
+use crate::model::{Error, Result};
+use crate::prompt;
+
+/// The version of the shell.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The git commit from which we built the shell, or `unknown`.
+const COMMIT: &str = env!("XV6SH_GIT_COMMIT");
+
+/// The target triple for which we built the shell.
+const TARGET: &str = env!("XV6SH_TARGET");
+
+/// The capabilities of the shell regardless of the enabled features.
+const CAPABILITIES: [&str; 7] = [
+    "arithmetic",
+    "command-substitution",
+    "jobcontrol",
+    "parameter-expansion",
+    "printf-time",
+    "subshells",
+    "tilde",
+];
+
+/// Returns the capabilities of the shell, including the enabled features.
+pub fn capabilities() -> Vec<&'static str> {
+    let mut capabilities: Vec<&'static str> = CAPABILITIES.to_vec();
+    capabilities.extend(features());
+    capabilities.sort();
+    capabilities
+}
+
+/// Returns the enabled cargo features.
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "alloc-stats") {
+        features.push("alloc-stats");
+    }
+    if cfg!(feature = "plugins") {
+        features.push("plugins");
+    }
+    features
+}
+
+/// Returns the build information in the given format, which
+/// is either `human` or `json`.
+pub fn format(format: &str) -> Result<String> {
+    match format {
+        "human" => Ok(human()),
+        "json" => Ok(json()),
+        _ => Err(Error::new(&format!("{}: unknown format", format))),
+    }
+}
+
+/// Formats the build information for humans.
+fn human() -> String {
+    let features = match features() {
+        features if features.len() > 0 => features.join(" "),
+        _ => String::from("none"),
+    };
+    format!(
+        "xv6sh {}\ncommit: {}\nfeatures: {}\ntarget: {}\ncapabilities: {}\n",
+        VERSION,
+        COMMIT,
+        features,
+        TARGET,
+        capabilities().join(" ")
+    )
+}
+
+/// Formats the build information as a single line of JSON.
+fn json() -> String {
+    let list = |names: Vec<&str>| -> String {
+        let quoted: Vec<String> = names.into_iter().map(prompt::quote).collect();
+        quoted.join(",")
+    };
+    format!(
+        "{{\"name\":\"xv6sh\",\"version\":{},\"commit\":{},\"features\":[{}],\"target\":{},\"capabilities\":[{}]}}\n",
+        prompt::quote(VERSION),
+        prompt::quote(COMMIT),
+        list(features()),
+        prompt::quote(TARGET),
+        list(capabilities())
+    )
+}
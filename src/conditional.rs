@@ -0,0 +1,129 @@
+//! Evaluates the expressions of `[[ ... ]]` conditional commands.
+//!
+//! We expand each operand as a single word, without field splitting and
+//! pathname expansion, so that `[[ -n $x ]]` works even when `$x` is empty
+//! or contains spaces. The right operand of `==` (or `=`) and `!=` is a
+//! pattern (see expansion::matches), unless quoted. The operators are:
+//!
+//! - `-e`, `-f`, `-d`, `-r`, `-w`, `-x`, `-s`, `-L` (or `-h`), `-b`, `-c`,
+//!   `-p` and `-S`, testing whether a file exists, is a regular file, is a
+//!   directory, is readable, writable or executable, is not empty, is a
+//!   symbolic link, a block or character device, a FIFO or a socket;
+//! - `-n` and `-z`, testing whether a string is not empty or empty;
+//! - `-t`, testing whether a file descriptor refers to a terminal;
+//! - `-v` and `-o`, testing whether a variable is set and whether a
+//!   `set -o` option is enabled;
+//! - `==`, `=` and `!=`, matching a string against a pattern, and `<` and
+//!   `>`, comparing strings according to the locale;
+//! - `-eq`, `-ne`, `-lt`, `-le`, `-gt` and `-ge`, comparing integers;
+//! - `-nt`, `-ot` and `-ef`, testing whether a file is newer or older than
+//!   another file, or whether both names refer to the same file.
+//!
+//! A word alone is true when it expands to a non-empty string, while `!`,
+//! `&&`, `||` and parentheses combine expressions, and we evaluate the right
+//! side of `&&` and `||` only when needed.
+
+use crate::expansion::{self, Parameters};
+use crate::locale;
+use crate::model::{Condition, Error, Result};
+use std::cmp::Ordering;
+use std::ffi::CString;
+use std::fs::Metadata;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+/// Evaluates the condition, expanding its words using the parameters.
+pub fn evaluate(condition: &Condition, params: &dyn Parameters) -> Result<bool> {
+    match condition {
+        Condition::Word(word) => Ok(expansion::expand(word, params)?.len() > 0),
+        Condition::Unary(operator, word) => {
+            unary(operator, &expansion::expand(word, params)?, params)
+        }
+        Condition::Binary(left, operator, right) => {
+            let value = expansion::expand(left, params)?;
+            match operator.as_str() {
+                "==" | "=" => expansion::matches(&value, right, params),
+                "!=" => Ok(!expansion::matches(&value, right, params)?),
+                _ => binary(&value, operator, &expansion::expand(right, params)?),
+            }
+        }
+        Condition::Not(inner) => Ok(!evaluate(inner, params)?),
+        Condition::And(left, right) => Ok(evaluate(left, params)? && evaluate(right, params)?),
+        Condition::Or(left, right) => Ok(evaluate(left, params)? || evaluate(right, params)?),
+    }
+}
+
+/// Evaluates a unary operator.
+fn unary(operator: &str, operand: &str, params: &dyn Parameters) -> Result<bool> {
+    let file = || std::fs::metadata(operand);
+    let result = match operator {
+        "-e" => file().is_ok(),
+        "-f" => file().is_ok_and(|metadata| metadata.is_file()),
+        "-d" => file().is_ok_and(|metadata| metadata.is_dir()),
+        "-r" => access(operand, libc::R_OK),
+        "-w" => access(operand, libc::W_OK),
+        "-x" => access(operand, libc::X_OK),
+        "-s" => file().is_ok_and(|metadata| metadata.len() > 0),
+        "-L" | "-h" => std::fs::symlink_metadata(operand).is_ok_and(|m| m.is_symlink()),
+        "-b" => file().is_ok_and(|metadata| metadata.file_type().is_block_device()),
+        "-c" => file().is_ok_and(|metadata| metadata.file_type().is_char_device()),
+        "-p" => file().is_ok_and(|metadata| metadata.file_type().is_fifo()),
+        "-S" => file().is_ok_and(|metadata| metadata.file_type().is_socket()),
+        "-n" => operand.len() > 0,
+        "-z" => operand.len() == 0,
+        "-t" => match operand.parse::<i32>() {
+            Err(_) => return Err(Error::new(&format!("[[: {}: invalid fd", operand))),
+            Ok(fd) => unsafe { libc::isatty(fd) == 1 },
+        },
+        "-v" => params.lookup(operand).is_some(),
+        "-o" => params.option(operand),
+        _ => return Err(Error::new(&format!("[[: {}: unknown operator", operator))),
+    };
+    Ok(result)
+}
+
+/// Evaluates a binary operator other than the pattern matching ones.
+fn binary(left: &str, operator: &str, right: &str) -> Result<bool> {
+    let number = |value: &str| match expansion::number(value.trim()) {
+        Err(err) => Err(Error::new(&format!("[[: {}", err))),
+        Ok(value) => Ok(value),
+    };
+    let modified = |name: &str| std::fs::metadata(name).and_then(|m| m.modified()).ok();
+    let result = match operator {
+        "<" => locale::current().compare(left, right) == Ordering::Less,
+        ">" => locale::current().compare(left, right) == Ordering::Greater,
+        "-eq" => number(left)? == number(right)?,
+        "-ne" => number(left)? != number(right)?,
+        "-lt" => number(left)? < number(right)?,
+        "-le" => number(left)? <= number(right)?,
+        "-gt" => number(left)? > number(right)?,
+        "-ge" => number(left)? >= number(right)?,
+        // note: a missing file is older than an existing one
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, _) => left.is_some(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (_, right) => right.is_some(),
+        },
+        "-ef" => match (std::fs::metadata(left), std::fs::metadata(right)) {
+            (Ok(left), Ok(right)) => same_file(&left, &right),
+            _ => false,
+        },
+        _ => return Err(Error::new(&format!("[[: {}: unknown operator", operator))),
+    };
+    Ok(result)
+}
+
+/// Tells whether we can access the file with the given mode.
+fn access(name: &str, mode: libc::c_int) -> bool {
+    match CString::new(name) {
+        Err(_) => false,
+        Ok(cname) => unsafe { libc::access(cname.as_ptr(), mode) == 0 },
+    }
+}
+
+/// Tells whether two metadata refer to the same file.
+fn same_file(left: &Metadata, right: &Metadata) -> bool {
+    left.dev() == right.dev() && left.ino() == right.ino()
+}
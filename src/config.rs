@@ -0,0 +1,76 @@
+//! Loads and reloads the configuration file.
+//!
+//! Before reading the first command, the interactive shell sources the
+//! file named by `$XV6SHRC` or, if unset, `$HOME/.xv6shrc`. The shell
+//! sources the file again, before showing the next prompt, when its
+//! modification time changes or when the shell receives SIGUSR1, so that
+//! editing the configuration does not require restarting the shell.
+//!
+//! Since the shell has no aliases and cannot set variables, the file can
+//! only change the options (e.g., `set -C`). When reloading, the options
+//! that the user explicitly changed with `set` at the prompt win over the
+//! values set by the file.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+/// Whether we received SIGUSR1 since we last checked.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Watches the configuration file for changes.
+pub struct Watcher {
+    /// The name of the configuration file.
+    pub filename: String,
+
+    /// The modification time when we last loaded the file.
+    mtime: Option<SystemTime>,
+}
+
+/// Returns the name of the configuration file, if any.
+pub fn filename() -> Option<String> {
+    match (std::env::var("XV6SHRC"), std::env::var("HOME")) {
+        (Ok(filename), _) if filename.len() > 0 => Some(filename),
+        (_, Ok(home)) if home.len() > 0 => Some(format!("{}/.xv6shrc", home)),
+        _ => None,
+    }
+}
+
+/// Installs the SIGUSR1 handler requesting to reload the configuration.
+pub fn install_handler() {
+    unsafe {
+        let mut sa: libc::sigaction = std::mem::zeroed();
+        sa.sa_sigaction = on_sigusr1 as extern "C" fn(libc::c_int) as usize;
+        sa.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut sa.sa_mask);
+        libc::sigaction(libc::SIGUSR1, &sa, std::ptr::null_mut());
+    }
+}
+
+/// Records the request to reload the configuration.
+extern "C" fn on_sigusr1(_sig: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+impl Watcher {
+    /// Creates a new watcher for the given file.
+    pub fn new(filename: String) -> Watcher {
+        Watcher {
+            filename: filename,
+            mtime: None,
+        }
+    }
+
+    /// Tells whether we should (re)load the configuration file, because
+    /// it exists and it changed since we last loaded it or because we
+    /// received SIGUSR1 in the meanwhile.
+    pub fn changed(self: &mut Self) -> bool {
+        let requested = REQUESTED.swap(false, Ordering::SeqCst);
+        let mtime = match std::fs::metadata(&self.filename) {
+            Err(_) => return false, // there's nothing to load
+            Ok(metadata) => metadata.modified().ok(),
+        };
+        let changed = mtime != self.mtime;
+        self.mtime = mtime;
+        changed || requested
+    }
+}
@@ -0,0 +1,167 @@
+//! Records which commands of a script executed.
+//!
+//! With `--coverage FILE`, the shell collects the pipelines of the script
+//! before running it and counts how many times each of them executes, then
+//! writes to `FILE`, when it exits, a JSON object listing each pipeline with
+//! its line, its column, the source line and the number of executions, as
+//! well as the lines containing pipelines that executed and those that
+//! did not. We track the pipelines that the shell runs itself, including the
+//! ones inside `if` clauses and brace groups, while a pipeline running in a
+//! subshell (e.g., `( ... )` or a pipeline ending with `&`) counts as a
+//! single unit. We do not track the commands of sourced files, of traps
+//! and of `eval`-like builtins, whose offsets refer to other input.
+
+use crate::model::{Error, Result};
+use crate::parser::{Command, Pipeline, Program};
+use crate::script::Script;
+use crate::visitor::{self, Visitor};
+use crate::{prompt, translator};
+use std::collections::BTreeMap;
+
+/// The coverage of a script.
+pub struct Coverage {
+    /// The file where to write the coverage.
+    output: String,
+
+    /// The name of the script.
+    script: String,
+
+    /// The lines of the script.
+    lines: Vec<String>,
+
+    /// The pipelines of the script, indexed by their offset in chars.
+    pipelines: BTreeMap<usize, Entry>,
+}
+
+/// A pipeline of the script.
+struct Entry {
+    /// The line number starting from one.
+    line: usize,
+
+    /// The column number starting from one.
+    column: usize,
+
+    /// How many times the pipeline executed.
+    hits: usize,
+}
+
+/// Collects the offsets of the pipelines that the shell runs itself.
+struct Collector {
+    offsets: Vec<usize>,
+}
+
+impl Visitor for Collector {
+    fn visit_pipeline(self: &mut Self, pipeline: &Pipeline) {
+        // note: the parser produces an empty command after a trailing `&`
+        let empty = match pipeline.commands.front() {
+            None => true,
+            Some(Command::SimpleCommand(scmd)) => {
+                scmd.assignments.len() == 0
+                    && scmd.arguments.len() == 0
+                    && scmd.redirs.redirs.len() == 0
+            }
+            Some(_) => false,
+        };
+        if empty {
+            return;
+        }
+        self.offsets.push(pipeline.offset());
+        // note: the subshells run the other pipelines, out of our sight
+        if translator::runs_in_shell(pipeline) {
+            visitor::walk_pipeline(self, pipeline);
+        }
+    }
+}
+
+/// Creates the coverage, which we write into the given file.
+pub fn create(output: &str) -> Coverage {
+    Coverage {
+        output: String::from(output),
+        script: String::new(),
+        lines: Vec::new(),
+        pipelines: BTreeMap::new(),
+    }
+}
+
+impl Coverage {
+    /// Collects the pipelines of the given script, which we're about to run.
+    pub fn collect(self: &mut Self, script: &Script, program: &Program) {
+        let mut collector = Collector {
+            offsets: Vec::new(),
+        };
+        collector.visit_program(program);
+        collector.offsets.sort();
+        self.script = script.filename.clone();
+        self.lines = script.content.lines().map(String::from).collect();
+        let mut offsets = collector.offsets.into_iter().peekable();
+        let (mut line, mut column) = (1, 1);
+        for (index, c) in script.content.chars().enumerate() {
+            while let Some(offset) = offsets.next_if(|offset| *offset <= index) {
+                let entry = Entry {
+                    line: line,
+                    column: column,
+                    hits: 0,
+                };
+                self.pipelines.insert(offset, entry);
+            }
+            if c == '\n' {
+                (line, column) = (line + 1, 1);
+            } else {
+                column += 1;
+            }
+        }
+    }
+
+    /// Records that the pipeline at the given offset executed.
+    pub fn hit(self: &mut Self, offset: usize) {
+        if let Some(entry) = self.pipelines.get_mut(&offset) {
+            entry.hits += 1;
+        }
+    }
+
+    /// Writes the coverage into the output file.
+    pub fn save(self: &Self) -> Result<()> {
+        if let Err(err) = std::fs::write(&self.output, self.json()) {
+            return Err(Error::new(&format!("{}: {}", self.output, err)));
+        }
+        Ok(())
+    }
+
+    /// Encodes the coverage as JSON.
+    fn json(self: &Self) -> String {
+        let mut pipelines = Vec::new();
+        let mut executed = BTreeMap::<usize, bool>::new();
+        for entry in self.pipelines.values() {
+            let source = match self.lines.get(entry.line - 1) {
+                None => "",
+                Some(source) => source.trim(),
+            };
+            pipelines.push(format!(
+                "{{\"line\": {}, \"column\": {}, \"hits\": {}, \"source\": {}}}",
+                entry.line,
+                entry.column,
+                entry.hits,
+                prompt::quote(source)
+            ));
+            *executed.entry(entry.line).or_default() |= entry.hits > 0;
+        }
+        let lines = |wanted: bool| {
+            let lines: Vec<String> = executed
+                .iter()
+                .filter(|(_, executed)| **executed == wanted)
+                .map(|(line, _)| line.to_string())
+                .collect();
+            lines.join(", ")
+        };
+        let count = self.pipelines.values().filter(|entry| entry.hits > 0);
+        format!(
+            "{{\n  \"script\": {},\n  \"pipelines\": {},\n  \"executed\": {},\n  \"entries\": [\n    {}\n  ],\n  \"executed_lines\": [{}],\n  \"missed_lines\": [{}]\n}}\n",
+            prompt::quote(&self.script),
+            self.pipelines.len(),
+            count.count(),
+            pipelines.join(",\n    "),
+            lines(true),
+            lines(false)
+        )
+    }
+}
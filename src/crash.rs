@@ -0,0 +1,182 @@
+//! Writes a crash report when the shell panics.
+//!
+//! We install a panic hook that, besides printing the panic message,
+//! writes a report to `$HOME/.xv6sh/crashes` (or to the temporary directory
+//! if `HOME` is unset) and tells the user where we saved it, so that bug
+//! reports about panics contain actionable data. The report contains the
+//! build information, the panic message, the input being processed, the
+//! stage we reached, a summary of the tokens and of the syntax tree, and a
+//! backtrace. Since the input may contain secrets, we redact the values of
+//! variables whose name suggests a secret (e.g., `API_TOKEN=...`), the
+//! credentials inside URLs, long random-looking strings and the home
+//! directory, which we replace with `~`. In hardened mode, we do not
+//! save the report (see hardened.rs).
+
+use crate::{buildinfo, hardened, lexer, parser, serializer};
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The maximum number of chars of input we include in the report.
+const MAX_INPUT: usize = 4096;
+
+/// The parts of names that suggest that a variable contains a secret.
+const SECRETS: [&str; 6] = ["pass", "token", "secret", "key", "auth", "credential"];
+
+/// What the shell was doing when it panicked.
+struct Context {
+    input: String,
+    stage: String,
+    tokens: String,
+    ast: String,
+}
+
+/// The context of the command we're currently processing.
+static CONTEXT: Lazy<Mutex<Context>> = Lazy::new(|| {
+    Mutex::new(Context {
+        input: String::new(),
+        stage: String::from("startup"),
+        tokens: String::new(),
+        ast: String::new(),
+    })
+});
+
+/// Installs the panic hook writing the crash report.
+pub fn install() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default(info);
+        if hardened::enabled() {
+            eprintln!("xv6sh: this is a bug; not saving a crash report in hardened mode");
+            return;
+        }
+        match save(&report(&info.to_string())) {
+            Err(err) => eprintln!("xv6sh: cannot save the crash report: {}", err),
+            Ok(path) => eprintln!(
+                "xv6sh: this is a bug; please attach the crash report saved to {} to your bug report",
+                path.display()
+            ),
+        }
+    }));
+}
+
+/// Records that we're about to scan the given input.
+pub fn input(input: &str) {
+    update(|context| {
+        context.input = input.chars().take(MAX_INPUT).collect();
+        context.stage = String::from("scan");
+        context.tokens.clear();
+        context.ast.clear();
+    });
+}
+
+/// Records the stage we reached (e.g., `plan`).
+pub fn stage(stage: &str) {
+    update(|context| context.stage = String::from(stage));
+}
+
+/// Records the kinds of the tokens we scanned.
+pub fn tokens(tokens: &VecDeque<lexer::Token>) {
+    let kinds: Vec<String> = tokens
+        .iter()
+        .map(|token| format!("{:?}", token.kind))
+        .collect();
+    update(|context| {
+        context.tokens = format!("{} tokens: {}", kinds.len(), kinds.join(" "));
+        context.stage = String::from("parse");
+    });
+}
+
+/// Records the complete command we're about to process.
+pub fn ast(cc: &parser::CompleteCommand) {
+    let ast = match serializer::serialize(cc) {
+        Err(err) => format!("<cannot serialize: {}>", err),
+        Ok(ast) => ast,
+    };
+    update(|context| context.ast = ast);
+}
+
+/// Updates the context, unless a panic left it locked.
+fn update(change: impl FnOnce(&mut Context)) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        change(&mut context);
+    }
+}
+
+/// Formats the report for the given panic message.
+fn report(message: &str) -> String {
+    let mut report = String::from("xv6sh crash report\n\n");
+    if let Ok(info) = buildinfo::format("human") {
+        report.push_str(&info);
+    }
+    report.push_str(&format!("message: {}\n", redact(message)));
+    // note: try_lock because the panic may have occurred while updating
+    if let Ok(context) = CONTEXT.try_lock() {
+        report.push_str(&format!("stage: {}\n", context.stage));
+        report.push_str(&format!("input: {}\n", redact(&context.input)));
+        report.push_str(&format!("tokens: {}\n", context.tokens));
+        report.push_str(&format!("ast: {}\n", redact(&context.ast)));
+    }
+    let backtrace = std::backtrace::Backtrace::force_capture();
+    report.push_str(&format!("\nbacktrace:\n{}\n", backtrace));
+    report
+}
+
+/// Saves the report and returns its path.
+fn save(report: &str) -> std::io::Result<PathBuf> {
+    let dir = match std::env::var("HOME") {
+        Ok(home) if home.len() > 0 => PathBuf::from(home).join(".xv6sh/crashes"),
+        _ => std::env::temp_dir(),
+    };
+    std::fs::create_dir_all(&dir)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let path = dir.join(format!("crash-{}-{}.txt", now, std::process::id()));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// Redacts the secrets inside the given text (see the module documentation).
+fn redact(text: &str) -> String {
+    let text = match std::env::var("HOME") {
+        Ok(home) if home.len() > 1 => text.replace(&home, "~"),
+        _ => String::from(text),
+    };
+    let mut output = String::new();
+    for piece in text.split_inclusive(char::is_whitespace) {
+        let word = piece.trim_end_matches(char::is_whitespace);
+        output.push_str(&redact_word(word));
+        output.push_str(&piece[word.len()..]);
+    }
+    output
+}
+
+/// Redacts a single whitespace-separated word.
+fn redact_word(word: &str) -> String {
+    if let Some((name, _)) = word.split_once('=') {
+        let lowercase = name.to_lowercase();
+        if SECRETS.iter().any(|secret| lowercase.contains(secret)) {
+            return format!("{}=<redacted>", name);
+        }
+    }
+    if let Some(start) = word.find("://") {
+        if let Some(at) = word[start + 3..].find('@') {
+            let end = start + 3 + at;
+            return format!("{}<redacted>{}", &word[..start + 3], &word[end..]);
+        }
+    }
+    let random = word.len() >= 24
+        && word.chars().any(|c| c.is_ascii_digit())
+        && word.chars().any(|c| c.is_ascii_alphabetic())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+/=_-.".contains(c));
+    match random {
+        true => String::from("<redacted>"),
+        false => String::from(word),
+    }
+}
@@ -0,0 +1,126 @@
+//! Helps investigating background jobs that seem stuck.
+//!
+//! The `debugjob JOB` builtin lists the processes of a supervised command
+//! (`%N`) or background process (its pid), i.e., the process itself and
+//! its descendants, such as the commands of a pipeline running inside a
+//! subshell, along with their state and the kernel function they're
+//! waiting in (e.g., `pipe_read`). With `debugjob JOB PID`, the shell runs
+//! the debugger named by `$XV6SH_DEBUGGER` (default: `strace -p`) attached
+//! to one of these processes as a foreground job, replacing `{}` inside the
+//! debugger's words with the pid, or appending the pid when there's no `{}`
+//! (e.g., `XV6SH_DEBUGGER='gdb -p {}'`). We read the processes from `/proc`,
+//! hence listing them only works on Linux.
+
+use std::collections::HashMap;
+
+/// The default debugger.
+const DEBUGGER: &str = "strace -p";
+
+/// A process of a job.
+pub struct Process {
+    pub pid: u32,
+
+    /// How many ancestors separate the process from the job's process.
+    pub depth: usize,
+
+    /// The state of the process (e.g., `S` for sleeping).
+    pub state: String,
+
+    /// The kernel function the process is waiting in, if any.
+    pub wchan: String,
+
+    /// The command line of the process.
+    pub command: String,
+}
+
+/// Returns the processes of the job whose process has the given pid,
+/// in depth-first order, starting with the job's process itself.
+pub fn processes(pid: u32) -> Vec<Process> {
+    let mut children = HashMap::<u32, Vec<u32>>::new();
+    let mut states = HashMap::<u32, String>::new();
+    for entry in std::fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+            Err(_) => continue,
+            Ok(pid) => pid,
+        };
+        if let Some((state, ppid)) = stat(pid) {
+            children.entry(ppid).or_default().push(pid);
+            states.insert(pid, state);
+        }
+    }
+    let mut output = Vec::<Process>::new();
+    let mut pending = vec![(pid, 0)];
+    while let Some((pid, depth)) = pending.pop() {
+        let state = match states.get(&pid) {
+            None => continue, // the process has terminated
+            Some(state) => state.clone(),
+        };
+        output.push(Process {
+            pid: pid,
+            depth: depth,
+            state: state,
+            wchan: wchan(pid),
+            command: command(pid),
+        });
+        if let Some(pids) = children.get_mut(&pid) {
+            pids.sort();
+            pending.extend(pids.iter().rev().map(|pid| (*pid, depth + 1)));
+        }
+    }
+    output
+}
+
+/// Returns the debugger command line attached to the given pid, given
+/// the value of `$XV6SH_DEBUGGER`, if set.
+pub fn debugger(configured: Option<String>, pid: u32) -> Vec<String> {
+    let configured = match configured {
+        Some(value) if value.trim().len() > 0 => value,
+        _ => String::from(DEBUGGER),
+    };
+    let pid = pid.to_string();
+    let mut words: Vec<String> = configured
+        .split_whitespace()
+        .map(|word| word.replace("{}", &pid))
+        .collect();
+    if !configured.contains("{}") {
+        words.push(pid);
+    }
+    words
+}
+
+/// Returns the state and the parent pid of the given process.
+fn stat(pid: u32) -> Option<(String, u32)> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // note: the command name may contain spaces, hence we split after it
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace();
+    let state = String::from(fields.next()?);
+    let ppid = fields.next()?.parse::<u32>().ok()?;
+    Some((state, ppid))
+}
+
+/// Returns the kernel function the given process is waiting in.
+fn wchan(pid: u32) -> String {
+    match std::fs::read_to_string(format!("/proc/{}/wchan", pid)) {
+        Ok(wchan) if wchan.len() > 0 && wchan != "0" => wchan,
+        _ => String::from("-"),
+    }
+}
+
+/// Returns the command line of the given process, falling back to its
+/// name (e.g., for zombies, whose command line is empty).
+fn command(pid: u32) -> String {
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+    let words: Vec<String> = cmdline
+        .split(|c| *c == 0)
+        .filter(|word| word.len() > 0)
+        .map(|word| String::from_utf8_lossy(word).into_owned())
+        .collect();
+    if words.len() > 0 {
+        return words.join(" ");
+    }
+    match std::fs::read_to_string(format!("/proc/{}/comm", pid)) {
+        Err(_) => String::from("?"),
+        Ok(comm) => format!("[{}]", comm.trim_end()),
+    }
+}
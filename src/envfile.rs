@@ -0,0 +1,170 @@
+//! Loads variables from dotenv-style files.
+//!
+//! The `envfile FILE COMMAND...` builtin runs `COMMAND` with the variables
+//! defined by `FILE` added to its environment, without setting them in the
+//! shell. Each definition has the form `NAME=VALUE`, optionally preceded by
+//! `export`, and lines starting with `#` are comments. An unquoted value
+//! extends to the end of the line, minus a trailing ` # comment` and
+//! the surrounding whitespace. Within single quotes, every char is
+//! literal, while within double quotes `\n`, `\t`, `\"`, `\\` and `\$` are
+//! escapes. Quoted values may span multiple lines. We do not expand
+//! variables inside the values.
+
+use crate::lexer;
+use crate::model::{Error, Result};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Reads the given file and returns the variables it defines, in order.
+pub fn load(path: &str) -> Result<Vec<(String, String)>> {
+    let content = match std::fs::read_to_string(path) {
+        Err(err) => return Err(Error::new(&format!("envfile: {}: {}", path, err))),
+        Ok(content) => content,
+    };
+    let mut parser = Parser {
+        chars: content.chars().peekable(),
+        line: 1,
+    };
+    match parser.definitions() {
+        Err(reason) => Err(Error::new(&format!(
+            "envfile: {}:{}: {}",
+            path, parser.line, reason
+        ))),
+        Ok(variables) => Ok(variables),
+    }
+}
+
+/// Parses the content of a dotenv-style file.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+
+    /// The line we're parsing, for error messages.
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Parses all the definitions.
+    fn definitions(self: &mut Self) -> std::result::Result<Vec<(String, String)>, String> {
+        let mut variables = Vec::new();
+        loop {
+            self.skip_blanks();
+            match self.chars.peek() {
+                None => return Ok(variables),
+                Some('\n') => {
+                    self.next();
+                    continue;
+                }
+                Some('#') => {
+                    self.skip_line();
+                    continue;
+                }
+                Some(_) => (),
+            }
+            let mut name = self.word();
+            if name == "export" && matches!(self.chars.peek(), Some(' ' | '\t')) {
+                self.skip_blanks();
+                name = self.word();
+            }
+            if !lexer::is_name(&name) || self.next() != Some('=') {
+                return Err(String::from("expected NAME=VALUE"));
+            }
+            let value = self.value()?;
+            variables.push((name, value));
+        }
+    }
+
+    /// Parses a value, which may be quoted, and the rest of the line.
+    fn value(self: &mut Self) -> std::result::Result<String, String> {
+        self.skip_blanks();
+        let value = match self.chars.peek() {
+            Some('\'') => {
+                self.next();
+                self.quoted('\'')?
+            }
+            Some('"') => {
+                self.next();
+                self.quoted('"')?
+            }
+            _ => {
+                let mut value = String::new();
+                while let Some(c) = self.chars.next_if(|c| *c != '\n') {
+                    value.push(c);
+                }
+                // note: like sh, `#` starts a comment only after a blank
+                let blank = |index: usize| value[..index].ends_with([' ', '\t']);
+                if let Some((index, _)) = value
+                    .char_indices()
+                    .find(|(index, c)| *c == '#' && blank(*index))
+                {
+                    value.truncate(index);
+                }
+                return Ok(String::from(value.trim()));
+            }
+        };
+        self.skip_blanks();
+        match self.chars.peek() {
+            None | Some('\n') | Some('#') => {
+                self.skip_line();
+                Ok(value)
+            }
+            Some(_) => Err(String::from("unexpected text after the closing quote")),
+        }
+    }
+
+    /// Parses a value up to the given closing quote.
+    fn quoted(self: &mut Self, quote: char) -> std::result::Result<String, String> {
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                None => return Err(format!("missing closing {}", quote)),
+                Some(c) if c == quote => return Ok(value),
+                Some('\\') if quote == '"' => match self.next() {
+                    None => return Err(format!("missing closing {}", quote)),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                },
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    /// Returns the chars up to the next `=`, blank or newline.
+    fn word(self: &mut Self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| !matches!(c, '=' | ' ' | '\t' | '\n'))
+        {
+            word.push(c);
+        }
+        word
+    }
+
+    /// Skips the spaces and tabs.
+    fn skip_blanks(self: &mut Self) {
+        while self.chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+    }
+
+    /// Skips the rest of the line, including the newline.
+    fn skip_line(self: &mut Self) {
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    /// Returns the next char, keeping track of the line number.
+    fn next(self: &mut Self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+}
@@ -0,0 +1,897 @@
+//! Expands the variables and the command substitutions inside words.
+//!
+//! The lexer records which `$` signs start an expansion, i.e., those that
+//! are neither single-quoted nor escaped with a backslash, so that `'$HOME'`
+//! and `\$HOME` remain literal while `$HOME` and `"$HOME"` expand. We
+//! substitute `$NAME` and `${NAME}` with the value of the variable, the
+//! special and positional parameters (e.g., `$?`, `$1`, `${10}`) with the
+//! value the interpreter gives them and a `$` not followed by a name with
+//! itself.
+//!
+//! We also implement the POSIX operators `${NAME:-word}` (use the word
+//! if unset or empty), `${NAME:=word}` (also assign it), `${NAME:?word}`
+//! (fail with the word as message), `${NAME:+word}` (use the word unless
+//! unset or empty) and, without the colon, their variants testing only
+//! whether NAME is unset, plus `${#NAME}` (the length of the value). We
+//! expand the word only when we use it.
+//!
+//! An arithmetic expansion, `$((expression))`, expands to the value of the
+//! expression, which we evaluate using 64-bit signed integers. We expand
+//! the parameters inside the expression first, and a name that is not
+//! preceded by `$` evaluates to the number inside the variable (or zero if
+//! the variable is unset or empty). The operators, from the lowest to the
+//! highest precedence, are `||`, `&&`, `|`, `^`, `&`, `==` and `!=`, `<`,
+//! `<=`, `>` and `>=`, `<<` and `>>`, `+` and `-`, `*`, `/` and `%`, and
+//! the unary `+`, `-`, `!` and `~`, while parentheses group.
+//!
+//! A word starting with an unquoted `~` begins with a tilde prefix, which
+//! extends up to the first `/`: `~` expands to `$HOME` and `~user` to the
+//! home directory of `user`, so that `cd ~` and `ls ~/src` work.
+//!
+//! A command substitution, `$(command)` or `` `command` ``, which the lexer
+//! stores as `$(command)`, expands to the standard output of the command,
+//! without its trailing newlines, unless `set -o keepnewlines`. Output
+//! containing NUL bytes, which arguments cannot contain, is an error,
+//! rather than being silently truncated, and so is output that is not
+//! valid UTF-8, rather than being silently altered. When the substitution is not inside
+//! double quotes, we split its output into fields (see below), so that
+//! `ls $(cat files)` passes each file as a separate argument.
+//!
+//! The interpreter expands the words of each pipeline right before
+//! executing it, so that the expansion sees the effect of the previous
+//! pipelines (e.g., `cd /tmp; echo $PWD`). Unset variables expand to the
+//! empty string and we remove the unquoted words expanding to nothing. We
+//! split the results of the unquoted expansions into multiple words using
+//! the chars inside `$IFS` (default: space, tab and newline), while a word
+//! consisting of just `$@` expands to one word per positional parameter.
+//!
+//! We expand each word in the POSIX order: first the tilde, parameter,
+//! command and arithmetic expansions, then field splitting and finally
+//! pathname expansion, in which a field containing the unquoted pattern
+//! chars `*`, `?` and `[...]`, or pattern chars resulting from unquoted
+//! expansions, expands to the sorted list of matching pathnames or stays
+//! unchanged when nothing matches (e.g., `ls *.rs`). Quote removal needs
+//! no separate stage, since the lexer removes the quotes while recording
+//! the offsets of the expansions and of the pattern chars. Redirections
+//! undergo neither field splitting nor pathname expansion.
+
+use crate::model::{Error, Result, Word};
+use crate::{lexer, locale};
+use std::collections::VecDeque;
+
+/// The default value of `$IFS`, the chars at which we split the results
+/// of the unquoted expansions, which are also the IFS whitespace chars.
+const IFS: &str = " \t\n";
+
+/// The binary arithmetic operators grouped by increasing precedence.
+const OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// The special parameters, whose name is a single char: `$?` is the
+/// exit status of the last pipeline, `$$` is the shell's process ID, `$#`
+/// is the number of positional parameters, `$@` and `$*` are all of them
+/// and `$0`, `$1`, ... are the script name and the positional parameters.
+const SPECIAL: &str = "?$#@*0123456789";
+
+/// Provides the values of the parameters and variables.
+pub trait Parameters {
+    /// Returns the value of a named parameter or variable, if set,
+    /// including `$?`, `$$` and `$0`.
+    fn lookup(self: &Self, name: &str) -> Option<String>;
+
+    /// Returns the positional parameters (i.e., `$1`, `$2`, ...).
+    fn positional(self: &Self) -> &[String];
+
+    /// Sets the value of a variable (i.e., `${NAME=word}`).
+    fn assign(self: &Self, name: &str, value: &str) -> Result<()>;
+
+    /// Runs the given command and returns its standard output
+    /// (i.e., `$(command)`).
+    fn substitute(self: &Self, command: &str) -> Result<String>;
+
+    /// Tells whether the given `set -o` option is enabled.
+    fn option(self: &Self, name: &str) -> bool;
+}
+
+/// A field resulting from expanding and splitting a word.
+#[derive(Default)]
+struct Field {
+    value: String,
+
+    /// The offsets in chars of the pattern chars, which either were
+    /// unquoted inside the word or result from unquoted expansions.
+    globs: Vec<usize>,
+}
+
+/// Expands each word, removing the unquoted words expanding to nothing.
+/// A word consisting of just `$@` (quoted or not) expands to one word for
+/// each positional parameter, so that `"$@"` passes them along unchanged.
+/// A word containing unquoted expansions may expand to multiple words, or
+/// to none, depending on their values, and a word containing unquoted
+/// pattern chars expands to the matching pathnames, if any.
+pub fn words(input: VecDeque<Word>, params: &dyn Parameters) -> Result<VecDeque<String>> {
+    let mut output = VecDeque::<String>::new();
+    for word in input {
+        for field in fields(&word, params)? {
+            output.extend(pathnames(field, params));
+        }
+    }
+    Ok(output)
+}
+
+/// Runs the tilde, parameter, command and arithmetic expansions on the
+/// word and splits the results of the unquoted ones into fields.
+fn fields(word: &Word, params: &dyn Parameters) -> Result<Vec<Field>> {
+    if (word.value == "$@" || word.value == "${@}") && word.expansions == [0] {
+        let positional = params.positional().iter();
+        return Ok(positional.map(|value| Field::literal(value)).collect());
+    }
+    if word.splits.len() > 0 || word.globs.len() > 0 {
+        let chars: Vec<char> = word.value.chars().collect();
+        let expander = Expander {
+            word: word,
+            chars: chars,
+            params: params,
+        };
+        return expander.fields();
+    }
+    let value = expand(word, params)?;
+    match value.len() > 0 || word.quoted || word.expansions.len() == 0 {
+        true => Ok(vec![Field::literal(&value)]),
+        false => Ok(Vec::new()),
+    }
+}
+
+impl Field {
+    /// creates a field without pattern chars.
+    fn literal(value: &str) -> Field {
+        Field {
+            value: String::from(value),
+            globs: Vec::new(),
+        }
+    }
+
+    /// appends a char, which may be a pattern char.
+    fn push(self: &mut Self, c: char, glob: bool) {
+        if glob {
+            self.globs.push(self.value.chars().count());
+        }
+        self.value.push(c);
+    }
+}
+
+/// Expands the field containing pattern chars into the pathnames matching
+/// it, sorted according to the locale, or returns the field unchanged if
+/// there are no matches. The `/` chars and a leading `.` inside a name
+/// must match explicitly. With `set -o nocaseglob`, we ignore case.
+fn pathnames(field: Field, params: &dyn Parameters) -> Vec<String> {
+    if field.globs.len() == 0 {
+        return vec![field.value];
+    }
+    let glob = Glob {
+        locale: locale::current(),
+        nocase: params.option("nocaseglob"),
+    };
+    let pattern: Vec<(char, bool)> = field
+        .value
+        .chars()
+        .enumerate()
+        .map(|(index, c)| (c, field.globs.contains(&index)))
+        .collect();
+    let mut paths = vec![String::new()];
+    for (index, component) in pattern.split(|(c, _)| *c == '/').enumerate() {
+        let mut next = Vec::<String>::new();
+        for path in &paths {
+            let prefix = match index {
+                0 => String::new(),
+                _ => format!("{}/", path),
+            };
+            if !component.iter().any(|(_, glob)| *glob) {
+                next.push(prefix + &component.iter().map(|(c, _)| c).collect::<String>());
+                continue;
+            }
+            let entries = match std::fs::read_dir(if index == 0 { "." } else { &prefix }) {
+                Err(_) => continue,
+                Ok(entries) => entries,
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| {
+                    let name: Vec<char> = name.chars().collect();
+                    (name.first() != Some(&'.') || component.first() == Some(&('.', false)))
+                        && glob.matches(component, &name)
+                })
+                .collect();
+            names.sort_by(|left, right| glob.locale.compare(left, right));
+            next.extend(names.into_iter().map(|name| format!("{}{}", prefix, name)));
+        }
+        paths = next;
+    }
+    paths.retain(|path| std::fs::symlink_metadata(path).is_ok());
+    match paths.len() {
+        0 => vec![field.value],
+        _ => paths,
+    }
+}
+
+/// Matches names against patterns.
+struct Glob {
+    locale: locale::Locale,
+
+    /// Whether we ignore case (i.e., `set -o nocaseglob`).
+    nocase: bool,
+}
+
+impl Glob {
+    /// tells whether the name matches the pattern, whose pattern chars are
+    /// `*` (any string), `?` (any char) and `[...]` (any char in the set,
+    /// which may contain ranges and character classes such as `[:alpha:]`,
+    /// or not in the set when it starts with `!`).
+    fn matches(self: &Self, pattern: &[(char, bool)], name: &[char]) -> bool {
+        match pattern.first() {
+            None => name.len() == 0,
+            Some(('*', true)) => {
+                (0..=name.len()).any(|skip| self.matches(&pattern[1..], &name[skip..]))
+            }
+            Some(('?', true)) => name.len() > 0 && self.matches(&pattern[1..], &name[1..]),
+            Some(('[', true)) => match self.bracket(&pattern[1..], name.first().copied()) {
+                Some((matched, length)) => {
+                    matched && self.matches(&pattern[1 + length..], &name[1..])
+                }
+                None => self.same(Some('['), name) && self.matches(&pattern[1..], &name[1..]),
+            },
+            Some((c, _)) => self.same(Some(*c), name) && self.matches(&pattern[1..], &name[1..]),
+        }
+    }
+
+    /// tells whether the char is the first char of the name.
+    fn same(self: &Self, c: Option<char>, name: &[char]) -> bool {
+        match (c, name.first()) {
+            (Some(c), Some(first)) if self.nocase => {
+                self.locale.lowercase(c) == self.locale.lowercase(*first)
+            }
+            (Some(c), Some(first)) => c == *first,
+            _ => false,
+        }
+    }
+
+    /// matches the char against the bracket expression following a `[`,
+    /// returning whether it matched and the length of the expression up to
+    /// and including the closing `]`, or None when there is no closing `]`
+    /// or the class is unknown, in which case the `[` is a literal char.
+    fn bracket(self: &Self, pattern: &[(char, bool)], c: Option<char>) -> Option<(bool, usize)> {
+        let negated = matches!(pattern.first(), Some(('!', _)) | Some(('^', _)));
+        let mut index = if negated { 1 } else { 0 };
+        let mut matched = false;
+        let mut first = true;
+        // note: ignoring case, we also try the other case of the char
+        let candidates: Vec<char> = match (c, self.nocase) {
+            (None, _) => Vec::new(),
+            (Some(c), false) => vec![c],
+            (Some(c), true) => vec![c, self.locale.lowercase(c), self.locale.uppercase(c)],
+        };
+        loop {
+            let (start, _) = *pattern.get(index)?;
+            if start == ']' && !first {
+                break;
+            }
+            first = false;
+            if start == '[' && pattern.get(index + 1) == Some(&(':', false)) {
+                let rest: String = pattern[index + 2..].iter().map(|(c, _)| c).collect();
+                let length = rest.find(":]")?;
+                let name = &rest[..length];
+                self.locale.class(name, 'a')?; // the `[` is literal if unknown
+                matched = matched
+                    || candidates
+                        .iter()
+                        .any(|c| self.locale.class(name, *c) == Some(true));
+                index += 2 + name.chars().count() + 2;
+                continue;
+            }
+            let end = match (pattern.get(index + 1), pattern.get(index + 2)) {
+                (Some(('-', _)), Some((end, _))) if *end != ']' => {
+                    index += 2;
+                    *end
+                }
+                _ => start,
+            };
+            matched = matched || candidates.iter().any(|c| start <= *c && *c <= end);
+            index += 1;
+        }
+        Some((matched != negated && c.is_some(), index + 1))
+    }
+}
+
+/// Expands a single word.
+pub fn expand(word: &Word, params: &dyn Parameters) -> Result<String> {
+    if word.expansions.len() == 0 {
+        return Ok(word.value.clone());
+    }
+    let chars: Vec<char> = word.value.chars().collect();
+    Expander {
+        word: word,
+        chars: chars,
+        params: params,
+    }
+    .range(0, word.value.chars().count())
+}
+
+/// Tells whether the value matches the word used as a pattern, as the
+/// right operand of `==` and `!=` inside `[[ ... ]]`. Like in pathname
+/// expansion, the pattern chars are those that were unquoted inside the
+/// word and those resulting from unquoted expansions, but `*` and `?`
+/// also match `/` and a leading `.`.
+pub fn matches(value: &str, word: &Word, params: &dyn Parameters) -> Result<bool> {
+    let chars: Vec<char> = word.value.chars().collect();
+    let expander = Expander {
+        word: word,
+        chars: chars,
+        params: params,
+    };
+    let mut pattern = Vec::<(char, bool)>::new();
+    let mut index = 0;
+    while index < expander.chars.len() {
+        if !word.expansions.contains(&index) {
+            pattern.push((expander.chars[index], word.globs.contains(&index)));
+            index += 1;
+            continue;
+        }
+        let (expanded, next) = expander.expansion(index)?;
+        let unquoted = word.splits.contains(&index);
+        pattern.extend(expanded.chars().map(|c| (c, unquoted && "*?[".contains(c))));
+        index = next;
+    }
+    let glob = Glob {
+        locale: locale::current(),
+        nocase: false,
+    };
+    let name: Vec<char> = value.chars().collect();
+    Ok(glob.matches(&pattern, &name))
+}
+
+/// Expands the parameters inside a word.
+struct Expander<'a> {
+    word: &'a Word,
+    chars: Vec<char>,
+    params: &'a dyn Parameters,
+}
+
+impl<'a> Expander<'a> {
+    /// expands the whole word splitting the results of the unquoted
+    /// expansions into fields at the chars inside `$IFS`. Sequences of
+    /// IFS whitespace (i.e., spaces, tabs and newlines) delimit fields,
+    /// while each other IFS char, along with the adjacent whitespace,
+    /// delimits a field, which may be empty (e.g., `a::b` with `IFS=:`).
+    /// We also record which chars of each field are pattern chars.
+    fn fields(self: &Self) -> Result<Vec<Field>> {
+        let ifs = self.params.lookup("IFS").unwrap_or(String::from(IFS));
+        let mut fields = Vec::<Field>::new();
+        let mut current = Field::default();
+        // note: started tells whether the current field exists even
+        // if it's empty, because we have seen a literal char, and
+        // blanked tells whether IFS whitespace ended the last field
+        let mut started = false;
+        let mut blanked = false;
+        let mut index = 0;
+        while index < self.chars.len() {
+            if !self.word.expansions.contains(&index) {
+                current.push(self.chars[index], self.word.globs.contains(&index));
+                (started, blanked) = (true, false);
+                index += 1;
+                continue;
+            }
+            let (value, next) = self.expansion(index)?;
+            let split = self.word.splits.contains(&index);
+            index = next;
+            if !split {
+                if value.len() > 0 {
+                    (started, blanked) = (true, false);
+                }
+                value.chars().for_each(|c| current.push(c, false));
+                continue;
+            }
+            for c in value.chars() {
+                if !ifs.contains(c) {
+                    current.push(c, "*?[".contains(c));
+                    (started, blanked) = (true, false);
+                } else if IFS.contains(c) {
+                    if started {
+                        fields.push(std::mem::take(&mut current));
+                        (started, blanked) = (false, true);
+                    }
+                } else {
+                    if started || !blanked {
+                        fields.push(std::mem::take(&mut current));
+                    }
+                    (started, blanked) = (false, false);
+                }
+            }
+        }
+        if started || (fields.len() == 0 && self.word.quoted) {
+            fields.push(current);
+        }
+        Ok(fields)
+    }
+
+    /// expands the chars between start and end.
+    fn range(self: &Self, start: usize, end: usize) -> Result<String> {
+        let mut output = String::new();
+        let mut index = start;
+        while index < end {
+            if !self.word.expansions.contains(&index) {
+                output.push(self.chars[index]);
+                index += 1;
+                continue;
+            }
+            let (value, next) = self.expansion(index)?;
+            output.push_str(&value);
+            index = next;
+        }
+        Ok(output)
+    }
+
+    /// expands the expansion starting at the given index, which is either
+    /// a `$` sign or a `~`, returning the value along with the index of the
+    /// first char following the expansion.
+    fn expansion(self: &Self, index: usize) -> Result<(String, usize)> {
+        match self.chars[index] {
+            '~' => Ok(self.tilde(index + 1)),
+            _ => self.substitute(index + 1),
+        }
+    }
+
+    /// expands the tilde prefix whose login name, if any, starts at the
+    /// given index and ends before the first `/`. Without a login name,
+    /// the prefix expands to `$HOME`, otherwise to the home directory of
+    /// the user. If we cannot determine the directory, the prefix remains
+    /// unchanged (e.g., `~nobody-here/x`).
+    fn tilde(self: &Self, start: usize) -> (String, usize) {
+        let length = self.chars[start..]
+            .iter()
+            .take_while(|c| **c != '/')
+            .count();
+        let name: String = self.chars[start..start + length].iter().collect();
+        let home = match name.len() {
+            0 => self.params.lookup("HOME"),
+            _ => home_directory(&name),
+        };
+        match home {
+            None => (format!("~{}", name), start + length),
+            Some(home) => (home, start + length),
+        }
+    }
+
+    /// substitutes the parameter following the `$` sign, which starts at
+    /// the given index, and returns its value along with the index of the
+    /// first char following the expansion. Within braces, the name of a
+    /// positional parameter may have multiple digits (e.g., `${10}`).
+    fn substitute(self: &Self, start: usize) -> Result<(String, usize)> {
+        match self.chars.get(start) {
+            Some(c) if SPECIAL.contains(*c) => {
+                let value = self.value(&c.to_string())?;
+                return Ok((value, start + 1));
+            }
+            Some('{') => (),
+            Some('(') => {
+                let close = match lexer::substitution_end(&self.chars[start + 1..]) {
+                    None => return Err(self.bad_substitution()),
+                    Some(offset) => start + 1 + offset,
+                };
+                let inner = &self.chars[start + 1..close];
+                if let Some(expression) = arithmetic_expression(inner) {
+                    let value = self.arithmetic(&expression)?;
+                    return Ok((value.to_string(), close + 1));
+                }
+                let command: String = inner.iter().collect();
+                let output = self.params.substitute(&command)?;
+                if output.contains('\0') {
+                    return Err(Error::new(&format!(
+                        "$({}): the output contains NUL bytes",
+                        command
+                    )));
+                }
+                let output = match self.params.option("keepnewlines") {
+                    true => output,
+                    false => String::from(output.trim_end_matches('\n')),
+                };
+                return Ok((output, close + 1));
+            }
+            _ => {
+                let end = name_end(&self.chars, start);
+                if end <= start {
+                    return Ok((String::from("$"), start)); // just a `$`
+                }
+                let name: String = self.chars[start..end].iter().collect();
+                return Ok((self.value(&name)?, end));
+            }
+        }
+        let close = self.closing_brace(start + 1)?;
+        let value = self.braced(start + 1, close)?;
+        Ok((value, close + 1))
+    }
+
+    /// returns the index of the `}` closing the `${` expansion whose
+    /// content starts at the given index (see closing_brace).
+    fn closing_brace(self: &Self, start: usize) -> Result<usize> {
+        match closing_brace(self.word, &self.chars, start) {
+            None => Err(self.bad_substitution()),
+            Some(index) => Ok(index),
+        }
+    }
+
+    /// expands the content of `${...}` between start and end, which is
+    /// either `#NAME`, `NAME` or `NAME` followed by an operator and a word.
+    fn braced(self: &Self, start: usize, end: usize) -> Result<String> {
+        if self.chars.get(start) == Some(&'#') && start + 1 < end {
+            let name = self.name(start + 1, end)?;
+            if name.len() != end - start - 1 {
+                return Err(self.bad_substitution());
+            }
+            let value = self.value(&name)?;
+            return Ok(value.chars().count().to_string());
+        }
+        let name = self.name(start, end)?;
+        let index = start + name.chars().count();
+        if index >= end {
+            return self.value(&name);
+        }
+        let colon = self.chars[index] == ':';
+        let index = if colon { index + 1 } else { index };
+        let operator = match self.chars.get(index) {
+            Some(c) if index < end && "-=?+".contains(*c) => *c,
+            _ => return Err(self.bad_substitution()),
+        };
+        let operand = || self.range(index + 1, end);
+        let value = self.lookup(&name);
+        // note: with a colon, we treat the empty value as unset
+        let set = match &value {
+            None => false,
+            Some(value) => !colon || value.len() > 0,
+        };
+        match (operator, value) {
+            ('-', Some(value)) | ('=', Some(value)) | ('?', Some(value)) if set => Ok(value),
+            ('-', _) => operand(),
+            ('=', _) => {
+                let value = operand()?;
+                self.params.assign(&name, &value)?;
+                Ok(value)
+            }
+            ('?', _) => {
+                let message = match operand()? {
+                    message if message.len() > 0 => message,
+                    _ => String::from("parameter null or not set"),
+                };
+                Err(Error::new(&format!("{}: {}", name, message)))
+            }
+            ('+', _) if set => operand(),
+            _ => Ok(String::new()),
+        }
+    }
+
+    /// returns the name of the parameter starting at the given index,
+    /// failing if there's no name before the end.
+    fn name(self: &Self, start: usize, end: usize) -> Result<String> {
+        let length = match self.chars[start] {
+            c if c.is_ascii_digit() => {
+                let digits = self.chars[start..end].iter();
+                digits.take_while(|c| c.is_ascii_digit()).count()
+            }
+            c if SPECIAL.contains(c) => 1,
+            _ => name_end(&self.chars[..end], start) - start,
+        };
+        if length == 0 {
+            return Err(self.bad_substitution());
+        }
+        Ok(self.chars[start..start + length].iter().collect())
+    }
+
+    /// returns the value of a parameter, which is empty when unset.
+    fn value(self: &Self, name: &str) -> Result<String> {
+        match self.lookup(name) {
+            Some(value) => Ok(value),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// returns the value of a parameter, if set.
+    fn lookup(self: &Self, name: &str) -> Option<String> {
+        let positional = self.params.positional();
+        match name {
+            "#" => Some(positional.len().to_string()),
+            "@" | "*" => Some(positional.join(" ")),
+            "0" => self.params.lookup(name),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => match name.parse::<usize>() {
+                Ok(index) if index >= 1 => positional.get(index - 1).cloned(),
+                _ => None,
+            },
+            _ => self.params.lookup(name),
+        }
+    }
+
+    /// expands the parameters inside the given arithmetic expression,
+    /// then evaluates it.
+    fn arithmetic(self: &Self, expression: &str) -> Result<i64> {
+        let word = Word {
+            value: String::from(expression),
+            quoted: true,
+            expansions: expression
+                .chars()
+                .enumerate()
+                .filter(|(_, c)| *c == '$')
+                .map(|(index, _)| index)
+                .collect(),
+            splits: Vec::new(),
+            globs: Vec::new(),
+        };
+        let expression = expand(&word, self.params)?;
+        let mut evaluator = Arithmetic {
+            tokens: tokenize(&expression)?,
+            index: 0,
+            expander: self,
+        };
+        let value = evaluator.binary(0)?;
+        match evaluator.tokens.get(evaluator.index) {
+            None => Ok(value),
+            Some(token) => Err(syntax_error(Some(token))),
+        }
+    }
+
+    /// creates the error for a malformed `${...}` expansion.
+    fn bad_substitution(self: &Self) -> Error {
+        Error::new(&format!("{}: bad substitution", self.word.value))
+    }
+}
+
+/// Returns the index of the `}` closing the `${` expansion whose content
+/// starts at the given index, skipping nested expansions, if any.
+fn closing_brace(word: &Word, chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = start;
+    while index < chars.len() {
+        match chars[index] {
+            '$' if word.expansions.contains(&index) && chars.get(index + 1) == Some(&'(') => {
+                // note: the command may contain a `}` (e.g., `${X:-$(echo })}`)
+                index += 2 + lexer::substitution_end(&chars[index + 2..])?;
+            }
+            '$' if word.expansions.contains(&index) && chars.get(index + 1) == Some(&'{') => {
+                depth += 1;
+                index += 1;
+            }
+            '}' if depth <= 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => (),
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Returns the index of the first char following the expansion starting
+/// at the given index of the word, whose chars are given.
+pub fn span_end(word: &Word, chars: &[char], index: usize) -> usize {
+    let start = index + 1;
+    match (chars[index], chars.get(start)) {
+        ('~', _) => start + chars[start..].iter().take_while(|c| **c != '/').count(),
+        (_, Some('(')) => match lexer::substitution_end(&chars[start + 1..]) {
+            None => chars.len(),
+            Some(offset) => start + 2 + offset,
+        },
+        (_, Some('{')) => match closing_brace(word, chars, start + 1) {
+            None => chars.len(),
+            Some(close) => close + 1,
+        },
+        (_, Some(c)) if SPECIAL.contains(*c) => start + 1,
+        _ => name_end(chars, start),
+    }
+}
+
+/// Returns the index of the first char following the name starting
+/// at the given index, which is the index itself if there's no name.
+fn name_end(chars: &[char], start: usize) -> usize {
+    let mut end = start;
+    while end < chars.len() {
+        let c = chars[end];
+        let valid = c == '_' || c.is_ascii_alphabetic() || (end > start && c.is_ascii_digit());
+        if !valid {
+            break;
+        }
+        end += 1;
+    }
+    end
+}
+
+/// Returns the home directory of the given user using the passwd database.
+fn home_directory(name: &str) -> Option<String> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    unsafe {
+        let mut pwd: libc::passwd = std::mem::zeroed();
+        let rv = libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        );
+        if rv != 0 || result.is_null() || pwd.pw_dir.is_null() {
+            return None;
+        }
+        let dir = std::ffi::CStr::from_ptr(pwd.pw_dir);
+        Some(dir.to_string_lossy().into_owned())
+    }
+}
+
+/// Returns the expression inside `((expression))` if the given content of a
+/// `$(...)` is an arithmetic expansion rather than a command substitution.
+fn arithmetic_expression(inner: &[char]) -> Option<String> {
+    if inner.len() < 2 || inner[0] != '(' || inner[inner.len() - 1] != ')' {
+        return None;
+    }
+    match lexer::substitution_end(&inner[1..]) {
+        Some(end) if end == inner.len() - 2 => Some(inner[1..end + 1].iter().collect()),
+        _ => None,
+    }
+}
+
+/// Splits an arithmetic expression into numbers, names and operators.
+fn tokenize(expression: &str) -> Result<Vec<String>> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::<String>::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        if c.is_whitespace() {
+            index += 1;
+        } else if c == '_' || c.is_ascii_alphanumeric() {
+            let length = chars[index..]
+                .iter()
+                .take_while(|c| **c == '_' || c.is_ascii_alphanumeric())
+                .count();
+            tokens.push(chars[index..index + length].iter().collect());
+            index += length;
+        } else if "+-*/%()<>=!~&|^".contains(c) {
+            let pair: String = chars[index..chars.len().min(index + 2)].iter().collect();
+            if ["<=", ">=", "==", "!=", "&&", "||", "<<", ">>"].contains(&pair.as_str()) {
+                tokens.push(pair);
+                index += 2;
+            } else {
+                tokens.push(c.to_string());
+                index += 1;
+            }
+        } else {
+            return Err(syntax_error(Some(&c.to_string())));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Evaluates an arithmetic expression using precedence climbing.
+struct Arithmetic<'a> {
+    tokens: Vec<String>,
+    index: usize,
+    expander: &'a Expander<'a>,
+}
+
+impl<'a> Arithmetic<'a> {
+    /// evaluates the binary operators at the given precedence level
+    /// and above (see OPERATORS).
+    fn binary(self: &mut Self, level: usize) -> Result<i64> {
+        if level >= OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(token) = self.tokens.get(self.index) {
+            if !OPERATORS[level].contains(&token.as_str()) {
+                break;
+            }
+            let operator = token.clone();
+            self.index += 1;
+            let right = self.binary(level + 1)?;
+            left = apply(&operator, left, right)?;
+        }
+        Ok(left)
+    }
+
+    /// evaluates a unary operator, a parenthesized expression,
+    /// a number or a variable.
+    fn unary(self: &mut Self) -> Result<i64> {
+        let token = match self.tokens.get(self.index) {
+            None => return Err(syntax_error(None)),
+            Some(token) => token.clone(),
+        };
+        self.index += 1;
+        match token.as_str() {
+            "+" => self.unary(),
+            "-" => Ok(self.unary()?.wrapping_neg()),
+            "!" => Ok((self.unary()? == 0) as i64),
+            "~" => Ok(!self.unary()?),
+            "(" => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.index).map(|token| token.as_str()) {
+                    Some(")") => {
+                        self.index += 1;
+                        Ok(value)
+                    }
+                    Some(token) => Err(syntax_error(Some(token))),
+                    None => Err(syntax_error(None)),
+                }
+            }
+            _ if token.starts_with(|c: char| c.is_ascii_digit()) => number(&token),
+            _ if token.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) => {
+                let value = self.expander.value(&token)?;
+                match value.trim() {
+                    "" => Ok(0),
+                    value => number(value),
+                }
+            }
+            _ => Err(syntax_error(Some(&token))),
+        }
+    }
+}
+
+/// Applies a binary arithmetic operator.
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64> {
+    let value = match operator {
+        "||" => (left != 0 || right != 0) as i64,
+        "&&" => (left != 0 && right != 0) as i64,
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err(Error::new("division by zero")),
+        "/" => left.wrapping_div(right),
+        _ => left.wrapping_rem(right),
+    };
+    Ok(value)
+}
+
+/// Parses a decimal, octal (e.g., `017`) or hexadecimal (e.g., `0x1f`)
+/// number, which may have a sign when it's the value of a variable.
+pub fn number(token: &str) -> Result<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token.strip_prefix('+').unwrap_or(token)),
+    };
+    let parsed = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<i64>()
+    };
+    match parsed {
+        Err(_) => Err(Error::new(&format!("{}: invalid number", token))),
+        Ok(value) if negative => Ok(value.wrapping_neg()),
+        Ok(value) => Ok(value),
+    }
+}
+
+/// Creates the error for a malformed arithmetic expression given the
+/// unexpected token, or None if the expression ended too early.
+fn syntax_error(token: Option<&str>) -> Error {
+    match token {
+        None => Error::new("arithmetic syntax error: unexpected end of expression"),
+        Some(token) => Error::new(&format!("arithmetic syntax error near '{}'", token)),
+    }
+}
@@ -0,0 +1,145 @@
+//! Tracks the descriptors we open for a command.
+//!
+//! Before spawning a command, we collect into an FdTable the descriptors
+//! that the child should see, indexed by number: the ends of the pipes
+//! connecting the commands of a pipeline, the pipes through which we feed
+//! here-documents and here-strings, and the files and copies opened by
+//! redirections. A missing entry means that the child inherits the
+//! descriptor of the shell. The table owns the descriptors, hence the shell
+//! closes them when preparing or spawning the command fails. Otherwise,
+//! FdTable::configure moves them into the command, which the spawner
+//! consumes, so that the shell closes them right after spawning the child
+//! rather than when the child exits: a pipe whose writing end stays open in
+//! the shell never delivers EOF to its reader, and each command would
+//! otherwise leak descriptors during long sessions. The threads feeding
+//! here-documents exit once the text is written or the readers are gone.
+
+use crate::model::{Error, Result};
+use std::collections::HashMap;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+/// The descriptors of a child process indexed by number.
+pub struct FdTable {
+    fds: HashMap<i32, OwnedFd>,
+}
+
+impl FdTable {
+    /// Creates an empty table, i.e., the child inherits all descriptors.
+    pub fn new() -> FdTable {
+        FdTable {
+            fds: HashMap::new(),
+        }
+    }
+
+    /// Makes the given descriptor the target one, closing the descriptor
+    /// that the table contained for the target number, if any.
+    pub fn insert(self: &mut Self, target: i32, fd: OwnedFd) {
+        self.fds.insert(target, fd);
+    }
+
+    /// Removes the descriptor with the given number from the table.
+    pub fn remove(self: &mut Self, target: i32) -> Option<OwnedFd> {
+        self.fds.remove(&target)
+    }
+
+    /// Tells whether the table contains the given number.
+    pub fn contains(self: &Self, target: i32) -> bool {
+        self.fds.contains_key(&target)
+    }
+
+    /// Duplicates the target descriptor, which is one of ours or, if
+    /// missing, the descriptor of the shell.
+    pub fn duplicate(self: &Self, target: i32) -> Result<OwnedFd> {
+        let fd = match self.fds.get(&target) {
+            Some(fd) => fd.as_raw_fd(),
+            None => target,
+        };
+        match dup_above(fd, 0) {
+            Err(err) => Err(Error::new(&format!("{}: {}", target, err))),
+            Ok(fd) => Ok(fd),
+        }
+    }
+
+    /// Returns a table containing copies of our descriptors, which we use
+    /// to spawn the same command more than once.
+    pub fn try_clone(self: &Self) -> Result<FdTable> {
+        let mut copies = FdTable::new();
+        for (target, fd) in self.fds.iter() {
+            match fd.try_clone() {
+                Err(err) => return Err(Error::new(&err.to_string())),
+                Ok(fd) => copies.insert(*target, fd),
+            };
+        }
+        Ok(copies)
+    }
+
+    /// Configures the command to use the descriptors, moving them into
+    /// the command, so that they're closed when the command is dropped.
+    pub fn configure(mut self: Self, cmd: &mut Command) -> Result<()> {
+        if let Some(fd) = self.remove(0) {
+            cmd.stdin(Stdio::from(fd));
+        }
+        if let Some(fd) = self.remove(1) {
+            cmd.stdout(Stdio::from(fd));
+        }
+        if let Some(fd) = self.remove(2) {
+            cmd.stderr(Stdio::from(fd));
+        }
+        if self.fds.len() == 0 {
+            return Ok(());
+        }
+        let pairs = self.raise()?;
+        unsafe {
+            cmd.pre_exec(move || {
+                for (target, fd) in pairs.iter() {
+                    if libc::dup2(fd.as_raw_fd(), *target) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the descriptors, moved above the largest target number, so
+    /// that calling dup2 to put one of them in place cannot overwrite
+    /// another one we still need to duplicate (e.g., with `4>a 3>b`, the
+    /// descriptor for `a` may be 3), along with their targets.
+    pub fn raise(self: Self) -> Result<Vec<(i32, OwnedFd)>> {
+        let minimum = self.minimum();
+        let mut pairs = Vec::<(i32, OwnedFd)>::new();
+        for (target, fd) in self {
+            match dup_above(fd.as_raw_fd(), minimum) {
+                Err(err) => return Err(Error::new(&err.to_string())),
+                Ok(fd) => pairs.push((target, fd)),
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Returns the lowest number above all the target numbers.
+    pub fn minimum(self: &Self) -> i32 {
+        self.fds.keys().max().map_or(0, |target| target + 1)
+    }
+}
+
+impl IntoIterator for FdTable {
+    type Item = (i32, OwnedFd);
+    type IntoIter = std::collections::hash_map::IntoIter<i32, OwnedFd>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fds.into_iter()
+    }
+}
+
+/// Duplicates the descriptor using the lowest available number that
+/// is greater than or equal to the given minimum number.
+pub fn dup_above(fd: i32, minimum: i32) -> std::io::Result<OwnedFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, minimum) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+    }
+}
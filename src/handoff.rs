@@ -0,0 +1,154 @@
+//! Hands the shell state over to a new instance of the shell.
+//!
+//! The `reload` builtin saves the state into a handoff file and then
+//! re-executes the shell binary passing it `--resume FILE`, so that the
+//! new instance can restore the state and then remove the file. The file
+//! contains a `key value` pair per line, where we escape the newlines and
+//! backslashes inside the positional parameters. The variables that are
+//! not exported travel through the environment, like for subshells, and
+//! the flags that are not part of the state (e.g., `--init`) travel on
+//! the command line. The shell keeps no command history, hence there is
+//! no history to hand over.
+//!
+//! Because the file contains the state of the shell, we create it inside
+//! a new directory that only the user can access (see mkdtemp(3)), rather
+//! than using a predictable name inside the shared temporary directory.
+
+use crate::model::{Error, Result};
+use crate::statefile;
+use std::collections::VecDeque;
+use std::ffi::{CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// State handed over to the new instance of the shell.
+#[derive(Debug)]
+pub struct State {
+    /// The current working directory.
+    pub cwd: String,
+
+    /// Whether the `-x` flag is enabled.
+    pub verbose: bool,
+
+    /// Whether the monitor mode is enabled.
+    pub monitor: bool,
+
+    /// Whether the noclobber option is enabled.
+    pub noclobber: bool,
+
+    /// The pids of the background processes.
+    pub pids: VecDeque<u32>,
+
+    /// The `set -o` options that are enabled, besides the above ones.
+    pub options: Vec<String>,
+
+    /// The positional parameters (i.e., `$1`, `$2`, ...).
+    pub positional: Vec<String>,
+}
+
+/// Saves the state into a new handoff file and returns its path.
+pub fn save(state: &State) -> Result<PathBuf> {
+    let mut content = String::new();
+    content.push_str(&format!("cwd {}\n", state.cwd));
+    content.push_str(&format!("verbose {}\n", state.verbose));
+    content.push_str(&format!("monitor {}\n", state.monitor));
+    content.push_str(&format!("noclobber {}\n", state.noclobber));
+    for pid in state.pids.iter() {
+        content.push_str(&format!("pid {}\n", pid));
+    }
+    for option in state.options.iter() {
+        content.push_str(&format!("option {}\n", option));
+    }
+    for value in state.positional.iter() {
+        content.push_str(&format!("positional {}\n", escape(value)));
+    }
+    let path = private_dir()?.join("state");
+    if let Err(err) = statefile::write(&path, &content) {
+        remove(&path);
+        return Err(err);
+    }
+    Ok(path)
+}
+
+/// Removes the handoff file and its directory.
+pub fn remove(path: &Path) {
+    let _ = std::fs::remove_file(path); // it's fine if we cannot remove it
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::remove_dir(dir); // fails unless empty
+    }
+}
+
+/// Creates a new directory that only the user can access inside the
+/// temporary directory and returns its path.
+fn private_dir() -> Result<PathBuf> {
+    let template = std::env::temp_dir().join("xv6sh-handoff-XXXXXX");
+    let mut bytes = match CString::new(template.as_os_str().as_bytes()) {
+        Err(err) => return Err(Error::new(&err.to_string())),
+        Ok(template) => template.into_bytes_with_nul(),
+    };
+    // note: mkdtemp replaces the XXXXXX with the name it creates
+    if unsafe { libc::mkdtemp(bytes.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+        let err = std::io::Error::last_os_error();
+        return Err(Error::new(&format!("{}: {}", template.display(), err)));
+    }
+    bytes.pop(); // the nul terminator
+    Ok(PathBuf::from(OsStr::from_bytes(&bytes)))
+}
+
+/// Loads the state from the given handoff file and removes the file.
+pub fn load(filename: &str) -> Result<State> {
+    let content = statefile::read(Path::new(filename))?;
+    remove(Path::new(filename));
+    let mut state = State {
+        cwd: String::new(),
+        verbose: false,
+        monitor: false,
+        noclobber: false,
+        pids: VecDeque::<_>::new(),
+        options: Vec::new(),
+        positional: Vec::new(),
+    };
+    for line in content.lines() {
+        let (key, value) = match line.split_once(' ') {
+            None => return Err(Error::new(&format!("{}: invalid line", filename))),
+            Some(pair) => pair,
+        };
+        match key {
+            "cwd" => state.cwd = String::from(value),
+            "verbose" => state.verbose = value == "true",
+            "monitor" => state.monitor = value == "true",
+            "noclobber" => state.noclobber = value == "true",
+            "pid" => match value.parse::<u32>() {
+                Err(_) => return Err(Error::new(&format!("{}: invalid pid", filename))),
+                Ok(pid) => state.pids.push_back(pid),
+            },
+            "option" => state.options.push(String::from(value)),
+            "positional" => state.positional.push(unescape(value)),
+            _ => (), // ignore unknown keys written by other versions
+        }
+    }
+    Ok(state)
+}
+
+/// Escapes the newlines and the backslashes inside the value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Undoes escape.
+fn unescape(value: &str) -> String {
+    let mut output = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some(c) => output.push(c),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
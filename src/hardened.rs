@@ -0,0 +1,59 @@
+//! Implements the hardened mode and the sensitive variables.
+//!
+//! With `--hardened`, the shell avoids leaving traces on disk, for use on
+//! shared or forensic-sensitive systems: it does not save crash reports,
+//! does not record when it last checked for updates (nor checks for them
+//! in the background) and refuses to `reload`, which would save the state
+//! into a handoff file. The shell keeps no command history, so there is no
+//! history file to disable. We export `XV6SH_HARDENED=1`, so that the
+//! subshells run in hardened mode too.
+//!
+//! Independently of the hardened mode, `declare -S NAME=VALUE` marks a
+//! variable as sensitive and, when the shell exits, we overwrite the
+//! values of the sensitive variables with zeros before unsetting them,
+//! so that they do not linger in the memory of the process.
+
+use std::ffi::CString;
+
+/// The variable telling whether the hardened mode is enabled.
+const VARIABLE: &str = "XV6SH_HARDENED";
+
+/// Enables the hardened mode for the shell and its subshells.
+pub fn enable() {
+    std::env::set_var(VARIABLE, "1");
+}
+
+/// Tells whether the hardened mode is enabled.
+pub fn enabled() -> bool {
+    matches!(std::env::var(VARIABLE), Ok(value) if value == "1")
+}
+
+/// Overwrites the given string with zeros.
+pub fn clear(value: &mut String) {
+    unsafe {
+        for byte in value.as_bytes_mut() {
+            std::ptr::write_volatile(byte, 0);
+        }
+    }
+    value.clear();
+}
+
+/// Overwrites the value of the given variable with zeros, then unsets it.
+pub fn zeroize(name: &str) {
+    let cname = match CString::new(name) {
+        Err(_) => return,
+        Ok(cname) => cname,
+    };
+    unsafe {
+        // note: getenv returns a pointer to the writable string inside
+        // the environment, rather than a copy
+        let value = libc::getenv(cname.as_ptr());
+        if !value.is_null() {
+            let length = libc::strlen(value);
+            for index in 0..length {
+                std::ptr::write_volatile(value.add(index), 0);
+            }
+        }
+    }
+    std::env::remove_var(name);
+}
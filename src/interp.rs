@@ -1,22 +1,188 @@
 //! Interprets the executable syntax tree generated
 //! by the translator module (translator.rs).
 
-use crate::model::{Error, ProcessSpawner, Result};
-use crate::parser::{InputRedir, OutputRedir};
-use crate::process::{Group, PeriodicReaper, Spawner};
+use crate::fdtable::FdTable;
+use crate::jobctl::JobControl;
+use crate::model::{
+    Assignment, CommandFactory, Error, InputRedir, OutputRedir, Process, ProcessSpawner, Redirect,
+    Result, Word,
+};
+use crate::process::{self, Group, PeriodicReaper, Spawner};
 use crate::translator::{
-    CompoundSerialCommand, FilterCommand, ListOfCommands, PipelinedCommands, SingleCommand,
-    SinkCommand, SourceCommand,
+    CompoundSerialCommand, FilterCommand, GroupCommand, IfCommand, ListOfCommands,
+    PipelinedCommands, SingleCommand, SinkCommand, SourceCommand, TestCommand,
+};
+use crate::{
+    buildinfo, conditional, coverage, debugjob, deterministic, envfile, expansion, fdtable,
+    handoff, hardened, lexer, mapped, parser, printf, privatetmp, pty, record, sched, script,
+    spawnctx, startup, supervise, timer, timings, transcript, translator, watchdog, xtrace,
 };
 use os_pipe::{pipe, PipeReader, PipeWriter};
-use std::collections::VecDeque;
-use std::convert::Into;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{File, OpenOptions};
-use std::process::{Command, Stdio};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// The environment variable through which a shell passes its variables
+/// that are not exported to its subshells (see Interpreter::variables).
+const VARIABLES: &str = "XV6SH_VARIABLES";
+
+/// The variables to add to the environment of a child process, which
+/// come from the assignments preceding the command (e.g., `X=1 cmd`).
+type Environment = Vec<(String, String)>;
+
+/// The builtin commands, which `enable -n` may disable.
+const BUILTINS: [&str; 32] = [
+    ".",
+    "affinity",
+    "after",
+    "argv0",
+    "assert",
+    "buildinfo",
+    "cancel",
+    "cd",
+    "daemonize",
+    "debugjob",
+    "declare",
+    "enable",
+    "envfile",
+    "every",
+    "exec",
+    "export",
+    "has",
+    "hash",
+    "ioprio",
+    "jobs",
+    "printf",
+    "pty",
+    "pwd",
+    "reload",
+    "require",
+    "set",
+    "shift",
+    "source",
+    "supervise",
+    "transcript",
+    "trap",
+    "unset",
+];
 
 /// Interprets the given ListOfCommands
 pub struct Interpreter {
+    pub jobs: JobControl,
+
+    /// Whether `>` refuses to overwrite existing files (i.e., `set -C`).
+    pub noclobber: bool,
+
+    /// The exit status of the last pipeline we executed (i.e., `$?`).
+    pub status: i32,
+
+    /// The process ID of the shell (i.e., `$$`), which subshells
+    /// inherit from their parent (see `--pid`).
+    pub pid: u32,
+
+    /// The name of the shell or of the script (i.e., `$0`).
+    pub arg0: String,
+
+    /// The positional parameters (i.e., `$1`, `$2`, ...).
+    pub positional: Vec<String>,
+
+    /// The builtins disabled with `enable -n`.
+    disabled: HashSet<String>,
+
+    /// The variables marked as sensitive with `declare -S`.
+    sensitive: Vec<String>,
+
+    /// The shell variables that are not exported (e.g., `x=5`), which
+    /// are visible to expansions but not to the commands we execute,
+    /// while the exported variables live in the shell's environment.
+    /// Subshells inherit the variables (see import_variables).
+    variables: RefCell<HashMap<String, String>>,
+
+    /// Records the pipelines we execute (see `--record`).
+    pub recorder: Option<record::Recorder>,
+
+    /// Counts the pipelines of the script we execute (see coverage.rs).
+    pub coverage: Option<coverage::Coverage>,
+
+    /// The commands to execute before each pipeline (i.e., `trap DEBUG`).
+    debug_trap: Option<String>,
+
+    /// Whether we're executing the DEBUG trap.
+    trapping: bool,
+
+    /// The commands to execute when the shell exits (i.e., `trap EXIT`).
+    exit_trap: Option<String>,
+
+    /// Whether to ask before executing each pipeline (i.e., `set -o step`).
+    step: bool,
+
+    /// Whether pathname expansion ignores case (i.e., `set -o nocaseglob`).
+    nocaseglob: bool,
+
+    /// Whether command substitutions keep the trailing newlines of the
+    /// output (i.e., `set -o keepnewlines`).
+    keepnewlines: bool,
+
+    /// Whether each subshell gets its own `$TMPDIR` (i.e., `set -o
+    /// privatetmp`).
+    privatetmp: bool,
+
+    /// Whether to write a summary of the resources we used when exiting
+    /// (i.e., `--stats-on-exit` or `set -o exitstats`).
+    pub exitstats: bool,
+
+    /// Whether the shell exits when a pipeline fails (i.e., `set -e`).
+    errexit: bool,
+
+    /// Whether only the last pipeline of a condition is exempt from
+    /// errexit (i.e., `set -o strict-errexit`).
+    strict_errexit: bool,
+
+    /// Whether we're executing a pipeline whose exit status is tested
+    /// (e.g., the condition of an `if`), which is exempt from errexit.
+    condition: bool,
+
+    /// The files already sourced by `require`.
+    required: HashSet<PathBuf>,
+
+    /// Whether we're loading the configuration file.
+    configuring: bool,
+
+    /// The options explicitly changed with `set` outside of the
+    /// configuration file, which win over the configuration file.
+    overrides: Vec<(String, bool)>,
+
     spawner: Box<dyn ProcessSpawner>,
+    supervisors: Vec<supervise::Supervisor>,
+    timers: timer::Scheduler,
+    watchdogs: watchdog::Watchdogs,
+
+    /// When the shell started, since the Unix epoch (for `$SECONDS`).
+    started: Duration,
+
+    /// The state of the generator of `$RANDOM`.
+    random: Cell<u64>,
+
+    /// The I/O priority and CPU affinity of the commands we're about to
+    /// spawn, set by the `ioprio` and `affinity` prefixes (see sched.rs).
+    scheduling: RefCell<sched::Scheduling>,
+
+    /// How to override the zeroth argument of the command we're about
+    /// to spawn, set by the `argv0` prefix.
+    zeroth: RefCell<Option<Zeroth>>,
+
+    /// The data we reuse across the commands we spawn (see spawnctx.rs).
+    spawnctx: RefCell<spawnctx::SpawnCtx>,
+
+    /// Where the `-x` trace goes (see xtrace.rs).
+    pub xtrace: xtrace::Trace,
+
     verbose: bool,
 }
 
@@ -29,229 +195,2563 @@ impl Interpreter {
     /// Creates a new interpreter with the given spawner.
     pub fn new_with_spawner(verbose: bool, spawner: Box<dyn ProcessSpawner>) -> Interpreter {
         Interpreter {
+            jobs: JobControl::new(),
+            noclobber: false,
+            status: 0,
+            pid: std::process::id(),
+            arg0: String::from("xv6sh"),
+            positional: Vec::new(),
+            disabled: HashSet::new(),
+            sensitive: Vec::new(),
+            variables: RefCell::new(HashMap::new()),
+            recorder: None,
+            coverage: None,
+            debug_trap: None,
+            trapping: false,
+            exit_trap: None,
+            step: false,
+            nocaseglob: false,
+            keepnewlines: false,
+            privatetmp: false,
+            exitstats: false,
+            errexit: false,
+            strict_errexit: false,
+            condition: false,
+            required: HashSet::new(),
+            configuring: false,
+            overrides: Vec::new(),
             spawner: spawner,
+            supervisors: Vec::new(),
+            timers: timer::Scheduler::new(),
+            watchdogs: watchdog::Watchdogs::new(),
+            started: deterministic::now(),
+            random: Cell::new(deterministic::seed()),
+            scheduling: RefCell::new(sched::Scheduling::default()),
+            zeroth: RefCell::new(None),
+            spawnctx: RefCell::new(spawnctx::SpawnCtx::new()),
+            xtrace: xtrace::Trace::new(),
             verbose: verbose,
         }
     }
 
+    /// Translates the given syntax tree, writing the warnings to the
+    /// trace when tracing (see translator::translate).
+    pub fn translate(self: &Self, cc: parser::CompleteCommand) -> Result<ListOfCommands> {
+        let trace = match self.verbose {
+            false => None,
+            true => Some(&self.xtrace),
+        };
+        translator::translate(cc, trace, self.noclobber)
+    }
+
     /// Runs the interpreter
-    pub fn run(self: &Self, mut loc: ListOfCommands, reaper: &mut PeriodicReaper) -> Result<()> {
-        loop {
-            match loc.pipelines.pop_front() {
-                None => return Ok(()),
-                Some(p) => {
-                    self.compound_serial_command(p, reaper)?;
-                    continue;
-                }
+    pub fn run(
+        self: &mut Self,
+        mut loc: ListOfCommands,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<()> {
+        while self.step(&mut loc, reaper)? {}
+        Ok(())
+    }
+
+    /// Executes the next CompoundSerialCommand of the plan, if any, and
+    /// returns whether there was a command to execute. A command failing
+    /// with an error sets the exit status to 1.
+    pub fn step(
+        self: &mut Self,
+        loc: &mut ListOfCommands,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<bool> {
+        let p = match loc.pipelines.pop_front() {
+            None => return Ok(false),
+            Some(p) => p,
+        };
+        let entry = match &self.recorder {
+            None => None,
+            Some(recorder) => recorder.describe(&p)?,
+        };
+        // note: with strict-errexit, only the last pipeline of a
+        // condition determines its exit status and is exempt
+        let condition = self.condition;
+        self.condition = condition && (!self.strict_errexit || loc.pipelines.len() == 0);
+        let exempt = self.condition || Self::exempt(&p);
+        let result = self.compound_serial_command(p, reaper);
+        self.condition = condition;
+        self.status = match &result {
+            Err(_) => 1,
+            Ok(status) => *status,
+        };
+        if let (Some(recorder), Some(entry)) = (&mut self.recorder, entry) {
+            recorder.append(entry, self.status)?;
+        }
+        if self.errexit && !exempt && !self.trapping && self.status != 0 {
+            if let Err(err) = &result {
+                eprintln!("xv6sh: error: {}", err);
+            }
+            self.exit();
+        }
+        match result {
+            Err(err) => Err(err),
+            Ok(_) => Ok(true),
+        }
+    }
+
+    /// Tells whether the failure of the given command cannot trigger
+    /// errexit: like in POSIX, negated and asynchronous pipelines are
+    /// exempt, and so are compound commands, whose failure comes from
+    /// pipelines that were either exempt or already checked.
+    fn exempt(csc: &CompoundSerialCommand) -> bool {
+        match csc {
+            CompoundSerialCommand::SingleCommand(sc) => sc.negated || !sc.sync,
+            CompoundSerialCommand::PipelinedCommands(pc) => pc.negated || !pc.sync,
+            CompoundSerialCommand::GroupCommand(_) => true,
+            CompoundSerialCommand::IfCommand(_) => true,
+            CompoundSerialCommand::TestCommand(tc) => tc.negated,
+        }
+    }
+
+    /// Runs the EXIT trap, if any, overwrites the sensitive variables and
+    /// exits with the exit status of the last pipeline.
+    pub fn exit(self: &mut Self) -> ! {
+        self.exit_trap();
+        if self.exitstats {
+            Self::write_stats();
+        }
+        if let Some(coverage) = &self.coverage {
+            if let Err(err) = coverage.save() {
+                eprintln!("xv6sh: error: coverage: {}", err);
             }
         }
+        self.zeroize();
+        std::process::exit(self.status);
     }
 
-    /// Executes a CompoundSerialCommand
+    /// Writes how many children we spawned, how many of them ran at the
+    /// same time at most, the CPU time that they and the shell used,
+    /// which only counts the children we waited for, and how long the
+    /// shell ran.
+    fn write_stats() {
+        let (spawned, peak) = process::accounting();
+        let usage = |who: libc::c_int| {
+            let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+            unsafe { libc::getrusage(who, &mut usage) };
+            let seconds = |tv: libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1e6;
+            (seconds(usage.ru_utime), seconds(usage.ru_stime))
+        };
+        let (children_user, children_sys) = usage(libc::RUSAGE_CHILDREN);
+        let (shell_user, shell_sys) = usage(libc::RUSAGE_SELF);
+        eprintln!(
+            "xv6sh: stats: {} {} spawned, at most {} at once, cpu {:.3}s user {:.3}s sys (children), {:.3}s user {:.3}s sys (shell), wall {:.3}s",
+            spawned,
+            if spawned == 1 { "child" } else { "children" },
+            peak,
+            children_user,
+            children_sys,
+            shell_user,
+            shell_sys,
+            startup::elapsed().as_secs_f64()
+        );
+    }
+
+    /// Executes a CompoundSerialCommand and returns its exit status, which
+    /// is inverted when the pipeline starts with `!`. Like in POSIX, the
+    /// exit status of an asynchronous pipeline is always zero.
     fn compound_serial_command(
-        self: &Self,
+        self: &mut Self,
         csc: CompoundSerialCommand,
         reaper: &mut PeriodicReaper,
-    ) -> Result<()> {
+    ) -> Result<i32> {
+        if !self.before_pipeline(&csc, reaper)? {
+            return Ok(0); // the user chose to skip the pipeline
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.hit(csc.offset());
+        }
+        let (status, sync, negated) = match csc {
+            CompoundSerialCommand::SingleCommand(sc) => {
+                let (sync, negated) = (sc.sync, sc.negated);
+                (self.single_command(sc, reaper)?, sync, negated)
+            }
+            CompoundSerialCommand::PipelinedCommands(pc) => {
+                let (sync, negated) = (pc.sync, pc.negated);
+                (self.pipelined_commands(pc, reaper)?, sync, negated)
+            }
+            CompoundSerialCommand::GroupCommand(gc) => {
+                let negated = gc.negated;
+                (self.group_command(gc, reaper)?, true, negated)
+            }
+            CompoundSerialCommand::IfCommand(ic) => {
+                let negated = ic.negated;
+                (self.if_command(ic, reaper)?, true, negated)
+            }
+            CompoundSerialCommand::TestCommand(tc) => {
+                let negated = tc.negated;
+                (self.test_command(tc)?, true, negated)
+            }
+        };
+        match (sync, negated, status) {
+            (false, _, _) => Ok(0),
+            (true, false, status) => Ok(status),
+            (true, true, 0) => Ok(1),
+            (true, true, _) => Ok(0),
+        }
+    }
+
+    /// Executes the DEBUG trap, if any, before a pipeline and, in step
+    /// mode, asks the user whether to execute it. Returns whether to
+    /// execute the pipeline. We do neither for the commands of the trap
+    /// itself nor for compound commands, whose pipelines we'll see later
+    fn before_pipeline(
+        self: &mut Self,
+        csc: &CompoundSerialCommand,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<bool> {
+        let command = match Self::describe(csc) {
+            None => return Ok(true),
+            Some(_) if self.trapping => return Ok(true),
+            Some(command) => command,
+        };
+        if let Some(action) = self.debug_trap.clone() {
+            // note: the trap must not change the exit status
+            let status = self.status;
+            self.trapping = true;
+            let result = self.eval(&action, reaper);
+            self.trapping = false;
+            self.status = status;
+            if let Err(err) = result {
+                eprintln!("xv6sh: trap: {}", err);
+            }
+        }
+        match self.step {
+            false => Ok(true),
+            true => Self::ask(&command),
+        }
+    }
+
+    /// Returns the command line of a pipeline, or None for empty
+    /// commands and compound commands
+    fn describe(csc: &CompoundSerialCommand) -> Option<String> {
+        let join = |arguments: &VecDeque<Word>| {
+            let values: Vec<&str> = arguments.iter().map(|word| word.value.as_str()).collect();
+            values.join(" ")
+        };
         match csc {
-            CompoundSerialCommand::SingleCommand(sc) => self.single_command(sc, reaper),
-            CompoundSerialCommand::PipelinedCommands(pc) => self.pipelined_commands(pc, reaper),
+            CompoundSerialCommand::SingleCommand(sc) if sc.arguments.len() > 0 => {
+                Some(join(&sc.arguments))
+            }
+            CompoundSerialCommand::PipelinedCommands(pc) => {
+                let mut commands = vec![join(&pc.source.arguments)];
+                commands.extend(pc.filters.iter().map(|filter| join(&filter.arguments)));
+                commands.push(join(&pc.sink.arguments));
+                Some(commands.join(" | "))
+            }
+            CompoundSerialCommand::TestCommand(tc) => Some(format!("[[ {} ]]", tc.condition)),
+            _ => None,
+        }
+    }
+
+    /// Asks the user, through the terminal, whether to execute the
+    /// given command. Quitting fails with an error, which interrupts
+    /// the script we're executing
+    fn ask(command: &str) -> Result<bool> {
+        use std::io::BufRead;
+        let tty = match OpenOptions::new().read(true).write(true).open("/dev/tty") {
+            Err(err) => return Err(Error::new(&format!("step: /dev/tty: {}", err))),
+            Ok(tty) => tty,
+        };
+        let mut writer = &tty;
+        let mut reader = std::io::BufReader::new(&tty);
+        loop {
+            let _ = write!(writer, "xv6sh: step: {} [y/n/q]? ", command);
+            let mut answer = String::new();
+            match reader.read_line(&mut answer) {
+                Err(err) => return Err(Error::new(&format!("step: {}", err))),
+                Ok(0) => return Err(Error::new("step: quit")),
+                Ok(_) => (),
+            }
+            match answer.trim() {
+                "y" | "" => return Ok(true),
+                "n" => return Ok(false),
+                "q" => return Err(Error::new("step: quit")),
+                _ => (),
+            }
+        }
+    }
+
+    /// Executes the given commands in the current shell
+    fn eval(self: &mut Self, source: &str, reaper: &mut PeriodicReaper) -> Result<()> {
+        // note: the offsets of the commands refer to the source
+        let coverage = self.coverage.take();
+        let result = self.eval_uncovered(source, reaper);
+        self.coverage = coverage;
+        result
+    }
+
+    /// Implements eval, without tracking the coverage
+    fn eval_uncovered(self: &mut Self, source: &str, reaper: &mut PeriodicReaper) -> Result<()> {
+        let tokens = lexer::scan(String::from(source), &mut || None)?;
+        let program = parser::parse_program(tokens)?;
+        for cc in program.commands {
+            let loc = self.translate(cc)?;
+            self.run(loc, reaper)?;
+        }
+        Ok(())
+    }
+
+    /// Executes a GroupCommand and returns the exit status of the last
+    /// pipeline. We temporarily apply the redirections to the descriptors
+    /// of the shell itself, so that they also affect the builtins
+    fn group_command(
+        self: &mut Self,
+        gc: GroupCommand,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<i32> {
+        let fds = self.redirect(FdTable::new(), &gc.redirs)?;
+        let saved = Self::replace_shell_fds(fds)?;
+        let result = self.run(gc.body, reaper);
+        Self::restore_shell_fds(saved);
+        result?;
+        Ok(self.status)
+    }
+
+    /// Makes the descriptors of the shell refer to the given descriptors,
+    /// returning copies of the original ones, if they were open.
+    fn replace_shell_fds(fds: FdTable) -> Result<Vec<(i32, Option<OwnedFd>)>> {
+        let mut saved = Vec::<(i32, Option<OwnedFd>)>::new();
+        let minimum = fds.minimum().max(10);
+        for (target, fd) in fds.raise()? {
+            // note: copy above the standard descriptors and the targets,
+            // so that the copies do not clash with what we replace
+            let copy = fdtable::dup_above(target, minimum).ok();
+            if unsafe { libc::dup2(fd.as_raw_fd(), target) } == -1 {
+                let err = std::io::Error::last_os_error();
+                saved.push((target, copy));
+                Self::restore_shell_fds(saved);
+                return Err(Error::new(&err.to_string()));
+            }
+            saved.push((target, copy));
+        }
+        Ok(saved)
+    }
+
+    /// Restores the descriptors saved by replace_shell_fds.
+    fn restore_shell_fds(saved: Vec<(i32, Option<OwnedFd>)>) {
+        // note: flush what builtins wrote to the redirected descriptors
+        let _ = std::io::stdout().flush();
+        for (target, copy) in saved.into_iter().rev() {
+            match copy {
+                Some(copy) => unsafe { libc::dup2(copy.as_raw_fd(), target) },
+                None => unsafe { libc::close(target) },
+            };
+        }
+    }
+
+    /// Executes an IfCommand and returns its exit status, which is the
+    /// exit status of the body we executed or zero if we executed none
+    fn if_command(self: &mut Self, ic: IfCommand, reaper: &mut PeriodicReaper) -> Result<i32> {
+        for (condition, body) in ic.branches {
+            let saved = self.condition;
+            self.condition = true;
+            let result = self.run(condition, reaper);
+            self.condition = saved;
+            result?;
+            if self.status == 0 {
+                self.run(body, reaper)?;
+                return Ok(self.status);
+            }
+        }
+        match ic.otherwise {
+            None => Ok(0),
+            Some(otherwise) => {
+                self.run(otherwise, reaper)?;
+                Ok(self.status)
+            }
+        }
+    }
+
+    /// Evaluates a TestCommand and returns its exit status
+    fn test_command(self: &Self, tc: TestCommand) -> Result<i32> {
+        match conditional::evaluate(&tc.condition, self)? {
+            true => Ok(0),
+            false => Ok(1),
         }
     }
 
-    /// Executes a SingleCommand
+    /// Executes a SingleCommand and returns its exit status
     fn single_command(
-        self: &Self,
-        mut sc: SingleCommand,
+        self: &mut Self,
+        sc: SingleCommand,
         reaper: &mut PeriodicReaper,
-    ) -> Result<()> {
+    ) -> Result<i32> {
         // Implementation note: we only check for builtin commands
         // when we're not in pipeline context - is this correct?
-        if sc.arguments.len() < 1 {
+        let mut measure = timings::start();
+        let mut arguments = self.expand_words(sc.arguments)?;
+        let env = self.expand_assignments(&sc.assignments)?;
+        if arguments.len() < 1 && env.len() > 0 {
+            // note: without a command, the assignments set shell variables
+            for (name, value) in env {
+                self.set_variable(&name, &value);
+            }
+            return Ok(0);
+        }
+        if arguments.len() < 1 {
             // we arrive here when we hit [Enter] at the prompt
             //eprintln!("bonsoir, Elliot!");
-            return Ok(());
+            return Ok(self.status);
         }
-        let argv0 = sc.arguments.pop_front().unwrap(); // cannot fail
-        match argv0.as_str() {
+        let argv0 = arguments.pop_front().unwrap(); // cannot fail
+        let builtin = match self.disabled.contains(&argv0) {
+            true => "", // i.e., look it up in the PATH
+            false => argv0.as_str(),
+        };
+        // note: builtins and plugins see the assignments until we drop this
+        let exported = Exported::new(&env);
+        let redirected = match Self::redirects_shell(builtin) {
+            false => None,
+            true => Some(Redirected::new(self.redirect(FdTable::new(), &sc.redirs)?)?),
+        };
+        match builtin {
             "cd" => {
-                return Self::builtin_cd(sc.arguments);
+                self.builtin_cd(arguments)?;
+                return Ok(0);
+            }
+            "pwd" => {
+                Self::builtin_pwd(arguments)?;
+                return Ok(0);
+            }
+            "source" | "." => {
+                self.builtin_source(arguments, reaper)?;
+                return Ok(self.status);
+            }
+            "require" => {
+                self.builtin_require(arguments, reaper)?;
+                return Ok(self.status);
+            }
+            "assert" => {
+                self.builtin_assert(arguments, reaper)?;
+                return Ok(0);
+            }
+            "set" => {
+                self.builtin_set(arguments)?;
+                return Ok(0);
+            }
+            "reload" => {
+                self.builtin_reload(arguments, reaper)?;
+                return Ok(0);
+            }
+            "daemonize" => {
+                self.builtin_daemonize(arguments, &sc.redirs)?;
+                return Ok(0);
+            }
+            "supervise" => {
+                self.builtin_supervise(arguments, &sc.redirs)?;
+                return Ok(0);
+            }
+            "after" | "every" => {
+                self.builtin_timer(&argv0, arguments, &sc.redirs)?;
+                return Ok(0);
+            }
+            "cancel" => {
+                self.builtin_cancel(arguments)?;
+                return Ok(0);
+            }
+            "jobs" => {
+                self.builtin_jobs(arguments, reaper)?;
+                return Ok(0);
+            }
+            "transcript" => {
+                Self::builtin_transcript(arguments)?;
+                return Ok(0);
+            }
+            "trap" => {
+                self.builtin_trap(arguments)?;
+                return Ok(0);
+            }
+            "shift" => {
+                self.builtin_shift(arguments)?;
+                return Ok(0);
+            }
+            "enable" => {
+                self.builtin_enable(arguments)?;
+                return Ok(0);
+            }
+            "has" => return self.builtin_has(arguments),
+            "debugjob" => return self.builtin_debugjob(arguments, reaper),
+            "export" => {
+                self.builtin_export(arguments)?;
+                return Ok(0);
+            }
+            "declare" => {
+                self.builtin_declare(arguments)?;
+                return Ok(0);
+            }
+            "unset" => {
+                self.builtin_unset(arguments)?;
+                return Ok(0);
+            }
+            "hash" => {
+                self.builtin_hash(arguments)?;
+                return Ok(0);
+            }
+            "buildinfo" => {
+                Self::builtin_buildinfo(arguments)?;
+                return Ok(0);
+            }
+            "printf" => {
+                Self::builtin_printf(arguments)?;
+                return Ok(0);
+            }
+            "exec" => {
+                self.builtin_exec(arguments, &env, &sc.redirs)?;
+                return Ok(0);
             }
             _ => (),
         }
-        let rin = Self::maybe_redirect_input(&sc.input)?;
-        let rout = Self::maybe_redirect_output(&sc.output)?;
+        #[cfg(feature = "plugins")]
+        if builtin.len() > 0 {
+            if let Some(status) = crate::plugin::run(&argv0, &arguments)? {
+                return Ok(status);
+            }
+        }
+        drop(redirected);
+        drop(exported);
+        let fds = self.redirect(FdTable::new(), &sc.redirs)?;
         let mut group = Group::new(reaper);
-        self.exec(&mut group, argv0, sc.arguments, rin, rout)?;
+        let command = std::iter::once(&argv0).chain(arguments.iter());
+        let command = command.cloned().collect::<Vec<_>>().join(" ");
+        measure.spawning();
+        self.exec(&mut group, argv0, arguments, env, fds)?;
+        measure.running();
+        let mut status = 0;
         if sc.sync {
-            group.wait();
+            status = self.wait_foreground(&mut group);
         }
-        Ok(())
+        measure.report(&command, 1);
+        Ok(status)
+    }
+
+    /// Waits for a foreground group, which owns the terminal meanwhile,
+    /// and returns the exit status of the group
+    fn wait_foreground(self: &Self, group: &mut Group) -> i32 {
+        self.jobs.foreground(group.pgid());
+        let status = group.wait();
+        self.jobs.reclaim();
+        status
     }
 
     /// Implements the builtin `cd` command
-    fn builtin_cd(args: VecDeque<String>) -> Result<()> {
-        // TODO(bassosimone): `cd` without arguments should bring
-        // the user to the home directory...
-        if args.len() != 1 {
-            return Err(Error::new("usage: cd <directory>"));
+    ///
+    /// Like POSIX shells, we track the logical working directory in $PWD,
+    /// which contains the symbolic links we traversed. With `-L` (the
+    /// default), we resolve `..` by removing the last component of the
+    /// logical path. With `-P`, we resolve symbolic links, hence `..`
+    /// refers to the parent of the physical directory.
+    ///
+    /// Without a directory, we go to $HOME, while `cd -` goes to $OLDPWD
+    /// and prints the new working directory.
+    fn builtin_cd(self: &Self, args: VecDeque<String>) -> Result<()> {
+        // note: like in POSIX, the last option wins (e.g., `cd -L -P`)
+        let mut physical = false;
+        let mut operands = args.iter().map(|s| s.as_str()).peekable();
+        while let Some(option) = operands.next_if(|arg| arg.starts_with('-') && *arg != "-") {
+            match option {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                "--" => break,
+                _ => return Err(Error::new("usage: cd [-L|-P] [<directory>|-]")),
+            }
         }
-        match std::env::set_current_dir(&args[0]) {
-            Err(err) => Err(Error::new(&err.to_string())),
-            Ok(_) => Ok(()),
+        let dir = operands.next();
+        if operands.next().is_some() {
+            return Err(Error::new("usage: cd [-L|-P] [<directory>|-]"));
+        }
+        let (mut dir, mut print) = match dir {
+            None => match self.variable("HOME") {
+                None => return Err(Error::new("cd: HOME not set")),
+                Some(home) => (home, false),
+            },
+            Some("-") => match self.variable("OLDPWD") {
+                None => return Err(Error::new("cd: OLDPWD not set")),
+                Some(oldpwd) => (oldpwd, true),
+            },
+            Some(dir) => (String::from(dir), false),
+        };
+        let previous = Self::logical_cwd()?;
+        if let Some(found) = self.search_cdpath(&previous, &dir) {
+            (dir, print) = (found, true);
+        }
+        let dir = dir.as_str();
+        let target = match physical {
+            true => String::from(dir),
+            false => Self::clean_path(&previous, dir),
+        };
+        if let Err(err) = std::env::set_current_dir(&target) {
+            return Err(Error::new(&format!("cd: {}: {}", dir, err)));
         }
+        let current = match physical {
+            true => Self::physical_cwd()?,
+            false => target,
+        };
+        if print {
+            println!("{}", current);
+        }
+        self.set_pwd("OLDPWD", &previous);
+        self.set_pwd("PWD", &current);
+        Ok(())
     }
 
-    /// Executes a pipeline of commands with at least a source and a sink
-    fn pipelined_commands(
-        self: &Self,
-        pc: PipelinedCommands,
-        reaper: &mut PeriodicReaper,
-    ) -> Result<()> {
-        let mut rxall = VecDeque::<PipeReader>::new();
-        let mut group = Group::new(reaper);
-        let source = pc.source;
-        let rx = self.source_command(&mut group, source)?;
-        rxall.push_back(rx);
-        for filter in pc.filters {
-            match self.filter_command(&mut group, filter, rxall.pop_back().unwrap()) {
-                Err(err) => {
-                    group.kill_and_wait();
-                    return Err(Error::new(&err.to_string()));
-                }
-                Ok(rx) => rxall.push_back(rx),
-            }
+    /// Sets $PWD to the logical working directory, which is needed when
+    /// the shell starts, since $PWD may be missing or stale, and after
+    /// changing the directory without `cd` (e.g., when resuming).
+    pub fn sync_pwd(self: &Self) {
+        if let Ok(current) = Self::logical_cwd() {
+            self.set_pwd("PWD", &current);
         }
-        match self.sink_command(&mut group, pc.sink, rxall.pop_back().unwrap()) {
-            Err(err) => {
-                group.kill_and_wait();
-                return Err(Error::new(&err.to_string()));
+    }
+
+    /// Sets $PWD or $OLDPWD, which we always export, so that the commands
+    /// we spawn see the correct value, dropping any shell variable with
+    /// the same name (e.g., after `unset PWD; PWD=x`) that would hide it.
+    fn set_pwd(self: &Self, name: &str, value: &str) {
+        if self.variables.borrow_mut().remove(name).is_some() {
+            self.spawnctx.borrow_mut().variables_changed();
+        }
+        std::env::set_var(name, value);
+    }
+
+    /// Returns the directory inside the colon-separated directories of
+    /// $CDPATH (where an empty entry means the current directory) that
+    /// matches the given relative directory, if the latter does not
+    /// exist in the current directory and does not start with `.` or
+    /// `..`. Like other shells, we skip the missing directories.
+    fn search_cdpath(self: &Self, cwd: &str, dir: &str) -> Option<String> {
+        let first = dir.split('/').next().unwrap_or_default();
+        if dir.starts_with('/') || first == "." || first == ".." {
+            return None;
+        }
+        if Path::new(&Self::clean_path(cwd, dir)).is_dir() {
+            return None;
+        }
+        let cdpath = self.variable("CDPATH")?;
+        for entry in cdpath.split(':') {
+            let base = if entry.len() == 0 { "." } else { entry };
+            let candidate = format!("{}/{}", base.trim_end_matches('/'), dir);
+            let resolved = Self::clean_path(cwd, &candidate);
+            if Path::new(&resolved).is_dir() {
+                return Some(resolved);
             }
-            Ok(_) => (),
         }
-        if pc.sync {
-            group.wait();
+        None
+    }
+
+    /// Implements the builtin `pwd` command
+    fn builtin_pwd(args: VecDeque<String>) -> Result<()> {
+        // note: like in POSIX, the last option wins (e.g., `pwd -L -P`)
+        let mut physical = false;
+        for arg in args.iter() {
+            match arg.as_str() {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                _ => return Err(Error::new("usage: pwd [-L|-P]")),
+            }
         }
+        let cwd = match physical {
+            false => Self::logical_cwd()?,
+            true => Self::physical_cwd()?,
+        };
+        println!("{}", cwd);
         Ok(())
     }
 
-    /// Executes the source command of the pipeline
-    fn source_command(self: &Self, group: &mut Group, mut sc: SourceCommand) -> Result<PipeReader> {
-        if sc.arguments.len() < 1 {
-            return Err(Error::new("pipeline with empty source command"));
+    /// Returns the logical working directory, which is $PWD when it's an
+    /// absolute path referring to the current directory, and otherwise
+    /// the physical working directory (e.g., when $PWD is stale)
+    fn logical_cwd() -> Result<String> {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(pwd) = std::env::var("PWD") {
+            let same = match (std::fs::metadata(&pwd), std::fs::metadata(".")) {
+                (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+                _ => false,
+            };
+            if pwd.starts_with('/') && same {
+                return Ok(pwd);
+            }
         }
-        let argv0 = sc.arguments.pop_front().unwrap(); // cannot fail
-        let rin = Self::maybe_redirect_input(&sc.input)?;
-        let (crx, cwx) = Self::wrap_os_pipe()?;
-        match self.exec(group, argv0, sc.arguments, rin, Some(cwx)) {
-            Err(err) => Err(err),
-            Ok(_) => Ok(crx),
+        Self::physical_cwd()
+    }
+
+    /// Returns the working directory with the symbolic links resolved
+    fn physical_cwd() -> Result<String> {
+        match std::env::current_dir() {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok(cwd) => Ok(cwd.to_string_lossy().to_string()),
         }
     }
 
-    /// Executes a filter command of a pipeline
-    fn filter_command(
-        self: &Self,
-        group: &mut Group,
-        mut fc: FilterCommand,
-        rx: PipeReader,
-    ) -> Result<PipeReader> {
-        if fc.arguments.len() < 1 {
-            return Err(Error::new("pipeline with empty filter command"));
-        }
-        let argv0 = fc.arguments.pop_front().unwrap(); // cannot fail
-        let (crx, cwx) = Self::wrap_os_pipe()?;
-        match self.exec(group, argv0, fc.arguments, Some(rx), Some(cwx)) {
-            Err(err) => Err(err),
-            Ok(_) => Ok(crx),
+    /// Joins the directory to the logical working directory, unless the
+    /// directory is absolute, and removes the `.` and `..` components
+    /// without resolving the symbolic links
+    fn clean_path(cwd: &str, dir: &str) -> String {
+        let joined = match dir.starts_with('/') {
+            true => String::from(dir),
+            false => format!("{}/{}", cwd, dir),
+        };
+        let mut components = Vec::<&str>::new();
+        for component in joined.split('/') {
+            match component {
+                "" | "." => (),
+                ".." => {
+                    components.pop();
+                }
+                _ => components.push(component),
+            }
         }
+        format!("/{}", components.join("/"))
     }
 
-    /// Executes the sink command of a pipeline
-    fn sink_command(
-        self: &Self,
-        group: &mut Group,
-        mut sc: SinkCommand,
-        rx: PipeReader,
+    /// Implements the builtin `source` (aka `.`) command
+    fn builtin_source(
+        self: &mut Self,
+        args: VecDeque<String>,
+        reaper: &mut PeriodicReaper,
     ) -> Result<()> {
-        if sc.arguments.len() < 1 {
-            return Err(Error::new("pipeline with empty sink command"));
+        if args.len() != 1 {
+            return Err(Error::new("usage: source <file>"));
         }
-        let argv0 = sc.arguments.pop_front().unwrap(); // cannot fail
-        let rou = Self::maybe_redirect_output(&sc.output)?;
-        self.exec(group, argv0, sc.arguments, Some(rx), rou)
+        let filename = self.find_library(&args[0]);
+        self.source(&filename, reaper)
     }
 
-    /// Creates the input redirection if needed.
-    fn maybe_redirect_input(input: &Option<InputRedir>) -> Result<Option<File>> {
-        match input {
-            None => Ok(None),
-            Some(input) => match File::open(&input.filename) {
-                Err(err) => Err(Error::new(&err.to_string())),
-                Ok(filep) => Ok(Some(filep)),
-            },
+    /// Implements the builtin `require` command, which sources each of
+    /// the given files unless it has already been sourced by `require`.
+    fn builtin_require(
+        self: &mut Self,
+        args: VecDeque<String>,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<()> {
+        if args.len() < 1 {
+            return Err(Error::new("usage: require <file>..."));
+        }
+        for name in args.iter() {
+            let filename = self.find_library(name);
+            let canonical = match std::fs::canonicalize(&filename) {
+                Err(err) => return Err(Error::new(&format!("require: {}: {}", name, err))),
+                Ok(canonical) => canonical,
+            };
+            // note: we record the file first, so that cycles terminate
+            if self.required.insert(canonical) {
+                self.source(&filename, reaper)?;
+            }
         }
+        Ok(())
     }
 
-    /// Creates the output redirection if needed.
-    fn maybe_redirect_output(output: &Option<OutputRedir>) -> Result<Option<File>> {
-        match output {
-            None => Ok(None),
-            Some(output) => match OpenOptions::new()
-                .write(true)
-                .create(true)
-                .append(!output.overwrite)
-                .open(&output.filename)
-            {
-                Err(err) => return Err(Error::new(&err.to_string())),
-                Ok(filep) => Ok(Some(filep)),
+    /// Implements the builtin `assert` command for test scripts, which
+    /// checks that two strings are equal, that a command exits with the
+    /// given status or that a file contains a string. A failed assertion
+    /// is an error, which stops the script, reporting its location.
+    fn builtin_assert(
+        self: &mut Self,
+        mut args: VecDeque<String>,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<()> {
+        let usage = || {
+            Error::new(
+                "usage: assert equal <actual> <expected> | status <status> <command> [args...] | file-contains <file> <string>",
+            )
+        };
+        let kind = args.pop_front().unwrap_or_default();
+        let args: Vec<String> = args.into();
+        let failure = match (kind.as_str(), &args[..]) {
+            ("equal", [actual, expected]) if actual != expected => {
+                Some(format!("expected '{}', got '{}'", expected, actual))
+            }
+            ("equal", [_, _]) => None,
+            ("status", [expected, command @ ..]) if command.len() > 0 => {
+                let expected = match expected.parse::<i32>() {
+                    Err(_) => return Err(usage()),
+                    Ok(expected) => expected,
+                };
+                let quoted: Vec<String> = command
+                    .iter()
+                    .map(|arg| format!("'{}'", arg.replace('\'', "'\\''")))
+                    .collect();
+                // note: the status is tested, hence exempt from errexit
+                let condition = self.condition;
+                self.condition = true;
+                let result = self.eval(&quoted.join(" "), reaper);
+                self.condition = condition;
+                let (status, reason) = match result {
+                    Err(err) => (1, format!(" ({})", err)),
+                    Ok(_) => (self.status, String::new()),
+                };
+                match status == expected {
+                    true => None,
+                    false => Some(format!(
+                        "{}: expected status {}, got {}{}",
+                        command.join(" "),
+                        expected,
+                        status,
+                        reason
+                    )),
+                }
+            }
+            ("file-contains", [file, string]) => match std::fs::read(file) {
+                Err(err) => Some(format!("{}: {}", file, err)),
+                Ok(content) => {
+                    let string = string.as_bytes();
+                    let found = string.len() == 0
+                        || content.windows(string.len()).any(|window| window == string);
+                    match found {
+                        true => None,
+                        false => Some(format!("{}: does not contain '{}'", file, args[1])),
+                    }
+                }
             },
+            _ => return Err(usage()),
+        };
+        match failure {
+            None => Ok(()),
+            Some(failure) => Err(Error::new(&format!("assert {}: {}", kind, failure))),
         }
     }
 
-    /// Common code for executing a child process.
-    fn exec<T1: Into<Stdio>, T2: Into<Stdio>>(
-        self: &Self,
-        group: &mut Group,
-        argv0: String,
-        mut args: VecDeque<String>,
-        stdin: Option<T1>,
-        stdout: Option<T2>,
+    /// Returns the file that `source` and `require` should read: a bare
+    /// name (i.e., without `/`) is looked up in the colon-separated
+    /// directories of `$XV6SH_LIB_PATH`, falling back to the name itself.
+    fn find_library(self: &Self, name: &str) -> String {
+        if name.contains('/') {
+            return String::from(name);
+        }
+        let path = self.variable("XV6SH_LIB_PATH").unwrap_or_default();
+        for dir in path.split(':').filter(|dir| dir.len() > 0) {
+            let candidate = Path::new(dir).join(name);
+            if candidate.is_file() {
+                return candidate.to_string_lossy().into_owned();
+            }
+        }
+        String::from(name)
+    }
+
+    /// Loads the configuration file, restoring afterwards the options
+    /// explicitly changed with `set` outside of the configuration file
+    pub fn load_config(self: &mut Self, filename: &str, reaper: &mut PeriodicReaper) -> Result<()> {
+        self.configuring = true;
+        let result = self.source(filename, reaper);
+        self.configuring = false;
+        for (option, value) in self.overrides.clone() {
+            self.set_option(&option, value)?;
+        }
+        result
+    }
+
+    /// Executes the commands in the given file in the current shell
+    fn source(self: &mut Self, filename: &str, reaper: &mut PeriodicReaper) -> Result<()> {
+        // note: the offsets of the commands refer to the file
+        let coverage = self.coverage.take();
+        let result = self.source_uncovered(filename, reaper);
+        self.coverage = coverage;
+        result
+    }
+
+    /// Implements source, without tracking the coverage
+    fn source_uncovered(
+        self: &mut Self,
+        filename: &str,
+        reaper: &mut PeriodicReaper,
     ) -> Result<()> {
-        self.maybe_debug(&argv0, &args);
-        let mut cmd = Command::new(argv0);
-        while args.len() > 0 {
-            let arg = args.pop_front().unwrap(); // cannot fail
-            cmd.arg(arg);
+        if let Some(mapped) = mapped::open(filename)? {
+            return self.source_mapped(mapped, reaper);
         }
-        if let Some(filep) = stdin {
-            cmd.stdin(filep);
+        let script = script::load(filename)?;
+        let program = match Self::parse_script(&script) {
+            Err(err) => return Err(script.locate(err, 0)),
+            Ok(program) => program,
+        };
+        for cc in program.commands {
+            let offset = cc.offset;
+            let loc = match self.translate(cc) {
+                Err(err) => return Err(script.locate(err, offset)),
+                Ok(loc) => loc,
+            };
+            if let Err(err) = self.run(loc, reaper) {
+                return Err(script.locate(err, offset));
+            }
         }
-        if let Some(filep) = stdout {
-            cmd.stdout(filep);
+        Ok(())
+    }
+
+    /// Executes the commands of a huge script one at a time
+    fn source_mapped(
+        self: &mut Self,
+        mut mapped: mapped::MappedScript,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<()> {
+        while let Some(chunk) = mapped.next_command()? {
+            let locate = |err: Error, offset: usize| {
+                chunk
+                    .script
+                    .locate_from(err, offset, chunk.line, chunk.byte)
+            };
+            let offset = chunk.command.offset;
+            let loc = match self.translate(chunk.command) {
+                Err(err) => return Err(locate(err, offset)),
+                Ok(loc) => loc,
+            };
+            reaper.reap(); // ensure we don't leave zombies around
+            if let Err(err) = self.run(loc, reaper) {
+                return Err(locate(err, offset));
+            }
         }
-        let proc = self.spawner.spawn(cmd)?;
-        group.add(proc); // ensure we track the child
         Ok(())
     }
 
-    /// Possibly log to stderr the commands we're about to execute.
-    fn maybe_debug(self: &Self, argv0: &str, args: &VecDeque<String>) {
-        if self.verbose {
-            let mut farg = String::new();
-            for arg in args.iter() {
-                farg.push_str(arg);
-                farg.push(' ');
+    /// Scans and parses a sourced script
+    fn parse_script(script: &script::Script) -> Result<parser::Program> {
+        let tokens = lexer::scan(script.content.clone(), &mut || None)?;
+        parser::parse_program(tokens)
+    }
+
+    /// Implements the builtin `set` command
+    fn builtin_set(self: &mut Self, mut args: VecDeque<String>) -> Result<()> {
+        while let Some(arg) = args.pop_front() {
+            let (option, value) = match arg.split_at_checked(1) {
+                Some(("-", "o")) => (args.pop_front().unwrap_or_default(), true),
+                Some(("+", "o")) => (args.pop_front().unwrap_or_default(), false),
+                Some(("-", option)) => (String::from(option), true),
+                Some(("+", option)) => (String::from(option), false),
+                _ => return Err(Self::set_usage()),
+            };
+            let option = option.as_str();
+            self.set_option(option, value)?;
+            if !self.configuring {
+                self.overrides.retain(|(name, _)| name != option);
+                self.overrides.push((String::from(option), value));
             }
-            eprintln!("+ {} {}", argv0, farg);
         }
+        Ok(())
     }
 
-    /// Wrapper to adapt os_pipe::pipe to our kind of Result
-    fn wrap_os_pipe() -> Result<(PipeReader, PipeWriter)> {
-        match pipe() {
-            Err(err) => Err(Error::new(&err.to_string())),
-            Ok((rx, wx)) => Ok((rx, wx)),
+    /// Enables or disables the given option
+    fn set_option(self: &mut Self, option: &str, value: bool) -> Result<()> {
+        match (option, value) {
+            ("m", true) => self.jobs.enable()?,
+            ("m", false) => self.jobs.disable(),
+            ("C", value) => self.noclobber = value,
+            ("step", value) => self.step = value,
+            ("nocaseglob", value) => self.nocaseglob = value,
+            ("keepnewlines", value) => self.keepnewlines = value,
+            ("exitstats", value) => self.exitstats = value,
+            ("privatetmp", value) => self.privatetmp = value,
+            ("e" | "errexit", value) => self.errexit = value,
+            ("strict-errexit", value) => self.strict_errexit = value,
+            _ => return Err(Self::set_usage()),
+        }
+        Ok(())
+    }
+
+    /// Returns the `set -o` options that are enabled, but for the monitor
+    /// mode and noclobber, which `reload` hands over separately.
+    fn enabled_options(self: &Self) -> Vec<String> {
+        let options = [
+            ("step", self.step),
+            ("nocaseglob", self.nocaseglob),
+            ("keepnewlines", self.keepnewlines),
+            ("exitstats", self.exitstats),
+            ("privatetmp", self.privatetmp),
+            ("errexit", self.errexit),
+            ("strict-errexit", self.strict_errexit),
+        ];
+        options
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| String::from(*name))
+            .collect()
+    }
+
+    /// Enables the options handed over by `reload`, which win over the
+    /// configuration file like the options set at the prompt.
+    pub fn resume_options(self: &mut Self, options: &[String]) -> Result<()> {
+        let mut args = VecDeque::<String>::new();
+        for option in options.iter() {
+            args.push_back(String::from("-o"));
+            args.push_back(option.clone());
+        }
+        self.builtin_set(args)
+    }
+
+    /// Returns the usage error of the builtin `set` command
+    fn set_usage() -> Error {
+        Error::new(
+            "usage: set [-m|+m|-C|+C|-e|+e|-o step|+o step|-o nocaseglob|+o nocaseglob|-o strict-errexit|+o strict-errexit|-o keepnewlines|+o keepnewlines|-o exitstats|+o exitstats|-o privatetmp|+o privatetmp]...",
+        )
+    }
+
+    /// Implements the builtin `trap` command, which only supports
+    /// the DEBUG condition for now
+    fn builtin_trap(self: &mut Self, args: VecDeque<String>) -> Result<()> {
+        let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        match args[..] {
+            [] => {
+                if let Some(action) = &self.debug_trap {
+                    println!("trap -- '{}' DEBUG", action.replace('\'', "'\\''"));
+                }
+                if let Some(action) = &self.exit_trap {
+                    println!("trap -- '{}' EXIT", action.replace('\'', "'\\''"));
+                }
+            }
+            ["-", "DEBUG"] => self.debug_trap = None,
+            [action, "DEBUG"] => self.debug_trap = Some(String::from(action)),
+            ["-", "EXIT" | "0"] => self.exit_trap = None,
+            [action, "EXIT" | "0"] => self.exit_trap = Some(String::from(action)),
+            [_, condition] => {
+                return Err(Error::new(&format!(
+                    "trap: {}: unsupported condition (only DEBUG and EXIT are supported)",
+                    condition
+                )))
+            }
+            _ => return Err(Error::new("usage: trap [<action>|-] DEBUG|EXIT")),
+        }
+        Ok(())
+    }
+
+    /// Executes the EXIT trap, if any, when the shell is about to exit. We
+    /// run the trap in a child shell, which we kill if it does not finish
+    /// within `$XV6SH_TRAP_TIMEOUT` seconds (default: 5), so that a trap
+    /// cannot hang the shell on logout. We clear the trap before running
+    /// it, hence it runs at most once and cannot re-trigger itself.
+    pub fn exit_trap(self: &mut Self) {
+        let action = match self.exit_trap.take() {
+            None => return,
+            Some(action) => action,
+        };
+        let timeout = std::env::var("XV6SH_TRAP_TIMEOUT")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+            .map(Duration::from_secs_f64)
+            .unwrap_or(Duration::from_secs(5));
+        // note: the trap runs in its own process group, so that we can
+        // also kill the commands it started when it times out
+        let spawn = |mut cmd: Command| {
+            cmd.process_group(0);
+            self.spawner.spawn(cmd)
+        };
+        let mut proc = match self.child_shell(&action).and_then(spawn) {
+            Err(err) => {
+                eprintln!("xv6sh: trap: {}", err);
+                return;
+            }
+            Ok(proc) => proc,
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            match proc.try_wait() {
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Ok(None) => {
+                    unsafe { libc::kill(-(proc.id() as libc::pid_t), libc::SIGKILL) };
+                    let _ = proc.wait();
+                    eprintln!(
+                        "xv6sh: trap: EXIT trap aborted after {:.1} seconds",
+                        timeout.as_secs_f64()
+                    );
+                    return;
+                }
+                Ok(Some(_)) | Err(_) => return,
+            }
+        }
+    }
+
+    /// Implements the builtin `reload` command, which re-executes the
+    /// shell binary handing the current state over to the new instance
+    fn builtin_reload(self: &Self, args: VecDeque<String>, reaper: &PeriodicReaper) -> Result<()> {
+        if args.len() != 0 {
+            return Err(Error::new("usage: reload"));
+        }
+        if hardened::enabled() {
+            return Err(Error::new("reload: disabled in hardened mode"));
+        }
+        let cwd = match std::env::current_dir() {
+            Err(err) => return Err(Error::new(&err.to_string())),
+            Ok(cwd) => cwd.to_string_lossy().to_string(),
+        };
+        let state = handoff::State {
+            cwd: cwd,
+            verbose: self.verbose,
+            monitor: self.jobs.enabled(),
+            noclobber: self.noclobber,
+            pids: reaper.pids().into_iter().collect(),
+            options: self.enabled_options(),
+            positional: self.positional.clone(),
+        };
+        let path = handoff::save(&state)?;
+        let exe = match std::env::current_exe() {
+            Err(err) => return Err(Error::new(&err.to_string())),
+            Ok(exe) => exe,
+        };
+        let mut cmd = Command::new(exe);
+        cmd.arg("--resume").arg(&path);
+        // note: like subshells, the new shell inherits the variables
+        cmd.env(VARIABLES, self.export_variables());
+        self.xtrace.inherit(&mut cmd);
+        if process::init_mode() {
+            let timeout = process::kill_timeout().as_secs().to_string();
+            cmd.arg("--init").arg("--kill-timeout").arg(timeout);
+        }
+        let err = cmd.exec();
+        handoff::remove(&path); // we're still running
+        Err(Error::new(&err.to_string()))
+    }
+
+    /// Implements the builtin `transcript` command
+    fn builtin_transcript(args: VecDeque<String>) -> Result<()> {
+        match (args.len(), args.front().map(|s| s.as_str())) {
+            (2, Some("start")) => transcript::start(&args[1]),
+            (1, Some("stop")) => transcript::stop(),
+            _ => Err(Error::new(
+                "usage: transcript start <file> | transcript stop",
+            )),
+        }
+    }
+
+    /// Implements the builtin `daemonize` command
+    fn builtin_daemonize(
+        self: &Self,
+        mut args: VecDeque<String>,
+        redirs: &VecDeque<Redirect>,
+    ) -> Result<()> {
+        if args.len() < 1 {
+            return Err(Error::new("usage: daemonize <command> [args...]"));
+        }
+        let argv0 = args.pop_front().unwrap(); // cannot fail
+        let mut fds = FdTable::new();
+        for fd in 0..3 {
+            fds.insert(fd, Self::null()?);
+        }
+        let fds = self.redirect(fds, redirs)?;
+        self.maybe_debug(&Environment::new(), &argv0, &args);
+        let mut cmd = Command::new(argv0);
+        cmd.args(args);
+        fds.configure(&mut cmd)?;
+        process::daemonize(&mut cmd);
+        let mut proc = self.spawner.spawn(cmd)?;
+        let _ = proc.wait(); // the intermediate child exits immediately
+        Ok(())
+    }
+
+    /// Implements the builtin `supervise` command
+    fn builtin_supervise(
+        self: &mut Self,
+        mut args: VecDeque<String>,
+        redirs: &VecDeque<Redirect>,
+    ) -> Result<()> {
+        let usage = || Error::new("usage: supervise [-n <max-restarts>] <command> [args...]");
+        let mut max_restarts = supervise::DEFAULT_MAX_RESTARTS;
+        if args.front().map(|s| s.as_str()) == Some("-n") {
+            args.pop_front();
+            max_restarts = match args.pop_front().map(|s| s.parse::<usize>()) {
+                Some(Ok(value)) => value,
+                _ => return Err(usage()),
+            };
+        }
+        if args.len() < 1 {
+            return Err(usage());
+        }
+        let (command, create) = self.background(args, redirs)?;
+        let id = self.next_job_id();
+        let supervisor = supervise::start(id, command, max_restarts, create)?;
+        self.supervisors.push(supervisor);
+        Ok(())
+    }
+
+    /// Implements the builtin `after` and `every` commands
+    fn builtin_timer(
+        self: &mut Self,
+        name: &str,
+        mut args: VecDeque<String>,
+        redirs: &VecDeque<Redirect>,
+    ) -> Result<()> {
+        if args.len() < 2 {
+            return Err(Error::new(&format!(
+                "usage: {} <duration> <command> [args...]",
+                name
+            )));
+        }
+        let delay = timer::parse_duration(&args.pop_front().unwrap())?; // cannot fail
+        let (command, create) = self.background(args, redirs)?;
+        let id = self.next_job_id();
+        self.timers
+            .schedule(id, command, delay, name == "every", create);
+        Ok(())
+    }
+
+    /// Implements the builtin `cancel` command
+    fn builtin_cancel(self: &Self, args: VecDeque<String>) -> Result<()> {
+        let usage = || Error::new("usage: cancel %<job>...");
+        if args.len() < 1 {
+            return Err(usage());
+        }
+        for arg in args.iter() {
+            match arg.strip_prefix('%').map(|id| id.parse::<usize>()) {
+                Some(Ok(id)) => self.timers.cancel(id)?,
+                _ => return Err(usage()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Implements the builtin `shift` command
+    fn builtin_shift(self: &mut Self, args: VecDeque<String>) -> Result<()> {
+        let count = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+            [] => 1,
+            [count] => match count.parse::<usize>() {
+                Err(_) => return Err(Error::new("usage: shift [count]")),
+                Ok(count) => count,
+            },
+            _ => return Err(Error::new("usage: shift [count]")),
+        };
+        if count > self.positional.len() {
+            return Err(Error::new(&format!("shift: {}: count out of range", count)));
+        }
+        self.positional.drain(..count);
+        Ok(())
+    }
+
+    /// Implements the builtin `declare` command, which sets variables
+    /// and, with `-S`, marks them as sensitive (see hardened.rs).
+    fn builtin_declare(self: &mut Self, mut args: VecDeque<String>) -> Result<()> {
+        let usage = || Error::new("usage: declare [-S] NAME[=VALUE]...");
+        let sensitive = args.front().map(|arg| arg.as_str()) == Some("-S");
+        if sensitive {
+            args.pop_front();
+        }
+        if args.len() == 0 {
+            return Err(usage());
+        }
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                None => (arg.as_str(), None),
+                Some((name, value)) => (name, Some(value)),
+            };
+            let valid = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+                && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric());
+            if !valid {
+                return Err(Error::new(&format!("declare: {}: invalid name", name)));
+            }
+            if let Some(value) = value {
+                self.set_variable(name, value);
+            }
+            if sensitive && !self.sensitive.iter().any(|known| known == name) {
+                self.sensitive.push(String::from(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Implements the builtin `unset` command, which removes the given
+    /// variables from the shell and from the environment, overwriting
+    /// the values of the sensitive ones with zeros. The shell has no
+    /// functions, hence `unset -f` fails.
+    fn builtin_unset(self: &mut Self, mut args: VecDeque<String>) -> Result<()> {
+        match args.front().map(|arg| arg.as_str()) {
+            Some("-v") => {
+                args.pop_front();
+            }
+            Some("-f") => return Err(Error::new("unset: -f: the shell has no functions")),
+            _ => (),
+        }
+        if args.len() == 0 {
+            return Err(Error::new("usage: unset [-v] NAME..."));
+        }
+        for name in args.iter() {
+            if !lexer::is_name(name) {
+                return Err(Error::new(&format!("unset: {}: invalid name", name)));
+            }
+            let value = self.variables.get_mut().remove(name);
+            self.spawnctx.borrow_mut().variables_changed();
+            match self.sensitive.iter().position(|known| known == name) {
+                None => std::env::remove_var(name),
+                Some(index) => {
+                    self.sensitive.remove(index);
+                    if let Some(mut value) = value {
+                        hardened::clear(&mut value);
+                    }
+                    hardened::zeroize(name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Overwrites the values of the sensitive variables with zeros and
+    /// unsets them, which we do before exiting.
+    pub fn zeroize(self: &mut Self) {
+        self.spawnctx.get_mut().variables_changed();
+        for name in self.sensitive.drain(..) {
+            if let Some(mut value) = self.variables.get_mut().remove(&name) {
+                hardened::clear(&mut value);
+            }
+            hardened::zeroize(&name);
+        }
+    }
+
+    /// Implements the builtin `export` command, which moves the given
+    /// variables, possibly setting them, to the environment, so that
+    /// the commands we execute inherit them. Without names, or with
+    /// `-p`, it prints the exported variables.
+    fn builtin_export(self: &Self, args: VecDeque<String>) -> Result<()> {
+        if args.len() == 0 || (args.len() == 1 && args[0] == "-p") {
+            let mut exported: Vec<(String, String)> = std::env::vars().collect();
+            exported.sort();
+            for (name, value) in exported.iter() {
+                println!("export {}='{}'", name, value.replace('\'', "'\\''"));
+            }
+            return Ok(());
+        }
+        for arg in args.iter() {
+            let (name, value) = match arg.split_once('=') {
+                None => (arg.as_str(), None),
+                Some((name, value)) => (name, Some(value)),
+            };
+            if !lexer::is_name(name) {
+                return Err(Error::new(&format!("export: {}: invalid name", name)));
+            }
+            let previous = self.variables.borrow_mut().remove(name);
+            self.spawnctx.borrow_mut().variables_changed();
+            // note: like other shells, we don't export unset variables
+            if let Some(value) = value.map(String::from).or(previous) {
+                std::env::set_var(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the given shell or environment variable.
+    pub fn variable(self: &Self, name: &str) -> Option<String> {
+        match self.variables.borrow().get(name) {
+            Some(value) => Some(value.clone()),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    /// Sets the given variable, which remains in the environment if
+    /// it was exported and otherwise becomes a shell variable.
+    fn set_variable(self: &Self, name: &str, value: &str) {
+        match std::env::var_os(name) {
+            Some(_) => std::env::set_var(name, value),
+            None => {
+                self.spawnctx.borrow_mut().variables_changed();
+                let mut variables = self.variables.borrow_mut();
+                variables.insert(String::from(name), String::from(value));
+            }
+        }
+    }
+
+    /// Imports the variables that the parent shell passed to us, if we
+    /// are a subshell, removing them from the environment, so that the
+    /// commands we execute do not inherit them. We encode each variable
+    /// as `NAME=LENGTH:VALUE`, where LENGTH is the length of the value
+    /// in bytes, which may contain any char, including `=` and `:`.
+    pub fn import_variables(self: &mut Self) {
+        let encoded = match std::env::var(VARIABLES) {
+            Err(_) => return,
+            Ok(encoded) => encoded,
+        };
+        std::env::remove_var(VARIABLES);
+        self.spawnctx.get_mut().variables_changed();
+        let variables = self.variables.get_mut();
+        let mut rest = encoded.as_str();
+        while let Some((name, tail)) = rest.split_once('=') {
+            let (length, tail) = match tail.split_once(':') {
+                None => break,
+                Some(pair) => pair,
+            };
+            let value = match length.parse::<usize>() {
+                Ok(length) if tail.is_char_boundary(length) => &tail[..length],
+                _ => break, // ignore the remaining, malformed data
+            };
+            variables.insert(String::from(name), String::from(value));
+            rest = &tail[value.len()..];
+        }
+    }
+
+    /// Encodes the variables that are not exported for a subshell.
+    fn export_variables(self: &Self) -> String {
+        self.spawnctx.borrow_mut().variables(|| {
+            let mut encoded = String::new();
+            for (name, value) in self.variables.borrow().iter() {
+                encoded.push_str(&format!("{}={}:{}", name, value.len(), value));
+            }
+            encoded
+        })
+    }
+
+    /// Implements the builtin `hash` command, which lists where we found
+    /// the commands we executed, while `hash -r` forgets them.
+    fn builtin_hash(self: &Self, args: VecDeque<String>) -> Result<()> {
+        let mut spawnctx = self.spawnctx.borrow_mut();
+        match args.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+            [] => {
+                for (name, program) in spawnctx.entries() {
+                    println!("{}\t{}", name, program.display());
+                }
+            }
+            ["-r"] => spawnctx.reset(),
+            _ => return Err(Error::new("usage: hash [-r]")),
+        }
+        Ok(())
+    }
+
+    /// Implements the builtin `enable` command, which disables (`-n`) or
+    /// enables builtins, lists them when there are no names, and loads
+    /// builtins from WASM plugins (`-f FILE`).
+    fn builtin_enable(self: &mut Self, mut args: VecDeque<String>) -> Result<()> {
+        let usage = || Error::new("usage: enable [-n] [-f FILE] [NAME...]");
+        let (mut disable, mut file) = (false, None);
+        while let Some(arg) = args.front() {
+            match arg.as_str() {
+                "-n" => disable = true,
+                "-f" => {
+                    args.pop_front();
+                    file = Some(args.front().cloned().ok_or_else(usage)?);
+                }
+                "--" => {
+                    args.pop_front();
+                    break;
+                }
+                flag if flag.starts_with('-') => return Err(usage()),
+                _ => break,
+            }
+            args.pop_front();
+        }
+        if let Some(file) = file {
+            if disable || args.len() < 1 {
+                return Err(usage());
+            }
+            return Self::enable_plugins(&file, &args);
+        }
+        if args.len() < 1 {
+            for name in BUILTINS.iter() {
+                match (disable, self.disabled.contains(*name)) {
+                    (false, false) => println!("enable {}", name),
+                    (true, true) => println!("enable -n {}", name),
+                    _ => (),
+                }
+            }
+            return Ok(());
+        }
+        for name in args.iter() {
+            if !Self::is_builtin(name) {
+                return Err(Error::new(&format!(
+                    "enable: {}: not a shell builtin",
+                    name
+                )));
+            }
+        }
+        for name in args {
+            match disable {
+                true => self.disabled.insert(name),
+                false => self.disabled.remove(&name),
+            };
+        }
+        Ok(())
+    }
+
+    /// Implements the buildinfo builtin.
+    fn builtin_buildinfo(args: VecDeque<String>) -> Result<()> {
+        let format = Self::buildinfo_format(&args)?;
+        print!("{}", buildinfo::format(&format)?);
+        Ok(())
+    }
+
+    /// Returns the format requested through the arguments of `buildinfo`.
+    fn buildinfo_format(args: &VecDeque<String>) -> Result<String> {
+        match args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>()[..] {
+            [] => Ok(String::from("human")),
+            ["--format", format] => Ok(String::from(format)),
+            _ => Err(Error::new("usage: buildinfo [--format human|json]")),
+        }
+    }
+
+    /// Implements the has builtin, which exits with zero if the shell has
+    /// all the given capabilities (see buildinfo.rs), where `builtin:NAME`
+    /// means that NAME is an enabled builtin, and lists them otherwise.
+    fn builtin_has(self: &Self, args: VecDeque<String>) -> Result<i32> {
+        if args.len() < 1 {
+            for capability in buildinfo::capabilities() {
+                println!("{}", capability);
+            }
+            return Ok(0);
+        }
+        for capability in args.iter() {
+            let present = match capability.strip_prefix("builtin:") {
+                Some(name) => Self::is_builtin(name) && !self.disabled.contains(name),
+                None => buildinfo::capabilities().contains(&capability.as_str()),
+            };
+            if !present {
+                return Ok(1);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Implements the printf builtin (see printf.rs), which, unlike the
+    /// other builtins, honours its redirections (e.g., `printf x > file`).
+    fn builtin_printf(mut args: VecDeque<String>) -> Result<()> {
+        let format = match args.pop_front() {
+            None => return Err(Error::new("usage: printf FORMAT [ARGUMENT...]")),
+            Some(format) => format,
+        };
+        let output = printf::format(&format, args)?;
+        match std::io::stdout().write_all(output.as_bytes()) {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Tells whether the builtin runs inside the shell with its redirections
+    /// applied to the descriptors of the shell (e.g., `pwd > file`), rather
+    /// than handing them to the command it spawns (e.g., `daemonize` and
+    /// the prefixes, such as `pty`) or applying them itself (i.e., `exec`).
+    fn redirects_shell(name: &str) -> bool {
+        match name {
+            "" | "daemonize" | "supervise" | "after" | "every" | "exec" => false,
+            "pty" | "envfile" | "ioprio" | "affinity" | "argv0" => false,
+            _ => Self::is_builtin(name),
+        }
+    }
+
+    /// Tells whether the given name is a builtin, including plugins.
+    fn is_builtin(name: &str) -> bool {
+        #[cfg(feature = "plugins")]
+        if crate::plugin::exists(name) {
+            return true;
+        }
+        BUILTINS.contains(&name)
+    }
+
+    /// Loads the given builtins from the WASM plugin (i.e., `enable -f`).
+    #[cfg(feature = "plugins")]
+    fn enable_plugins(file: &str, names: &VecDeque<String>) -> Result<()> {
+        for name in names.iter() {
+            crate::plugin::register(name, file)?;
+        }
+        Ok(())
+    }
+
+    /// Loads the given builtins from the WASM plugin (i.e., `enable -f`).
+    #[cfg(not(feature = "plugins"))]
+    fn enable_plugins(_file: &str, _names: &VecDeque<String>) -> Result<()> {
+        Err(Error::new(
+            "enable: -f: the shell was built without plugins",
+        ))
+    }
+
+    /// Returns the job number for a new supervised command or timer.
+    fn next_job_id(self: &Self) -> usize {
+        let supervisors = self.supervisors.iter().map(|supervisor| supervisor.id);
+        let last = supervisors.chain(self.timers.last_id()).max();
+        last.unwrap_or(0) + 1
+    }
+
+    /// Prepares a background command that we may spawn multiple times,
+    /// returning its command line and a function creating the command.
+    fn background(
+        self: &Self,
+        args: VecDeque<String>,
+        redirs: &VecDeque<Redirect>,
+    ) -> Result<(String, CommandFactory)> {
+        // note: the command runs in background, so it must not compete
+        // with the shell for reading the standard input
+        let mut fds = FdTable::new();
+        fds.insert(0, Self::null()?);
+        let fds = self.redirect(fds, redirs)?;
+        self.maybe_debug(
+            &Environment::new(),
+            &args[0],
+            &args.iter().skip(1).cloned().collect(),
+        );
+        let command = args.iter().cloned().collect::<Vec<_>>().join(" ");
+        let create = move || {
+            let copies = fds.try_clone()?;
+            let mut cmd = Command::new(&args[0]);
+            cmd.args(args.iter().skip(1));
+            cmd.process_group(0); // i.e., like a background job
+            copies.configure(&mut cmd)?;
+            Ok(cmd)
+        };
+        Ok((command, Box::new(create)))
+    }
+
+    /// Returns a function tracking the given supervised command (`%N`)
+    /// or background process (its pid) for the given builtin.
+    fn track_job(
+        self: &Self,
+        builtin: &str,
+        job: &str,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<watchdog::Tracker> {
+        let missing = || Error::new(&format!("{}: {}: no such job", builtin, job));
+        if let Some(id) = job.strip_prefix('%') {
+            let id = id.parse::<usize>().map_err(|_| missing())?;
+            return match self
+                .supervisors
+                .iter()
+                .find(|supervisor| supervisor.id == id)
+            {
+                Some(supervisor) if supervisor.active() => Ok(supervisor.tracker()),
+                _ => Err(missing()),
+            };
+        }
+        reaper.reap(); // don't watch the processes that have terminated
+        match job.parse::<u32>() {
+            Ok(pid) if reaper.pids().contains(&pid) => Ok(watchdog::track_pid(pid)),
+            _ => Err(missing()),
+        }
+    }
+
+    /// Implements the builtin `jobs` command
+    fn builtin_jobs(
+        self: &mut Self,
+        args: VecDeque<String>,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<()> {
+        let args = Vec::from(args);
+        match args.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+            [] => (),
+            ["-t"] => {
+                self.timers.list();
+                return Ok(());
+            }
+            ["--limit"] => {
+                self.watchdogs.list();
+                return Ok(());
+            }
+            ["--limit", job] => return self.watchdogs.detach(job),
+            ["--limit", job, ..] => {
+                let limits = watchdog::Limits::parse(&args[2..])?;
+                let track = self.track_job("jobs", job, reaper)?;
+                self.watchdogs.attach(String::from(job), limits, track);
+                return Ok(());
+            }
+            _ => {
+                return Err(Error::new(
+                    "usage: jobs [-t|--limit [%<job>|<pid> [cpu=<percent>] [mem=<size>] [signal=<name>]]]",
+                ))
+            }
+        }
+        reaper.reap(); // don't show the processes that have terminated
+        let mut pids: Vec<u32> = reaper.pids().into_iter().collect();
+        pids.sort();
+        for pid in pids {
+            println!("[-] running (pid {})", pid);
+        }
+        for supervisor in self.supervisors.iter() {
+            println!("{}", supervisor);
+        }
+        // note: like other shells, we show finished jobs just once
+        self.supervisors.retain(|supervisor| supervisor.active());
+        Ok(())
+    }
+
+    /// Implements the builtin `exec` command, which replaces the shell
+    /// with the given command, after applying the redirections, or, without
+    /// a command, applies the redirections to the shell itself (e.g., `exec
+    /// 2>errors.log`). Like in other shells, the EXIT trap does not run.
+    /// The `-a` and `-l` options set the command's zeroth argument.
+    fn builtin_exec(
+        self: &mut Self,
+        mut args: VecDeque<String>,
+        env: &Environment,
+        redirs: &VecDeque<Redirect>,
+    ) -> Result<()> {
+        let mut fds = FdTable::new();
+        for redir in redirs.iter() {
+            // note: nobody would feed the pipe of a here-document after
+            // exec, hence we spool here-documents to a file instead
+            let text = match self.expand_redirect(redir)? {
+                Redirect::Input(InputRedir::HereDoc(heredoc)) => heredoc.body,
+                Redirect::Input(InputRedir::HereString(word)) => format!("{}\n", word.value),
+                expanded => {
+                    self.redirect_expanded(&mut fds, &expanded)?;
+                    continue;
+                }
+            };
+            fds.insert(0, Self::spool(&text)?);
+        }
+        let usage = "usage: exec [-l] [-a <name>] [<command> [args...]]";
+        let zeroth = Self::parse_zeroth(usage, &mut args)?;
+        let argv0 = match (args.pop_front(), &zeroth) {
+            (None, None) => return Self::redirect_shell(fds),
+            (None, Some(_)) => return Err(Error::new(usage)),
+            (Some(argv0), _) => argv0,
+        };
+        self.maybe_debug(env, &argv0, &args);
+        let mut cmd = self.command(&argv0);
+        if let Some(zeroth) = zeroth {
+            cmd.arg0(zeroth.resolve(&argv0));
+        }
+        cmd.args(args);
+        cmd.envs(env.iter().cloned());
+        fds.configure(&mut cmd)?;
+        // note: the command inherits the signals that job control ignores
+        let monitor = self.jobs.enabled();
+        self.jobs.disable();
+        let err = cmd.exec();
+        if monitor {
+            self.jobs.enable()?;
+        }
+        Err(Error::new(&format!("exec: {}: {}", argv0, err)))
+    }
+
+    /// Parses the `[-l] [-a <name>]` options of `exec` and `argv0`, which
+    /// precede the command, returning how to override its zeroth argument.
+    fn parse_zeroth(usage: &str, args: &mut VecDeque<String>) -> Result<Option<Zeroth>> {
+        let mut zeroth = Zeroth {
+            name: None,
+            login: false,
+        };
+        loop {
+            match args.front().map(|arg| arg.as_str()) {
+                Some("-l") => zeroth.login = true,
+                Some("-a") => {
+                    args.pop_front();
+                    match args.front() {
+                        None => return Err(Error::new(usage)),
+                        Some(name) => zeroth.name = Some(name.clone()),
+                    }
+                }
+                Some("--") => {
+                    args.pop_front();
+                    break;
+                }
+                _ => break,
+            }
+            args.pop_front();
+        }
+        match (&zeroth.name, zeroth.login) {
+            (None, false) => Ok(None),
+            _ => Ok(Some(zeroth)),
+        }
+    }
+
+    /// Applies the descriptors resulting from redirections to the shell.
+    fn redirect_shell(fds: FdTable) -> Result<()> {
+        for (target, fd) in fds.raise()? {
+            if unsafe { libc::dup2(fd.as_raw_fd(), target) } == -1 {
+                return Err(Error::new(&format!(
+                    "exec: {}",
+                    std::io::Error::last_os_error()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the text into an unlinked temporary file and returns the
+    /// file, ready to be read from the beginning. We let mkostemp(3) choose
+    /// a new name, which only the user can access, rather than using a
+    /// predictable name, which anyone could create before us.
+    fn spool(text: &str) -> Result<OwnedFd> {
+        use std::ffi::{CString, OsStr};
+        use std::io::{Seek, SeekFrom};
+        use std::os::unix::ffi::OsStrExt;
+        let template = std::env::temp_dir().join("xv6sh-heredoc-XXXXXX");
+        let mut bytes = match CString::new(template.as_os_str().as_bytes()) {
+            Err(err) => return Err(Error::new(&err.to_string())),
+            Ok(template) => template.into_bytes_with_nul(),
+        };
+        // note: mkostemp replaces the XXXXXX with the name it creates and,
+        // like mkstemp, opens it for reading and writing, but close-on-exec
+        let template_ptr = bytes.as_mut_ptr() as *mut libc::c_char;
+        let fd = unsafe { libc::mkostemp(template_ptr, libc::O_CLOEXEC) };
+        if fd == -1 {
+            let err = std::io::Error::last_os_error();
+            return Err(Error::new(&format!("{}: {}", template.display(), err)));
+        }
+        let mut filep = unsafe { File::from_raw_fd(fd) };
+        bytes.pop(); // the nul terminator
+        let _ = std::fs::remove_file(OsStr::from_bytes(&bytes));
+        let result = filep
+            .write_all(text.as_bytes())
+            .and_then(|_| filep.seek(SeekFrom::Start(0)));
+        match result {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok(_) => Ok(OwnedFd::from(filep)),
+        }
+    }
+
+    /// Implements the builtin `debugjob` command, which lists the processes
+    /// of a job or attaches a debugger to one of them (see debugjob.rs)
+    fn builtin_debugjob(
+        self: &Self,
+        args: VecDeque<String>,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<i32> {
+        let (job, chosen) = match Vec::from(args).as_slice() {
+            [job] => (job.clone(), None),
+            [job, pid] => (job.clone(), Some(pid.clone())),
+            _ => return Err(Error::new("usage: debugjob %<job>|<pid> [<pid>]")),
+        };
+        let pid = match self.track_job("debugjob", &job, reaper)?() {
+            watchdog::Target::Running(pid) => pid,
+            _ => return Err(Error::new(&format!("debugjob: {}: not running", job))),
+        };
+        let processes = debugjob::processes(pid);
+        let chosen = match chosen {
+            None => {
+                for p in processes.iter() {
+                    let indent = "  ".repeat(p.depth);
+                    println!("{}{} {} {} {}", indent, p.pid, p.state, p.wchan, p.command);
+                }
+                return Ok(0);
+            }
+            Some(chosen) => chosen,
+        };
+        let pid = match chosen.parse::<u32>() {
+            Ok(pid) if processes.iter().any(|p| p.pid == pid) => pid,
+            _ => {
+                return Err(Error::new(&format!(
+                    "debugjob: {}: not a process of {}",
+                    chosen, job
+                )))
+            }
+        };
+        let mut args = VecDeque::from(debugjob::debugger(self.variable("XV6SH_DEBUGGER"), pid));
+        let argv0 = args.pop_front().unwrap(); // cannot fail
+        let mut group = Group::new(reaper);
+        self.exec(&mut group, argv0, args, Vec::new(), FdTable::new())?;
+        Ok(self.wait_foreground(&mut group))
+    }
+
+    /// Executes a pipeline and returns the exit status of its sink. We
+    /// prepare all the commands before spawning any of them, so that we
+    /// spawn the processes back to back and, when preparing a command
+    /// fails, we have not spawned any process yet.
+    fn pipelined_commands(
+        self: &Self,
+        pc: PipelinedCommands,
+        reaper: &mut PeriodicReaper,
+    ) -> Result<i32> {
+        let mut measure = timings::start();
+        let prepared = self.prepare_pipeline(pc.source, pc.filters, pc.sink)?;
+        let processes = prepared.len();
+        let command = prepared
+            .iter()
+            .map(|p| p.command_line())
+            .collect::<Vec<_>>()
+            .join(" | ");
+        let mut group = Group::new(reaper);
+        measure.spawning();
+        for p in prepared {
+            if let Err(err) = self.exec(&mut group, p.argv0, p.arguments, p.env, p.fds) {
+                group.kill_and_wait();
+                return Err(err);
+            }
+        }
+        measure.running();
+        let mut status = 0;
+        if pc.sync {
+            status = self.wait_foreground(&mut group);
+        }
+        measure.report(&command, processes);
+        Ok(status)
+    }
+
+    /// Prepares the commands of a pipeline, connecting them with pipes.
+    fn prepare_pipeline(
+        self: &Self,
+        source: SourceCommand,
+        filters: VecDeque<FilterCommand>,
+        sink: SinkCommand,
+    ) -> Result<Vec<Prepared>> {
+        let mut stages = vec![(
+            "source",
+            source.assignments,
+            source.arguments,
+            source.redirs,
+        )];
+        for filter in filters {
+            stages.push((
+                "filter",
+                filter.assignments,
+                filter.arguments,
+                filter.redirs,
+            ));
+        }
+        stages.push(("sink", sink.assignments, sink.arguments, sink.redirs));
+        let count = stages.len();
+        let mut prepared = Vec::<Prepared>::new();
+        let mut rx: Option<PipeReader> = None;
+        for (index, (kind, assignments, words, redirs)) in stages.into_iter().enumerate() {
+            let mut arguments = self.expand_words(words)?;
+            let env = self.expand_assignments(&assignments)?;
+            if arguments.len() < 1 {
+                return Err(Error::new(&format!("pipeline with empty {} command", kind)));
+            }
+            let argv0 = arguments.pop_front().unwrap(); // cannot fail
+            let mut fds = FdTable::new();
+            if let Some(rx) = rx.take() {
+                fds.insert(0, Self::owned(rx));
+            }
+            if index + 1 < count {
+                let (crx, cwx) = Self::wrap_os_pipe()?;
+                fds.insert(1, Self::owned(cwx));
+                rx = Some(crx);
+            }
+            let fds = self.redirect(fds, &redirs)?;
+            prepared.push(Prepared {
+                argv0: argv0,
+                arguments: arguments,
+                env: env,
+                fds: fds,
+            });
+        }
+        Ok(prepared)
+    }
+
+    /// Expands the variables inside the words of a command (see expansion.rs).
+    fn expand_words(self: &Self, words: VecDeque<Word>) -> Result<VecDeque<String>> {
+        expansion::words(words, self)
+    }
+
+    /// Expands the values of the assignments preceding a command.
+    fn expand_assignments(self: &Self, assignments: &VecDeque<Assignment>) -> Result<Environment> {
+        let mut env = Environment::new();
+        for assignment in assignments.iter() {
+            let value = expansion::expand(&assignment.value, self)?;
+            env.push((assignment.name.clone(), value));
+        }
+        Ok(env)
+    }
+
+    /// Returns a copy of the redirection whose file name, or string,
+    /// has been expanded. We do not expand here-documents.
+    fn expand_redirect(self: &Self, redir: &Redirect) -> Result<Redirect> {
+        let expand = |word: &Word| -> Result<Word> {
+            let value = expansion::expand(word, self)?;
+            Ok(Word {
+                value: value,
+                quoted: word.quoted,
+                expansions: Vec::new(),
+                splits: Vec::new(),
+                globs: Vec::new(),
+            })
+        };
+        match redir {
+            Redirect::Input(InputRedir::File(filename)) => {
+                Ok(Redirect::Input(InputRedir::File(expand(filename)?)))
+            }
+            Redirect::Input(InputRedir::HereString(word)) => {
+                Ok(Redirect::Input(InputRedir::HereString(expand(word)?)))
+            }
+            Redirect::Output(output) => {
+                let mut output = output.clone();
+                output.filename = expand(&output.filename)?;
+                Ok(Redirect::Output(output))
+            }
+            _ => Ok(redir.clone()),
+        }
+    }
+
+    /// Applies the redirections, from left to right, to the descriptors.
+    fn redirect(self: &Self, mut fds: FdTable, redirs: &VecDeque<Redirect>) -> Result<FdTable> {
+        for redir in redirs.iter() {
+            self.redirect_expanded(&mut fds, &self.expand_redirect(redir)?)?;
+        }
+        Ok(fds)
+    }
+
+    /// Applies a redirection, which expand_redirect has already expanded,
+    /// to the descriptors, so that we do not run the command substitutions
+    /// of its file name twice.
+    fn redirect_expanded(self: &Self, fds: &mut FdTable, redir: &Redirect) -> Result<()> {
+        match redir {
+            Redirect::Input(input) => {
+                let fd = Self::redirect_input(input)?;
+                fds.insert(0, fd);
+            }
+            Redirect::Output(output) => {
+                let fd = Self::redirect_output(output, self.noclobber)?;
+                fds.insert(output.fd, fd);
+            }
+            Redirect::Duplicate(dup) => {
+                let fd = fds.duplicate(dup.target)?;
+                fds.insert(dup.fd, fd);
+            }
+            Redirect::Close(fd) => {
+                // note: we cannot close the standard descriptors
+                // of the child, so we use the null device instead
+                let null = Self::null()?;
+                fds.insert(*fd, null);
+            }
+            Redirect::Combined(_) => {
+                return Err(Error::new("combined redirection was not translated"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the input redirection.
+    fn redirect_input(input: &InputRedir) -> Result<OwnedFd> {
+        match input {
+            InputRedir::File(filename) => match File::open(&filename.value) {
+                Err(err) => Err(Error::new(&err.to_string())),
+                Ok(filep) => Ok(OwnedFd::from(filep)),
+            },
+            InputRedir::HereDoc(heredoc) => Self::feed(heredoc.body.clone()),
+            InputRedir::HereString(word) => Self::feed(format!("{}\n", word.value)),
+        }
+    }
+
+    /// Returns the reading end of a pipe through which we feed the text.
+    fn feed(text: String) -> Result<OwnedFd> {
+        let (rx, mut wx) = Self::wrap_os_pipe()?;
+        // note: writing from a background thread because the text
+        // may not fit into the pipe buffer
+        std::thread::spawn(move || {
+            let _ = wx.write_all(text.as_bytes()); // ignore EPIPE
+        });
+        Ok(Self::owned(rx))
+    }
+
+    /// Creates the output redirection. With noclobber, we refuse to
+    /// overwrite existing regular files unless the redirection is forced.
+    fn redirect_output(output: &OutputRedir, noclobber: bool) -> Result<OwnedFd> {
+        let path = &output.filename.value;
+        if output.overwrite && noclobber && !output.force {
+            return Self::redirect_output_noclobber(path);
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create(true);
+        if !output.overwrite {
+            options.append(true);
+        } else {
+            options.truncate(true);
+        }
+        match options.open(path) {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok(filep) => Ok(OwnedFd::from(filep)),
+        }
+    }
+
+    /// Creates the output redirection with noclobber. To avoid races with
+    /// whoever is creating or replacing the file (e.g., with a symbolic
+    /// link), we do not check the file before opening it. Instead, we first
+    /// try to create a new file and, if the file exists, we open it without
+    /// truncating it and check whether what we have opened is a regular file.
+    fn redirect_output_noclobber(path: &str) -> Result<OwnedFd> {
+        loop {
+            let created = OpenOptions::new().write(true).create_new(true).open(path);
+            match created {
+                Ok(filep) => return Ok(OwnedFd::from(filep)),
+                Err(err) if err.kind() != std::io::ErrorKind::AlreadyExists => {
+                    return Err(Error::new(&err.to_string()));
+                }
+                Err(_) => (),
+            }
+            let exists = || Error::new(&format!("{}: cannot overwrite existing file", path));
+            let filep = match OpenOptions::new().write(true).open(path) {
+                // note: like other shells, refuse dangling symbolic links,
+                // otherwise the file was removed in the meanwhile
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    match std::fs::symlink_metadata(path) {
+                        Ok(meta) if meta.file_type().is_symlink() => return Err(exists()),
+                        _ => continue,
+                    }
+                }
+                Err(err) => return Err(Error::new(&err.to_string())),
+                Ok(filep) => filep,
+            };
+            return match filep.metadata() {
+                Err(err) => Err(Error::new(&err.to_string())),
+                Ok(meta) if meta.is_file() => Err(exists()),
+                Ok(_) => Ok(OwnedFd::from(filep)), // e.g., /dev/null
+            };
+        }
+    }
+
+    /// Opens the null device for reading and writing.
+    fn null() -> Result<OwnedFd> {
+        match OpenOptions::new().read(true).write(true).open("/dev/null") {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok(filep) => Ok(OwnedFd::from(filep)),
+        }
+    }
+
+    /// Converts a pipe end into an owned descriptor.
+    fn owned<T: IntoRawFd>(pipe: T) -> OwnedFd {
+        unsafe { OwnedFd::from_raw_fd(pipe.into_raw_fd()) }
+    }
+
+    /// Common code for executing a child process.
+    fn exec(
+        self: &Self,
+        group: &mut Group,
+        argv0: String,
+        mut args: VecDeque<String>,
+        env: Environment,
+        mut fds: FdTable,
+    ) -> Result<()> {
+        if argv0 == "pty" && !self.disabled.contains("pty") {
+            return self.exec_pty(group, args, env, fds);
+        }
+        if argv0 == "envfile" && !self.disabled.contains("envfile") {
+            return self.exec_envfile(group, args, env, fds);
+        }
+        if (argv0 == "ioprio" || argv0 == "affinity") && !self.disabled.contains(&argv0) {
+            return self.exec_sched(group, &argv0, args, env, fds);
+        }
+        if argv0 == "argv0" && !self.disabled.contains("argv0") {
+            return self.exec_zeroth(group, args, env, fds);
+        }
+        if argv0 == "buildinfo" && !self.disabled.contains("buildinfo") {
+            // note: inside pipelines, where builtins do not run, we ask the
+            // shell binary, whose `--version` prints the same information
+            Self::buildinfo_format(&args)?;
+            args.push_front(String::from("--version"));
+            return self.exec(group, translator::current_exe()?, args, env, fds);
+        }
+        if argv0 == "pwd" && !self.disabled.contains("pwd") && args.len() == 0 {
+            // note: inside pipelines, where builtins do not run, we ask
+            // pwd(1) for the logical directory, like the builtin does
+            args.push_back(String::from("-L"));
+        }
+        self.maybe_debug(&env, &argv0, &args);
+        // note: subshells, which run the current exe, inherit the variables
+        let subshell = translator::current_exe().is_ok_and(|exe| exe == argv0);
+        let mut cmd = self.command(&argv0);
+        if let Some(zeroth) = self.zeroth.take() {
+            cmd.arg0(zeroth.resolve(&argv0));
+        }
+        if subshell {
+            cmd.env(VARIABLES, self.export_variables());
+            self.xtrace.inherit(&mut cmd);
+        }
+        while args.len() > 0 {
+            let arg = args.pop_front().unwrap(); // cannot fail
+            cmd.arg(arg);
+        }
+        cmd.envs(env);
+        let transcribed = Self::transcribe(&mut fds)?;
+        fds.configure(&mut cmd)?;
+        self.jobs.prepare(&mut cmd, group.pgid());
+        if !subshell || !self.privatetmp {
+            let proc = self.spawn(&argv0, cmd)?;
+            group.add(Self::transcribed(proc, transcribed)?); // ensure we track the child
+            return Ok(());
+        }
+        let dir = privatetmp::create()?;
+        cmd.env("TMPDIR", &dir);
+        match self.spawn(&argv0, cmd) {
+            Err(err) => {
+                privatetmp::remove(&dir);
+                Err(err)
+            }
+            Ok(proc) => {
+                let proc = privatetmp::attach(proc, dir);
+                group.add(Self::transcribed(proc, transcribed)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Tells whether we're writing a transcript of the output that goes
+    /// to the shell's terminal (see transcript.rs).
+    fn transcribing() -> bool {
+        transcript::active() && pty::is_terminal(1)
+    }
+
+    /// Attaches the standard output and error of a command writing to the
+    /// shell's terminal to a pseudo-terminal, when we're writing a transcript,
+    /// returning the pseudo-terminal and the descriptor of the terminal.
+    fn transcribe(fds: &mut FdTable) -> Result<Option<(pty::Pty, OwnedFd)>> {
+        if fds.contains(1) || !Self::transcribing() {
+            return Ok(None);
+        }
+        let pty = pty::open()?;
+        let output = fds.duplicate(1)?;
+        for target in [1, 2] {
+            if fds.contains(target) {
+                continue; // e.g., `cmd 2>file`
+            }
+            match pty.slave.try_clone() {
+                Err(err) => return Err(Error::new(&err.to_string())),
+                Ok(fd) => fds.insert(target, fd),
+            }
+        }
+        Ok(Some((pty, output)))
+    }
+
+    /// Forwards and records the output of the process, if we attached it
+    /// to a pseudo-terminal in transcribe.
+    fn transcribed(
+        proc: Box<dyn Process>,
+        transcribed: Option<(pty::Pty, OwnedFd)>,
+    ) -> Result<Box<dyn Process>> {
+        match transcribed {
+            None => Ok(proc),
+            Some((pty, output)) => pty.attach(proc, None, output, true),
+        }
+    }
+
+    /// Creates the command executing the given program, which we look
+    /// up in the PATH through the spawn context.
+    fn command(self: &Self, argv0: &str) -> Command {
+        match self.spawnctx.borrow_mut().resolve(argv0) {
+            None => Command::new(argv0),
+            Some(program) => {
+                let mut cmd = Command::new(program);
+                cmd.arg0(argv0);
+                cmd
+            }
+        }
+    }
+
+    /// Spawns the given command, forgetting where we found the program
+    /// when spawning fails (e.g., because it has been removed).
+    fn spawn(self: &Self, argv0: &str, mut cmd: Command) -> Result<Box<dyn Process>> {
+        self.scheduling.borrow().apply(&mut cmd);
+        match self.spawner.spawn(cmd) {
+            Err(err) => {
+                self.spawnctx.borrow_mut().forget(argv0);
+                Err(err)
+            }
+            Ok(proc) => Ok(proc),
+        }
+    }
+
+    /// Executes a child process with the variables defined by the given
+    /// file added to its environment (see envfile.rs), where the
+    /// assignments preceding `envfile` win over the file.
+    fn exec_envfile(
+        self: &Self,
+        group: &mut Group,
+        mut args: VecDeque<String>,
+        env: Environment,
+        fds: FdTable,
+    ) -> Result<()> {
+        let (path, argv0) = match (args.pop_front(), args.pop_front()) {
+            (Some(path), Some(argv0)) => (path, argv0),
+            _ => return Err(Error::new("usage: envfile <file> <command> [args...]")),
+        };
+        let mut variables = envfile::load(&path)?;
+        variables.extend(env);
+        self.exec(group, argv0, args, variables, fds)
+    }
+
+    /// Executes a child process with the I/O priority or the CPU affinity
+    /// given by the `ioprio` or `affinity` prefix (see sched.rs), which
+    /// apply to the processes we spawn until the command is running.
+    fn exec_sched(
+        self: &Self,
+        group: &mut Group,
+        prefix: &str,
+        mut args: VecDeque<String>,
+        env: Environment,
+        fds: FdTable,
+    ) -> Result<()> {
+        let (value, argv0) = match (args.pop_front(), args.pop_front()) {
+            (Some(value), Some(argv0)) => (value, argv0),
+            _ if prefix == "ioprio" => {
+                return Err(Error::new(
+                    "usage: ioprio <class>[:<level>] <command> [args...]",
+                ))
+            }
+            _ => return Err(Error::new("usage: affinity <cpus> <command> [args...]")),
+        };
+        let mut scheduling = self.scheduling.borrow().clone();
+        match prefix {
+            "ioprio" => scheduling.set_ioprio(&value)?,
+            _ => scheduling.set_cpus(&value)?,
+        }
+        let saved = self.scheduling.replace(scheduling);
+        let result = self.exec(group, argv0, args, env, fds);
+        self.scheduling.replace(saved);
+        result
+    }
+
+    /// Executes a child process with the zeroth argument given by the
+    /// `argv0` prefix, which applies to the command only, rather than to
+    /// the processes it spawns.
+    fn exec_zeroth(
+        self: &Self,
+        group: &mut Group,
+        mut args: VecDeque<String>,
+        env: Environment,
+        fds: FdTable,
+    ) -> Result<()> {
+        let usage = "usage: argv0 [-l] [-a <name>] <command> [args...]";
+        let zeroth = Self::parse_zeroth(usage, &mut args)?;
+        let argv0 = match args.pop_front() {
+            None => return Err(Error::new(usage)),
+            Some(argv0) => argv0,
+        };
+        let saved = self.zeroth.replace(zeroth);
+        let result = self.exec(group, argv0, args, env, fds);
+        self.zeroth.replace(saved);
+        result
+    }
+
+    /// Executes a child process inside a pseudo-terminal (see pty.rs).
+    fn exec_pty(
+        self: &Self,
+        group: &mut Group,
+        mut args: VecDeque<String>,
+        env: Environment,
+        mut fds: FdTable,
+    ) -> Result<()> {
+        if args.len() < 1 {
+            return Err(Error::new("usage: pty <command> [args...]"));
+        }
+        let argv0 = args.pop_front().unwrap(); // cannot fail
+        let pty = pty::open()?;
+        let slave = || match pty.slave.try_clone() {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok(fd) => Ok(fd),
+        };
+        // note: the command inherits the shell's standard input, unless
+        // redirected, and the descriptors explicitly redirected but 0
+        // and 1 (e.g., `pty cmd 2>file`) are not attached to the pty
+        let input = fds.remove(0);
+        if input.is_some() {
+            fds.insert(0, slave()?);
+        }
+        let (output, record) = match fds.remove(1) {
+            Some(fd) => (fd, false),
+            None => (fds.duplicate(1)?, Self::transcribing()),
+        };
+        fds.insert(1, slave()?);
+        if !fds.contains(2) {
+            fds.insert(2, slave()?);
+        }
+        self.maybe_debug(&env, &argv0, &args);
+        let mut cmd = self.command(&argv0);
+        if let Some(zeroth) = self.zeroth.take() {
+            cmd.arg0(zeroth.resolve(&argv0));
+        }
+        cmd.args(args);
+        cmd.envs(env);
+        fds.configure(&mut cmd)?;
+        self.jobs.prepare(&mut cmd, group.pgid());
+        let proc = self.spawn(&argv0, cmd)?;
+        group.add(pty.attach(proc, input, output, record)?);
+        Ok(())
+    }
+
+    /// Possibly log to stderr the commands we're about to execute.
+    fn maybe_debug(self: &Self, env: &Environment, argv0: &str, args: &VecDeque<String>) {
+        if self.verbose {
+            let mut fenv = String::new();
+            for (name, value) in env.iter() {
+                fenv.push_str(&format!("{}={} ", name, value));
+            }
+            let mut farg = String::new();
+            for arg in args.iter() {
+                farg.push_str(arg);
+                farg.push(' ');
+            }
+            self.xtrace.write(&format!("+ {}{} {}", fenv, argv0, farg));
+        }
+    }
+
+    /// Creates the command running the given commands in a child shell,
+    /// which, like subshells, inherits `$?`, `$$` and the positional
+    /// parameters (e.g., for command substitutions).
+    fn child_shell(self: &Self, command: &str) -> Result<Command> {
+        let mut cmd = Command::new(translator::current_exe()?);
+        if self.verbose {
+            cmd.arg("-x");
+        }
+        self.xtrace.inherit(&mut cmd);
+        if self.noclobber {
+            cmd.arg("-C");
+        }
+        cmd.arg("--status").arg(self.status.to_string());
+        cmd.arg("--pid").arg(self.pid.to_string());
+        cmd.env(VARIABLES, self.export_variables());
+        cmd.arg("-c").arg(command).arg("--").arg(&self.arg0);
+        cmd.args(&self.positional);
+        Ok(cmd)
+    }
+
+    /// Wrapper to adapt os_pipe::pipe to our kind of Result
+    fn wrap_os_pipe() -> Result<(PipeReader, PipeWriter)> {
+        match pipe() {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok((rx, wx)) => Ok((rx, wx)),
+        }
+    }
+}
+
+impl expansion::Parameters for Interpreter {
+    fn lookup(self: &Self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "0" => Some(self.arg0.clone()),
+            "EPOCHSECONDS" | "EPOCHREALTIME" => {
+                // note: we read the clock only when these are expanded
+                let now = deterministic::now();
+                match name {
+                    "EPOCHSECONDS" => Some(now.as_secs().to_string()),
+                    _ => Some(format!("{}.{:06}", now.as_secs(), now.subsec_micros())),
+                }
+            }
+            "SECONDS" => {
+                let elapsed = deterministic::now().saturating_sub(self.started);
+                Some(elapsed.as_secs().to_string())
+            }
+            "RANDOM" => {
+                // note: like sh, a number between 0 and 32767 (from an LCG)
+                let state = self
+                    .random
+                    .get()
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                self.random.set(state);
+                Some(((state >> 33) & 0x7fff).to_string())
+            }
+            _ => self.variable(name),
+        }
+    }
+
+    fn positional(self: &Self) -> &[String] {
+        &self.positional
+    }
+
+    fn assign(self: &Self, name: &str, value: &str) -> Result<()> {
+        match name.chars().next() {
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                self.set_variable(name, value);
+                Ok(())
+            }
+            _ => Err(Error::new(&format!("{}: cannot assign in this way", name))),
+        }
+    }
+    fn option(self: &Self, name: &str) -> bool {
+        match name {
+            "nocaseglob" => self.nocaseglob,
+            "keepnewlines" => self.keepnewlines,
+            "exitstats" => self.exitstats,
+            "privatetmp" => self.privatetmp,
+            "errexit" => self.errexit,
+            "strict-errexit" => self.strict_errexit,
+            _ => false,
+        }
+    }
+
+    fn substitute(self: &Self, command: &str) -> Result<String> {
+        let mut cmd = self.child_shell(command)?;
+        let (mut rx, wx) = Self::wrap_os_pipe()?;
+        cmd.stdout(wx);
+        // note: spawn consumes cmd, closing our copy of the write end
+        let mut proc = self.spawner.spawn(cmd)?;
+        let mut output = Vec::<u8>::new();
+        let result = rx.read_to_end(&mut output);
+        let _ = proc.wait();
+        if let Err(err) = result {
+            return Err(Error::new(&err.to_string()));
+        }
+        // note: like NUL bytes (see expansion.rs), we reject the output
+        // rather than silently replacing the invalid sequences
+        match String::from_utf8(output) {
+            Err(_) => Err(Error::new(&format!(
+                "$({}): the output is not valid UTF-8",
+                command
+            ))),
+            Ok(output) => Ok(output),
+        }
+    }
+}
+
+/// A command of a pipeline that is ready to spawn.
+struct Prepared {
+    argv0: String,
+    arguments: VecDeque<String>,
+    env: Environment,
+    fds: FdTable,
+}
+
+impl Prepared {
+    /// Returns the command line of the command.
+    fn command_line(self: &Self) -> String {
+        let mut words = vec![self.argv0.as_str()];
+        words.extend(self.arguments.iter().map(|arg| arg.as_str()));
+        words.join(" ")
+    }
+}
+
+/// How to override the zeroth argument of a command, which `exec` and
+/// `argv0` set through their `-a` and `-l` options.
+struct Zeroth {
+    /// The name to use instead of the command name, if any.
+    name: Option<String>,
+
+    /// Whether to prepend a dash, which tells shells to act as login shells.
+    login: bool,
+}
+
+impl Zeroth {
+    /// Returns the zeroth argument of the given command.
+    fn resolve(self: &Self, argv0: &str) -> String {
+        let name = self.name.as_deref().unwrap_or(argv0);
+        match self.login {
+            false => String::from(name),
+            true => format!("-{}", name),
+        }
+    }
+}
+
+/// Applies the redirections of a builtin to the shell's descriptors,
+/// restoring the previous descriptors when dropped.
+struct Redirected {
+    saved: Vec<(i32, Option<OwnedFd>)>,
+}
+
+impl Redirected {
+    /// Makes the descriptors of the shell refer to the given descriptors.
+    fn new(fds: FdTable) -> Result<Redirected> {
+        let saved = Interpreter::replace_shell_fds(fds)?;
+        Ok(Redirected { saved: saved })
+    }
+}
+
+impl Drop for Redirected {
+    fn drop(self: &mut Self) {
+        Interpreter::restore_shell_fds(std::mem::take(&mut self.saved));
+    }
+}
+
+/// Adds the assignments preceding a builtin (e.g., `X=1 printf ...`) to
+/// the shell's environment, restoring the previous values when dropped.
+struct Exported {
+    saved: Vec<(String, Option<std::ffi::OsString>)>,
+}
+
+impl Exported {
+    /// Adds the given variables to the environment.
+    fn new(env: &Environment) -> Exported {
+        let mut saved = Vec::new();
+        for (name, value) in env.iter() {
+            saved.push((name.clone(), std::env::var_os(name)));
+            std::env::set_var(name, value);
+        }
+        Exported { saved: saved }
+    }
+}
+
+impl Drop for Exported {
+    fn drop(self: &mut Self) {
+        // note: restore in reverse order in case a name is repeated
+        while let Some((name, value)) = self.saved.pop() {
+            match value {
+                None => std::env::remove_var(name),
+                Some(value) => std::env::set_var(name, value),
+            }
         }
     }
 }
@@ -0,0 +1,127 @@
+//! Job control.
+//!
+//! When the monitor mode is enabled, each pipeline runs inside its own
+//! process group and the shell hands the terminal over to the process
+//! group of the foreground pipeline while waiting for it. The shell
+//! itself ignores the terminal-generated signals, so that, e.g., `^C`
+//! only interrupts the foreground pipeline.
+//!
+//! The monitor mode requires a controlling terminal, hence we never
+//! enable it automatically when running with `-c`, when running a
+//! script, or when the standard input is not a terminal.
+
+use crate::model::{Error, Result};
+use crate::process;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Signals ignored by the shell when the monitor mode is enabled.
+const SIGNALS: [libc::c_int; 5] = [
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+/// Signals reset to their default in children. We do not include
+/// SIGTSTP here, because we cannot yet resume stopped jobs and a
+/// stopped foreground job would therefore block the shell.
+const CHILD_SIGNALS: [libc::c_int; 4] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTTIN, libc::SIGTTOU];
+
+/// Manages job control.
+pub struct JobControl {
+    /// Whether the monitor mode is enabled.
+    enabled: bool,
+
+    /// The process group of the shell.
+    shell_pgid: libc::pid_t,
+}
+
+impl JobControl {
+    /// Creates a new instance with the monitor mode disabled.
+    pub fn new() -> JobControl {
+        JobControl {
+            enabled: false,
+            shell_pgid: 0,
+        }
+    }
+
+    /// Tells whether the monitor mode is enabled.
+    pub fn enabled(self: &Self) -> bool {
+        self.enabled
+    }
+
+    /// Enables the monitor mode, which fails when the standard input is
+    /// not a terminal or the shell is not in the foreground.
+    pub fn enable(self: &mut Self) -> Result<()> {
+        if self.enabled {
+            return Ok(());
+        }
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            return Err(Error::new("no job control: stdin is not a terminal"));
+        }
+        let pgid = unsafe { libc::getpgrp() };
+        if unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) } != pgid {
+            return Err(Error::new("no job control: shell is not in the foreground"));
+        }
+        for sig in SIGNALS.iter() {
+            unsafe { libc::signal(*sig, libc::SIG_IGN) };
+        }
+        unsafe {
+            // note: this fails for session leaders, which are already
+            // the leaders of their own process group
+            libc::setpgid(0, 0);
+            self.shell_pgid = libc::getpgrp();
+            libc::tcsetpgrp(libc::STDIN_FILENO, self.shell_pgid);
+        }
+        self.enabled = true;
+        Ok(())
+    }
+
+    /// Disables the monitor mode.
+    pub fn disable(self: &mut Self) {
+        if !self.enabled {
+            return;
+        }
+        for sig in SIGNALS.iter() {
+            unsafe { libc::signal(*sig, libc::SIG_DFL) };
+        }
+        self.enabled = false;
+    }
+
+    /// Prepares a command to run inside the given process group or inside
+    /// a new process group when the pgid is None. This is a no-op unless
+    /// the monitor mode or the init mode is enabled.
+    pub fn prepare(self: &Self, cmd: &mut Command, pgid: Option<u32>) {
+        if !self.enabled && !process::init_mode() {
+            return;
+        }
+        cmd.process_group(pgid.unwrap_or(0) as i32);
+        if !self.enabled {
+            return;
+        }
+        unsafe {
+            cmd.pre_exec(|| {
+                for sig in CHILD_SIGNALS.iter() {
+                    libc::signal(*sig, libc::SIG_DFL);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Gives the terminal to the given process group.
+    pub fn foreground(self: &Self, pgid: Option<u32>) {
+        if let (true, Some(pgid)) = (self.enabled, pgid) {
+            unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid as libc::pid_t) };
+        }
+    }
+
+    /// Gives the terminal back to the shell.
+    pub fn reclaim(self: &Self) {
+        if self.enabled {
+            unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, self.shell_pgid) };
+        }
+    }
+}
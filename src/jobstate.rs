@@ -0,0 +1,110 @@
+//! Samples the state of the background jobs for the prompt.
+//!
+//! Rendering the prompt must never wait for the jobs: with many background
+//! processes, or when reading `/proc` is slow, checking each of them before
+//! each prompt would delay the prompt and the echo of what the user types.
+//! Hence, after reaping the background processes that have terminated,
+//! which does not block, the shell sends the pids of the remaining ones to
+//! a thread that checks which of them are still running (i.e., they exist
+//! and are not zombies) and publishes the count into an atomic cell, which
+//! the prompt reads without blocking. The thread samples again whenever the
+//! pids change and every INTERVAL, so that the count reflects the jobs
+//! that terminated while the shell was waiting for input. Each time the pids
+//! change, we tag them with a new generation, and the thread publishes the
+//! count only if no newer pids have arrived in the meanwhile, so that a
+//! sample taken before the last change cannot overwrite its count.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often the thread samples the processes.
+const INTERVAL: Duration = Duration::from_millis(500);
+
+/// The number of running background processes we sampled last.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// The pids to sample, along with their generation.
+type Sample = (u64, Vec<u32>);
+
+/// The channel to send the pids to the thread, once started.
+static SAMPLER: Mutex<Option<Sender<Sample>>> = Mutex::new(None);
+
+/// The pids we sent to the thread last, along with their generation.
+struct Published {
+    generation: u64,
+    pids: Vec<u32>,
+}
+
+/// The pids we sent to the thread last.
+static PUBLISHED: Mutex<Published> = Mutex::new(Published {
+    generation: 0,
+    pids: Vec::new(),
+});
+
+/// Publishes the pids of the background processes that the shell did not
+/// reap yet, starting the thread on first use. When the pids change, we
+/// count all of them as running until the thread samples them, so that a
+/// job started by the last command counts, while otherwise we keep the
+/// count that the thread sampled.
+pub fn publish<I: IntoIterator<Item = u32>>(pids: I) {
+    let mut pids: Vec<u32> = pids.into_iter().collect();
+    pids.sort();
+    let mut sampler = SAMPLER.lock().unwrap();
+    let tx = sampler.get_or_insert_with(|| {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || sample(rx));
+        tx
+    });
+    let mut published = PUBLISHED.lock().unwrap();
+    if published.pids == pids {
+        return;
+    }
+    published.generation += 1;
+    RUNNING.store(pids.len(), Ordering::Relaxed);
+    published.pids = pids.clone();
+    let _ = tx.send((published.generation, pids)); // the thread never exits
+}
+
+/// Returns the number of running background processes we sampled last.
+pub fn running() -> usize {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Samples the latest pids we received until the shell exits.
+fn sample(rx: mpsc::Receiver<Sample>) {
+    let (mut generation, mut pids) = (0, Vec::<u32>::new());
+    loop {
+        match rx.recv_timeout(INTERVAL) {
+            Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => (),
+            Ok(latest) => (generation, pids) = latest,
+        }
+        // note: only the latest pids matter when several are pending
+        while let Ok(latest) = rx.try_recv() {
+            (generation, pids) = latest;
+        }
+        let count = pids.iter().filter(|pid| is_running(**pid)).count();
+        // note: publish changes the generation and the count while
+        // holding the lock, hence they cannot change between our check
+        // and our store, while a stale count is discarded
+        let published = PUBLISHED.lock().unwrap();
+        if published.generation == generation {
+            RUNNING.store(count, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Tells whether the given process exists and is not a zombie.
+fn is_running(pid: u32) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // note: the command name may contain spaces, hence we split after it
+        Ok(stat) => match stat.rsplit_once(')') {
+            None => true,
+            Some((_, fields)) => !matches!(fields.split_whitespace().next(), Some("Z" | "X")),
+        },
+        // note: without `/proc`, we cannot tell zombies apart
+        Err(_) => unsafe { libc::kill(pid as libc::pid_t, 0) == 0 },
+    }
+}
@@ -1,5 +1,6 @@
 //! Scanner implementation.
 
+use crate::model::{Error, Result};
 use std::collections::VecDeque;
 
 /// Kind of a scanned token.
@@ -11,9 +12,18 @@ pub enum Kind {
     Semicolon,
     Ampersand,
     Minor,
+    MinorMinor,
+    MinorMinorDash,
+    MinorMinorMinor,
+    MinorAmpersand,
     Major,
     MajorMajor,
+    MajorAmpersand,
+    MajorPipe,
+    AmpersandMajor,
+    AmpersandMajorMajor,
     CommandOrArgument,
+    Newline,
     EndOfLine,
 }
 
@@ -25,31 +35,90 @@ pub struct Token {
 
     /// The token's value.
     pub value: String,
+
+    /// Whether any part of a CommandOrArgument token was quoted.
+    pub quoted: bool,
+
+    /// The offsets in chars, inside the value, of the `$` signs that
+    /// start an expansion, i.e., those neither single-quoted nor escaped,
+    /// and of the unquoted `~` starting the token, if any.
+    pub expansions: Vec<usize>,
+
+    /// The offsets of the expansions that were not inside double quotes,
+    /// whose result is subject to field splitting.
+    pub splits: Vec<usize>,
+
+    /// The offsets of the unquoted `*`, `?` and `[` chars, which are
+    /// pattern chars subject to pathname expansion (e.g., `*.rs`).
+    pub globs: Vec<usize>,
+
+    /// Whether the token starts with an unquoted name followed by `=`
+    /// (e.g., `RUST_LOG=debug`), so that it may be an assignment.
+    pub assignment: bool,
+
+    /// The offset in chars of the token inside the input.
+    pub offset: usize,
 }
 
-/// Scans the command line.
-pub fn scan(cmdline: String) -> VecDeque<Token> {
-    let mut lexer = Lexer::new(cmdline);
-    lexer.run();
-    lexer.r
+/// Scans the command line, which may contain multiple lines. We read the
+/// bodies of here-documents from the lines following the redirection and,
+/// when we run out of input, we use the given function to read more lines.
+pub fn scan(cmdline: String, more: &mut dyn FnMut() -> Option<String>) -> Result<VecDeque<Token>> {
+    let mut lexer = Lexer::new(cmdline, more);
+    lexer.run()?;
+    Ok(lexer.r)
 }
 
 /// Lexer for the command line.
-struct Lexer {
+struct Lexer<'a> {
     /// buffer for constructing CommandOrArgument tokens.
     buff: String,
 
     /// whether we're inside a CommandOrArgment token.
     inside: bool,
 
+    /// whether the current CommandOrArgument token contains quotes.
+    quoted: bool,
+
+    /// offsets of the expansions inside the current CommandOrArgument token.
+    expansions: Vec<usize>,
+
+    /// offsets of the unquoted expansions inside the current token.
+    splits: Vec<usize>,
+
+    /// offsets of the unquoted pattern chars inside the current token.
+    globs: Vec<usize>,
+
+    /// whether the current token starts with an unquoted `NAME=`.
+    assignment: bool,
+
+    /// number of unquoted `${` expansions we're inside of, within which
+    /// blanks and operators do not end the token (e.g., `${X:-a b}`).
+    braces: usize,
+
     /// input contains the input.
     input: VecDeque<char>,
 
+    /// length of the original input in chars.
+    length: usize,
+
+    /// offset of the char we're currently processing.
+    current: usize,
+
+    /// offset where the current CommandOrArgument token starts.
+    start: usize,
+
     /// contains the stream of tokens.
     r: VecDeque<Token>,
+
+    /// indexes of the here-document tokens whose body we need to read.
+    heredocs: VecDeque<usize>,
+
+    /// function to read more lines of input.
+    more: &'a mut dyn FnMut() -> Option<String>,
 }
 
-impl Lexer {
+impl<'a> Lexer<'a> {
     /// converts the input to a deque.
     fn to_deque(input: String) -> VecDeque<char> {
         let mut r = VecDeque::<char>::new();
@@ -60,20 +129,33 @@ impl Lexer {
     }
 
     /// creates a new lexer instance.
-    fn new(input: String) -> Lexer {
+    fn new(input: String, more: &'a mut dyn FnMut() -> Option<String>) -> Lexer<'a> {
+        let input = Self::to_deque(input);
         Lexer {
             buff: String::new(),
             inside: false,
-            input: Self::to_deque(input),
+            quoted: false,
+            expansions: Vec::new(),
+            splits: Vec::new(),
+            globs: Vec::new(),
+            assignment: false,
+            braces: 0,
+            length: input.len(),
+            current: 0,
+            start: 0,
+            input: input,
             r: VecDeque::<Token>::new(),
+            heredocs: VecDeque::<usize>::new(),
+            more: more,
         }
     }
 
     /// runs the scanner.
-    fn run(self: &mut Self) {
+    fn run(self: &mut Self) -> Result<()> {
         loop {
+            self.current = self.offset();
             if let Some(c) = self.read() {
-                let end_of_line = self.process_current(c);
+                let end_of_line = self.process_current(c)?;
                 if end_of_line {
                     break;
                 }
@@ -81,16 +163,35 @@ impl Lexer {
                 break;
             }
         }
+        if self.braces > 0 {
+            return Err(Error::incomplete("unterminated '${'", self.start));
+        }
+        self.leave();
+        self.read_heredocs()?;
+        self.current = self.offset();
         self.leave_and_push_back(Kind::EndOfLine);
+        Ok(())
     }
 
     /// processes the current char of the input stream and, if needed,
     /// also processes subsequent chars. Returns true if we've
     /// reached the end of the input, false otherwise.
-    fn process_current(self: &mut Self, c: char) -> bool {
+    fn process_current(self: &mut Self, c: char) -> Result<bool> {
         let mut at_eol = false;
-        if c == ' ' || c == '\t' {
+        if c == '\0' {
+            return Err(Error::with_offset("unexpected NUL character", self.current));
+        } else if self.braces > 0 && !"'\"\\$`".contains(c) {
+            if c == '}' {
+                self.braces -= 1;
+            }
+            self.enter_or_persist(c);
+        } else if c == ' ' || c == '\t' {
             self.leave();
+        } else if c == '\n' {
+            self.leave_and_push_back(Kind::Newline);
+            self.read_heredocs()?;
+        } else if c == '#' && !self.inside {
+            self.comment();
         } else if c == '|' {
             self.leave_and_push_back(Kind::Pipe);
         } else if c == '(' {
@@ -100,32 +201,341 @@ impl Lexer {
         } else if c == ';' {
             self.leave_and_push_back(Kind::Semicolon);
         } else if c == '&' {
-            self.leave_and_push_back(Kind::Ampersand);
+            match self.read() {
+                Some('>') => match self.read() {
+                    Some('>') => self.leave_and_push_back(Kind::AmpersandMajorMajor),
+                    Some(c) => {
+                        self.leave_and_push_back(Kind::AmpersandMajor);
+                        self.unread(c);
+                    }
+                    None => {
+                        self.leave_and_push_back(Kind::AmpersandMajor);
+                        at_eol = true;
+                    }
+                },
+                Some(c) => {
+                    self.leave_and_push_back(Kind::Ampersand);
+                    self.unread(c);
+                }
+                None => {
+                    self.leave_and_push_back(Kind::Ampersand);
+                    at_eol = true;
+                }
+            }
         } else if c == '<' {
-            self.leave_and_push_back(Kind::Minor);
+            match self.read() {
+                Some('<') => match self.read() {
+                    Some('-') => {
+                        self.leave_and_push_back(Kind::MinorMinorDash);
+                        self.heredocs.push_back(self.r.len() - 1);
+                    }
+                    Some('<') => self.leave_and_push_back(Kind::MinorMinorMinor),
+                    Some(c) => {
+                        self.leave_and_push_back(Kind::MinorMinor);
+                        self.heredocs.push_back(self.r.len() - 1);
+                        self.unread(c);
+                    }
+                    None => {
+                        self.leave_and_push_back(Kind::MinorMinor);
+                        self.heredocs.push_back(self.r.len() - 1);
+                        at_eol = true;
+                    }
+                },
+                Some('&') => {
+                    let fd = self.io_number();
+                    self.leave_and_push_back_string(Kind::MinorAmpersand, fd);
+                }
+                Some(c) => {
+                    self.leave_and_push_back(Kind::Minor);
+                    self.unread(c);
+                }
+                None => {
+                    self.leave_and_push_back(Kind::Minor);
+                    at_eol = true;
+                }
+            }
         } else if c == '>' {
-            if let Some(c) = self.read() {
-                if c == '>' {
-                    self.leave_and_push_back(Kind::MajorMajor);
-                } else {
-                    self.leave_and_push_back(Kind::Major);
-                    self.unread(c)
+            // note: the value of output redirection tokens is the file
+            // descriptor number preceding them, if any (e.g., `2>`), and
+            // the same holds for the `<&` token above
+            let fd = self.io_number();
+            match self.read() {
+                Some('>') => self.leave_and_push_back_string(Kind::MajorMajor, fd),
+                Some('&') => self.leave_and_push_back_string(Kind::MajorAmpersand, fd),
+                Some('|') => self.leave_and_push_back_string(Kind::MajorPipe, fd),
+                Some(c) => {
+                    self.leave_and_push_back_string(Kind::Major, fd);
+                    self.unread(c);
+                }
+                None => {
+                    self.leave_and_push_back_string(Kind::Major, fd);
+                    at_eol = true;
                 }
-            } else {
-                self.leave_and_push_back(Kind::Major);
-                at_eol = true;
             }
+        } else if c == '\'' {
+            self.single_quoted()?;
+        } else if c == '"' {
+            self.double_quoted()?;
         } else if c == '\\' {
-            if let Some(c) = self.read() {
-                self.enter_or_persist(c);
-            } else {
-                self.leave();
-                at_eol = true;
+            at_eol = self.escaped();
+        } else if c == '$' {
+            self.expansion(true)?;
+        } else if c == '`' {
+            self.backquoted(true)?;
+        } else if "*?[".contains(c) {
+            // note: the `?` and `*` of `$?` and `$*` are parameter names
+            let offset = self.buff.chars().count();
+            if c == '[' || offset == 0 || self.expansions.last() != Some(&(offset - 1)) {
+                self.globs.push(offset);
             }
+            self.enter_or_persist(c);
+        } else if c == '=' && self.inside && !self.quoted && is_name(&self.buff) {
+            // note: is_name fails after any expansion, since it contains `$`
+            self.assignment = true;
+            self.buff.push(c);
+        } else if c == '~' && !self.inside {
+            // note: only an unquoted `~` starting a word is a tilde expansion
+            self.enter();
+            self.expansions.push(0);
+            self.buff.push(c);
         } else {
             self.enter_or_persist(c);
         }
-        return at_eol;
+        Ok(at_eol)
+    }
+
+    /// reads the bodies of the pending here-documents, which start at the
+    /// beginning of the next line. The body of each here-document becomes
+    /// the value of the corresponding MinorMinor or MinorMinorDash token,
+    /// which should be followed by the delimiter. With MinorMinorDash, we
+    /// strip the leading tabs of each line.
+    fn read_heredocs(self: &mut Self) -> Result<()> {
+        while let Some(index) = self.heredocs.pop_front() {
+            let strip_tabs = matches!(self.r[index].kind, Kind::MinorMinorDash);
+            let delimiter = match self.r.get(index + 1) {
+                Some(token) if matches!(token.kind, Kind::CommandOrArgument) => token.value.clone(),
+                _ => continue, // the parser will complain
+            };
+            let mut body = String::new();
+            loop {
+                match self.read_line() {
+                    None => {
+                        return Err(Error::with_offset(
+                            "unterminated here-document",
+                            self.r[index].offset,
+                        ))
+                    }
+                    Some(line) => {
+                        let line = if strip_tabs {
+                            line.trim_start_matches('\t')
+                        } else {
+                            &line
+                        };
+                        if line == delimiter {
+                            break;
+                        }
+                        body.push_str(line);
+                        body.push('\n');
+                    }
+                }
+            }
+            self.r[index].value = body;
+        }
+        Ok(())
+    }
+
+    /// reads the next line of input without the trailing newline,
+    /// using the more function when we've run out of input.
+    fn read_line(self: &mut Self) -> Option<String> {
+        if self.input.len() == 0 {
+            return (self.more)();
+        }
+        let mut line = String::new();
+        while let Some(c) = self.read() {
+            if c == '\n' {
+                break;
+            }
+            line.push(c);
+        }
+        Some(line)
+    }
+
+    /// checks whether the current CommandOrArgument token is an unquoted
+    /// number immediately preceding a `>` or `<&`, in which case the token
+    /// is the file descriptor of the redirection (e.g., `2>`). When
+    /// this happens, we discard the token, move the current offset back
+    /// to where the redirection starts and return the number.
+    fn io_number(self: &mut Self) -> String {
+        if !self.inside || self.quoted || !self.buff.chars().all(|c| c.is_ascii_digit()) {
+            return String::new();
+        }
+        let fd = self.buff.clone();
+        self.buff.clear();
+        self.inside = false;
+        self.current = self.start;
+        fd
+    }
+
+    /// skips a comment whose `#` we have already consumed. The comment
+    /// extends until the end of the line, excluding the newline.
+    fn comment(self: &mut Self) {
+        while let Some(c) = self.read() {
+            if c == '\n' {
+                self.unread(c);
+                break;
+            }
+        }
+    }
+
+    /// scans a single-quoted string whose opening quote we have
+    /// already consumed. All the chars until the closing quote
+    /// become part of the current CommandOrArgument token.
+    fn single_quoted(self: &mut Self) -> Result<()> {
+        let start = self.current;
+        self.enter_quoted();
+        loop {
+            match self.read() {
+                None => return Err(Error::incomplete("unterminated single quote", start)),
+                Some('\'') => return Ok(()),
+                Some(c) => self.enter_or_persist(c),
+            }
+        }
+    }
+
+    /// scans the char following a backslash, which becomes part of the
+    /// current CommandOrArgument token regardless of its meaning (e.g.,
+    /// `foo\ bar`, `\>`, `\\`). A trailing backslash is a literal
+    /// backslash. Returns true if we've reached the end of the input.
+    fn escaped(self: &mut Self) -> bool {
+        self.quoted = true;
+        match self.read() {
+            None => {
+                self.enter_or_persist('\\');
+                true
+            }
+            Some(c) => {
+                self.enter_or_persist(c);
+                false
+            }
+        }
+    }
+
+    /// scans a double-quoted string whose opening quote we have
+    /// already consumed. Like single-quoted strings, except that
+    /// the backslash escapes the `"`, `$` and `\\` chars and that
+    /// unescaped `$` signs start an expansion.
+    fn double_quoted(self: &mut Self) -> Result<()> {
+        let start = self.current;
+        self.enter_quoted();
+        loop {
+            match self.read() {
+                None => return Err(Error::incomplete("unterminated double quote", start)),
+                Some('"') => return Ok(()),
+                Some('\\') => match self.read() {
+                    None => return Err(Error::incomplete("unterminated double quote", start)),
+                    Some(c) if "\"$`\\".contains(c) => self.enter_or_persist(c),
+                    Some(c) => {
+                        self.enter_or_persist('\\');
+                        self.enter_or_persist(c);
+                    }
+                },
+                Some('$') => self.expansion(false)?,
+                Some('`') => self.backquoted(false)?,
+                Some(c) => self.enter_or_persist(c),
+            }
+        }
+    }
+
+    /// appends a `$` sign to the current CommandOrArgument token
+    /// remembering that it starts an expansion (e.g., `$HOME`), which
+    /// is subject to field splitting when unquoted. For command
+    /// substitutions (e.g., `$(date)`), we also append the command.
+    fn expansion(self: &mut Self, unquoted: bool) -> Result<()> {
+        self.enter();
+        let offset = self.buff.chars().count();
+        self.expansions.push(offset);
+        if unquoted {
+            self.splits.push(offset);
+        }
+        self.buff.push('$');
+        match self.input.front() {
+            Some('(') => {
+                self.read();
+                self.command_substitution()
+            }
+            Some('{') if unquoted => {
+                self.read();
+                self.braces += 1;
+                self.buff.push('{');
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// scans a command substitution whose `$(` we have already consumed,
+    /// appending the command, verbatim, and the closing `)` to the current
+    /// CommandOrArgument token. We find the closing `)` using the rules
+    /// of substitution_end, so that the command may contain quotes and
+    /// parentheses (e.g., `$(echo ')')`).
+    fn command_substitution(self: &mut Self) -> Result<()> {
+        let start = self.current;
+        let mut text = Vec::<char>::new();
+        loop {
+            match self.read() {
+                None => return Err(Error::incomplete("unterminated '$('", start)),
+                Some(c) => {
+                    text.push(c);
+                    if c == ')' && substitution_end(&text) == Some(text.len() - 1) {
+                        break;
+                    }
+                }
+            }
+        }
+        self.buff.push('(');
+        self.buff.extend(text);
+        Ok(())
+    }
+
+    /// scans a backquoted command substitution (e.g., `` `date` ``) whose
+    /// opening backquote we have already consumed. Inside backquotes, the
+    /// backslash escapes `$`, `` ` `` and `\`, as well as `"` when the
+    /// backquotes are inside double quotes. We append the command to
+    /// the current CommandOrArgument token as `$(command)`.
+    fn backquoted(self: &mut Self, unquoted: bool) -> Result<()> {
+        let start = self.current;
+        self.enter();
+        let offset = self.buff.chars().count();
+        self.expansions.push(offset);
+        if unquoted {
+            self.splits.push(offset);
+        }
+        self.buff.push_str("$(");
+        loop {
+            match self.read() {
+                None => return Err(Error::incomplete("unterminated '`'", start)),
+                Some('`') => break,
+                Some('\\') => match self.read() {
+                    None => return Err(Error::incomplete("unterminated '`'", start)),
+                    Some(c) if "$`\\".contains(c) || (c == '"' && !unquoted) => self.buff.push(c),
+                    Some(c) => {
+                        self.buff.push('\\');
+                        self.buff.push(c);
+                    }
+                },
+                Some(c) => self.buff.push(c),
+            }
+        }
+        self.buff.push(')');
+        Ok(())
+    }
+
+    /// enters or continues to be inside a CommandOrArgument token
+    /// remembering that the token contains quotes. Because of this,
+    /// an empty quoted string (e.g., `''`) is an empty argument.
+    fn enter_quoted(self: &mut Self) {
+        self.enter();
+        self.quoted = true;
     }
 
     /// read returns the next character in input or None on EOL.
@@ -133,6 +543,11 @@ impl Lexer {
         self.input.pop_front()
     }
 
+    /// offset returns the offset of the next char in input.
+    fn offset(self: &Self) -> usize {
+        self.length - self.input.len()
+    }
+
     /// unread puts a character back into the input stream.
     fn unread(self: &mut Self, c: char) {
         self.input.push_front(c);
@@ -141,10 +556,19 @@ impl Lexer {
     /// enters or continues to be inside a CommandOrArgument token
     /// and appends the current char to the token's value.
     fn enter_or_persist(self: &mut Self, c: char) {
-        self.inside = true;
+        self.enter();
         self.buff.push(c);
     }
 
+    /// enters or continues to be inside a CommandOrArgument token
+    /// remembering where the token starts when entering.
+    fn enter(self: &mut Self) {
+        if !self.inside {
+            self.start = self.current;
+        }
+        self.inside = true;
+    }
+
     /// possibly leaves the current token and then pushes back
     /// the given token into the token stream.
     fn leave_and_push_back(self: &mut Self, kind: Kind) {
@@ -158,19 +582,73 @@ impl Lexer {
         self.r.push_back(Token {
             kind: kind,
             value: value,
+            quoted: false,
+            expansions: Vec::new(),
+            splits: Vec::new(),
+            globs: Vec::new(),
+            assignment: false,
+            offset: self.current,
         });
     }
 
     /// called when we stop being inside a CommandOrArgument to
     /// gracefully leave the CommandOrArgument state.
-    fn leave(self: &mut Self) -> () {
-        self.inside = false;
-        if self.buff != "" {
+    fn leave(self: &mut Self) {
+        if self.inside {
             self.r.push_back(Token {
                 kind: Kind::CommandOrArgument,
                 value: self.buff.clone(),
+                quoted: self.quoted,
+                expansions: std::mem::take(&mut self.expansions),
+                splits: std::mem::take(&mut self.splits),
+                globs: std::mem::take(&mut self.globs),
+                assignment: std::mem::take(&mut self.assignment),
+                offset: self.start,
             });
             self.buff.clear();
         }
+        self.inside = false;
+        self.quoted = false;
+    }
+}
+
+/// Tells whether the string is a valid variable name.
+pub fn is_name(value: &str) -> bool {
+    let mut chars = value.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Returns the index of the `)` closing a command substitution, given
+/// the chars following its `$(`, skipping nested parentheses, quoted
+/// strings and escaped chars. Returns None if there's no such `)`.
+pub fn substitution_end(chars: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '\'' => {
+                index += 1;
+                while index < chars.len() && chars[index] != '\'' {
+                    index += 1;
+                }
+            }
+            '"' => {
+                index += 1;
+                while index < chars.len() && chars[index] != '"' {
+                    if chars[index] == '\\' {
+                        index += 1;
+                    }
+                    index += 1;
+                }
+            }
+            '(' => depth += 1,
+            ')' if depth <= 0 => return Some(index),
+            ')' => depth -= 1,
+            _ => (),
+        }
+        index += 1;
     }
+    None
 }
@@ -0,0 +1,131 @@
+//! Locale-aware collation and character classes.
+//!
+//! Pathname expansion sorts the matching pathnames according to the
+//! collation order of the locale selected by `LC_ALL`, `LC_COLLATE` or
+//! `LANG`, and decides which chars belong to the character classes (e.g.,
+//! `[[:alpha:]]`) and how to ignore case according to `LC_ALL`, `LC_CTYPE`
+//! or `LANG`. We query the C library each time, so that changing these
+//! variables (e.g., `export LC_COLLATE=C`) takes effect immediately.
+//!
+//! When the locale is `C` or `POSIX`, or when the C library does not have
+//! data for the selected locale, we fall back to sorting by bytes and to
+//! ASCII-only classes and case folding. Likewise, we consider non-ASCII
+//! chars only with UTF-8 locales.
+
+use std::cmp::Ordering;
+use std::ffi::{CStr, CString};
+
+/// The collation and character type rules of the current locale.
+pub struct Locale {
+    /// Whether we should use strcoll rather than comparing bytes.
+    collate: bool,
+
+    /// Whether the character type rules cover the non-ASCII chars.
+    unicode: bool,
+}
+
+/// Returns the rules of the locale selected by the environment.
+pub fn current() -> Locale {
+    let collate = match select(libc::LC_COLLATE) {
+        Some(name) => name != "C" && name != "POSIX",
+        None => false,
+    };
+    let unicode = match select(libc::LC_CTYPE) {
+        Some(name) => {
+            let name = name.to_lowercase();
+            name.contains("utf-8") || name.contains("utf8")
+        }
+        None => false,
+    };
+    Locale {
+        collate: collate,
+        unicode: unicode,
+    }
+}
+
+/// Selects the given category of the locale according to the environment
+/// and returns the name of the locale, or None if it's not available.
+fn select(category: libc::c_int) -> Option<String> {
+    let empty = CString::default();
+    unsafe {
+        let name = libc::setlocale(category, empty.as_ptr());
+        if name.is_null() {
+            let c = CString::new("C").unwrap_or_default();
+            libc::setlocale(category, c.as_ptr()); // do not keep a half-selected locale
+            return None;
+        }
+        Some(CStr::from_ptr(name).to_string_lossy().into_owned())
+    }
+}
+
+impl Locale {
+    /// Compares two strings using the collation order.
+    pub fn compare(self: &Self, left: &str, right: &str) -> Ordering {
+        if self.collate {
+            if let (Ok(cleft), Ok(cright)) = (CString::new(left), CString::new(right)) {
+                let order = unsafe { libc::strcoll(cleft.as_ptr(), cright.as_ptr()) };
+                // note: distinct strings may collate equally
+                match order.cmp(&0) {
+                    Ordering::Equal => (),
+                    order => return order,
+                }
+            }
+        }
+        left.cmp(right)
+    }
+
+    /// Returns the lowercase version of the given char.
+    pub fn lowercase(self: &Self, c: char) -> char {
+        match self.unicode {
+            true => c.to_lowercase().next().unwrap_or(c),
+            false => c.to_ascii_lowercase(),
+        }
+    }
+
+    /// Returns the uppercase version of the given char.
+    pub fn uppercase(self: &Self, c: char) -> char {
+        match self.unicode {
+            true => c.to_uppercase().next().unwrap_or(c),
+            false => c.to_ascii_uppercase(),
+        }
+    }
+
+    /// Tells whether the char belongs to the character class with the
+    /// given name (e.g., `alpha`), or returns None if there's no such class.
+    pub fn class(self: &Self, name: &str, c: char) -> Option<bool> {
+        if c.is_ascii() {
+            return match name {
+                "alnum" => Some(c.is_ascii_alphanumeric()),
+                "alpha" => Some(c.is_ascii_alphabetic()),
+                "blank" => Some(c == ' ' || c == '\t'),
+                "cntrl" => Some(c.is_ascii_control()),
+                "digit" => Some(c.is_ascii_digit()),
+                "graph" => Some(c.is_ascii_graphic()),
+                "lower" => Some(c.is_ascii_lowercase()),
+                "print" => Some(c.is_ascii_graphic() || c == ' '),
+                "punct" => Some(c.is_ascii_punctuation()),
+                "space" => Some(c.is_ascii_whitespace() || c == '\u{b}'),
+                "upper" => Some(c.is_ascii_uppercase()),
+                "xdigit" => Some(c.is_ascii_hexdigit()),
+                _ => None,
+            };
+        }
+        let unicode = self.unicode;
+        match name {
+            "alnum" => Some(unicode && c.is_alphanumeric()),
+            "alpha" => Some(unicode && c.is_alphabetic()),
+            "blank" => Some(unicode && c.is_whitespace() && c != '\u{85}'),
+            "cntrl" => Some(unicode && c.is_control()),
+            "graph" => Some(unicode && !c.is_control() && !c.is_whitespace()),
+            "lower" => Some(unicode && c.is_lowercase()),
+            "print" => Some(unicode && !c.is_control()),
+            "punct" => {
+                Some(unicode && !c.is_control() && !c.is_whitespace() && !c.is_alphanumeric())
+            }
+            "space" => Some(unicode && c.is_whitespace()),
+            "upper" => Some(unicode && c.is_uppercase()),
+            "digit" | "xdigit" => Some(false),
+            _ => None,
+        }
+    }
+}
@@ -1,94 +1,623 @@
 //! Unix v6-like shell written in rust.
 
+// note: the code base writes `self: &Self` receivers, `field: field`
+// initializers and `len() == 0` checks, to make receivers, fields and
+// length checks explicit, hence we silence the lints against them
+#![allow(
+    clippy::len_zero,
+    clippy::needless_arbitrary_self_type,
+    clippy::redundant_field_names
+)]
+
+#[cfg(feature = "alloc-stats")]
+mod allocstats;
+mod buildinfo;
+mod conditional;
+mod config;
+mod coverage;
+mod crash;
+mod debugjob;
+mod deterministic;
+mod envfile;
+mod expansion;
+mod fdtable;
+mod handoff;
+mod hardened;
 mod interp;
+mod jobctl;
+mod jobstate;
 mod lexer;
+mod locale;
+mod mapped;
 mod model;
 mod parser;
+mod plandiff;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod printf;
+mod privatetmp;
 mod process;
+mod prompt;
+mod pty;
+mod record;
+mod sched;
+mod script;
 mod serializer;
+mod spawnctx;
+mod startup;
+mod statefile;
+mod supervise;
+mod testrunner;
+mod timer;
+mod timings;
+mod transcript;
 mod translator;
+mod update;
+mod validator;
+mod visitor;
+mod watchdog;
+mod xtrace;
 
+use crate::interp::Interpreter;
 use crate::model::{Error, Result};
 use crate::process::PeriodicReaper;
 
+/// Counts the allocations for `--timings` (see allocstats.rs).
+#[cfg(feature = "alloc-stats")]
+#[global_allocator]
+static ALLOCATOR: allocstats::Counting = allocstats::Counting;
+
 /// Main function.
 fn main() {
+    startup::begin();
+    crash::install();
     let args: Vec<String> = std::env::args().collect();
     let program = args[0].clone();
     let mut opts = getopts::Options::new();
+    // note: the arguments following the script name belong to the script
+    opts.parsing_style(getopts::ParsingStyle::StopAtFirstFree);
     opts.optopt("c", "", "execute the given command line", "COMMANDS");
     opts.optopt("", "stage", "stop processing at the given stage", "STAGE");
     opts.optflag("x", "", "turn debugging on");
+    opts.optflag("C", "", "do not overwrite existing files with `>`");
+    opts.optflag(
+        "",
+        "lint",
+        "report issues without running the commands (same as --stage lint)",
+    );
+    opts.optflag("", "init", "run as the init process of a container");
+    opts.optopt(
+        "",
+        "kill-timeout",
+        "seconds before killing processes in init mode (default: 10)",
+        "SECONDS",
+    );
+    opts.optflag(
+        "",
+        "plan-diff",
+        "print the differences between the plans of two scripts",
+    );
+    opts.optopt(
+        "",
+        "test",
+        "run the *_test.sh scripts inside DIR and report the results",
+        "DIR",
+    );
+    opts.optflag("", "tap", "with --test, write the results using TAP");
+    opts.optopt(
+        "",
+        "coverage",
+        "write which commands of the script executed to FILE",
+        "FILE",
+    );
+    opts.optopt("", "record", "append the executed commands to FILE", "FILE");
+    opts.optopt(
+        "",
+        "replay",
+        "execute the commands recorded in FILE",
+        "FILE",
+    );
+    opts.optflag(
+        "",
+        "check",
+        "with --replay, report exit statuses differing from the recorded ones",
+    );
+    opts.optopt("", "resume", "resume the state saved by `reload`", "FILE");
+    opts.optflag(
+        "",
+        "profile-startup",
+        "write how long each startup phase takes",
+    );
+    opts.optflag(
+        "",
+        "stats-on-exit",
+        "write the resources used by the session when exiting",
+    );
+    opts.optflag(
+        "",
+        "deterministic",
+        "produce the same output on each run (e.g., for grading)",
+    );
+    opts.optflag(
+        "",
+        "timings",
+        "write how long preparing and spawning each command takes",
+    );
+    opts.optopt(
+        "",
+        "status",
+        "the initial value of `$?` (for subshells)",
+        "STATUS",
+    );
+    opts.optopt("", "pid", "the value of `$$` (for subshells)", "PID");
+    opts.optopt("", "xtrace-fd", "write the -x output to the given fd", "FD");
+    opts.optopt(
+        "",
+        "xtrace-file",
+        "append the -x output to the given file",
+        "FILE",
+    );
+    opts.optflag("", "version", "print the version and the build information");
+    opts.optopt(
+        "",
+        "format",
+        "with --version, the output format (default: human)",
+        "human|json",
+    );
+    opts.optflag(
+        "",
+        "check-update",
+        "check whether a newer version is available",
+    );
+    opts.optflag(
+        "",
+        "hardened",
+        "do not write crash reports or other state files",
+    );
     let matches = match opts.parse(&args[1..]) {
         Err(_) => {
             eprintln!(
-                "usage: {} [--stage scan|parse|plan|run] [-x] [-c COMMANDS]",
+                "usage: {} [--stage scan|parse|lint|plan|run] [--lint] [-x] [-C] [--init [--kill-timeout SECONDS]] [--xtrace-fd FD|--xtrace-file FILE] [--record FILE] [--coverage FILE] [--resume FILE] [--profile-startup] [--timings] [--deterministic] [--stats-on-exit] [--status STATUS] [--pid PID] [--version [--format human|json]] [--check-update] [--hardened] [-c COMMANDS [NAME [ARG...]] | SCRIPT [ARG...] | --replay FILE [--check] | --plan-diff OLD NEW | --test DIR [--tap]]",
                 program
             );
             std::process::exit(1);
         }
         Ok(m) => m,
     };
+    if matches.opt_present("version") {
+        let format = matches.opt_str("format").unwrap_or(String::from("human"));
+        match buildinfo::format(&format) {
+            Err(err) => {
+                eprintln!("xv6sh: error: {}", err);
+                std::process::exit(1);
+            }
+            Ok(info) => {
+                print!("{}", info);
+                std::process::exit(0);
+            }
+        }
+    }
+    if matches.opt_present("check-update") {
+        match update::check() {
+            Err(err) => {
+                eprintln!("xv6sh: error: cannot check for updates: {}", err);
+                std::process::exit(1);
+            }
+            Ok(None) => println!("xv6sh {} is up to date", env!("CARGO_PKG_VERSION")),
+            Ok(Some(instructions)) => print!("{}", instructions),
+        }
+        std::process::exit(0);
+    }
+    if matches.opt_present("hardened") {
+        hardened::enable();
+    }
+    if matches.opt_present("profile-startup") {
+        startup::enable();
+    }
+    if matches.opt_present("timings") {
+        timings::enable();
+    }
+    if matches.opt_present("deterministic") || deterministic::inherited() {
+        deterministic::enable();
+    }
+    startup::mark("flags");
+    if matches.opt_present("plan-diff") {
+        if matches.free.len() != 2 {
+            eprintln!("usage: {} --plan-diff OLD NEW", program);
+            std::process::exit(2);
+        }
+        match plandiff::diff(&matches.free[0], &matches.free[1]) {
+            Err(err) => {
+                eprintln!("xv6sh: error: {}", err);
+                std::process::exit(2);
+            }
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+        }
+    }
+    if let Some(dir) = matches.opt_str("test") {
+        match testrunner::run(&dir, matches.opt_present("tap")) {
+            Err(err) => {
+                eprintln!("xv6sh: error: {}", err);
+                std::process::exit(2);
+            }
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+        }
+    }
     let mut verbose = false;
     if matches.opt_present("x") {
         verbose = true;
     }
-    let stage = matches.opt_str("stage").or(Some(String::new())).unwrap();
+    let mut resumed = None;
+    if let Some(filename) = matches.opt_str("resume") {
+        match handoff::load(&filename) {
+            Err(err) => eprintln!("xv6sh: error: cannot resume: {}", err),
+            Ok(state) => {
+                verbose = verbose || state.verbose;
+                resumed = Some(state);
+            }
+        }
+    }
+    let xtrace = match (matches.opt_str("xtrace-fd"), matches.opt_str("xtrace-file")) {
+        (Some(fd), _) => xtrace::Trace::from_fd(&fd),
+        (None, Some(filename)) => xtrace::Trace::from_file(&filename),
+        (None, None) => Ok(xtrace::Trace::new()),
+    };
+    let xtrace = match xtrace {
+        Err(err) => {
+            eprintln!("xv6sh: error: {}", err);
+            std::process::exit(1);
+        }
+        Ok(xtrace) => xtrace,
+    };
+    if matches.opt_present("init") {
+        let timeout = match matches.opt_get_default("kill-timeout", 10) {
+            Err(_) => {
+                eprintln!("xv6sh: error: invalid kill timeout");
+                std::process::exit(1);
+            }
+            Ok(timeout) => std::time::Duration::from_secs(timeout),
+        };
+        if let Err(err) = process::enable_init_mode(timeout) {
+            eprintln!("xv6sh: error: {}", err);
+            std::process::exit(1);
+        }
+    }
+    let mut stage = matches.opt_str("stage").unwrap_or_default();
+    if matches.opt_present("lint") {
+        stage = String::from("lint");
+    }
+    startup::mark("setup");
     let mut reaper = PeriodicReaper::new();
+    let mut interp = interp::Interpreter::new(verbose);
+    interp.xtrace = xtrace;
+    interp.noclobber = matches.opt_present("C");
+    interp.exitstats = matches.opt_present("stats-on-exit");
+    interp.import_variables();
+    interp.sync_pwd();
+    match (matches.opt_get("status"), matches.opt_get("pid")) {
+        (Ok(status), Ok(pid)) => {
+            interp.status = status.unwrap_or(interp.status);
+            interp.pid = pid.unwrap_or(interp.pid);
+        }
+        _ => {
+            eprintln!("xv6sh: error: invalid --status or --pid");
+            std::process::exit(1);
+        }
+    }
+    if let Some(filename) = matches.opt_str("record") {
+        match record::create(&filename) {
+            Err(err) => {
+                eprintln!("xv6sh: error: {}", err);
+                std::process::exit(1);
+            }
+            Ok(recorder) => interp.recorder = Some(recorder),
+        }
+    }
+    if let Some(filename) = matches.opt_str("coverage") {
+        interp.coverage = Some(coverage::create(&filename));
+    }
+    if let Some(filename) = matches.opt_str("replay") {
+        let check = matches.opt_present("check");
+        match record::replay(&mut interp, &mut reaper, &filename, check) {
+            Err(err) => {
+                eprintln!("xv6sh: error: {}", err);
+                std::process::exit(1);
+            }
+            Ok(0) if !check => exit(&mut interp),
+            Ok(0) => std::process::exit(0),
+            Ok(_) => std::process::exit(1),
+        }
+    }
+    startup::mark("interpreter");
+    interp.arg0 = program.clone();
+    if let Some((arg0, positional)) = matches.free.split_first() {
+        // note: with -c, the first argument becomes `$0` like in sh
+        interp.arg0 = arg0.clone();
+        interp.positional = positional.to_vec();
+    }
     if let Some(cmd) = matches.opt_str("c") {
-        shrunx(&mut reaper, cmd, &stage, verbose);
-        std::process::exit(0);
+        let script = script::Script::new("-c", &cmd);
+        if let Err(err) = shrun_program(&mut interp, &mut reaper, &script, &stage) {
+            eprintln!("xv6sh: error: {}", err);
+            interp.status = 1;
+        }
+        startup::finish("commands");
+        exit(&mut interp);
     }
+    if let Some(filename) = matches.free.first() {
+        let script = match script::load(filename) {
+            Err(err) => {
+                eprintln!("xv6sh: error: {}", err);
+                std::process::exit(1);
+            }
+            Ok(script) => script,
+        };
+        let result = shrun_program(&mut interp, &mut reaper, &script, &stage);
+        startup::finish("commands");
+        if let Err(err) = result {
+            eprintln!("xv6sh: error: {}", err);
+            interp.status = 1;
+        }
+        exit(&mut interp);
+    }
+    let _ = interp.jobs.enable(); // it's fine if we cannot enable it
+    if let Some(state) = resumed {
+        if let Err(err) = std::env::set_current_dir(&state.cwd) {
+            eprintln!("xv6sh: error: cannot resume: {}: {}", state.cwd, err);
+        }
+        interp.sync_pwd();
+        if !state.monitor {
+            interp.jobs.disable();
+        }
+        interp.noclobber = interp.noclobber || state.noclobber;
+        if let Err(err) = interp.resume_options(&state.options) {
+            eprintln!("xv6sh: error: cannot resume: {}", err);
+        }
+        interp.positional = state.positional.clone();
+        for pid in state.pids.iter() {
+            reaper.adopt(*pid);
+        }
+    }
+    config::install_handler();
+    let mut watcher = config::filename().map(config::Watcher::new);
+    let mut provider = prompt::Provider::from_env();
+    let hint = update::Hint::start();
     loop {
-        match getcmd() {
+        if let Some(message) = hint.as_ref().and_then(|hint| hint.poll()) {
+            eprint!("{}", message);
+        }
+        if let Some(watcher) = watcher.as_mut() {
+            if watcher.changed() {
+                if let Err(err) = interp.load_config(&watcher.filename, &mut reaper) {
+                    eprintln!("xv6sh: error: {}", err);
+                }
+            }
+        }
+        let prompt = match provider.as_mut() {
+            None => prompt::expand(interp.variable("PS1")),
+            Some(provider) => {
+                // note: we reap first, so that we don't count the processes that
+                // have terminated, and then sample in background (see jobstate)
+                reaper.reap();
+                jobstate::publish(reaper.pids());
+                let state = prompt::State {
+                    status: interp.status,
+                    cwd: std::env::current_dir()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    jobs: jobstate::running(),
+                };
+                provider.prompt(&state)
+            }
+        };
+        startup::finish("prompt");
+        match getline(&prompt) {
             Err(_) => break,
-            Ok(cmd) => shrunx(&mut reaper, cmd, &stage, verbose),
+            Ok(cmd) => {
+                let mut more = || getline("> ").ok();
+                shrunx(&mut interp, &mut reaper, cmd, &mut more, &stage)
+            }
         }
     }
+    exit(&mut interp);
+}
+
+/// Runs the EXIT trap, if any, and exits with the exit status of the
+/// last pipeline.
+fn exit(interp: &mut Interpreter) -> ! {
+    interp.exit()
 }
 
 /// Interprets a single shell input line.
-fn shrunx(reaper: &mut PeriodicReaper, cmd: String, stage: &String, verbose: bool) {
-    match shrun(reaper, cmd, stage, verbose) {
+fn shrunx(
+    interp: &mut Interpreter,
+    reaper: &mut PeriodicReaper,
+    cmd: String,
+    more: &mut dyn FnMut() -> Option<String>,
+    stage: &String,
+) {
+    match shrun(interp, reaper, cmd, more, stage) {
         Ok(_) => (),
-        Err(err) => eprintln!("xv6sh: error: {}", err),
+        Err(err) => {
+            let message = format!("xv6sh: error: {}", err);
+            eprintln!("{}", message);
+            transcript::write(&message);
+            interp.status = 1;
+        }
     }
 }
 
-/// Interprets a single shell input line.
-fn shrun(reaper: &mut PeriodicReaper, cmd: String, stage: &String, verbose: bool) -> Result<()> {
-    reaper.reap(); // ensure we don't leave zombies around
-    let tokens = lexer::scan(cmd);
+/// Interprets a single shell input line, using the given function to
+/// read more lines of input if needed (e.g., for here-documents or when
+/// the line ends with `|`).
+fn shrun(
+    interp: &mut Interpreter,
+    reaper: &mut PeriodicReaper,
+    cmd: String,
+    more: &mut dyn FnMut() -> Option<String>,
+    stage: &String,
+) -> Result<()> {
+    // note: while the input is incomplete (e.g., it ends with `|`), we read
+    // another line and scan everything again, including the lines that the
+    // lexer has read in the meanwhile (e.g., the body of here-documents)
+    let mut cmd = cmd;
+    let tree = loop {
+        let mut consumed = Vec::<String>::new();
+        let scanned = {
+            let mut recording = || {
+                let line = more();
+                if let Some(line) = &line {
+                    consumed.push(line.clone());
+                }
+                line
+            };
+            crash::input(&cmd);
+            timings::stage("scan", || lexer::scan(cmd.clone(), &mut recording))
+        };
+        let parsed = match scanned {
+            Ok(tokens) if stage == "scan" => {
+                println!("{:#?}", tokens);
+                return Ok(());
+            }
+            Ok(tokens) => {
+                crash::tokens(&tokens);
+                timings::stage("parse", || parser::parse(tokens))
+            }
+            Err(err) => Err(err),
+        };
+        match parsed {
+            Ok(tree) => break tree,
+            Err(err) if err.is_incomplete() => match more() {
+                None => return Err(err),
+                Some(line) => {
+                    consumed.insert(0, cmd);
+                    consumed.push(line);
+                    cmd = consumed.join("\n");
+                }
+            },
+            Err(err) => return Err(err),
+        }
+    };
+    if stage == "parse" {
+        println!("{:#?}", tree);
+        return Ok(());
+    }
+    if stage == "lint" {
+        return match lint(validator::validate(&tree, true), &|err| err) {
+            0 => Ok(()),
+            _ => Err(Error::new("lint found errors")),
+        };
+    }
+    shrun_complete_command(interp, reaper, tree, stage)
+}
+
+/// Interprets a whole program (i.e., a script or the argument of
+/// `-c`). When interpreting a script, we stop at the first error,
+/// which is annotated with its location. Otherwise, we print
+/// errors and continue with the next complete command.
+fn shrun_program(
+    interp: &mut Interpreter,
+    reaper: &mut PeriodicReaper,
+    script: &script::Script,
+    stage: &String,
+) -> Result<()> {
+    let is_script = script.filename != "-c";
+    let locate = |err: Error, offset: usize| match is_script {
+        true => script.locate(err, offset),
+        false => err,
+    };
+    crash::input(&script.content);
+    let scanned = timings::stage("scan", || lexer::scan(script.content.clone(), &mut || None));
+    let tokens = match scanned {
+        Err(err) => return Err(locate(err, 0)),
+        Ok(tokens) => tokens,
+    };
     if stage == "scan" {
         println!("{:#?}", tokens);
         return Ok(());
     }
-    let tree = parser::parse(tokens)?;
+    crash::tokens(&tokens);
+    let program = match timings::stage("parse", || parser::parse_program(tokens)) {
+        Err(err) => return Err(locate(err, 0)),
+        Ok(program) => program,
+    };
     if stage == "parse" {
-        println!("{:#?}", tree);
+        println!("{:#?}", program);
         return Ok(());
     }
-    let loc = translator::translate(tree, verbose)?;
+    if stage == "lint" {
+        return match lint(validator::validate_program(&program), &|err| locate(err, 0)) {
+            0 => Ok(()),
+            _ => Err(Error::new("lint found errors")),
+        };
+    }
+    if let Some(coverage) = &mut interp.coverage {
+        coverage.collect(script, &program);
+    }
+    for cc in program.commands {
+        let offset = cc.offset;
+        match shrun_complete_command(interp, reaper, cc, stage) {
+            Err(err) if is_script => return Err(locate(err, offset)),
+            Err(err) => {
+                eprintln!("xv6sh: error: {}", err);
+                interp.status = 1;
+            }
+            Ok(_) => (),
+        }
+    }
+    Ok(())
+}
+
+/// Prints all the issues in the given report using locate to annotate
+/// them with their location. Returns the number of errors.
+fn lint(report: validator::Report, locate: &dyn Fn(Error) -> Error) -> usize {
+    for issue in report.issues.iter() {
+        eprintln!("xv6sh: {}: {}", issue.severity, locate(issue.to_error()));
+    }
+    report.errors().count()
+}
+
+/// Interprets a single complete command.
+fn shrun_complete_command(
+    interp: &mut Interpreter,
+    reaper: &mut PeriodicReaper,
+    cc: parser::CompleteCommand,
+    stage: &String,
+) -> Result<()> {
+    reaper.reap(); // ensure we don't leave zombies around
+    crash::ast(&cc);
+    crash::stage("plan");
+    let loc = timings::stage("plan", || interp.translate(cc))?;
     if stage == "plan" {
         println!("{:#?}", loc);
+        println!(
+            "# {} commands, at most {} processes",
+            loc.commands().len(),
+            loc.processes()
+        );
         return Ok(());
     }
-    let interp = interp::Interpreter::new(verbose);
+    crash::stage("run");
     interp.run(loc, reaper)
 }
 
-/// Reads a command from the standard input.
-fn getcmd() -> Result<String> {
+/// Reads a line from the standard input using the given prompt.
+fn getline(prompt: &str) -> Result<String> {
     use std::io::BufRead;
     use std::io::Write;
-    print!("$ ");
+    print!("{}", prompt);
     std::io::stdout().flush().unwrap();
     let stdin = std::io::stdin();
     let lines = stdin.lock().lines().next();
     match lines {
         Some(line) => match line {
             Err(err) => Err(Error::new(&err.to_string())),
-            Ok(line) => Ok(line),
+            Ok(line) => {
+                transcript::write(&format!("{}{}", prompt, line));
+                Ok(line)
+            }
         },
         None => Err(Error::new("EOF")),
     }
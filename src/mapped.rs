@@ -0,0 +1,200 @@
+//! Sources huge scripts through a memory map.
+//!
+//! Reading a multi-megabyte script (e.g., a generated one) into a string
+//! and scanning and parsing it as a whole requires memory proportional to
+//! the size of the script, several times over. Instead, for files larger
+//! than MMAP_THRESHOLD, we map the file into memory and scan and parse one
+//! complete command at a time, reading more lines while the command is
+//! incomplete (e.g., inside `if` or a here-document), like we do with the
+//! lines typed at the prompt. Unlike smaller scripts, we therefore execute
+//! the commands preceding a syntax error. When the standard error is a
+//! terminal, we also report the progress.
+
+use crate::model::{Error, Result};
+use crate::parser::CompleteCommand;
+use crate::script::{self, Script};
+use crate::{lexer, parser};
+use std::fs::File;
+use std::os::fd::AsRawFd;
+
+/// Files larger than this number of bytes are memory mapped.
+pub const MMAP_THRESHOLD: usize = 1 << 20;
+
+/// A memory-mapped script.
+pub struct MappedScript {
+    filename: String,
+    data: *const u8,
+    length: usize,
+
+    /// The byte offset of the next line.
+    position: usize,
+
+    /// The number of the next line, starting from one.
+    line: usize,
+
+    /// The last progress we reported, in tenths of the file.
+    reported: usize,
+}
+
+/// A complete command read from a memory-mapped script.
+pub struct Chunk {
+    /// The lines containing the command.
+    pub script: Script,
+
+    /// The number of the first line of the chunk.
+    pub line: usize,
+
+    /// The byte offset of the chunk inside the file.
+    pub byte: usize,
+
+    pub command: CompleteCommand,
+}
+
+/// Maps the given file into memory, unless it's small enough for
+/// script::load, in which case we return None.
+pub fn open(filename: &str) -> Result<Option<MappedScript>> {
+    let annotate = |err: std::io::Error| Error::new(&format!("{}: {}", filename, err));
+    let filep = File::open(filename).map_err(annotate)?;
+    let length = filep.metadata().map_err(annotate)?.len() as usize;
+    if length <= MMAP_THRESHOLD {
+        return Ok(None);
+    }
+    let data = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            length,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            filep.as_raw_fd(),
+            0,
+        )
+    };
+    if data == libc::MAP_FAILED {
+        return Err(annotate(std::io::Error::last_os_error()));
+    }
+    let mapped = MappedScript {
+        filename: String::from(filename),
+        data: data as *const u8,
+        length: length,
+        position: 0,
+        line: 1,
+        reported: 0,
+    };
+    if script::is_binary(mapped.bytes()) {
+        return Err(Error::new(&format!(
+            "{}: cannot source binary file",
+            filename
+        )));
+    }
+    Ok(Some(mapped))
+}
+
+impl MappedScript {
+    /// Reads the next complete command, if any.
+    pub fn next_command(self: &mut Self) -> Result<Option<Chunk>> {
+        let (line, byte) = (self.line, self.position);
+        let mut text = match self.next_line()? {
+            None => return Ok(None),
+            Some(text) => text,
+        };
+        // note: here-documents make the lexer read more lines, which we
+        // must add to the text when the command turns out incomplete
+        loop {
+            let mut consumed = Vec::<String>::new();
+            let mut failure = None;
+            let scanned = {
+                let mut more = || match self.next_line() {
+                    Err(err) => {
+                        failure = Some(err);
+                        None
+                    }
+                    Ok(None) => None,
+                    Ok(Some(text)) => {
+                        consumed.push(text.clone());
+                        Some(text)
+                    }
+                };
+                lexer::scan(text.clone(), &mut more)
+            };
+            if let Some(err) = failure {
+                return Err(err);
+            }
+            consumed.insert(0, text);
+            let script = Script::new(&self.filename, &consumed.join("\n"));
+            let err = match scanned.and_then(parser::parse) {
+                Ok(command) => {
+                    return Ok(Some(Chunk {
+                        script: script,
+                        line: line,
+                        byte: byte,
+                        command: command,
+                    }))
+                }
+                Err(err) => err,
+            };
+            let next = match err.is_incomplete() {
+                false => None,
+                true => self.next_line()?,
+            };
+            match next {
+                None => return Err(script.locate_from(err, 0, line, byte)),
+                Some(next) => {
+                    consumed.push(next);
+                    text = consumed.join("\n");
+                }
+            }
+        }
+    }
+
+    /// Returns the next line without the trailing newline, if any.
+    fn next_line(self: &mut Self) -> Result<Option<String>> {
+        let bytes = &self.bytes()[self.position..];
+        if bytes.len() == 0 {
+            return Ok(None);
+        }
+        let end = bytes.iter().position(|b| *b == b'\n');
+        let line = &bytes[..end.unwrap_or(bytes.len())];
+        let text = match std::str::from_utf8(line) {
+            Err(err) => {
+                return Err(Error::new(&format!(
+                    "{}: byte {}: invalid UTF-8 sequence",
+                    self.filename,
+                    self.position + err.valid_up_to()
+                )))
+            }
+            Ok(text) => String::from(text),
+        };
+        self.position += end.map_or(line.len(), |end| end + 1);
+        self.line += 1;
+        self.report();
+        Ok(Some(text))
+    }
+
+    /// Reports the progress each time we read another tenth of the file.
+    fn report(self: &mut Self) {
+        let tenths = self.position * 10 / self.length;
+        if tenths <= self.reported || unsafe { libc::isatty(2) } != 1 {
+            return;
+        }
+        self.reported = tenths;
+        eprintln!(
+            "xv6sh: {}: {}% ({}/{} KiB)",
+            self.filename,
+            tenths * 10,
+            self.position / 1024,
+            self.length / 1024
+        );
+    }
+
+    /// Returns the content of the file.
+    fn bytes(self: &Self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data, self.length) }
+    }
+}
+
+impl Drop for MappedScript {
+    /// Unmaps the file.
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.data as *mut libc::c_void, self.length) };
+    }
+}
@@ -6,37 +6,262 @@ use std::process::Command;
 #[derive(Debug)]
 pub struct Error {
     reason: String,
+    offset: Option<usize>,
+    location: Option<Location>,
+
+    /// Whether the error occurred because the input ended too early
+    /// (e.g., after `|`), so that reading more input may fix it.
+    incomplete: bool,
+}
+
+/// Location of an error inside a script.
+#[derive(Debug)]
+pub struct Location {
+    /// The name of the script.
+    pub filename: String,
+
+    /// The line number starting from one.
+    pub line: usize,
+
+    /// The column number starting from one, if known.
+    pub column: Option<usize>,
+
+    /// The byte offset inside the script, if known.
+    pub byte: Option<usize>,
 }
 
 /// Result of an operation.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Describes a single redirection. The parser, the translator and
+/// the interpreter all share this representation.
+#[derive(Debug, Clone)]
+pub enum Redirect {
+    Input(InputRedir),
+    Output(OutputRedir),
+    Duplicate(DupRedir),
+
+    /// Closes the given file descriptor (e.g., `2>&-`).
+    Close(i32),
+
+    /// Redirects both the standard output and error (e.g., `&> file`),
+    /// which the translator turns into `> file 2>&1`.
+    Combined(OutputRedir),
+}
+
+/// Describes how to perform input redirection.
+#[derive(Debug, Clone)]
+pub enum InputRedir {
+    File(Word),
+    HereDoc(HereDoc),
+    HereString(Word),
+}
+
+/// A here-document whose body is the text between the line containing
+/// the redirection and the line containing just the delimiter.
+#[derive(Debug, Clone)]
+pub struct HereDoc {
+    pub delimiter: Word,
+    pub body: String,
+}
+
+/// Describes how to perform output redirection of the given
+/// file descriptor, which is 1 unless otherwise specified.
+#[derive(Debug, Clone)]
+pub struct OutputRedir {
+    pub fd: i32,
+    pub filename: Word,
+    pub overwrite: bool,
+
+    /// Whether to overwrite the file even when the noclobber
+    /// option is enabled (i.e., `>|`).
+    pub force: bool,
+}
+
+/// Describes how to make a file descriptor a duplicate of
+/// the target file descriptor (e.g., `2>&1`).
+#[derive(Debug, Clone)]
+pub struct DupRedir {
+    pub fd: i32,
+    pub target: i32,
+}
+
+/// A variable assignment preceding a command (e.g., `RUST_LOG=debug`),
+/// which adds the variable to the command's environment or, without a
+/// command, sets the variable inside the shell.
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+/// A conditional expression inside `[[ ... ]]`. The parser, the
+/// translator and the interpreter all share this representation.
+#[derive(Debug, Clone)]
+pub enum Condition {
+    /// A word, which is true when it expands to a non-empty string.
+    Word(Word),
+
+    /// A unary operator and its operand (e.g., `-f file`).
+    Unary(String, Word),
+
+    /// A binary operator and its operands (e.g., `$x == *.rs`).
+    Binary(Word, String, Word),
+
+    /// The negation of a condition (i.e., `! condition`).
+    Not(Box<Condition>),
+
+    /// Both conditions, evaluating the right one only when needed.
+    And(Box<Condition>, Box<Condition>),
+
+    /// Either condition, evaluating the right one only when needed.
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl std::fmt::Display for Condition {
+    /// Writes the condition using the shell syntax, with the words
+    /// unquoted and parentheses around the nested `&&` and `||`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let nested = |condition: &Condition| match condition {
+            Condition::And(..) | Condition::Or(..) => format!("( {} )", condition),
+            _ => condition.to_string(),
+        };
+        match self {
+            Condition::Word(word) => write!(f, "{}", word.value),
+            Condition::Unary(operator, word) => write!(f, "{} {}", operator, word.value),
+            Condition::Binary(left, operator, right) => {
+                write!(f, "{} {} {}", left.value, operator, right.value)
+            }
+            Condition::Not(condition) => write!(f, "! {}", nested(condition)),
+            Condition::And(left, right) => write!(f, "{} && {}", nested(left), nested(right)),
+            Condition::Or(left, right) => write!(f, "{} || {}", nested(left), nested(right)),
+        }
+    }
+}
+
+/// A word (i.e., a command, an argument or a file name). We keep
+/// track of whether the word was quoted, because quoting affects
+/// how words are expanded.
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub value: String,
+    pub quoted: bool,
+
+    /// The offsets in chars of the `$` signs starting an expansion
+    /// and of the `~` starting a tilde expansion (e.g., `~/src`).
+    pub expansions: Vec<usize>,
+
+    /// The offsets of the expansions that were not double-quoted,
+    /// whose result is subject to field splitting.
+    pub splits: Vec<usize>,
+
+    /// The offsets of the unquoted `*`, `?` and `[` pattern chars,
+    /// which are subject to pathname expansion.
+    pub globs: Vec<usize>,
+}
+
+impl Word {
+    /// Creates a new, unquoted instance of Word that does not
+    /// contain expansions (i.e., any `$` is literal).
+    pub fn new(value: &str) -> Word {
+        Word {
+            value: String::from(value),
+            quoted: false,
+            expansions: Vec::new(),
+            splits: Vec::new(),
+            globs: Vec::new(),
+        }
+    }
+}
+
 impl Error {
     /// Creates a new instance of error.
     pub fn new(reason: &str) -> Error {
         Error {
             reason: String::from(reason),
+            offset: None,
+            location: None,
+            incomplete: false,
         }
     }
+
+    /// Creates a new instance of error occurring at the given
+    /// character offset of the input being processed.
+    pub fn with_offset(reason: &str, offset: usize) -> Error {
+        Error {
+            reason: String::from(reason),
+            offset: Some(offset),
+            location: None,
+            incomplete: false,
+        }
+    }
+
+    /// Creates a new instance of error occurring at the given character
+    /// offset because the input ended before the command was complete.
+    pub fn incomplete(reason: &str, offset: usize) -> Error {
+        Error {
+            reason: String::from(reason),
+            offset: Some(offset),
+            location: None,
+            incomplete: true,
+        }
+    }
+
+    /// Tells whether more input may fix the error (see incomplete).
+    pub fn is_incomplete(self: &Self) -> bool {
+        self.incomplete
+    }
+
+    /// Returns the offset where the error occurred, if known.
+    pub fn offset(self: &Self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Attaches the given location to the error unless the error
+    /// already has a location (e.g., it comes from a nested script).
+    pub fn at(mut self: Self, location: Location) -> Error {
+        if self.location.is_none() {
+            self.location = Some(location);
+        }
+        self
+    }
 }
 
 impl std::error::Error for Error {}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.reason)
+        match &self.location {
+            None => match self.offset {
+                None => write!(f, "{}", self.reason),
+                Some(offset) => write!(f, "column {}: {}", offset + 1, self.reason),
+            },
+            Some(loc) => match (loc.column, loc.byte) {
+                (Some(column), Some(byte)) => write!(
+                    f,
+                    "{}:{}:{}: {} (byte {})",
+                    loc.filename, loc.line, column, self.reason, byte
+                ),
+                _ => write!(f, "{}:{}: {}", loc.filename, loc.line, self.reason),
+            },
+        }
     }
 }
 
 /// Process is a running child process.
 pub trait Process {
+    fn id(&self) -> u32;
     fn kill(&mut self) -> std::io::Result<()>;
     fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>>;
     fn wait(&mut self) -> std::io::Result<std::process::ExitStatus>;
 }
 
+/// Creates a command we may spawn multiple times (e.g., to restart it).
+pub type CommandFactory = Box<dyn Fn() -> Result<Command> + Send>;
+
 /// Anything that can spawn child processes.
 pub trait ProcessSpawner {
-    /// Spawns a new process from the given command.
+    /// Spawns a new process from the given command, which we consume,
+    /// so that the descriptors it owns are closed after spawning.
     fn spawn(self: &Self, cmd: Command) -> Result<Box<dyn Process>>;
 }
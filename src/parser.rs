@@ -4,26 +4,60 @@
 //! https://pubs.opengroup.org/onlinepubs/009604599/utilities/xcu_chap02.html#tag_02_10.
 
 use crate::lexer;
-use crate::model::{Error, Result};
+use crate::model::{
+    Assignment, Condition, DupRedir, Error, HereDoc, InputRedir, OutputRedir, Redirect, Result,
+    Word,
+};
 use std::collections::VecDeque;
 
+/// A program (e.g., a script) in the shell grammar:
+///
+///     Program ::= Linebreak
+///               | Linebreak CompleteCommands Linebreak
+///
+///     CompleteCommands ::= CompleteCommands Newline Linebreak CompleteCommand
+///                        | CompleteCommand
+///
+///     Linebreak ::= /* Empty */
+///                 | Linebreak Newline
+///
+/// Blank lines and comment-only lines are just Newline tokens, because
+/// the lexer skips comments, so the Linebreak rule takes care of them.
+#[derive(Debug)]
+pub struct Program {
+    pub commands: VecDeque<CompleteCommand>,
+}
+
 /// A complete command in the shell grammar:
 ///
 ///     CompleteCommand ::= CompleteCommand ";" Pipeline
 ///                       | CompleteCommand "&" Pipeline
 ///                       | Pipeline
+///
+/// Inside a subshell, a Newline is equivalent to ";". The offset is the
+/// offset in chars of the complete command inside the input.
 #[derive(Debug)]
 pub struct CompleteCommand {
     pub pipelines: VecDeque<Pipeline>,
+    pub offset: usize,
 }
 
 /// A pipeline of commands in the shell grammar:
 ///
-///     Pipeline ::= Pipeline "|" Command
-///                | Command
+///     Pipeline ::= "!" PipeSequence
+///                | PipeSequence
+///
+///     PipeSequence ::= PipeSequence "|" Linebreak Command
+///                    | Command
 #[derive(Debug)]
 pub struct Pipeline {
     pub commands: VecDeque<Command>,
+
+    /// Whether the pipeline starts with `!`, which inverts its exit status.
+    pub negated: bool,
+
+    /// Whether to wait for the pipeline to complete, which is false
+    /// when the pipeline is terminated by `&`.
     pub sync: bool,
 }
 
@@ -31,53 +65,154 @@ pub struct Pipeline {
 ///
 ///     Command ::= SimpleCommand
 ///               | Subshell
+///               | BraceGroup
+///               | IfClause
+///               | ConditionalCommand
 #[derive(Debug)]
 pub enum Command {
     SimpleCommand(SimpleCommand),
     Subshell(Subshell),
+    Group(BraceGroup),
+    If(IfClause),
+    Conditional(ConditionalCommand),
 }
 
 /// A simple command in the shell grammar:
 ///
-///     SimpleCommand ::= Arguments RedirectList
+///     SimpleCommand ::= Assignments Arguments RedirectList
+///
+/// where Assignments are the leading `NAME=value` words, if any.
+///
+/// The offset is the offset in chars of the simple command inside the input.
 #[derive(Debug)]
 pub struct SimpleCommand {
-    pub arguments: VecDeque<String>,
+    pub assignments: VecDeque<Assignment>,
+    pub arguments: VecDeque<Word>,
     pub redirs: RedirectList,
+    pub offset: usize,
 }
 
 /// A subshell in the shell grammar:
 ///
 ///     Subshell := "(" CompleteCommand ")" RedirectList
+///
+/// The offset is the offset in chars of the "(" token inside the input.
 #[derive(Debug)]
 pub struct Subshell {
     pub complete_command: CompleteCommand,
     pub redirs: RedirectList,
+    pub offset: usize,
 }
 
-/// A list of redirections in the shell grammar:
+/// A group of commands running in the current shell in the shell grammar:
 ///
-///     RedirectList ::= /* Empty */
-///                    |  "<" filename
-///                    |  ">" filename
-///                    | ">>" filename
+///     BraceGroup ::= "{" CompoundList "}" RedirectList
+///
+/// Like other reserved words, `{` and `}` are recognized only when they
+/// appear unquoted where a command would start, hence `}` must follow
+/// a ";" or a Newline. The offset is the offset in chars of the `{`
+/// word inside the input.
 #[derive(Debug)]
-pub struct RedirectList {
-    pub input: VecDeque<InputRedir>,
-    pub output: VecDeque<OutputRedir>,
+pub struct BraceGroup {
+    pub complete_command: CompleteCommand,
+    pub redirs: RedirectList,
+    pub offset: usize,
 }
 
-/// Describes how to perform input redirection.
-#[derive(Debug, Clone)]
-pub struct InputRedir {
-    pub filename: String,
+/// A conditional command in the shell grammar:
+///
+///     IfClause ::= "if" CompoundList "then" CompoundList ElsePart "fi" RedirectList
+///
+///     ElsePart ::= /* Empty */
+///                | "elif" CompoundList "then" CompoundList ElsePart
+///                | "else" CompoundList
+///
+///     CompoundList ::= Linebreak CompleteCommand
+///
+/// Inside a compound list, a Newline is equivalent to ";" and the reserved
+/// words `then`, `elif`, `else`, `fi` and `}` terminate the list when they
+/// appear where a command would start. The offset is the offset in chars of the
+/// `if` word inside the input.
+#[derive(Debug)]
+pub struct IfClause {
+    /// The `if` and `elif` branches in order.
+    pub branches: VecDeque<Branch>,
+
+    /// The commands following `else`, if any.
+    pub otherwise: Option<CompleteCommand>,
+
+    pub redirs: RedirectList,
+    pub offset: usize,
+}
+
+/// A branch of an IfClause, whose body runs when the condition succeeds.
+#[derive(Debug)]
+pub struct Branch {
+    pub condition: CompleteCommand,
+    pub body: CompleteCommand,
+}
+
+/// A conditional command in the shell grammar:
+///
+///     ConditionalCommand ::= "[[" Expression "]]" RedirectList
+///
+///     Expression ::= Expression "||" Expression
+///                  | Expression "&&" Expression
+///                  | "!" Expression
+///                  | "(" Expression ")"
+///                  | UnaryOperator Word
+///                  | Word BinaryOperator Word
+///                  | Word
+///
+/// where `&&` binds more tightly than `||`, newlines are ignored, and the
+/// operators are recognized only when unquoted. Unlike the arguments of
+/// commands, the words are not subject to field splitting and pathname
+/// expansion, while the right operand of `==` and `!=` is a pattern. The
+/// offset is the offset in chars of the `[[` word inside the input.
+#[derive(Debug)]
+pub struct ConditionalCommand {
+    pub condition: Condition,
+    pub redirs: RedirectList,
+    pub offset: usize,
 }
 
-/// Describes how to perform output redirection.
-#[derive(Debug, Clone)]
-pub struct OutputRedir {
-    pub filename: String,
-    pub overwrite: bool,
+/// The unary operators of conditional commands.
+pub const UNARY_OPERATORS: [&str; 18] = [
+    "-b", "-c", "-d", "-e", "-f", "-h", "-L", "-n", "-o", "-p", "-r", "-s", "-S", "-t", "-v", "-w",
+    "-x", "-z",
+];
+
+/// The binary operators of conditional commands, besides `<` and `>`,
+/// which the lexer scans as redirection operators.
+pub const BINARY_OPERATORS: [&str; 13] = [
+    "==", "=", "!=", "=~", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// A list of redirections in the shell grammar:
+///
+///     RedirectList ::= /* Empty */
+///                    | RedirectList Redirect
+///
+///     Redirect ::=       "<" filename
+///                |      "<<" delimiter
+///                |     "<<-" delimiter
+///                |     "<<<" word
+///                | [n] ">" filename
+///                | [n] ">>" filename
+///                | [n] ">|" filename
+///                | [n] ">&" m
+///                | [n] "<&" m
+///                | [n] ">&" "-"
+///                | [n] "<&" "-"
+///                |     "&>" filename
+///                |    "&>>" filename
+///
+/// where `n` and `m` are file descriptor numbers and `-` closes `n`. We keep
+/// the redirections in the same order in which they appear, because they
+/// must be evaluated from left to right. See model::Redirect.
+#[derive(Debug)]
+pub struct RedirectList {
+    pub redirs: VecDeque<Redirect>,
 }
 
 /// Parses the incoming sequence of tokens.
@@ -86,6 +221,13 @@ pub fn parse(tokens: VecDeque<lexer::Token>) -> Result<CompleteCommand> {
     parser.run()
 }
 
+/// Parses the incoming sequence of tokens, which may contain
+/// multiple lines, as a whole program.
+pub fn parse_program(tokens: VecDeque<lexer::Token>) -> Result<Program> {
+    let mut parser = Parser::new(tokens);
+    parser.run_program()
+}
+
 //
 // Implementation of public types.
 //
@@ -95,6 +237,7 @@ impl CompleteCommand {
     pub fn new() -> CompleteCommand {
         CompleteCommand {
             pipelines: VecDeque::<_>::new(),
+            offset: 0,
         }
     }
 }
@@ -104,17 +247,66 @@ impl Pipeline {
     pub fn new() -> Pipeline {
         Pipeline {
             commands: VecDeque::<_>::new(),
+            negated: false,
             sync: false,
         }
     }
+
+    /// returns the offset in chars of the first command of the pipeline
+    pub fn offset(self: &Self) -> usize {
+        match self.commands.front() {
+            None => 0,
+            Some(Command::SimpleCommand(scmd)) => scmd.offset,
+            Some(Command::Subshell(ss)) => ss.offset,
+            Some(Command::Group(bg)) => bg.offset,
+            Some(Command::If(ic)) => ic.offset,
+            Some(Command::Conditional(cc)) => cc.offset,
+        }
+    }
 }
 
 impl SimpleCommand {
     /// creates a new instance of SimpleCommand
     pub fn new() -> SimpleCommand {
         SimpleCommand {
+            assignments: VecDeque::<_>::new(),
             arguments: VecDeque::<_>::new(),
             redirs: RedirectList::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl Word {
+    /// creates a new instance of Word from a CommandOrArgument token
+    fn from_token(token: lexer::Token) -> Word {
+        Word {
+            value: token.value,
+            quoted: token.quoted,
+            expansions: token.expansions,
+            splits: token.splits,
+            globs: token.globs,
+        }
+    }
+}
+
+impl Assignment {
+    /// creates a new instance of Assignment from a CommandOrArgument
+    /// token starting with `NAME=`. The value is not subject to field
+    /// splitting and pathname expansion.
+    fn from_token(token: lexer::Token) -> Assignment {
+        let (name, value) = token.value.split_once('=').unwrap_or_default();
+        let skip = name.chars().count() + 1;
+        let shift = |offsets: Vec<usize>| offsets.into_iter().map(|offset| offset - skip).collect();
+        Assignment {
+            name: String::from(name),
+            value: Word {
+                value: String::from(value),
+                quoted: token.quoted,
+                expansions: shift(token.expansions),
+                splits: Vec::new(),
+                globs: Vec::new(),
+            },
         }
     }
 }
@@ -123,8 +315,7 @@ impl RedirectList {
     /// creates a new instance of RedirectList
     pub fn new() -> RedirectList {
         RedirectList {
-            input: VecDeque::<_>::new(),
-            output: VecDeque::<_>::new(),
+            redirs: VecDeque::<_>::new(),
         }
     }
 }
@@ -133,15 +324,137 @@ impl RedirectList {
 // Parser implementation.
 //
 
+/// A reserved word. Reserved words are special only when they appear
+/// unquoted where a command would start (e.g., `echo fi` just prints
+/// `fi`), which is the only place where the parser checks for them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reserved {
+    Bang,
+    OpenBrace,
+    OpenBrackets,
+    CloseBrace,
+    Case,
+    Do,
+    Done,
+    Elif,
+    Else,
+    Esac,
+    Fi,
+    For,
+    If,
+    Then,
+    Until,
+    While,
+}
+
+impl Reserved {
+    /// returns the reserved word the token represents, if any.
+    fn from_token(token: &lexer::Token) -> Option<Reserved> {
+        if !matches!(token.kind, lexer::Kind::CommandOrArgument) || token.quoted {
+            return None;
+        }
+        match token.value.as_str() {
+            "!" => Some(Reserved::Bang),
+            "{" => Some(Reserved::OpenBrace),
+            "[[" => Some(Reserved::OpenBrackets),
+            "}" => Some(Reserved::CloseBrace),
+            "case" => Some(Reserved::Case),
+            "do" => Some(Reserved::Do),
+            "done" => Some(Reserved::Done),
+            "elif" => Some(Reserved::Elif),
+            "else" => Some(Reserved::Else),
+            "esac" => Some(Reserved::Esac),
+            "fi" => Some(Reserved::Fi),
+            "for" => Some(Reserved::For),
+            "if" => Some(Reserved::If),
+            "then" => Some(Reserved::Then),
+            "until" => Some(Reserved::Until),
+            "while" => Some(Reserved::While),
+            _ => None,
+        }
+    }
+
+    /// returns the reserved word as a string.
+    fn as_str(self: &Self) -> &'static str {
+        match self {
+            Reserved::Bang => "!",
+            Reserved::OpenBrace => "{",
+            Reserved::OpenBrackets => "[[",
+            Reserved::CloseBrace => "}",
+            Reserved::Case => "case",
+            Reserved::Do => "do",
+            Reserved::Done => "done",
+            Reserved::Elif => "elif",
+            Reserved::Else => "else",
+            Reserved::Esac => "esac",
+            Reserved::Fi => "fi",
+            Reserved::For => "for",
+            Reserved::If => "if",
+            Reserved::Then => "then",
+            Reserved::Until => "until",
+            Reserved::While => "while",
+        }
+    }
+
+    /// tells whether the reserved word terminates a compound list.
+    fn terminates(self: &Self) -> bool {
+        matches!(
+            self,
+            Reserved::CloseBrace
+                | Reserved::Do
+                | Reserved::Done
+                | Reserved::Elif
+                | Reserved::Else
+                | Reserved::Esac
+                | Reserved::Fi
+                | Reserved::Then
+        )
+    }
+}
+
 /// Parses a complete command.
 struct Parser {
     tokens: VecDeque<lexer::Token>,
+
+    /// Number of subshells and compound commands we're currently inside of.
+    depth: usize,
+
+    /// Number of if clauses and brace groups we're currently inside of.
+    compound: usize,
 }
 
 impl Parser {
     /// Creates a new parser instance
     fn new(tokens: VecDeque<lexer::Token>) -> Parser {
-        Parser { tokens: tokens }
+        Parser {
+            tokens: tokens,
+            depth: 0,
+            compound: 0,
+        }
+    }
+
+    /// Runs the shell parser for a whole program.
+    fn run_program(self: &mut Self) -> Result<Program> {
+        let mut program = Program {
+            commands: VecDeque::<_>::new(),
+        };
+        loop {
+            self.skip_newlines()?;
+            let token = self.read()?;
+            match token.kind {
+                lexer::Kind::EndOfLine => break,
+                _ => self.unread(token),
+            }
+            let cc = self.parse_complete_command()?;
+            program.commands.push_back(cc);
+            let token = self.read()?;
+            match token.kind {
+                lexer::Kind::Newline => (),
+                lexer::Kind::EndOfLine => break,
+                _ => return Err(Error::with_offset("expected newline or EOL", token.offset)),
+            }
+        }
+        Ok(program)
     }
 
     /// Runs the shell parser.
@@ -150,7 +463,7 @@ impl Parser {
         let token = self.read()?;
         match token.kind {
             lexer::Kind::EndOfLine => (),
-            _ => return Err(Error::new("expected EOL")),
+            _ => return Err(Error::with_offset("expected EOL", token.offset)),
         }
         Ok(cc)
     }
@@ -158,7 +471,24 @@ impl Parser {
     /// Parses a complete command.
     fn parse_complete_command(self: &mut Self) -> Result<CompleteCommand> {
         let mut cc = CompleteCommand::new();
+        let token = self.read()?;
+        cc.offset = token.offset;
+        self.unread(token);
         loop {
+            let token = self.read()?;
+            match Self::terminator(&token) {
+                Some(word) if self.compound == 0 => {
+                    return Err(Error::with_offset(
+                        &format!("unexpected '{}'", word),
+                        token.offset,
+                    ));
+                }
+                Some(_) => {
+                    self.unread(token);
+                    break;
+                }
+                None => self.unread(token),
+            }
             let mut pipeline = self.parse_pipeline()?;
             let token = self.read()?;
             match token.kind {
@@ -170,7 +500,12 @@ impl Parser {
                     pipeline.sync = false;
                     cc.pipelines.push_back(pipeline);
                 }
-                lexer::Kind::EndOfLine => {
+                lexer::Kind::Newline if self.depth > 0 => {
+                    pipeline.sync = true;
+                    cc.pipelines.push_back(pipeline);
+                    self.skip_newlines()?;
+                }
+                lexer::Kind::EndOfLine | lexer::Kind::Newline => {
                     pipeline.sync = true;
                     cc.pipelines.push_back(pipeline);
                     self.unread(token);
@@ -183,7 +518,7 @@ impl Parser {
                     break;
                 }
                 _ => {
-                    return Err(Error::new("expected ;&) or EOL"));
+                    return Err(Error::with_offset("expected ;&) or EOL", token.offset));
                 }
             }
         }
@@ -193,12 +528,41 @@ impl Parser {
     /// Parses a pipeline statement.
     fn parse_pipeline(self: &mut Self) -> Result<Pipeline> {
         let mut pipeline = Pipeline::new();
+        let token = self.read()?;
+        match Reserved::from_token(&token) {
+            Some(Reserved::Bang) => {
+                pipeline.negated = true;
+                let next = self.read()?;
+                let reserved = Reserved::from_token(&next);
+                match next.kind {
+                    lexer::Kind::CommandOrArgument | lexer::Kind::OpenBrace
+                        if reserved.is_none_or(|word| !word.terminates()) =>
+                    {
+                        self.unread(next)
+                    }
+                    _ => {
+                        return Err(Error::with_offset(
+                            "expected command after '!'",
+                            next.offset,
+                        ))
+                    }
+                }
+            }
+            _ => self.unread(token),
+        }
         loop {
             let command = self.parse_command()?;
             pipeline.commands.push_back(command);
             let token = self.read()?;
             match token.kind {
-                lexer::Kind::Pipe => (),
+                lexer::Kind::Pipe => {
+                    self.skip_newlines()?;
+                    let next = self.read()?;
+                    if let lexer::Kind::EndOfLine = next.kind {
+                        return Err(Self::unexpected("expected command after '|'", &next));
+                    }
+                    self.unread(next);
+                }
                 _ => {
                     self.unread(token);
                     break;
@@ -211,9 +575,24 @@ impl Parser {
     /// Parses a command statement.
     fn parse_command(self: &mut Self) -> Result<Command> {
         let token = self.read()?;
-        match token.kind {
-            lexer::Kind::OpenBrace => self.parse_subshell(),
-            _ => {
+        if let lexer::Kind::OpenBrace = token.kind {
+            return self.parse_subshell(token.offset);
+        }
+        match Reserved::from_token(&token) {
+            Some(Reserved::If) => self.parse_if(token.offset),
+            Some(Reserved::OpenBrace) => self.parse_brace_group(token.offset),
+            Some(Reserved::OpenBrackets) => self.parse_conditional(token.offset),
+            Some(word @ (Reserved::Case | Reserved::For | Reserved::Until | Reserved::While)) => {
+                Err(Error::with_offset(
+                    &format!("'{}' is not supported", word.as_str()),
+                    token.offset,
+                ))
+            }
+            Some(word) => Err(Error::with_offset(
+                &format!("unexpected '{}'", word.as_str()),
+                token.offset,
+            )),
+            None => {
                 self.unread(token);
                 self.parse_simple_command()
             }
@@ -221,29 +600,276 @@ impl Parser {
     }
 
     /// Parses a subshell command.
-    fn parse_subshell(self: &mut Self) -> Result<Command> {
+    fn parse_subshell(self: &mut Self, offset: usize) -> Result<Command> {
         // We have already consumed the '(' token
+        self.depth += 1;
+        self.skip_newlines()?;
         let cc = self.parse_complete_command()?;
+        self.depth -= 1;
         let token = self.read()?;
         match token.kind {
             lexer::Kind::CloseBrace => (),
-            _ => return Err(Error::new("expected ')' token")),
+            _ => return Err(Self::unexpected("expected ')' token", &token)),
         }
         let redirs = self.parse_redirs()?;
         Ok(Command::Subshell(Subshell {
             complete_command: cc,
             redirs: redirs,
+            offset: offset,
+        }))
+    }
+
+    /// Parses a brace group.
+    fn parse_brace_group(self: &mut Self, offset: usize) -> Result<Command> {
+        // We have already consumed the '{' word
+        self.depth += 1;
+        self.compound += 1;
+        let cc = self.parse_compound_list(&["}"])?;
+        self.read()?; // i.e., "}"
+        self.compound -= 1;
+        self.depth -= 1;
+        let redirs = self.parse_redirs()?;
+        Ok(Command::Group(BraceGroup {
+            complete_command: cc,
+            redirs: redirs,
+            offset: offset,
+        }))
+    }
+
+    /// Parses an if clause.
+    fn parse_if(self: &mut Self, offset: usize) -> Result<Command> {
+        // We have already consumed the 'if' word
+        self.depth += 1;
+        self.compound += 1;
+        let mut branches = VecDeque::<Branch>::new();
+        let mut otherwise = None;
+        // note: parse_compound_list ensures that the next token is one
+        // of the expected reserved words, so reading cannot fail
+        loop {
+            let condition = self.parse_compound_list(&["then"])?;
+            self.read()?;
+            let body = self.parse_compound_list(&["elif", "else", "fi"])?;
+            branches.push_back(Branch {
+                condition: condition,
+                body: body,
+            });
+            let token = self.read()?;
+            match token.value.as_str() {
+                "elif" => (),
+                "else" => {
+                    otherwise = Some(self.parse_compound_list(&["fi"])?);
+                    self.read()?;
+                    break;
+                }
+                _ => break, // i.e., "fi"
+            }
+        }
+        self.compound -= 1;
+        self.depth -= 1;
+        let redirs = self.parse_redirs()?;
+        Ok(Command::If(IfClause {
+            branches: branches,
+            otherwise: otherwise,
+            redirs: redirs,
+            offset: offset,
         }))
     }
 
+    /// Parses a conditional command.
+    fn parse_conditional(self: &mut Self, offset: usize) -> Result<Command> {
+        // We have already consumed the '[[' token
+        let condition = self.parse_or()?;
+        let token = self.read_in_conditional()?;
+        if !Self::is_word(&token, "]]") {
+            return Err(Self::unexpected("expected ']]'", &token));
+        }
+        let redirs = self.parse_redirs()?;
+        Ok(Command::Conditional(ConditionalCommand {
+            condition: condition,
+            redirs: redirs,
+            offset: offset,
+        }))
+    }
+
+    /// Parses conditional expressions separated by `||`.
+    fn parse_or(self: &mut Self) -> Result<Condition> {
+        let mut condition = self.parse_and()?;
+        while self.read_double(lexer::Kind::Pipe)? {
+            let right = self.parse_and()?;
+            condition = Condition::Or(Box::new(condition), Box::new(right));
+        }
+        Ok(condition)
+    }
+
+    /// Parses conditional expressions separated by `&&`.
+    fn parse_and(self: &mut Self) -> Result<Condition> {
+        let mut condition = self.parse_not()?;
+        while self.read_double(lexer::Kind::Ampersand)? {
+            let right = self.parse_not()?;
+            condition = Condition::And(Box::new(condition), Box::new(right));
+        }
+        Ok(condition)
+    }
+
+    /// Parses a conditional expression, which may be negated.
+    fn parse_not(self: &mut Self) -> Result<Condition> {
+        let token = self.read_in_conditional()?;
+        if Self::is_word(&token, "!") {
+            return Ok(Condition::Not(Box::new(self.parse_not()?)));
+        }
+        self.unread(token);
+        self.parse_primary()
+    }
+
+    /// Parses a parenthesized or a unary or binary conditional expression.
+    fn parse_primary(self: &mut Self) -> Result<Condition> {
+        let token = self.read_in_conditional()?;
+        match token.kind {
+            lexer::Kind::OpenBrace => {
+                let condition = self.parse_or()?;
+                let token = self.read_in_conditional()?;
+                return match token.kind {
+                    lexer::Kind::CloseBrace => Ok(condition),
+                    _ => Err(Self::unexpected("expected ')'", &token)),
+                };
+            }
+            lexer::Kind::CommandOrArgument if !Self::is_word(&token, "]]") => (),
+            _ => return Err(Self::unexpected("expected conditional expression", &token)),
+        }
+        let operand = self.read_in_conditional()?;
+        let unary = !token.quoted && UNARY_OPERATORS.contains(&token.value.as_str());
+        let word = matches!(operand.kind, lexer::Kind::CommandOrArgument);
+        if unary && word && !Self::is_word(&operand, "]]") {
+            return Ok(Condition::Unary(
+                token.value.clone(),
+                Word::from_token(operand),
+            ));
+        }
+        let operator = match operand.kind {
+            lexer::Kind::Minor => Some(String::from("<")),
+            lexer::Kind::Major if operand.value.len() == 0 => Some(String::from(">")),
+            lexer::Kind::CommandOrArgument
+                if !operand.quoted && BINARY_OPERATORS.contains(&operand.value.as_str()) =>
+            {
+                Some(operand.value.clone())
+            }
+            _ => None,
+        };
+        let operator = match operator {
+            None => {
+                self.unread(operand);
+                return Ok(Condition::Word(Word::from_token(token)));
+            }
+            Some(operator) if operator == "=~" => {
+                return Err(Error::with_offset("'=~' is not supported", operand.offset));
+            }
+            Some(operator) => operator,
+        };
+        let right = self.read_in_conditional()?;
+        match right.kind {
+            lexer::Kind::CommandOrArgument if !Self::is_word(&right, "]]") => Ok(
+                Condition::Binary(Word::from_token(token), operator, Word::from_token(right)),
+            ),
+            _ => Err(Self::unexpected(
+                &format!("expected operand after '{}'", operator),
+                &right,
+            )),
+        }
+    }
+
+    /// Reads two adjacent tokens of the given kind (i.e., `&&` or `||`),
+    /// returning whether we found them, in which case we consume them.
+    fn read_double(self: &mut Self, kind: lexer::Kind) -> Result<bool> {
+        let first = self.read_in_conditional()?;
+        if std::mem::discriminant(&first.kind) != std::mem::discriminant(&kind) {
+            self.unread(first);
+            return Ok(false);
+        }
+        let second = self.read()?;
+        if std::mem::discriminant(&second.kind) != std::mem::discriminant(&kind)
+            || second.offset != first.offset + 1
+        {
+            return Err(Error::with_offset(
+                "expected '&&' or '||' inside '[['",
+                first.offset,
+            ));
+        }
+        Ok(true)
+    }
+
+    /// Reads the next token inside a conditional command, skipping newlines.
+    fn read_in_conditional(self: &mut Self) -> Result<lexer::Token> {
+        self.skip_newlines()?;
+        self.read()
+    }
+
+    /// Tells whether the token is the given unquoted word.
+    fn is_word(token: &lexer::Token, word: &str) -> bool {
+        matches!(token.kind, lexer::Kind::CommandOrArgument) && !token.quoted && token.value == word
+    }
+
+    /// parses a compound list, which must contain at least a command
+    /// and must be followed by one of the expected reserved words.
+    fn parse_compound_list(self: &mut Self, expected: &[&str]) -> Result<CompleteCommand> {
+        self.skip_newlines()?;
+        let cc = self.parse_complete_command()?;
+        let token = self.read()?;
+        let word = match Self::terminator(&token) {
+            Some(word) if expected.contains(&word) => word,
+            _ => {
+                let expected: Vec<String> = expected.iter().map(|w| format!("'{}'", w)).collect();
+                return Err(Self::unexpected(
+                    &format!("expected {}", expected.join(" or ")),
+                    &token,
+                ));
+            }
+        };
+        let empty = cc.pipelines.iter().all(|p| match p.commands.front() {
+            Some(Command::SimpleCommand(scmd)) => scmd.arguments.len() == 0,
+            _ => false,
+        });
+        if empty {
+            return Err(Error::with_offset(
+                &format!("expected command before '{}'", word),
+                token.offset,
+            ));
+        }
+        self.unread(token);
+        Ok(cc)
+    }
+
+    /// returns an error for the unexpected token, which is incomplete
+    /// when the token is the end of the input (see Error::incomplete).
+    fn unexpected(reason: &str, token: &lexer::Token) -> Error {
+        match token.kind {
+            lexer::Kind::EndOfLine => Error::incomplete(reason, token.offset),
+            _ => Error::with_offset(reason, token.offset),
+        }
+    }
+
+    /// returns the reserved word terminating a compound list, if the
+    /// token is such a word.
+    fn terminator(token: &lexer::Token) -> Option<&'static str> {
+        match Reserved::from_token(token) {
+            Some(word) if word.terminates() => Some(word.as_str()),
+            _ => None,
+        }
+    }
+
     /// Parses a simple command.
     fn parse_simple_command(self: &mut Self) -> Result<Command> {
         let mut scmd = SimpleCommand::new();
+        let token = self.read()?;
+        scmd.offset = token.offset;
+        self.unread(token);
         loop {
             let token = self.read()?;
             match token.kind {
+                lexer::Kind::CommandOrArgument if token.assignment && scmd.arguments.len() == 0 => {
+                    scmd.assignments.push_back(Assignment::from_token(token));
+                }
                 lexer::Kind::CommandOrArgument => {
-                    scmd.arguments.push_back(token.value);
+                    scmd.arguments.push_back(Word::from_token(token));
                 }
                 _ => {
                     self.unread(token);
@@ -261,43 +887,110 @@ impl Parser {
         let mut redirs = RedirectList::new();
         loop {
             let token = self.read()?;
-            match token.kind {
+            let redir = match token.kind {
                 lexer::Kind::Minor => {
                     let value = self.read_command_or_argument_token()?;
-                    redirs.input.push_front(InputRedir {
-                        filename: value.value,
-                    });
+                    Redirect::Input(InputRedir::File(Word::from_token(value)))
+                }
+                lexer::Kind::MinorMinorMinor => {
+                    let value = self.read_command_or_argument_token()?;
+                    Redirect::Input(InputRedir::HereString(Word::from_token(value)))
+                }
+                lexer::Kind::MinorMinor | lexer::Kind::MinorMinorDash => {
+                    let value = self.read_command_or_argument_token()?;
+                    Redirect::Input(InputRedir::HereDoc(HereDoc {
+                        delimiter: Word::from_token(value),
+                        body: token.value,
+                    }))
+                }
+                lexer::Kind::Major | lexer::Kind::MajorMajor | lexer::Kind::MajorPipe => {
+                    let fd = Self::io_number(&token.value, 1, token.offset)?;
+                    let value = self.read_command_or_argument_token()?;
+                    Redirect::Output(OutputRedir {
+                        fd: fd,
+                        filename: Word::from_token(value),
+                        overwrite: !matches!(token.kind, lexer::Kind::MajorMajor),
+                        force: matches!(token.kind, lexer::Kind::MajorPipe),
+                    })
                 }
-                lexer::Kind::Major => {
+                lexer::Kind::AmpersandMajor | lexer::Kind::AmpersandMajorMajor => {
                     let value = self.read_command_or_argument_token()?;
-                    redirs.output.push_front(OutputRedir {
-                        filename: value.value,
-                        overwrite: true,
-                    });
+                    Redirect::Combined(OutputRedir {
+                        fd: 1,
+                        filename: Word::from_token(value),
+                        overwrite: matches!(token.kind, lexer::Kind::AmpersandMajor),
+                        force: false,
+                    })
                 }
-                lexer::Kind::MajorMajor => {
+                lexer::Kind::MajorAmpersand | lexer::Kind::MinorAmpersand => {
+                    let default = match token.kind {
+                        lexer::Kind::MinorAmpersand => 0,
+                        _ => 1,
+                    };
+                    let fd = Self::io_number(&token.value, default, token.offset)?;
                     let value = self.read_command_or_argument_token()?;
-                    redirs.output.push_front(OutputRedir {
-                        filename: value.value,
-                        overwrite: false,
-                    });
+                    if value.value == "-" && !value.quoted {
+                        redirs.redirs.push_back(Redirect::Close(fd));
+                        continue;
+                    }
+                    let target = Self::io_number(&value.value, -1, value.offset)?;
+                    if target < 0 || value.quoted {
+                        return Err(Error::with_offset(
+                            "expected file descriptor number",
+                            value.offset,
+                        ));
+                    }
+                    Redirect::Duplicate(DupRedir {
+                        fd: fd,
+                        target: target,
+                    })
                 }
                 _ => {
                     self.unread(token);
                     break;
                 }
-            }
+            };
+            redirs.redirs.push_back(redir);
         }
         Ok(redirs)
     }
 
+    /// parses the file descriptor number of a redirection, returning
+    /// the given default value when the number is missing.
+    fn io_number(value: &str, default: i32, offset: usize) -> Result<i32> {
+        if value.len() == 0 {
+            return Ok(default);
+        }
+        match value.parse::<i32>() {
+            Err(_) => Err(Error::with_offset("invalid file descriptor number", offset)),
+            Ok(fd) => Ok(fd),
+        }
+    }
+
     /// Returns the next CommandOrArgument token or an error if
     /// we cannot find a token of this type in the input.
     fn read_command_or_argument_token(self: &mut Self) -> Result<lexer::Token> {
         let token = self.read()?;
         match token.kind {
             lexer::Kind::CommandOrArgument => Ok(token),
-            _ => Err(Error::new("expected CommandOrArgument token")),
+            _ => Err(Error::with_offset(
+                "expected CommandOrArgument token",
+                token.offset,
+            )),
+        }
+    }
+
+    /// Skips the Newline tokens, if any.
+    fn skip_newlines(self: &mut Self) -> Result<()> {
+        loop {
+            let token = self.read()?;
+            match token.kind {
+                lexer::Kind::Newline => (),
+                _ => {
+                    self.unread(token);
+                    return Ok(());
+                }
+            }
         }
     }
 
@@ -0,0 +1,288 @@
+//! Structural diff of the execution plans of two scripts.
+//!
+//! We translate each script into its execution plan and flatten the plan
+//! into a sequence of commands. Then, we match the commands of the two
+//! scripts by their arguments using the longest common subsequence and
+//! report the commands that were added, removed or moved, as well as the
+//! matching commands whose redirections (or pipes) or assignments changed.
+
+use crate::model::{Assignment, InputRedir, Redirect, Result, Word};
+use crate::translator::{CompoundSerialCommand, ListOfCommands};
+use crate::{lexer, parser, script, translator};
+use std::collections::VecDeque;
+
+/// A command inside the flattened execution plan.
+#[derive(Debug)]
+struct Entry {
+    /// The command's arguments separated by spaces.
+    command: String,
+
+    /// The redirections, pipes and sync and negated flags of the command.
+    details: String,
+}
+
+/// Compares the plans of the two scripts and prints their differences.
+/// Returns whether the plans are identical.
+pub fn diff(old: &str, new: &str) -> Result<bool> {
+    let old_entries = load(old)?;
+    let new_entries = load(new)?;
+    let pairs = lcs(&old_entries, &new_entries);
+    let mut old_matched = vec![false; old_entries.len()];
+    let mut new_matched = vec![false; new_entries.len()];
+    let mut lines = Vec::<String>::new();
+    for (i, j) in pairs.iter() {
+        old_matched[*i] = true;
+        new_matched[*j] = true;
+        let (o, n) = (&old_entries[*i], &new_entries[*j]);
+        if o.details != n.details {
+            lines.push(format!(
+                "~ [{}->{}] {}: {} -> {}",
+                i + 1,
+                j + 1,
+                n.command,
+                show(&o.details),
+                show(&n.details)
+            ));
+        }
+    }
+    // note: a command that was removed and added again has moved
+    for i in 0..old_entries.len() {
+        if old_matched[i] {
+            continue;
+        }
+        let moved = (0..new_entries.len())
+            .find(|j| !new_matched[*j] && new_entries[*j].command == old_entries[i].command);
+        if let Some(j) = moved {
+            old_matched[i] = true;
+            new_matched[j] = true;
+            lines.push(format!(
+                "> [{}->{}] {} (moved)",
+                i + 1,
+                j + 1,
+                new_entries[j].command
+            ));
+        }
+    }
+    for (i, entry) in old_entries.iter().enumerate() {
+        if !old_matched[i] {
+            lines.push(format!("- [{}] {}", i + 1, entry.command));
+        }
+    }
+    for (j, entry) in new_entries.iter().enumerate() {
+        if !new_matched[j] {
+            lines.push(format!("+ [{}] {}", j + 1, entry.command));
+        }
+    }
+    if lines.len() == 0 {
+        return Ok(true);
+    }
+    println!("--- {}", old);
+    println!("+++ {}", new);
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(false)
+}
+
+/// Loads a script and flattens its execution plan.
+fn load(filename: &str) -> Result<Vec<Entry>> {
+    let script = script::load(filename)?;
+    let tokens = match lexer::scan(script.content.clone(), &mut || None) {
+        Err(err) => return Err(script.locate(err, 0)),
+        Ok(tokens) => tokens,
+    };
+    let program = match parser::parse_program(tokens) {
+        Err(err) => return Err(script.locate(err, 0)),
+        Ok(program) => program,
+    };
+    let mut entries = Vec::<Entry>::new();
+    for cc in program.commands {
+        let offset = cc.offset;
+        match translator::translate(cc, None, false) {
+            Err(err) => return Err(script.locate(err, offset)),
+            Ok(loc) => flatten(loc, &mut entries),
+        }
+    }
+    Ok(entries)
+}
+
+/// Flattens the execution plan into a sequence of entries.
+fn flatten(loc: ListOfCommands, entries: &mut Vec<Entry>) {
+    for csc in loc.pipelines {
+        match csc {
+            CompoundSerialCommand::SingleCommand(sc) => {
+                entries.push(entry(
+                    &sc.assignments,
+                    &sc.arguments,
+                    &sc.redirs,
+                    "",
+                    "",
+                    sc.sync,
+                    sc.negated,
+                ));
+            }
+            CompoundSerialCommand::PipelinedCommands(pc) => {
+                entries.push(entry(
+                    &pc.source.assignments,
+                    &pc.source.arguments,
+                    &pc.source.redirs,
+                    "",
+                    "|",
+                    pc.sync,
+                    pc.negated,
+                ));
+                for filter in pc.filters.iter() {
+                    entries.push(entry(
+                        &filter.assignments,
+                        &filter.arguments,
+                        &filter.redirs,
+                        "|",
+                        "|",
+                        pc.sync,
+                        pc.negated,
+                    ));
+                }
+                entries.push(entry(
+                    &pc.sink.assignments,
+                    &pc.sink.arguments,
+                    &pc.sink.redirs,
+                    "|",
+                    "",
+                    pc.sync,
+                    pc.negated,
+                ));
+            }
+            CompoundSerialCommand::GroupCommand(gc) => {
+                let mut keyword = vec![String::from("{")];
+                keyword.extend(gc.redirs.iter().map(describe));
+                if gc.negated {
+                    keyword.insert(0, String::from("!"));
+                }
+                nested(gc.body, &keyword.join(" "), entries);
+            }
+            CompoundSerialCommand::IfCommand(ic) => {
+                for (index, (condition, body)) in ic.branches.into_iter().enumerate() {
+                    let keyword = if index > 0 { "elif" } else { "if" };
+                    nested(condition, keyword, entries);
+                    nested(body, "then", entries);
+                }
+                if let Some(otherwise) = ic.otherwise {
+                    nested(otherwise, "else", entries);
+                }
+            }
+            CompoundSerialCommand::TestCommand(tc) => {
+                let details = if tc.negated { "!" } else { "" };
+                entries.push(Entry {
+                    command: format!("[[ {} ]]", tc.condition),
+                    details: String::from(details),
+                });
+            }
+        }
+    }
+}
+
+/// Flattens the plan nested inside an if command or a group command,
+/// marking the entries with the reserved word introducing the plan.
+fn nested(loc: ListOfCommands, keyword: &str, entries: &mut Vec<Entry>) {
+    let mut inner = Vec::<Entry>::new();
+    flatten(loc, &mut inner);
+    for mut entry in inner {
+        entry.details = format!("({}) {}", keyword, entry.details)
+            .trim_end()
+            .to_string();
+        entries.push(entry);
+    }
+}
+
+/// Creates a new entry.
+fn entry(
+    assignments: &VecDeque<Assignment>,
+    arguments: &VecDeque<Word>,
+    redirs: &VecDeque<Redirect>,
+    input: &str,
+    output: &str,
+    sync: bool,
+    negated: bool,
+) -> Entry {
+    let mut details = Vec::<String>::new();
+    if negated {
+        details.push(String::from("!"));
+    }
+    for assignment in assignments.iter() {
+        details.push(format!("{}={}", assignment.name, assignment.value.value));
+    }
+    if input.len() > 0 {
+        details.push(format!("{} (stdin)", input));
+    }
+    for redir in redirs.iter() {
+        details.push(describe(redir));
+    }
+    if output.len() > 0 {
+        details.push(format!("{} (stdout)", output));
+    }
+    if !sync {
+        details.push(String::from("&"));
+    }
+    Entry {
+        command: arguments
+            .iter()
+            .map(|word| word.value.as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+        details: details.join(" "),
+    }
+}
+
+/// Describes a redirection using the shell syntax.
+fn describe(redir: &Redirect) -> String {
+    match redir {
+        Redirect::Input(InputRedir::File(filename)) => format!("<{}", filename.value),
+        Redirect::Input(InputRedir::HereDoc(heredoc)) => format!("<<{}", heredoc.delimiter.value),
+        Redirect::Input(InputRedir::HereString(word)) => format!("<<<{}", word.value),
+        Redirect::Output(output) => match (output.overwrite, output.force) {
+            (false, _) => format!("{}>>{}", output.fd, output.filename.value),
+            (true, false) => format!("{}>{}", output.fd, output.filename.value),
+            (true, true) => format!("{}>|{}", output.fd, output.filename.value),
+        },
+        Redirect::Duplicate(dup) => format!("{}>&{}", dup.fd, dup.target),
+        Redirect::Close(fd) => format!("{}>&-", fd),
+        Redirect::Combined(output) => format!("&>{}", output.filename.value),
+    }
+}
+
+/// Shows the details of an entry, which may be empty.
+fn show(details: &str) -> &str {
+    match details.len() {
+        0 => "(none)",
+        _ => details,
+    }
+}
+
+/// Returns the pairs of indexes of the longest common subsequence of
+/// the two sequences of entries, matching entries by command.
+fn lcs(old: &[Entry], new: &[Entry]) -> Vec<(usize, usize)> {
+    let (n, m) = (old.len(), new.len());
+    let mut table = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i][j] = match old[i].command == new[j].command {
+                true => table[i + 1][j + 1] + 1,
+                false => table[i + 1][j].max(table[i][j + 1]),
+            };
+        }
+    }
+    let mut pairs = Vec::<(usize, usize)>::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i].command == new[j].command {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
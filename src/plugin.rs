@@ -0,0 +1,259 @@
+//! Runs builtins implemented by WASM plugins.
+//!
+//! When the shell is built with the `plugins` feature, a command that is
+//! not a builtin runs the `NAME.wasm` module inside the plugin directory,
+//! if it exists, where `NAME` is the command name, or the module loaded
+//! using `enable -f FILE NAME`. The plugin directory is `$XV6SH_PLUGINS`
+//! or, if unset, `$HOME/.xv6sh/plugins`. We compile each
+//! module the first time we need it and we create a fresh instance for
+//! each invocation, so that invocations do not share state.
+//!
+//! Plugins are sandboxed: they can only use the functions the shell
+//! imports into them and they run with a fuel budget, so that a plugin
+//! stuck in a loop fails rather than hanging the shell. Version 1 of the
+//! host API consists of these functions, where strings are UTF-8 bytes in
+//! the module's exported `memory`:
+//!
+//! - the module exports `xv6sh_api_version() -> i32`, returning 1;
+//!
+//! - the module exports `xv6sh_alloc(len: i32) -> i32`, returning the
+//!   address of a buffer of `len` bytes, in which the shell writes the
+//!   arguments, each terminated by a NUL byte;
+//!
+//! - the module exports `xv6sh_main(argv: i32, len: i32) -> i32`, which
+//!   runs the builtin with the arguments the shell wrote and returns its
+//!   exit status;
+//!
+//! - the module may import `xv6sh.write(fd: i32, ptr: i32, len: i32) ->
+//!   i32`, which writes to the standard output (1) or error (2) of the shell
+//!   and returns the number of bytes written or -1;
+//!
+//! - the module may import `xv6sh.getenv(name: i32, name_len: i32, buf:
+//!   i32, buf_len: i32) -> i32`, which copies the value of the environment
+//!   variable into the buffer, if it fits, and returns the length of the
+//!   value or -1 if the variable is not set.
+
+use crate::model::{Error, Result};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store};
+
+/// The version of the host API we implement.
+const API_VERSION: i32 = 1;
+
+/// The fuel we give to each invocation (roughly, the number of
+/// WASM instructions a plugin may execute).
+const FUEL: u64 = 100_000_000;
+
+/// The engine, the modules we have already compiled and the builtins
+/// loaded with `enable -f`.
+struct Plugins {
+    engine: Engine,
+    modules: HashMap<PathBuf, Arc<Module>>,
+    builtins: HashMap<String, PathBuf>,
+}
+
+/// The plugins we have loaded so far.
+static PLUGINS: Lazy<Mutex<Plugins>> = Lazy::new(|| {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    Mutex::new(Plugins {
+        engine: Engine::new(&config),
+        modules: HashMap::new(),
+        builtins: HashMap::new(),
+    })
+});
+
+/// Returns the plugin directory, if any.
+fn directory() -> Option<PathBuf> {
+    match (std::env::var("XV6SH_PLUGINS"), std::env::var("HOME")) {
+        (Ok(dir), _) if dir.len() > 0 => Some(PathBuf::from(dir)),
+        (_, Ok(home)) if home.len() > 0 => Some(PathBuf::from(home).join(".xv6sh/plugins")),
+        _ => None,
+    }
+}
+
+/// Loads the module in the given file as the builtin with the given name
+/// (i.e., `enable -f FILE NAME`), which takes precedence over the modules
+/// inside the plugin directory.
+pub fn register(name: &str, path: &str) -> Result<()> {
+    let path = PathBuf::from(path);
+    load(&path).map_err(|err| prefix(name, err))?;
+    let mut plugins = PLUGINS.lock().unwrap();
+    plugins.builtins.insert(String::from(name), path);
+    Ok(())
+}
+
+/// Tells whether there is a plugin implementing the given builtin.
+pub fn exists(name: &str) -> bool {
+    path(name).is_some()
+}
+
+/// Returns the path of the module implementing the given builtin, if any.
+fn path(name: &str) -> Option<PathBuf> {
+    if let Some(path) = PLUGINS.lock().unwrap().builtins.get(name) {
+        return Some(path.clone());
+    }
+    if name.len() == 0 || name.contains('/') || name.starts_with('.') {
+        return None;
+    }
+    let path = directory()?.join(format!("{}.wasm", name));
+    match path.is_file() {
+        true => Some(path),
+        false => None,
+    }
+}
+
+/// Runs the builtin implemented by the plugin with the given name, if any,
+/// and returns its exit status, or None if there is no such plugin.
+pub fn run(name: &str, args: &VecDeque<String>) -> Result<Option<i32>> {
+    let path = match path(name) {
+        None => return Ok(None),
+        Some(path) => path,
+    };
+    let (engine, module) = load(&path).map_err(|err| prefix(name, err))?;
+    let mut argv = Vec::<u8>::new();
+    for arg in std::iter::once(name).chain(args.iter().map(|arg| arg.as_str())) {
+        argv.extend_from_slice(arg.as_bytes());
+        argv.push(0);
+    }
+    match invoke(&engine, &module, &argv) {
+        Err(err) => Err(prefix(name, err)),
+        Ok(status) => Ok(Some(status)),
+    }
+}
+
+/// Prefixes the error with the name of the plugin.
+fn prefix(name: &str, err: Error) -> Error {
+    Error::new(&format!("{}: plugin: {}", name, err))
+}
+
+/// Returns the module at the given path, compiling it if needed.
+fn load(path: &PathBuf) -> Result<(Engine, Arc<Module>)> {
+    let mut plugins = PLUGINS.lock().unwrap();
+    let Plugins {
+        engine, modules, ..
+    } = &mut *plugins;
+    if let Some(module) = modules.get(path) {
+        return Ok((engine.clone(), Arc::clone(module)));
+    }
+    let bytes = match std::fs::read(path) {
+        Err(err) => return Err(Error::new(&err.to_string())),
+        Ok(bytes) => bytes,
+    };
+    let module = match Module::new(engine, &bytes[..]) {
+        Err(err) => return Err(Error::new(&err.to_string())),
+        Ok(module) => Arc::new(module),
+    };
+    modules.insert(path.clone(), Arc::clone(&module));
+    Ok((engine.clone(), module))
+}
+
+/// Instantiates the module and calls its main function.
+fn invoke(engine: &Engine, module: &Module, argv: &[u8]) -> Result<i32> {
+    let wrap = |err: &dyn std::fmt::Display| Error::new(&err.to_string());
+    let mut store = Store::new(engine, ());
+    store.add_fuel(FUEL).map_err(|err| wrap(&err))?;
+    let mut linker = <Linker<()>>::new(engine);
+    linker
+        .func_wrap("xv6sh", "write", host_write)
+        .map_err(|err| wrap(&err))?;
+    linker
+        .func_wrap("xv6sh", "getenv", host_getenv)
+        .map_err(|err| wrap(&err))?;
+    let instance = linker
+        .instantiate(&mut store, module)
+        .and_then(|pre| pre.start(&mut store))
+        .map_err(|err| wrap(&err))?;
+    let version = instance
+        .get_typed_func::<(), i32>(&store, "xv6sh_api_version")
+        .map_err(|err| wrap(&err))?
+        .call(&mut store, ())
+        .map_err(|err| wrap(&err))?;
+    if version != API_VERSION {
+        return Err(Error::new(&format!("unsupported API version {}", version)));
+    }
+    let memory = match instance.get_memory(&store, "memory") {
+        None => return Err(Error::new("missing memory export")),
+        Some(memory) => memory,
+    };
+    let len = argv.len() as i32;
+    let ptr = instance
+        .get_typed_func::<i32, i32>(&store, "xv6sh_alloc")
+        .map_err(|err| wrap(&err))?
+        .call(&mut store, len)
+        .map_err(|err| wrap(&err))?;
+    memory
+        .write(&mut store, ptr as u32 as usize, argv)
+        .map_err(|err| wrap(&err))?;
+    let status = instance
+        .get_typed_func::<(i32, i32), i32>(&store, "xv6sh_main")
+        .map_err(|err| wrap(&err))?
+        .call(&mut store, (ptr, len))
+        .map_err(|err| wrap(&err))?;
+    let _ = std::io::stdout().flush();
+    Ok(status)
+}
+
+/// Returns the memory exported by the calling plugin.
+fn memory(caller: &Caller<'_, ()>) -> Option<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory)
+}
+
+/// Reads the given range of the plugin's memory.
+fn read(caller: &Caller<'_, ()>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let mut buffer = vec![0u8; usize::try_from(len).ok()?];
+    memory(caller)?
+        .read(caller, ptr as u32 as usize, &mut buffer)
+        .ok()?;
+    Some(buffer)
+}
+
+/// Implements `xv6sh.write`.
+fn host_write(caller: Caller<'_, ()>, fd: i32, ptr: i32, len: i32) -> i32 {
+    let buffer = match read(&caller, ptr, len) {
+        None => return -1,
+        Some(buffer) => buffer,
+    };
+    let written = match fd {
+        1 => std::io::stdout().write_all(&buffer),
+        2 => std::io::stderr().write_all(&buffer),
+        _ => return -1,
+    };
+    match written {
+        Err(_) => -1,
+        Ok(_) => len,
+    }
+}
+
+/// Implements `xv6sh.getenv`.
+fn host_getenv(
+    mut caller: Caller<'_, ()>,
+    name: i32,
+    name_len: i32,
+    buf: i32,
+    buf_len: i32,
+) -> i32 {
+    let name = match read(&caller, name, name_len).map(String::from_utf8) {
+        Some(Ok(name)) => name,
+        _ => return -1,
+    };
+    let value = match std::env::var(name) {
+        Err(_) => return -1,
+        Ok(value) => value,
+    };
+    if value.len() <= buf_len.max(0) as usize {
+        if let Some(memory) = memory(&caller) {
+            if memory
+                .write(&mut caller, buf as u32 as usize, value.as_bytes())
+                .is_err()
+            {
+                return -1;
+            }
+        }
+    }
+    value.len() as i32
+}
@@ -0,0 +1,274 @@
+//! Implements the printf builtin.
+//!
+//! The format may contain the escapes `\n`, `\t`, `\r`, `\a`, `\b`, `\f`,
+//! `\v`, `\e` and `\\` and the conversions `%s` (a string), `%b` (a string
+//! whose escapes we expand), `%c` (the first char of a string), `%d` and
+//! `%i` (a decimal number), `%u`, `%o`, `%x` and `%X` (an unsigned decimal,
+//! octal or hexadecimal number) and `%%` (a `%` sign). A conversion may
+//! have the `-` (align left), `0` (pad with zeros) and `+` (show the sign)
+//! flags, a width and a precision (e.g., `%-10.3s`).
+//!
+//! The `%(FORMAT)T` conversion formats the argument, which is the number
+//! of seconds since the Unix epoch, according to the local time rules using
+//! strftime's FORMAT (e.g., `printf '%(%F %T)T\n' 0`). When the argument
+//! is missing or is `-1`, we format the current time, so that scripts may
+//! obtain the date without running `date`.
+//!
+//! Like the printf command, we reuse the format as long as there are
+//! arguments left and missing arguments count as empty strings or zero.
+
+use crate::model::{Error, Result};
+use crate::{deterministic, expansion};
+use std::collections::VecDeque;
+use std::ffi::CString;
+
+/// Formats the arguments according to the format.
+pub fn format(format: &str, mut args: VecDeque<String>) -> Result<String> {
+    let format: Vec<char> = format.chars().collect();
+    let mut output = String::new();
+    loop {
+        let consumed = args.len();
+        Formatter {
+            format: &format,
+            index: 0,
+            args: &mut args,
+            output: &mut output,
+        }
+        .run()?;
+        // note: stop if the format has no conversion consuming arguments
+        if args.len() == 0 || args.len() == consumed {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// Formats the given time, or the current time if None, using strftime.
+pub fn format_time(format: &str, time: Option<i64>) -> String {
+    let format = match CString::new(format) {
+        Err(_) => return String::new(),
+        Ok(format) => format,
+    };
+    let mut buffer = [0u8; 256];
+    let length = unsafe {
+        let time = match time {
+            Some(time) => time as libc::time_t,
+            None => deterministic::now().as_secs() as libc::time_t,
+        };
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+extern "C" {
+    // note: the libc crate does not bind strftime
+    fn strftime(
+        s: *mut libc::c_char,
+        max: libc::size_t,
+        format: *const libc::c_char,
+        tm: *const libc::tm,
+    ) -> libc::size_t;
+}
+
+/// Formats the arguments using the format once.
+struct Formatter<'a> {
+    format: &'a [char],
+    index: usize,
+    args: &'a mut VecDeque<String>,
+    output: &'a mut String,
+}
+
+/// The flags, width and precision of a conversion.
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    /// formats the whole format.
+    fn run(self: &mut Self) -> Result<()> {
+        while let Some(c) = self.next() {
+            match c {
+                '\\' => {
+                    let c = self.next();
+                    escape(c, self.output);
+                }
+                '%' => self.conversion()?,
+                c => self.output.push(c),
+            }
+        }
+        Ok(())
+    }
+
+    /// returns the next char of the format, if any.
+    fn next(self: &mut Self) -> Option<char> {
+        let c = self.format.get(self.index).copied();
+        self.index += 1;
+        c
+    }
+
+    /// returns the next argument or the empty string.
+    fn arg(self: &mut Self) -> String {
+        self.args.pop_front().unwrap_or_default()
+    }
+
+    /// formats the conversion following a `%` sign.
+    fn conversion(self: &mut Self) -> Result<()> {
+        let mut spec = Spec {
+            left: false,
+            zero: false,
+            plus: false,
+            width: 0,
+            precision: None,
+        };
+        let mut c = self.next();
+        while let Some(flag) = c.filter(|c| "-0+".contains(*c)) {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                _ => spec.plus = true,
+            }
+            c = self.next();
+        }
+        spec.width = self.digits(&mut c).unwrap_or(0);
+        if c == Some('.') {
+            c = self.next();
+            spec.precision = Some(self.digits(&mut c).unwrap_or(0));
+        }
+        let value = match c {
+            Some('%') => String::from("%"),
+            Some('s') => {
+                let arg = self.arg();
+                truncate(arg, spec.precision)
+            }
+            Some('b') => {
+                let mut value = String::new();
+                let arg: Vec<char> = self.arg().chars().collect();
+                let mut chars = arg.into_iter();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => escape(chars.next(), &mut value),
+                        c => value.push(c),
+                    }
+                }
+                truncate(value, spec.precision)
+            }
+            Some('c') => self.arg().chars().take(1).collect(),
+            Some(conv) if "diuoxX".contains(conv) => {
+                let arg = self.arg();
+                let number = match arg.trim() {
+                    "" => 0,
+                    arg => expansion::number(arg)
+                        .map_err(|_| Error::new(&format!("printf: {}: invalid number", arg)))?,
+                };
+                let (negative, digits) = match conv {
+                    'd' | 'i' => (number < 0, number.unsigned_abs().to_string()),
+                    'u' => (false, (number as u64).to_string()),
+                    'o' => (false, format!("{:o}", number as u64)),
+                    'x' => (false, format!("{:x}", number as u64)),
+                    _ => (false, format!("{:X}", number as u64)),
+                };
+                let sign = match (negative, spec.plus) {
+                    (true, _) => "-",
+                    (false, true) if "di".contains(conv) => "+",
+                    _ => "",
+                };
+                if spec.zero && !spec.left && sign.len() + digits.len() < spec.width {
+                    let zeros = "0".repeat(spec.width - sign.len() - digits.len());
+                    format!("{}{}{}", sign, zeros, digits)
+                } else {
+                    format!("{}{}", sign, digits)
+                }
+            }
+            Some('(') => {
+                let start = self.index;
+                let length = match self.format[start..].iter().position(|c| *c == ')') {
+                    None => return Err(Error::new("printf: missing ')' in %(...)T")),
+                    Some(length) => length,
+                };
+                let time_format: String = self.format[start..start + length].iter().collect();
+                self.index += length + 1;
+                if self.next() != Some('T') {
+                    return Err(Error::new("printf: expected 'T' after %(...)"));
+                }
+                let arg = self.arg();
+                let time = match arg.trim() {
+                    "" | "-1" => None,
+                    arg => match arg.parse::<i64>() {
+                        Err(_) => {
+                            return Err(Error::new(&format!("printf: {}: invalid time", arg)))
+                        }
+                        Ok(time) => Some(time),
+                    },
+                };
+                truncate(format_time(&time_format, time), spec.precision)
+            }
+            Some(c) => return Err(Error::new(&format!("printf: %{}: invalid conversion", c))),
+            None => return Err(Error::new("printf: missing conversion after '%'")),
+        };
+        let padding = spec.width.saturating_sub(value.chars().count());
+        match spec.left {
+            true => {
+                self.output.push_str(&value);
+                self.output.push_str(&" ".repeat(padding));
+            }
+            false => {
+                self.output.push_str(&" ".repeat(padding));
+                self.output.push_str(&value);
+            }
+        }
+        Ok(())
+    }
+
+    /// reads the decimal number starting with the current char, if any,
+    /// leaving the first following char in c.
+    fn digits(self: &mut Self, c: &mut Option<char>) -> Option<usize> {
+        let mut number = None;
+        while let Some(digit) = c.and_then(|c| c.to_digit(10)) {
+            number = Some(number.unwrap_or(0) * 10 + digit as usize);
+            *c = self.next();
+        }
+        number
+    }
+}
+
+/// Appends the char corresponding to the escape whose backslash
+/// precedes the given char, keeping unknown escapes as they are.
+fn escape(c: Option<char>, output: &mut String) {
+    match c {
+        None => output.push('\\'),
+        Some('n') => output.push('\n'),
+        Some('t') => output.push('\t'),
+        Some('r') => output.push('\r'),
+        Some('a') => output.push('\u{7}'),
+        Some('b') => output.push('\u{8}'),
+        Some('f') => output.push('\u{c}'),
+        Some('v') => output.push('\u{b}'),
+        Some('e') => output.push('\u{1b}'),
+        Some('\\') => output.push('\\'),
+        Some(c) => {
+            output.push('\\');
+            output.push(c);
+        }
+    }
+}
+
+/// Truncates the string to the given precision, if any.
+fn truncate(value: String, precision: Option<usize>) -> String {
+    match precision {
+        None => value,
+        Some(precision) => value.chars().take(precision).collect(),
+    }
+}
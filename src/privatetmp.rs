@@ -0,0 +1,89 @@
+//! Gives each subshell its own temporary directory.
+//!
+//! With `set -o privatetmp`, before spawning a subshell (e.g., `( ... )`
+//! or a brace group running in background), the shell creates a new
+//! directory inside the current temporary directory and points the
+//! `$TMPDIR` of the subshell to it, so that tools writing predictable
+//! temporary file names do not clash when subshells run in parallel with
+//! `&`. We remove the directory, along with its content, once we know the
+//! subshell exited, i.e., when we wait for it or the PeriodicReaper reaps
+//! it. A subshell still running when the shell exits keeps its directory.
+
+use crate::model::{Error, Process, Result};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of directories we created, which makes their names unique.
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// A subshell owning a private temporary directory.
+struct PrivateProcess {
+    proc: Box<dyn Process>,
+
+    /// The directory, until we remove it.
+    dir: Option<PathBuf>,
+}
+
+/// Creates a new private temporary directory, readable only by us.
+pub fn create() -> Result<PathBuf> {
+    let count = CREATED.fetch_add(1, Ordering::Relaxed);
+    let name = format!("xv6sh-tmp-{}-{}", std::process::id(), count);
+    let dir = std::env::temp_dir().join(name);
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(err) => Err(Error::new(&format!("{}: {}", dir.display(), err))),
+        Ok(_) => Ok(dir),
+    }
+}
+
+/// Removes the given private temporary directory.
+pub fn remove(dir: &PathBuf) {
+    let _ = std::fs::remove_dir_all(dir); // best effort
+}
+
+/// Makes the process own the directory, which we remove once it exits.
+pub fn attach(proc: Box<dyn Process>, dir: PathBuf) -> Box<dyn Process> {
+    Box::new(PrivateProcess {
+        proc: proc,
+        dir: Some(dir),
+    })
+}
+
+impl PrivateProcess {
+    /// Removes the directory if the process has exited.
+    fn cleanup(
+        self: &mut Self,
+        status: std::io::Result<Option<ExitStatus>>,
+    ) -> std::io::Result<Option<ExitStatus>> {
+        if let Ok(Some(_)) = status {
+            if let Some(dir) = self.dir.take() {
+                remove(&dir);
+            }
+        }
+        status
+    }
+}
+
+impl Process for PrivateProcess {
+    fn id(&self) -> u32 {
+        self.proc.id()
+    }
+
+    fn kill(&mut self) -> std::io::Result<()> {
+        self.proc.kill()
+    }
+
+    fn try_wait(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        let status = self.proc.try_wait();
+        self.cleanup(status)
+    }
+
+    fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        let status = self.proc.wait().map(Some);
+        match self.cleanup(status) {
+            Err(err) => Err(err),
+            Ok(status) => Ok(status.unwrap()), // cannot fail
+        }
+    }
+}
@@ -1,25 +1,138 @@
 //! Processes management code.
 
 use crate::model::{Error, Process, ProcessSpawner, Result};
-use std::collections::VecDeque;
-use std::process::{Child, Command};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Whether we're running in init mode (see enable_init_mode).
+static INIT_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Seconds to wait after forwarding a signal before sending SIGKILL.
+static KILL_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+/// The writing end of the pipe through which the handler passes the
+/// signals to forward in init mode (see enable_init_mode).
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// The process group of the foreground group in init mode, if any,
+/// to which we forward the signals we receive.
+static FOREGROUND: Mutex<Option<u32>> = Mutex::new(None);
+
+/// The number of children we spawned (see accounting).
+static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+/// The maximum number of children we managed at the same time.
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Exit status of the tracked children reaped in init mode.
+static REAPED: Lazy<Mutex<HashMap<u32, ExitStatus>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The children that other threads wait for (e.g., supervised commands),
+/// whose exit status we save when we reap them in init mode.
+static DETACHED: Lazy<Mutex<HashSet<u32>>> = Lazy::new(|| Mutex::new(HashSet::new()));
 
 /// A child process implementing model::Process.
 struct ChildProcess {
     child: Child,
+    status: Option<ExitStatus>,
+}
+
+impl ChildProcess {
+    /// Returns the exit status if init mode has already reaped the child.
+    fn reaped(self: &mut Self) -> Option<ExitStatus> {
+        if self.status.is_none() {
+            self.status = REAPED.lock().unwrap().remove(&self.child.id());
+        }
+        self.status
+    }
 }
 
 impl Process for ChildProcess {
+    fn id(&self) -> u32 {
+        self.child.id()
+    }
+
     fn kill(&mut self) -> std::io::Result<()> {
+        if self.reaped().is_some() {
+            return Ok(()); // don't signal a recycled pid
+        }
         self.child.kill()
     }
 
     fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
-        self.child.try_wait()
+        match self.reaped() {
+            Some(status) => Ok(Some(status)),
+            None => self.child.try_wait(),
+        }
     }
 
     fn wait(&mut self) -> std::io::Result<std::process::ExitStatus> {
-        self.child.wait()
+        match self.reaped() {
+            Some(status) => Ok(status),
+            None => self.child.wait(),
+        }
+    }
+}
+
+/// A child process we did not spawn ourselves, e.g., a background
+/// process spawned before the shell re-executed itself.
+struct AdoptedProcess {
+    pid: u32,
+    status: Option<ExitStatus>,
+}
+
+impl AdoptedProcess {
+    /// Waits for the process using the given waitpid flags.
+    fn waitpid(self: &mut Self, flags: libc::c_int) -> std::io::Result<Option<ExitStatus>> {
+        if self.status.is_none() {
+            self.status = REAPED.lock().unwrap().remove(&self.pid);
+        }
+        if self.status.is_some() {
+            return Ok(self.status);
+        }
+        let mut status: libc::c_int = 0;
+        match unsafe { libc::waitpid(self.pid as libc::pid_t, &mut status, flags) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            0 => Ok(None),
+            _ => {
+                self.status = Some(ExitStatus::from_raw(status));
+                Ok(self.status)
+            }
+        }
+    }
+}
+
+impl Process for AdoptedProcess {
+    fn id(&self) -> u32 {
+        self.pid
+    }
+
+    fn kill(&mut self) -> std::io::Result<()> {
+        if self.status.is_some() {
+            return Ok(()); // don't signal a recycled pid
+        }
+        match unsafe { libc::kill(self.pid as libc::pid_t, libc::SIGKILL) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        self.waitpid(libc::WNOHANG)
+    }
+
+    fn wait(&mut self) -> std::io::Result<std::process::ExitStatus> {
+        loop {
+            if let Some(status) = self.waitpid(0)? {
+                return Ok(status);
+            }
+        }
     }
 }
 
@@ -28,6 +141,8 @@ pub struct Spawner {}
 
 impl Spawner {
     /// Crates a new generic ProcessSpawner instance.
+    // note: callers only need the trait, hence we return it boxed
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> Box<dyn ProcessSpawner> {
         Box::new(Spawner {})
     }
@@ -37,11 +152,236 @@ impl ProcessSpawner for Spawner {
     fn spawn(self: &Self, mut cmd: Command) -> Result<Box<dyn Process>> {
         match cmd.spawn() {
             Err(err) => Err(Error::new(&err.to_string())),
-            Ok(child) => Ok(Box::new(ChildProcess { child: child })),
+            Ok(child) => Ok(Box::new(ChildProcess {
+                child: child,
+                status: None,
+            })),
         }
     }
 }
 
+/// Prepares the command to run as a daemon using the classic double
+/// fork dance. The child we spawn calls setsid to detach from the
+/// controlling terminal and then forks again and exits, so that the
+/// grandchild, which executes the command, is re-parented to init and
+/// cannot acquire a controlling terminal again. The command also runs
+/// from the root directory, so it does not keep a mount point busy.
+pub fn daemonize(cmd: &mut Command) {
+    cmd.current_dir("/");
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            match libc::fork() {
+                -1 => Err(std::io::Error::last_os_error()),
+                0 => Ok(()),
+                _ => libc::_exit(0),
+            }
+        });
+    }
+}
+
+/// Enables the init mode, suitable for running as PID 1 inside a
+/// container. In this mode, we become the reaper of orphaned
+/// descendants (which happens automatically when we're PID 1) and
+/// we reap any child, not just the children we spawned. Also, each
+/// group of processes runs inside its own process group and we
+/// forward SIGTERM, SIGINT and SIGQUIT to the foreground process
+/// group. If the foreground processes do not terminate within the
+/// given timeout, we kill the whole process group. Without a
+/// foreground group, SIGTERM and SIGINT make the shell exit.
+///
+/// Because any thread may receive a signal, the handler only writes
+/// the signal number into a pipe, from which a thread dedicated to
+/// forwarding the signals reads it, so that we neither depend on
+/// which thread runs the handler nor lose signals.
+pub fn enable_init_mode(timeout: Duration) -> Result<()> {
+    if unsafe { libc::getpid() } != 1
+        && unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } == -1
+    {
+        return Err(Error::new(&std::io::Error::last_os_error().to_string()));
+    }
+    let mut fds: [libc::c_int; 2] = [-1, -1];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(Error::new(&std::io::Error::last_os_error().to_string()));
+    }
+    let reader = unsafe { OwnedFd::from_raw_fd(fds[0]) };
+    SIGNAL_PIPE.store(fds[1], Ordering::SeqCst); // never closed
+    for sig in [libc::SIGTERM, libc::SIGINT, libc::SIGQUIT].iter() {
+        unsafe {
+            let mut sa: libc::sigaction = std::mem::zeroed();
+            sa.sa_sigaction = on_forwarded_signal as extern "C" fn(libc::c_int) as usize;
+            sa.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut sa.sa_mask);
+            if libc::sigaction(*sig, &sa, std::ptr::null_mut()) == -1 {
+                return Err(Error::new(&std::io::Error::last_os_error().to_string()));
+            }
+        }
+    }
+    std::thread::spawn(move || forward_signals(reader, timeout));
+    KILL_TIMEOUT.store(timeout.as_secs(), Ordering::SeqCst);
+    INIT_MODE.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Converts the exit status of a process to a shell exit status, where
+/// a process killed by a signal has exited with 128 plus the signal.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(sig)) => 128 + sig,
+        (None, None) => 1,
+    }
+}
+
+/// Tells whether we're running in init mode.
+pub fn init_mode() -> bool {
+    INIT_MODE.load(Ordering::SeqCst)
+}
+
+/// Returns the kill timeout of the init mode (see enable_init_mode).
+pub fn kill_timeout() -> Duration {
+    Duration::from_secs(KILL_TIMEOUT.load(Ordering::SeqCst))
+}
+
+/// Handler for the signals we forward in init mode.
+extern "C" fn on_forwarded_signal(sig: libc::c_int) {
+    let byte = sig as u8;
+    let fd = SIGNAL_PIPE.load(Ordering::SeqCst);
+    unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+}
+
+/// Reads the signals we forward in init mode until the shell exits,
+/// killing the foreground process group when it does not terminate
+/// within the timeout after we forwarded a signal to it.
+fn forward_signals(reader: OwnedFd, timeout: Duration) {
+    let mut deadline: Option<(u32, Instant)> = None;
+    loop {
+        let wait = match deadline {
+            None => -1,
+            Some((_, when)) => {
+                let remaining = when.saturating_duration_since(Instant::now());
+                remaining.as_millis().min(i32::MAX as u128 - 1) as libc::c_int + 1
+            }
+        };
+        let sig = read_signal(&reader, wait);
+        // note: holding the lock, so that the group cannot change meanwhile
+        let foreground = FOREGROUND.lock().unwrap();
+        if deadline.is_some_and(|(pgid, _)| *foreground != Some(pgid)) {
+            deadline = None; // the group we signaled has terminated
+        }
+        match (sig, *foreground) {
+            // note: we timed out or a signal interrupted us
+            (None, _) => {
+                if let Some((pgid, when)) = deadline {
+                    if Instant::now() >= when {
+                        unsafe { libc::kill(-(pgid as libc::pid_t), libc::SIGKILL) };
+                        deadline = None;
+                    }
+                }
+            }
+            (Some(sig), None) if sig != libc::SIGQUIT => std::process::exit(128 + sig),
+            (Some(_), None) => (),
+            (Some(sig), Some(pgid)) => {
+                unsafe { libc::kill(-(pgid as libc::pid_t), sig) };
+                deadline = deadline.or(Some((pgid, Instant::now() + timeout)));
+            }
+        }
+    }
+}
+
+/// Reads a signal number from the pipe, waiting at most the given
+/// number of milliseconds (or forever when negative).
+fn read_signal(reader: &OwnedFd, wait: libc::c_int) -> Option<libc::c_int> {
+    let mut pfd = libc::pollfd {
+        fd: reader.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut pfd, 1, wait) } != 1 {
+        return None;
+    }
+    let mut byte: u8 = 0;
+    match unsafe { libc::read(pfd.fd, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+        1 => Some(byte as libc::c_int),
+        _ => None,
+    }
+}
+
+/// Outcome of reap_any.
+enum Reaped {
+    /// We reaped the child with the given pid.
+    Child(u32),
+
+    /// Nothing to reap (either we're not blocking or a signal handler
+    /// interrupted us).
+    Nothing,
+
+    /// There are no children left.
+    NoChildren,
+}
+
+/// Reaps any child process, saving the exit status of the children for
+/// which tracked returns true, so that ChildProcess can later find it.
+fn reap_any(block: bool, tracked: &HashSet<u32>) -> Reaped {
+    let mut status: libc::c_int = 0;
+    let flags = if block { 0 } else { libc::WNOHANG };
+    match unsafe { libc::waitpid(-1, &mut status, flags) } {
+        -1 => match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::ECHILD) => Reaped::NoChildren,
+            _ => Reaped::Nothing,
+        },
+        0 => Reaped::Nothing,
+        pid => {
+            let pid = pid as u32;
+            if tracked.contains(&pid) || DETACHED.lock().unwrap().contains(&pid) {
+                let status = ExitStatus::from_raw(status);
+                REAPED.lock().unwrap().insert(pid, status);
+            }
+            Reaped::Child(pid)
+        }
+    }
+}
+
+/// Spawns a child that another thread waits for using wait_detached, so
+/// that init mode, which reaps any child, saves its exit status.
+pub fn spawn_detached(cmd: &mut Command) -> std::io::Result<Child> {
+    // note: we hold the lock while spawning, so that reap_any cannot
+    // check whether to save the status before we add the child
+    let mut detached = DETACHED.lock().unwrap();
+    let child = cmd.spawn()?;
+    detached.insert(child.id());
+    Ok(child)
+}
+
+/// Waits for a child spawned using spawn_detached.
+pub fn wait_detached(child: &mut Child) -> std::io::Result<ExitStatus> {
+    let pid = child.id();
+    let result = match child.wait() {
+        Err(err) if init_mode() && err.raw_os_error() == Some(libc::ECHILD) => loop {
+            // note: reap_any reaped the child and is about to save its status
+            if let Some(status) = REAPED.lock().unwrap().remove(&pid) {
+                break Ok(status);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        },
+        result => result,
+    };
+    DETACHED.lock().unwrap().remove(&pid);
+    result
+}
+
+/// Returns the number of children we spawned and the maximum number of
+/// children running at the same time, including the background ones,
+/// which we count until the PeriodicReaper reaps them.
+pub fn accounting() -> (usize, usize) {
+    (
+        SPAWNED.load(Ordering::Relaxed),
+        PEAK.load(Ordering::Relaxed),
+    )
+}
+
 /// PeriodicReaper periodically reaps zombie processes.
 pub struct PeriodicReaper {
     c: VecDeque<Box<dyn Process>>,
@@ -58,10 +398,28 @@ impl PeriodicReaper {
     /// Adds a process to the pool of background processes we manage.
     fn add(self: &mut Self, proc: Box<dyn Process>) {
         self.c.push_back(proc);
+        PEAK.fetch_max(self.c.len(), Ordering::Relaxed);
+    }
+
+    /// Adopts a child process we did not spawn ourselves.
+    pub fn adopt(self: &mut Self, pid: u32) {
+        self.add(Box::new(AdoptedProcess {
+            pid: pid,
+            status: None,
+        }));
+    }
+
+    /// Returns the pids of the processes we manage.
+    pub fn pids(self: &Self) -> HashSet<u32> {
+        self.c.iter().map(|p| p.id()).collect()
     }
 
     /// Reaps all the zombies processes.
     pub fn reap(self: &mut Self) {
+        if init_mode() {
+            let tracked = self.pids();
+            while let Reaped::Child(_) = reap_any(false, &tracked) {}
+        }
         let mut running = VecDeque::<_>::new();
         while self.c.len() > 0 {
             let mut cur = self.c.pop_front().unwrap(); // cannot fail
@@ -84,6 +442,7 @@ impl PeriodicReaper {
 /// is dropped because it has gone out of the scope.
 pub struct Group<'a> {
     c: VecDeque<Box<dyn Process>>,
+    pgid: Option<u32>,
     pr: &'a mut PeriodicReaper,
 }
 
@@ -92,13 +451,27 @@ impl<'a> Group<'a> {
     pub fn new(pr: &'a mut PeriodicReaper) -> Group<'a> {
         Group {
             c: VecDeque::<_>::new(),
+            pgid: None,
             pr: pr,
         }
     }
 
-    /// Adds a process to the group.
+    /// Adds a process to the group. The first process we add
+    /// becomes the leader of the group's process group.
     pub fn add(self: &mut Self, proc: Box<dyn Process>) {
+        if self.pgid.is_none() {
+            self.pgid = Some(proc.id());
+        }
         self.c.push_back(proc);
+        SPAWNED.fetch_add(1, Ordering::Relaxed);
+        PEAK.fetch_max(self.c.len() + self.pr.c.len(), Ordering::Relaxed);
+    }
+
+    /// Returns the process group ID of the group, i.e., the process
+    /// ID of the first process we added, if any. This process group
+    /// exists only when job control is enabled.
+    pub fn pgid(self: &Self) -> Option<u32> {
+        self.pgid
     }
 
     /// Kills each process in the group and then waits for each of them.
@@ -106,16 +479,51 @@ impl<'a> Group<'a> {
         for p in self.c.iter_mut() {
             let _ = p.kill(); // ignore return value
         }
-        self.wait();
+        let _ = self.wait(); // ignore return value
     }
 
-    /// Waits for each process in the group to terminate.
-    pub fn wait(self: &mut Self) {
+    /// Waits for each process in the group to terminate and returns
+    /// the exit status of the last process (i.e., of the pipeline).
+    pub fn wait(self: &mut Self) -> i32 {
+        if init_mode() {
+            self.reap_until_done();
+        }
+        let mut code = 0;
+        let mut last = true;
         while self.c.len() > 0 {
             // note: proceed backwards
             let mut p = self.c.pop_back().unwrap(); // cannot fail
-            let _ = p.wait(); // ignore return value
+            let status = p.wait();
+            if last {
+                code = match status {
+                    Err(_) => 1,
+                    Ok(status) => exit_code(status),
+                };
+                last = false;
+            }
+        }
+        code
+    }
+}
+
+impl<'a> Group<'a> {
+    /// Reaps any child until all the processes in the group have
+    /// terminated, while the group is the foreground one to which
+    /// forward_signals forwards the signals we receive.
+    fn reap_until_done(self: &mut Self) {
+        let mut pending: HashSet<u32> = self.c.iter().map(|p| p.id()).collect();
+        let tracked: HashSet<u32> = pending.union(&self.pr.pids()).cloned().collect();
+        *FOREGROUND.lock().unwrap() = self.pgid;
+        while pending.len() > 0 {
+            match reap_any(true, &tracked) {
+                Reaped::Child(pid) => {
+                    pending.remove(&pid);
+                }
+                Reaped::Nothing => (),
+                Reaped::NoChildren => break,
+            }
         }
+        *FOREGROUND.lock().unwrap() = None;
     }
 }
 
@@ -0,0 +1,367 @@
+//! Obtains the prompt from an external program.
+//!
+//! When the `PROMPT_PROVIDER` environment variable contains a command
+//! (e.g., `PROMPT_PROVIDER=~/bin/myprompt`), the interactive shell runs the
+//! command before showing each prompt. The shell writes to the standard
+//! input of the command a single line containing a JSON object describing
+//! its state, such as:
+//!
+//! ```text
+//! {"version":1,"status":0,"cwd":"/home/user","pid":1234,"jobs":1}
+//! ```
+//!
+//! and reads from its standard output a JSON object whose `prompt` string
+//! is the prompt to show, such as `{"prompt":"~ $ "}`, ignoring any other
+//! member. The command must reply within a strict timeout, otherwise we
+//! kill it. When the command fails, times out or replies with invalid JSON,
+//! we warn once and show the prompt we obtained last time, which we cache
+//! between commands, or the default prompt.
+//!
+//! Otherwise, the prompt is the value of the `PS1` variable, if set, in which `\D{FORMAT}` expands to the current time formatted
+//! using strftime (e.g., `PS1='\D{%H:%M} $ '`), `\D{}` to the time in
+//! the locale's format and `\\` to a backslash. We expand `PS1` each
+//! time we show the prompt, hence the time is that of the prompt.
+
+use crate::model::{Error, Result};
+use crate::printf;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// The version of the protocol.
+const VERSION: i32 = 1;
+
+/// How long we wait for the prompt provider to reply.
+const TIMEOUT: Duration = Duration::from_millis(250);
+
+/// The prompt we show when we do not have a better one.
+pub const DEFAULT: &str = "$ ";
+
+/// Returns the prompt configured through the given value of `PS1`, if
+/// set, expanding its escapes, or the default prompt.
+pub fn expand(ps1: Option<String>) -> String {
+    match ps1 {
+        None => String::from(DEFAULT),
+        Some(template) => escapes(&template),
+    }
+}
+
+/// Expands the escapes inside the given prompt template, keeping
+/// the backslashes that do not start a known escape.
+fn escapes(template: &str) -> String {
+    let chars: Vec<char> = template.chars().collect();
+    let mut output = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        match (chars[index], chars.get(index + 1), chars.get(index + 2)) {
+            ('\\', Some('\\'), _) => {
+                output.push('\\');
+                index += 2;
+            }
+            ('\\', Some('D'), Some('{')) => match chars[index + 3..].iter().position(|c| *c == '}')
+            {
+                None => {
+                    output.push('\\');
+                    index += 1;
+                }
+                Some(length) => {
+                    let format: String = chars[index + 3..index + 3 + length].iter().collect();
+                    output.push_str(&printf::format_time(
+                        if format.len() > 0 { &format } else { "%X" },
+                        None,
+                    ));
+                    index += 4 + length;
+                }
+            },
+            (c, _, _) => {
+                output.push(c);
+                index += 1;
+            }
+        }
+    }
+    output
+}
+
+/// The state of the shell that we pass to the prompt provider.
+pub struct State {
+    /// The exit status of the last pipeline.
+    pub status: i32,
+
+    /// The current working directory.
+    pub cwd: String,
+
+    /// The number of running background jobs (see jobstate).
+    pub jobs: usize,
+}
+
+/// Runs the prompt provider and caches its prompt.
+pub struct Provider {
+    /// The command and its arguments.
+    argv: Vec<String>,
+
+    /// The last prompt the provider gave us.
+    cached: Option<String>,
+
+    /// Whether the last invocation failed, so that we print a warning
+    /// when the provider starts failing rather than before each prompt.
+    failing: bool,
+}
+
+impl Provider {
+    /// Creates the provider configured through `PROMPT_PROVIDER`, if any.
+    pub fn from_env() -> Option<Provider> {
+        let value = std::env::var("PROMPT_PROVIDER").ok()?;
+        let argv: Vec<String> = value.split_whitespace().map(String::from).collect();
+        if argv.len() == 0 {
+            return None;
+        }
+        Some(Provider {
+            argv: argv,
+            cached: None,
+            failing: false,
+        })
+    }
+
+    /// Returns the prompt to show given the state of the shell.
+    pub fn prompt(self: &mut Self, state: &State) -> String {
+        match self.invoke(&encode(state)) {
+            Err(err) => {
+                if !self.failing {
+                    eprintln!("xv6sh: warning: {}: {}", self.argv[0], err);
+                }
+                self.failing = true;
+            }
+            Ok(prompt) => {
+                self.cached = Some(prompt);
+                self.failing = false;
+            }
+        }
+        match &self.cached {
+            None => String::from(DEFAULT),
+            Some(prompt) => prompt.clone(),
+        }
+    }
+
+    /// Runs the provider passing it the request and parses its reply.
+    fn invoke(self: &Self, request: &str) -> Result<String> {
+        let mut child = match Command::new(&self.argv[0])
+            .args(&self.argv[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Err(err) => return Err(Error::new(&err.to_string())),
+            Ok(child) => child,
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            // note: the request fits into the pipe buffer and the
+            // provider may legitimately ignore it, hence EPIPE is fine
+            let _ = stdin.write_all(request.as_bytes());
+        }
+        let (tx, rx) = mpsc::channel();
+        if let Some(mut stdout) = child.stdout.take() {
+            std::thread::spawn(move || {
+                let mut reply = String::new();
+                let _ = tx.send(stdout.read_to_string(&mut reply).map(|_| reply));
+            });
+        }
+        let reply = rx.recv_timeout(TIMEOUT);
+        if reply.is_err() {
+            let _ = child.kill();
+        }
+        // note: the periodic reaper may have reaped the child already
+        let _ = child.wait();
+        match reply {
+            Err(_) => Err(Error::new("timed out")),
+            Ok(Err(err)) => Err(Error::new(&err.to_string())),
+            Ok(Ok(reply)) => decode(&reply),
+        }
+    }
+}
+
+/// Encodes the state as a single line of JSON.
+fn encode(state: &State) -> String {
+    format!(
+        "{{\"version\":{},\"status\":{},\"cwd\":{},\"pid\":{},\"jobs\":{}}}\n",
+        VERSION,
+        state.status,
+        quote(&state.cwd),
+        std::process::id(),
+        state.jobs
+    )
+}
+
+/// Encodes a JSON string.
+pub fn quote(value: &str) -> String {
+    let mut output = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+/// Extracts the prompt from the reply of the provider, which must be a
+/// JSON object whose members are strings, numbers, booleans or null.
+fn decode(reply: &str) -> Result<String> {
+    let mut reader = Reader {
+        chars: reply.chars().collect(),
+        index: 0,
+    };
+    let mut prompt = None;
+    reader.expect('{')?;
+    if !reader.accept('}') {
+        loop {
+            let key = reader.string()?;
+            reader.expect(':')?;
+            let value = reader.value()?;
+            if key == "prompt" {
+                prompt = value;
+            }
+            if reader.accept('}') {
+                break;
+            }
+            reader.expect(',')?;
+        }
+    }
+    reader.skip_whitespace();
+    if reader.index < reader.chars.len() {
+        return Err(Error::new("invalid JSON: trailing data"));
+    }
+    match prompt {
+        None => Err(Error::new("invalid JSON: missing prompt string")),
+        Some(prompt) => Ok(prompt),
+    }
+}
+
+/// Reads a JSON document.
+struct Reader {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl Reader {
+    /// skips whitespace and consumes the given char, if present.
+    fn accept(self: &mut Self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.get(self.index) == Some(&c) {
+            self.index += 1;
+            return true;
+        }
+        false
+    }
+
+    /// like accept but fails if the given char is not present.
+    fn expect(self: &mut Self, c: char) -> Result<()> {
+        match self.accept(c) {
+            true => Ok(()),
+            false => Err(Error::new(&format!("invalid JSON: expected '{}'", c))),
+        }
+    }
+
+    /// skips the whitespace before the next token.
+    fn skip_whitespace(self: &mut Self) {
+        while let Some(c) = self.chars.get(self.index) {
+            if !c.is_ascii_whitespace() {
+                break;
+            }
+            self.index += 1;
+        }
+    }
+
+    /// reads a scalar value, returning its content if it's a string.
+    fn value(self: &mut Self) -> Result<Option<String>> {
+        self.skip_whitespace();
+        if self.chars.get(self.index) == Some(&'"') {
+            return Ok(Some(self.string()?));
+        }
+        let start = self.index;
+        while let Some(c) = self.chars.get(self.index) {
+            if !c.is_ascii_alphanumeric() && !"+-.".contains(*c) {
+                break;
+            }
+            self.index += 1;
+        }
+        let token: String = self.chars[start..self.index].iter().collect();
+        let valid = match token.as_str() {
+            "true" | "false" | "null" => true,
+            _ => token.parse::<f64>().is_ok(),
+        };
+        match valid {
+            true => Ok(None),
+            false => Err(Error::new(
+                "invalid JSON: expected a string, number, boolean or null",
+            )),
+        }
+    }
+
+    /// reads a string, decoding its escape sequences.
+    fn string(self: &mut Self) -> Result<String> {
+        let invalid = || Error::new("invalid JSON: bad string");
+        self.expect('"')?;
+        let mut output = String::new();
+        loop {
+            let c = *self.chars.get(self.index).ok_or_else(invalid)?;
+            self.index += 1;
+            match c {
+                '"' => return Ok(output),
+                '\\' => {
+                    let c = *self.chars.get(self.index).ok_or_else(invalid)?;
+                    self.index += 1;
+                    match c {
+                        '"' | '\\' | '/' => output.push(c),
+                        'b' => output.push('\u{8}'),
+                        'f' => output.push('\u{c}'),
+                        'n' => output.push('\n'),
+                        'r' => output.push('\r'),
+                        't' => output.push('\t'),
+                        'u' => output.push(self.unicode()?),
+                        _ => return Err(invalid()),
+                    }
+                }
+                c => output.push(c),
+            }
+        }
+    }
+
+    /// reads the code point following `\u`, including surrogate pairs.
+    fn unicode(self: &mut Self) -> Result<char> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| Error::new("invalid JSON: bad escape"));
+        }
+        if self.chars.get(self.index) != Some(&'\\') || self.chars.get(self.index + 1) != Some(&'u')
+        {
+            return Err(Error::new("invalid JSON: bad surrogate pair"));
+        }
+        self.index += 2;
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(Error::new("invalid JSON: bad surrogate pair"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| Error::new("invalid JSON: bad escape"))
+    }
+
+    /// reads four hex digits.
+    fn hex4(self: &mut Self) -> Result<u32> {
+        let end = self.index + 4;
+        if end > self.chars.len() {
+            return Err(Error::new("invalid JSON: bad escape"));
+        }
+        let digits: String = self.chars[self.index..end].iter().collect();
+        self.index = end;
+        match u32::from_str_radix(&digits, 16) {
+            Err(_) => Err(Error::new("invalid JSON: bad escape")),
+            Ok(value) => Ok(value),
+        }
+    }
+}
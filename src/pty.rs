@@ -0,0 +1,340 @@
+//! Runs commands inside a pseudo-terminal.
+//!
+//! The `pty` prefix (e.g., `pty ls | cat`) runs the command with its
+//! standard output and error attached to a pseudo-terminal allocated by
+//! the shell, so that programs behaving differently when not writing to
+//! a terminal (e.g., coloring their output) behave interactively also
+//! inside pipelines. A thread forwards what the command writes to the
+//! pseudo-terminal to where the standard output would otherwise go. When
+//! the standard input is redirected, another thread forwards the input
+//! to the pseudo-terminal, so that it's a terminal as well.
+//!
+//! While the shell writes a transcript (see transcript.rs), the thread
+//! also records the output that it forwards to the shell's terminal, and
+//! the shell runs inside a pseudo-terminal any command writing there.
+//!
+//! The command remains in the process group of its pipeline, hence the
+//! terminal-generated signals (e.g., `^C`) reach it as usual. We copy the
+//! window size of the shell's terminal when allocating the pseudo-terminal
+//! and whenever the shell receives SIGWINCH.
+
+use crate::model::{Error, Process, Result};
+use crate::transcript;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Once;
+use std::thread::JoinHandle;
+
+/// Masters of the pseudo-terminals to resize on SIGWINCH, where -1
+/// marks a free slot. We use atomics because the signal handler
+/// cannot take locks.
+static MASTERS: [AtomicI32; 16] = [
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+    AtomicI32::new(-1),
+];
+
+/// Ensures we install the SIGWINCH handler just once.
+static HANDLER: Once = Once::new();
+
+/// A pseudo-terminal allocated by the shell.
+pub struct Pty {
+    master: OwnedFd,
+
+    /// The terminal side, which the command uses.
+    pub slave: OwnedFd,
+}
+
+/// A process running inside a pseudo-terminal.
+struct PtyProcess {
+    inner: Box<dyn Process>,
+    master: OwnedFd,
+    output: Option<JoinHandle<()>>,
+}
+
+/// Allocates a new pseudo-terminal with the window size of the shell's
+/// terminal. We disable echoing the input and post-processing the
+/// output (e.g., turning `\n` into `\r\n`), since we forward the output
+/// to pipes and files rather than to a terminal, and translating the
+/// input (e.g., `\r` into `\n`) and flow control (i.e., `^S` and `^Q`),
+/// so that the data flows through unchanged.
+pub fn open() -> Result<Pty> {
+    let (mut master, mut slave) = (-1, -1);
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+    let winp = match window_size(&mut winsize) {
+        true => &mut winsize as *mut libc::winsize,
+        false => std::ptr::null_mut(),
+    };
+    let rv = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            winp,
+        )
+    };
+    if rv == -1 {
+        return Err(Error::new(&std::io::Error::last_os_error().to_string()));
+    }
+    let pty = unsafe {
+        Pty {
+            master: OwnedFd::from_raw_fd(master),
+            slave: OwnedFd::from_raw_fd(slave),
+        }
+    };
+    for fd in [master, slave] {
+        // note: the command must not inherit the master
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave, &mut termios) == 0 {
+            termios.c_lflag &= !(libc::ECHO | libc::ECHONL);
+            termios.c_lflag |= libc::IEXTEN; // i.e., VLNEXT (see forward_input)
+            termios.c_iflag &= !(libc::ICRNL | libc::INLCR | libc::IGNCR | libc::ISTRIP);
+            termios.c_iflag &= !(libc::IXON | libc::IXOFF);
+            termios.c_oflag &= !libc::OPOST;
+            libc::tcsetattr(slave, libc::TCSANOW, &termios);
+        }
+    }
+    HANDLER.call_once(install_handler);
+    Ok(pty)
+}
+
+impl Pty {
+    /// Wraps the process running inside the pseudo-terminal, forwarding
+    /// the input, if any, to the pseudo-terminal and the output of the
+    /// pseudo-terminal to the given descriptor, which we also record into
+    /// the transcript when `record` is true. Waiting for the returned
+    /// process also waits for all the output to be forwarded.
+    pub fn attach(
+        self: Self,
+        inner: Box<dyn Process>,
+        input: Option<OwnedFd>,
+        output: OwnedFd,
+        record: bool,
+    ) -> Result<Box<dyn Process>> {
+        // note: we must close our copy of the slave, otherwise reading
+        // from the master would not fail once the process terminates
+        drop(self.slave);
+        let reader = Self::clone(&self.master)?;
+        if let Some(input) = input {
+            let writer = Self::clone(&self.master)?;
+            std::thread::spawn(move || forward_input(input, writer));
+        }
+        let handle = std::thread::spawn(move || forward_output(reader, output, record));
+        register(self.master.as_raw_fd());
+        Ok(Box::new(PtyProcess {
+            inner: inner,
+            master: self.master,
+            output: Some(handle),
+        }))
+    }
+
+    /// Helper to duplicate the master.
+    fn clone(fd: &OwnedFd) -> Result<File> {
+        match fd.try_clone() {
+            Err(err) => Err(Error::new(&err.to_string())),
+            Ok(fd) => Ok(File::from(fd)),
+        }
+    }
+}
+
+impl Process for PtyProcess {
+    fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    fn kill(&mut self) -> std::io::Result<()> {
+        self.inner.kill()
+    }
+
+    fn try_wait(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        self.inner.try_wait()
+    }
+
+    fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        let status = self.inner.wait()?;
+        if let Some(handle) = self.output.take() {
+            let _ = handle.join(); // ignore panics
+        }
+        Ok(status)
+    }
+}
+
+impl Drop for PtyProcess {
+    /// Stops resizing the pseudo-terminal, whose master we're closing.
+    fn drop(&mut self) {
+        unregister(self.master.as_raw_fd());
+    }
+}
+
+/// Copies the input to the pseudo-terminal and then sends the
+/// end-of-file character, so that the command sees the end of file.
+/// We precede the special chars of the terminal (e.g., `^C` or `^D`)
+/// with the literal-next char (i.e., `^V`), so that the terminal passes
+/// them to the command rather than acting on them.
+fn forward_input(input: OwnedFd, mut master: File) {
+    let special = special_chars(&master);
+    let mut input = File::from(input);
+    let mut buffer = [0u8; 4096];
+    let mut quoted = Vec::<u8>::with_capacity(2 * buffer.len());
+    let mut newline = true;
+    loop {
+        match input.read(&mut buffer) {
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) | Ok(0) => break,
+            Ok(count) => {
+                quoted.clear();
+                for byte in buffer[..count].iter() {
+                    if special.contains(byte) {
+                        quoted.push(VLNEXT);
+                    }
+                    quoted.push(*byte);
+                }
+                if master.write_all(&quoted).is_err() {
+                    return;
+                }
+                newline = buffer[count - 1] == b'\n';
+            }
+        }
+    }
+    // note: ^D in the middle of a line just delivers the line
+    if !newline {
+        let _ = master.write_all(&[4]);
+    }
+    let _ = master.write_all(&[4]); // i.e., ^D
+}
+
+/// The literal-next char (i.e., `^V`), which we set in special_chars.
+const VLNEXT: u8 = 0x16;
+
+/// Returns the chars that the pseudo-terminal interprets specially
+/// when reading the input, making sure that `^V` is the literal-next
+/// char. We include `^V` itself, so that it's literal too.
+fn special_chars(master: &File) -> Vec<u8> {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(master.as_raw_fd(), &mut termios) } != 0 {
+        return Vec::new();
+    }
+    termios.c_cc[libc::VLNEXT] = VLNEXT;
+    unsafe { libc::tcsetattr(master.as_raw_fd(), libc::TCSANOW, &termios) };
+    let indexes = [
+        libc::VINTR,
+        libc::VQUIT,
+        libc::VERASE,
+        libc::VKILL,
+        libc::VEOF,
+        libc::VEOL,
+        libc::VEOL2,
+        libc::VSUSP,
+        libc::VREPRINT,
+        libc::VWERASE,
+        libc::VLNEXT,
+    ];
+    // note: zero marks the disabled chars (i.e., _POSIX_VDISABLE)
+    let chars = indexes.iter().map(|index| termios.c_cc[*index]);
+    chars.filter(|c| *c != 0).collect()
+}
+
+/// Copies the output of the pseudo-terminal, possibly recording it, until
+/// reading fails with EIO because the command has terminated.
+fn forward_output(mut master: File, output: OwnedFd, record: bool) {
+    let mut output = File::from(output);
+    let mut transcript = match record {
+        true => Some(transcript::Output::new()),
+        false => None,
+    };
+    let mut buffer = [0u8; 4096];
+    loop {
+        match master.read(&mut buffer) {
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) | Ok(0) => break,
+            Ok(count) => {
+                if let Some(transcript) = transcript.as_mut() {
+                    transcript.write(&buffer[..count]);
+                }
+                if output.write_all(&buffer[..count]).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Tells whether the given descriptor of the shell is the terminal, which
+/// is where the output we record into the transcript goes.
+pub fn is_terminal(fd: i32) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Reads the window size of the shell's terminal.
+fn window_size(winsize: &mut libc::winsize) -> bool {
+    for fd in 0..3 {
+        if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, winsize as *mut libc::winsize) } == 0 {
+            return true;
+        }
+    }
+    false
+}
+
+/// Installs the SIGWINCH handler.
+fn install_handler() {
+    unsafe {
+        let mut sa: libc::sigaction = std::mem::zeroed();
+        sa.sa_sigaction = on_sigwinch as extern "C" fn(libc::c_int) as usize;
+        sa.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut sa.sa_mask);
+        libc::sigaction(libc::SIGWINCH, &sa, std::ptr::null_mut());
+    }
+}
+
+/// Copies the window size of the shell's terminal to the pseudo-terminals.
+extern "C" fn on_sigwinch(_sig: libc::c_int) {
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+    if !window_size(&mut winsize) {
+        return;
+    }
+    for slot in MASTERS.iter() {
+        let fd = slot.load(Ordering::SeqCst);
+        if fd >= 0 {
+            unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &winsize as *const libc::winsize) };
+        }
+    }
+}
+
+/// Registers a master to resize on SIGWINCH, if there's a free slot.
+fn register(fd: i32) {
+    for slot in MASTERS.iter() {
+        if slot
+            .compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// Unregisters a master previously registered.
+fn unregister(fd: i32) {
+    for slot in MASTERS.iter() {
+        let _ = slot.compare_exchange(fd, -1, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
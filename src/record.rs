@@ -0,0 +1,348 @@
+//! Records the executed commands and replays them.
+//!
+//! With `--record FILE`, the interpreter appends an entry to the file
+//! for each pipeline it executes. Each entry contains one `key value`
+//! pair per line and ends with an empty line. The keys are:
+//!
+//! - `run`, the pipeline serialized to shell syntax;
+//! - `cwd`, the working directory of the shell;
+//! - `stdin`, where the first command of the pipeline reads from;
+//! - `env` and `unset`, the environment variables that were set (or
+//!   changed) and removed since we started recording;
+//! - `status`, the exit status of the pipeline.
+//!
+//! Values are escaped so that they fit on a single line. With `--replay
+//! FILE`, we execute the recorded pipelines again, restoring their working
+//! directory and environment. In check mode, we also report the pipelines
+//! whose exit status differs from the recorded one.
+
+use crate::interp::Interpreter;
+use crate::model::{Error, InputRedir, Redirect, Result, Word};
+use crate::parser::{
+    Command, CompleteCommand, ConditionalCommand, Pipeline, RedirectList, SimpleCommand,
+};
+use crate::process::PeriodicReaper;
+use crate::translator::CompoundSerialCommand;
+use crate::{lexer, parser, serializer};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+/// A recorded pipeline.
+#[derive(Debug)]
+pub struct Entry {
+    /// The pipeline serialized to shell syntax.
+    pub run: String,
+
+    /// The working directory of the shell.
+    pub cwd: String,
+
+    /// Where the first command of the pipeline reads from.
+    pub stdin: String,
+
+    /// The environment variables set or changed since we started recording.
+    pub env: VecDeque<(String, String)>,
+
+    /// The environment variables removed since we started recording.
+    pub unset: VecDeque<String>,
+
+    /// The exit status of the pipeline.
+    pub status: i32,
+}
+
+/// Appends entries to the record file.
+pub struct Recorder {
+    filep: File,
+    filename: String,
+
+    /// The environment when we started recording.
+    environ: HashMap<String, String>,
+}
+
+/// Opens the given record file for appending entries.
+pub fn create(filename: &str) -> Result<Recorder> {
+    match OpenOptions::new().create(true).append(true).open(filename) {
+        Err(err) => Err(Error::new(&format!("{}: {}", filename, err))),
+        Ok(filep) => Ok(Recorder {
+            filep: filep,
+            filename: String::from(filename),
+            environ: std::env::vars().collect(),
+        }),
+    }
+}
+
+/// Loads the entries of the given record file.
+pub fn load(filename: &str) -> Result<VecDeque<Entry>> {
+    let content = match std::fs::read_to_string(filename) {
+        Err(err) => return Err(Error::new(&format!("{}: {}", filename, err))),
+        Ok(content) => content,
+    };
+    let mut entries = VecDeque::<Entry>::new();
+    let mut current: Option<Entry> = None;
+    for (index, line) in content.lines().enumerate() {
+        if line.len() == 0 {
+            if let Some(entry) = current.take() {
+                entries.push_back(entry);
+            }
+            continue;
+        }
+        let invalid = || Error::new(&format!("{}:{}: invalid line", filename, index + 1));
+        let (key, value) = match line.split_once(' ') {
+            None => return Err(invalid()),
+            Some((key, value)) => (key, unescape(value)),
+        };
+        let entry = current.get_or_insert_with(Entry::new);
+        match key {
+            "run" => entry.run = value,
+            "cwd" => entry.cwd = value,
+            "stdin" => entry.stdin = value,
+            "env" => match value.split_once('=') {
+                None => return Err(invalid()),
+                Some((name, value)) => entry.env.push_back((name.into(), value.into())),
+            },
+            "unset" => entry.unset.push_back(value),
+            "status" => match value.parse::<i32>() {
+                Err(_) => return Err(invalid()),
+                Ok(status) => entry.status = status,
+            },
+            _ => (), // ignore unknown keys written by other versions
+        }
+    }
+    if let Some(entry) = current.take() {
+        entries.push_back(entry);
+    }
+    Ok(entries)
+}
+
+/// Replays the entries of the given record file using the interpreter
+/// and returns the number of entries whose exit status differs from the
+/// recorded one, which we report only in check mode.
+pub fn replay(
+    interp: &mut Interpreter,
+    reaper: &mut PeriodicReaper,
+    filename: &str,
+    check: bool,
+) -> Result<usize> {
+    let mut mismatches = 0;
+    for (index, entry) in load(filename)?.into_iter().enumerate() {
+        let locate =
+            |err: Error| Error::new(&format!("{}: entry {}: {}", filename, index + 1, err));
+        if let Err(err) = std::env::set_current_dir(&entry.cwd) {
+            return Err(locate(Error::new(&format!("{}: {}", entry.cwd, err))));
+        }
+        for (name, value) in entry.env.iter() {
+            std::env::set_var(name, value);
+        }
+        for name in entry.unset.iter() {
+            std::env::remove_var(name);
+        }
+        interp.sync_pwd();
+        if let Err(err) = run(interp, reaper, &entry.run) {
+            eprintln!("xv6sh: error: {}", locate(err));
+        }
+        if check && interp.status != entry.status {
+            eprintln!(
+                "xv6sh: {}: entry {}: {}: exit status {}, recorded {}",
+                filename,
+                index + 1,
+                entry.run.lines().next().unwrap_or(""),
+                interp.status,
+                entry.status
+            );
+            mismatches += 1;
+        }
+    }
+    Ok(mismatches)
+}
+
+//
+// Implementation of public types.
+//
+
+impl Entry {
+    /// Creates a new empty entry.
+    pub fn new() -> Entry {
+        Entry {
+            run: String::new(),
+            cwd: String::new(),
+            stdin: String::new(),
+            env: VecDeque::<_>::new(),
+            unset: VecDeque::<_>::new(),
+            status: 0,
+        }
+    }
+}
+
+impl Recorder {
+    /// Describes the pipeline we're about to execute. We don't describe if
+    /// commands and group commands, since we describe the pipelines they
+    /// execute instead.
+    pub fn describe(self: &Self, csc: &CompoundSerialCommand) -> Result<Option<Entry>> {
+        let cc = match untranslate(csc) {
+            None => return Ok(None),
+            Some(cc) => cc,
+        };
+        let mut entry = Entry::new();
+        entry.run = serializer::serialize(&cc)?;
+        entry.cwd = match std::env::current_dir() {
+            Err(err) => return Err(Error::new(&err.to_string())),
+            Ok(cwd) => cwd.to_string_lossy().to_string(),
+        };
+        entry.stdin = stdin(&cc);
+        let mut environ: Vec<(String, String)> = std::env::vars().collect();
+        environ.sort();
+        for (name, value) in environ.iter() {
+            if self.environ.get(name) != Some(value) {
+                entry.env.push_back((name.clone(), value.clone()));
+            }
+        }
+        let mut names: Vec<&String> = self.environ.keys().collect();
+        names.sort();
+        for name in names {
+            if std::env::var_os(name).is_none() {
+                entry.unset.push_back(name.clone());
+            }
+        }
+        Ok(Some(entry))
+    }
+
+    /// Appends the entry, which completed with the given status, to the file.
+    pub fn append(self: &mut Self, mut entry: Entry, status: i32) -> Result<()> {
+        entry.status = status;
+        let mut content = String::new();
+        content.push_str(&format!("run {}\n", escape(&entry.run)));
+        content.push_str(&format!("cwd {}\n", escape(&entry.cwd)));
+        content.push_str(&format!("stdin {}\n", escape(&entry.stdin)));
+        for (name, value) in entry.env.iter() {
+            content.push_str(&format!("env {}={}\n", escape(name), escape(value)));
+        }
+        for name in entry.unset.iter() {
+            content.push_str(&format!("unset {}\n", escape(name)));
+        }
+        content.push_str(&format!("status {}\n\n", entry.status));
+        match self.filep.write_all(content.as_bytes()) {
+            Err(err) => Err(Error::new(&format!("{}: {}", self.filename, err))),
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+//
+// Private functions.
+//
+
+/// Runs a recorded pipeline.
+fn run(interp: &mut Interpreter, reaper: &mut PeriodicReaper, source: &str) -> Result<()> {
+    // note: parse_program because here-documents span multiple lines
+    let tokens = lexer::scan(String::from(source), &mut || None)?;
+    let program = parser::parse_program(tokens)?;
+    for cc in program.commands {
+        reaper.reap(); // ensure we don't leave zombies around
+        let loc = interp.translate(cc)?;
+        interp.run(loc, reaper)?;
+    }
+    Ok(())
+}
+
+/// Converts a pipeline of the plan back to a parse tree, which we can
+/// then serialize to shell syntax. Returns None for if commands and
+/// group commands.
+fn untranslate(csc: &CompoundSerialCommand) -> Option<CompleteCommand> {
+    let mut pipeline = Pipeline::new();
+    match csc {
+        CompoundSerialCommand::SingleCommand(sc) => {
+            pipeline
+                .commands
+                .push_back(command(&sc.arguments, &sc.redirs));
+            pipeline.sync = sc.sync;
+            pipeline.negated = sc.negated;
+        }
+        CompoundSerialCommand::PipelinedCommands(pc) => {
+            pipeline
+                .commands
+                .push_back(command(&pc.source.arguments, &pc.source.redirs));
+            for filter in pc.filters.iter() {
+                pipeline
+                    .commands
+                    .push_back(command(&filter.arguments, &filter.redirs));
+            }
+            pipeline
+                .commands
+                .push_back(command(&pc.sink.arguments, &pc.sink.redirs));
+            pipeline.sync = pc.sync;
+            pipeline.negated = pc.negated;
+        }
+        CompoundSerialCommand::GroupCommand(_) => return None,
+        CompoundSerialCommand::IfCommand(_) => return None,
+        CompoundSerialCommand::TestCommand(tc) => {
+            pipeline
+                .commands
+                .push_back(Command::Conditional(ConditionalCommand {
+                    condition: tc.condition.clone(),
+                    redirs: RedirectList::new(),
+                    offset: tc.offset,
+                }));
+            pipeline.sync = true;
+            pipeline.negated = tc.negated;
+        }
+    }
+    let mut cc = CompleteCommand::new();
+    cc.pipelines.push_back(pipeline);
+    Some(cc)
+}
+
+/// Helper for untranslate.
+fn command(arguments: &VecDeque<Word>, redirs: &VecDeque<Redirect>) -> Command {
+    let mut scmd = SimpleCommand::new();
+    scmd.arguments = arguments.clone();
+    scmd.redirs.redirs = redirs.clone();
+    Command::SimpleCommand(scmd)
+}
+
+/// Describes where the first command of the untranslated pipeline reads from.
+fn stdin(cc: &CompleteCommand) -> String {
+    let redirs = match cc.pipelines.front().and_then(|p| p.commands.front()) {
+        Some(Command::SimpleCommand(scmd)) => scmd.redirs.redirs.iter().collect(),
+        _ => Vec::new(),
+    };
+    let mut source = match unsafe { libc::isatty(0) } {
+        1 => String::from("tty"),
+        _ => String::from("inherited"),
+    };
+    for redir in redirs.iter() {
+        match redir {
+            Redirect::Input(InputRedir::File(filename)) => {
+                source = format!("file {}", filename.value);
+            }
+            Redirect::Input(InputRedir::HereDoc(_)) => source = String::from("heredoc"),
+            Redirect::Input(InputRedir::HereString(_)) => source = String::from("herestring"),
+            Redirect::Duplicate(dup) if dup.fd == 0 => source = format!("fd {}", dup.target),
+            Redirect::Close(0) => source = String::from("closed"),
+            _ => (),
+        }
+    }
+    source
+}
+
+/// Escapes the backslashes and the newlines of the given value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Reverses the escaping performed by escape.
+fn unescape(value: &str) -> String {
+    let mut output = String::new();
+    let mut chars = value.chars();
+    loop {
+        match chars.next() {
+            None => break,
+            Some('\\') => match chars.next() {
+                Some('n') => output.push('\n'),
+                Some(c) => output.push(c),
+                None => output.push('\\'),
+            },
+            Some(c) => output.push(c),
+        }
+    }
+    output
+}
@@ -0,0 +1,121 @@
+//! Sets the I/O priority and the CPU affinity of the spawned commands.
+//!
+//! The `ioprio CLASS[:LEVEL] COMMAND...` and `affinity CPUS COMMAND...`
+//! builtins are prefixes, like `envfile` and `pty`, that run `COMMAND` with
+//! the given I/O scheduling class and with the given CPU affinity, as the
+//! `ionice` and `taskset` tools do, so that heavy background pipelines do
+//! not slow down interactive work. The class is `realtime`, `best-effort`
+//! or `idle` and the level, from 0 (highest priority) to 7, defaults to 4
+//! and does not apply to `idle`. The CPUs are a list of numbers and ranges
+//! (e.g., `0,2-3`). The child applies the settings to itself right before
+//! executing the command, hence they only work on Linux.
+
+use crate::model::{Error, Result};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// The scheduling settings of the commands we spawn.
+#[derive(Debug, Clone, Default)]
+pub struct Scheduling {
+    /// The I/O scheduling class and level, if any.
+    ioprio: Option<(i32, i32)>,
+
+    /// The CPUs the command may run on, if restricted.
+    cpus: Option<Vec<usize>>,
+}
+
+/// The maximum number of CPUs of a CPU set.
+const CPU_SETSIZE: usize = 1024;
+
+/// The shift of the class inside the I/O priority value.
+const IOPRIO_CLASS_SHIFT: i32 = 13;
+
+/// The ioprio_set target meaning a single process.
+const IOPRIO_WHO_PROCESS: i32 = 1;
+
+impl Scheduling {
+    /// Sets the I/O priority from the `CLASS[:LEVEL]` argument of `ioprio`.
+    pub fn set_ioprio(self: &mut Self, value: &str) -> Result<()> {
+        let (name, level) = match value.split_once(':') {
+            None => (value, None),
+            Some((name, level)) => (name, Some(level)),
+        };
+        let class = match name {
+            "realtime" | "1" => 1,
+            "best-effort" | "2" => 2,
+            "idle" | "3" => 3,
+            _ => return Err(Error::new(&format!("ioprio: {}: invalid class", name))),
+        };
+        let level = match (class, level) {
+            (3, Some(_)) => return Err(Error::new("ioprio: idle does not take a level")),
+            (3, None) => 0,
+            (_, None) => 4,
+            (_, Some(level)) => match level.parse::<i32>() {
+                Ok(level) if (0..=7).contains(&level) => level,
+                _ => return Err(Error::new(&format!("ioprio: {}: invalid level", level))),
+            },
+        };
+        self.ioprio = Some((class, level));
+        Ok(())
+    }
+
+    /// Sets the CPU affinity from the `CPUS` argument of `affinity`.
+    pub fn set_cpus(self: &mut Self, value: &str) -> Result<()> {
+        let invalid = || Error::new(&format!("affinity: {}: invalid CPU list", value));
+        let mut cpus = Vec::<usize>::new();
+        for item in value.split(',') {
+            let (first, last) = match item.split_once('-') {
+                None => (item, item),
+                Some((first, last)) => (first, last),
+            };
+            match (first.parse::<usize>(), last.parse::<usize>()) {
+                (Ok(first), Ok(last)) if first <= last && last < CPU_SETSIZE => {
+                    cpus.extend(first..=last)
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        self.cpus = Some(cpus);
+        Ok(())
+    }
+
+    /// Makes the child apply the settings before executing the command.
+    pub fn apply(self: &Self, cmd: &mut Command) {
+        if self.ioprio.is_none() && self.cpus.is_none() {
+            return;
+        }
+        let settings = self.clone();
+        unsafe {
+            cmd.pre_exec(move || settings.apply_to_self());
+        }
+    }
+
+    /// Applies the settings to the current process.
+    #[cfg(target_os = "linux")]
+    fn apply_to_self(self: &Self) -> std::io::Result<()> {
+        if let Some((class, level)) = self.ioprio {
+            let value = (class << IOPRIO_CLASS_SHIFT) | level;
+            let rv = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, value) };
+            if rv == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        if let Some(cpus) = &self.cpus {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for cpu in cpus.iter() {
+                unsafe { libc::CPU_SET(*cpu, &mut set) };
+            }
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            if unsafe { libc::sched_setaffinity(0, size, &set) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the settings to the current process.
+    #[cfg(not(target_os = "linux"))]
+    fn apply_to_self(self: &Self) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+}
@@ -0,0 +1,97 @@
+//! Reads shell scripts from files.
+
+use crate::model::{Error, Location, Result};
+
+/// Number of leading bytes we inspect to detect binary files.
+const SNIFF_LENGTH: usize = 512;
+
+/// A script loaded from a file.
+#[derive(Debug)]
+pub struct Script {
+    /// The name of the file containing the script.
+    pub filename: String,
+
+    /// The content of the script.
+    pub content: String,
+}
+
+/// Loads the script contained by the given file.
+pub fn load(filename: &str) -> Result<Script> {
+    let data = match std::fs::read(filename) {
+        Err(err) => return Err(Error::new(&format!("{}: {}", filename, err))),
+        Ok(data) => data,
+    };
+    if is_binary(&data) {
+        return Err(Error::new(&format!(
+            "{}: cannot source binary file",
+            filename
+        )));
+    }
+    let content = match String::from_utf8(data) {
+        Err(err) => {
+            return Err(Error::new(&format!(
+                "{}: byte {}: invalid UTF-8 sequence",
+                filename,
+                err.utf8_error().valid_up_to()
+            )))
+        }
+        Ok(content) => content,
+    };
+    Ok(Script::new(filename, &content))
+}
+
+/// Tells whether the content looks like a binary file, i.e., whether
+/// there is a NUL byte within the first bytes of the file.
+pub fn is_binary(data: &[u8]) -> bool {
+    let length = std::cmp::min(data.len(), SNIFF_LENGTH);
+    data[..length].contains(&0)
+}
+
+impl Script {
+    /// Creates a new script with the given name and content.
+    pub fn new(filename: &str, content: &str) -> Script {
+        Script {
+            filename: String::from(filename),
+            content: String::from(content),
+        }
+    }
+
+    /// Annotates an error with the file name and the line number. If the
+    /// error has an offset (e.g., a syntax error), we use it to compute the
+    /// line number, the column and the byte offset. Otherwise, we use the
+    /// given offset (e.g., the offset of the command that failed).
+    pub fn locate(self: &Self, err: Error, offset: usize) -> Error {
+        self.locate_from(err, offset, 1, 0)
+    }
+
+    /// Like locate, but for a script that is a fragment of a larger file,
+    /// starting at the given line number and byte offset of the file.
+    pub fn locate_from(self: &Self, err: Error, offset: usize, line: usize, base: usize) -> Error {
+        let mut location = Location {
+            filename: self.filename.clone(),
+            line: line,
+            column: None,
+            byte: None,
+        };
+        let mut column = 1;
+        let mut byte = self.content.len();
+        let chars = err.offset().unwrap_or(offset);
+        for (index, (bytes, c)) in self.content.char_indices().enumerate() {
+            if index >= chars {
+                byte = bytes;
+                break;
+            }
+            if c == '\n' {
+                location.line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        if err.offset().is_some() {
+            location.column = Some(column);
+            location.byte = Some(base + byte);
+        }
+        err.at(location)
+    }
+}
@@ -1,100 +1,128 @@
 //! Serializes parse tree to shell syntax
 
-use crate::model::{Error, Result};
-use crate::parser;
+use crate::model::{self, Error, Result};
+use crate::visitor::Visitor;
+use crate::{expansion, parser};
 
 /// Serializes the parse tree to string.
-pub fn serialize(cc: parser::CompleteCommand) -> Result<String> {
+pub fn serialize(cc: &parser::CompleteCommand) -> Result<String> {
     let mut serializer = Serializer::new();
-    serializer.complete_command(cc)?;
-    return Ok(serializer.out);
+    serializer.visit_complete_command(cc);
+    if let Some(err) = serializer.err {
+        return Err(err);
+    }
+    serializer.heredocs();
+    Ok(serializer.out)
 }
 
 /// Implements serialization.
 struct Serializer {
     out: String,
+    heredocs: Vec<model::HereDoc>,
+    err: Option<Error>,
 }
 
-impl Serializer {
-    /// creates a new serializer instance.
-    fn new() -> Serializer {
-        Serializer { out: String::new() }
-    }
-
+impl Visitor for Serializer {
     /// visits each pipeline inside the complete command.
-    fn complete_command(self: &mut Self, cc: parser::CompleteCommand) -> Result<()> {
-        let mut pipelines = cc.pipelines;
-        loop {
-            match pipelines.pop_front() {
-                None => break,
-                Some(p) => {
-                    let syncpipe = p.sync;
-                    self.pipeline(p)?;
-                    if pipelines.len() > 0 {
-                        if syncpipe {
-                            self.out.push(';');
-                        } else {
-                            self.out.push('&');
-                        }
-                    }
-                }
+    fn visit_complete_command(self: &mut Self, cc: &parser::CompleteCommand) {
+        for (index, p) in cc.pipelines.iter().enumerate() {
+            self.visit_pipeline(p);
+            // note: we always emit `&` for asynchronous pipelines,
+            // including the last one, to preserve the sync flag
+            if !p.sync {
+                self.out.push('&');
+            } else if index + 1 < cc.pipelines.len() {
+                self.out.push(';');
             }
         }
-        Ok(())
     }
 
     /// visits each command inside the pipeline.
-    fn pipeline(self: &mut Self, pipeline: parser::Pipeline) -> Result<()> {
-        let mut commands = pipeline.commands;
-        if commands.len() <= 0 {
-            return Err(Error::new("empty pipeline"));
-        }
-        loop {
-            match commands.pop_front() {
-                None => break,
-                Some(cmd) => {
-                    self.command(cmd)?;
-                    if commands.len() > 0 {
-                        self.out.push('|');
-                    }
-                }
-            }
+    fn visit_pipeline(self: &mut Self, pipeline: &parser::Pipeline) {
+        if pipeline.commands.len() == 0 {
+            self.err = Some(Error::new("empty pipeline"));
+            return;
         }
-        Ok(())
-    }
-
-    /// visits a specific command
-    fn command(self: &mut Self, command: parser::Command) -> Result<()> {
-        match command {
-            parser::Command::SimpleCommand(cmd) => self.simple_command(cmd),
-            parser::Command::Subshell(ss) => self.subshell(ss),
+        if pipeline.negated {
+            self.out.push_str("! ");
+        }
+        for (index, cmd) in pipeline.commands.iter().enumerate() {
+            if index > 0 {
+                self.out.push('|');
+            }
+            self.visit_command(cmd);
         }
     }
 
     /// visits a simple command
-    fn simple_command(self: &mut Self, sc: parser::SimpleCommand) -> Result<()> {
-        let mut arguments = sc.arguments;
-        loop {
-            match arguments.pop_front() {
-                None => break,
-                Some(argument) => {
-                    self.out.push_str(&argument);
-                    if arguments.len() > 0 {
-                        self.out.push(' ');
-                    }
-                }
+    fn visit_simple_command(self: &mut Self, sc: &parser::SimpleCommand) {
+        for assignment in sc.assignments.iter() {
+            self.out.push_str(&assignment.name);
+            self.out.push('=');
+            self.visit_word(&assignment.value);
+            self.out.push(' ');
+        }
+        for (index, argument) in sc.arguments.iter().enumerate() {
+            if index > 0 {
+                self.out.push(' ');
             }
+            self.visit_word(argument);
+        }
+        for redir in sc.redirs.redirs.iter() {
+            self.visit_redirect(redir);
         }
-        self.redirs(sc.redirs)?;
-        Ok(())
     }
 
     /// visits a subshell
-    fn subshell(self: &mut Self, ss: parser::Subshell) -> Result<()> {
+    fn visit_subshell(self: &mut Self, ss: &parser::Subshell) {
         self.out.push('(');
-        self.complete_command(ss.complete_command)?;
+        self.visit_complete_command(&ss.complete_command);
         self.out.push(')');
-        self.redirs(ss.redirs)
+        for redir in ss.redirs.redirs.iter() {
+            self.visit_redirect(redir);
+        }
+    }
+
+    /// visits a brace group
+    fn visit_brace_group(self: &mut Self, bg: &parser::BraceGroup) {
+        self.out.push_str("{ ");
+        self.compound_list(&bg.complete_command);
+        self.out.push_str(" }");
+        for redir in bg.redirs.redirs.iter() {
+            self.visit_redirect(redir);
+        }
+    }
+
+    /// visits an if clause
+    fn visit_if_clause(self: &mut Self, ic: &parser::IfClause) {
+        for (index, branch) in ic.branches.iter().enumerate() {
+            if index > 0 {
+                self.out.push_str(" elif ");
+            } else {
+                self.out.push_str("if ");
+            }
+            self.compound_list(&branch.condition);
+            self.out.push_str(" then ");
+            self.compound_list(&branch.body);
+        }
+        if let Some(otherwise) = &ic.otherwise {
+            self.out.push_str(" else ");
+            self.compound_list(otherwise);
+        }
+        self.out.push_str(" fi");
+        for redir in ic.redirs.redirs.iter() {
+            self.visit_redirect(redir);
+        }
+    }
+
+    /// visits a conditional command
+    fn visit_conditional(self: &mut Self, cc: &parser::ConditionalCommand) {
+        self.out.push_str("[[ ");
+        self.condition(&cc.condition);
+        self.out.push_str(" ]]");
+        for redir in cc.redirs.redirs.iter() {
+            self.visit_redirect(redir);
+        }
     }
 
     // TODO(bassosimone): the serializer should probably fail to
@@ -105,20 +133,206 @@ impl Serializer {
     // If we don't do that, the error indicating we have multiple
     // redirections is instead emitted by a subshell.
 
-    /// visit redirs
-    fn redirs(self: &mut Self, redirs: parser::RedirectList) -> Result<()> {
-        if redirs.input.len() > 0 {
-            self.out.push('<');
-            self.out.push_str(&redirs.input[0].filename);
+    /// visits a redirection
+    fn visit_redirect(self: &mut Self, redir: &model::Redirect) {
+        // note: we separate redirections with a space such that, e.g.,
+        // `echo 2 >file` does not become a stderr redirection
+        match redir {
+            model::Redirect::Input(model::InputRedir::File(filename)) => {
+                self.out.push_str(" <");
+                self.visit_word(filename);
+            }
+            model::Redirect::Input(model::InputRedir::HereDoc(heredoc)) => {
+                self.out.push_str(" <<");
+                self.visit_word(&heredoc.delimiter);
+                self.heredocs.push(heredoc.clone());
+            }
+            model::Redirect::Input(model::InputRedir::HereString(word)) => {
+                self.out.push_str(" <<<");
+                self.visit_word(word);
+            }
+            model::Redirect::Output(output) => {
+                self.out.push_str(&format!(" {}>", output.fd));
+                if !output.overwrite {
+                    self.out.push('>');
+                } else if output.force {
+                    self.out.push('|');
+                }
+                self.visit_word(&output.filename);
+            }
+            model::Redirect::Duplicate(dup) => {
+                self.out.push_str(&format!(" {}>&{}", dup.fd, dup.target));
+            }
+            model::Redirect::Close(fd) => {
+                self.out.push_str(&format!(" {}>&-", fd));
+            }
+            model::Redirect::Combined(output) => {
+                self.out.push_str(" &>");
+                if !output.overwrite {
+                    self.out.push('>');
+                }
+                self.visit_word(&output.filename);
+            }
+        }
+    }
+
+    /// emits a word, quoting it when it was originally quoted or when it
+    /// would otherwise be scanned differently by the subshell's lexer.
+    fn visit_word(self: &mut Self, word: &model::Word) {
+        let plain =
+            word.value.len() > 0 && word.value.chars().all(|c| !"|()&;<> \t\\'\"".contains(c));
+        // note: a literal `$`, a literal leading `~` and a literal
+        // pattern char (e.g., `'*'`) must be quoted
+        let literal = word.value.chars().enumerate().any(|(index, c)| {
+            ((c == '$' || (c == '~' && index == 0)) && !word.expansions.contains(&index))
+                || ("*?[".contains(c) && !word.globs.contains(&index))
+        });
+        if plain && !word.quoted && !literal {
+            self.out.push_str(&word.value);
+            return;
+        }
+        if word.expansions.len() > 0 || word.globs.len() > 0 {
+            self.expanding_word(word);
+            return;
         }
-        if redirs.output.len() > 0 {
-            if redirs.output[0].overwrite {
-                self.out.push('>')
+        self.out.push('\'');
+        for c in word.value.chars() {
+            if c == '\'' {
+                self.out.push_str("'\\''");
             } else {
-                self.out.push_str(">>")
+                self.out.push(c);
+            }
+        }
+        self.out.push('\'');
+    }
+}
+
+impl Serializer {
+    /// emits the expression of a conditional command, wrapping the
+    /// nested `&&` and `||` expressions with parentheses.
+    fn condition(self: &mut Self, condition: &model::Condition) {
+        match condition {
+            model::Condition::Word(word) => self.visit_word(word),
+            model::Condition::Unary(operator, word) => {
+                self.out.push_str(operator);
+                self.out.push(' ');
+                self.visit_word(word);
             }
-            self.out.push_str(&redirs.output[0].filename);
+            model::Condition::Binary(left, operator, right) => {
+                self.visit_word(left);
+                self.out.push_str(&format!(" {} ", operator));
+                self.visit_word(right);
+            }
+            model::Condition::Not(inner) => {
+                self.out.push_str("! ");
+                self.nested(inner);
+            }
+            model::Condition::And(left, right) => {
+                self.nested(left);
+                self.out.push_str(" && ");
+                self.nested(right);
+            }
+            model::Condition::Or(left, right) => {
+                self.nested(left);
+                self.out.push_str(" || ");
+                self.nested(right);
+            }
+        }
+    }
+
+    /// helper for condition.
+    fn nested(self: &mut Self, condition: &model::Condition) {
+        match condition {
+            model::Condition::And(_, _) | model::Condition::Or(_, _) => {
+                self.out.push_str("( ");
+                self.condition(condition);
+                self.out.push_str(" )");
+            }
+            _ => self.condition(condition),
+        }
+    }
+
+    /// creates a new serializer instance.
+    fn new() -> Serializer {
+        Serializer {
+            out: String::new(),
+            heredocs: Vec::new(),
+            err: None,
+        }
+    }
+
+    /// emits a compound list, which must be terminated by `;` or `&`
+    /// before the reserved word that follows it.
+    fn compound_list(self: &mut Self, cc: &parser::CompleteCommand) {
+        self.visit_complete_command(cc);
+        if !self.out.ends_with(';') && !self.out.ends_with('&') {
+            self.out.push(';');
+        }
+    }
+
+    /// emits the bodies of the here-documents, which must follow
+    /// the line containing the corresponding redirections.
+    fn heredocs(self: &mut Self) {
+        for heredoc in self.heredocs.iter() {
+            self.out.push('\n');
+            self.out.push_str(&heredoc.body);
+            self.out.push_str(&heredoc.delimiter.value);
+        }
+    }
+
+    /// emits a word containing expansions. Double quotes preserve the
+    /// expansions while we escape the `$` signs that should remain literal.
+    /// We emit command substitutions verbatim, because their command is
+    /// not subject to escaping, and the expansions that were unquoted
+    /// outside of the double quotes, so that the subshell splits them,
+    /// unless they contain chars we would need to escape. Likewise, we
+    /// emit the unquoted pattern chars outside of the double quotes.
+    fn expanding_word(self: &mut Self, word: &model::Word) {
+        let chars: Vec<char> = word.value.chars().collect();
+        let mut quoting = false;
+        let mut quoted = false;
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            let expansion = word.expansions.contains(&index);
+            if expansion {
+                let end = expansion::span_end(word, &chars, index);
+                let span = &chars[index..end];
+                let unquoted = c == '~' || word.splits.contains(&index);
+                let substitution = chars.get(index + 1) == Some(&'(');
+                if substitution || (unquoted && span.iter().all(|c| !"'\"\\".contains(*c))) {
+                    if quoting == unquoted {
+                        self.out.push('"');
+                        quoting = !quoting;
+                        quoted = true;
+                    }
+                    self.out.extend(span);
+                    index = end;
+                    continue;
+                }
+            }
+            let glob = word.globs.contains(&index);
+            if quoting == glob {
+                self.out.push('"');
+                quoting = !quoting;
+                quoted = true;
+            }
+            if glob {
+                self.out.push(c);
+                index += 1;
+                continue;
+            }
+            if (c == '$' && !expansion) || c == '"' || c == '\\' {
+                self.out.push('\\');
+            }
+            self.out.push(c);
+            index += 1;
+        }
+        if quoting {
+            self.out.push('"');
+        }
+        if word.quoted && !quoted {
+            self.out.push_str("\"\""); // preserve the empty word
         }
-        Ok(())
     }
 }
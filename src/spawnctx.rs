@@ -0,0 +1,111 @@
+//! Caches the data that the shell needs to spawn each command.
+//!
+//! Like the hash table of sh, we remember where we found each command in
+//! the `PATH`, so that executing the same command again (e.g., inside a
+//! script) does not look for it in each directory of the `PATH` again.
+//! Changing `PATH` resets the cache, and so does `hash -r`, which is
+//! needed when a new program shadows one we have already found. We also
+//! keep the encoded shell variables that we pass to subshells (see
+//! Interpreter::export_variables) until the variables change.
+
+use crate::hardened;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+/// The data reused across the commands we spawn.
+pub struct SpawnCtx {
+    /// The value of `PATH` from which we computed dirs.
+    path: Option<OsString>,
+
+    /// The directories of the `PATH`.
+    dirs: Vec<PathBuf>,
+
+    /// Where we found each command.
+    resolved: HashMap<String, PathBuf>,
+
+    /// The encoded shell variables, if still valid.
+    variables: Option<String>,
+}
+
+impl SpawnCtx {
+    /// Creates a new, empty context.
+    pub fn new() -> SpawnCtx {
+        SpawnCtx {
+            path: None,
+            dirs: Vec::new(),
+            resolved: HashMap::new(),
+            variables: None,
+        }
+    }
+
+    /// Returns the program implementing the given command, or None when
+    /// the name contains a `/` or we cannot find it, in which case the
+    /// caller should execute the name as it is. We do not remember the
+    /// commands found in relative directories of the `PATH`, whose
+    /// meaning depends on the working directory.
+    pub fn resolve(self: &mut Self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') || name.len() == 0 {
+            return None;
+        }
+        let path = std::env::var_os("PATH");
+        if path != self.path {
+            self.dirs = match &path {
+                None => Vec::new(),
+                Some(path) => std::env::split_paths(path).collect(),
+            };
+            self.resolved.clear();
+            self.path = path;
+        }
+        if let Some(program) = self.resolved.get(name) {
+            return Some(program.clone());
+        }
+        for dir in self.dirs.iter() {
+            let program = dir.join(name);
+            let executable = match std::fs::metadata(&program) {
+                Err(_) => false,
+                Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+            };
+            if !executable {
+                continue;
+            }
+            if dir.is_absolute() {
+                self.resolved.insert(String::from(name), program.clone());
+            }
+            return Some(program);
+        }
+        None
+    }
+
+    /// Forgets where we found the given command (e.g., because executing
+    /// it failed, since it has been removed).
+    pub fn forget(self: &mut Self, name: &str) {
+        self.resolved.remove(name);
+    }
+
+    /// Forgets where we found all the commands (i.e., `hash -r`).
+    pub fn reset(self: &mut Self) {
+        self.resolved.clear();
+    }
+
+    /// Returns the commands we remember and where we found them.
+    pub fn entries(self: &Self) -> Vec<(&String, &PathBuf)> {
+        let mut entries: Vec<_> = self.resolved.iter().collect();
+        entries.sort();
+        entries
+    }
+
+    /// Returns the encoded shell variables, encoding them if needed.
+    pub fn variables(self: &mut Self, encode: impl FnOnce() -> String) -> String {
+        self.variables.get_or_insert_with(encode).clone()
+    }
+
+    /// Tells that the shell variables have changed, overwriting the
+    /// encoded variables, which may contain sensitive values.
+    pub fn variables_changed(self: &mut Self) {
+        if let Some(mut encoded) = self.variables.take() {
+            hardened::clear(&mut encoded);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Measures the startup latency of the shell.
+//!
+//! With `--profile-startup`, the shell writes to the standard error how
+//! long each startup phase took (e.g., parsing the command line flags),
+//! so that we notice when a new feature slows down the startup, which
+//! matters because each subshell is a new instance of the shell.
+//! Subsystems that are expensive to initialize should initialize lazily
+//! on first use (e.g., the name of the current executable, which we only
+//! need for subshells), keeping `xv6sh -c true` fast.
+
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Whether we're profiling the startup.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// When the shell started and when the previous phase ended.
+static TIMES: Lazy<Mutex<(Instant, Instant)>> = Lazy::new(|| {
+    let now = Instant::now();
+    Mutex::new((now, now))
+});
+
+/// Records the time when the shell started.
+pub fn begin() {
+    Lazy::force(&TIMES);
+}
+
+/// Returns how long ago the shell started.
+pub fn elapsed() -> Duration {
+    TIMES.lock().unwrap().0.elapsed()
+}
+
+/// Starts writing how long each startup phase takes.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Writes how long the given phase took, if we're profiling.
+pub fn mark(phase: &str) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut times = TIMES.lock().unwrap();
+    let now = Instant::now();
+    eprintln!(
+        "xv6sh: startup: {}: {:.3} ms (total {:.3} ms)",
+        phase,
+        (now - times.1).as_secs_f64() * 1000.0,
+        (now - times.0).as_secs_f64() * 1000.0
+    );
+    times.1 = now;
+}
+
+/// Writes how long the last startup phase took and stops profiling.
+pub fn finish(phase: &str) {
+    mark(phase);
+    ENABLED.store(false, Ordering::SeqCst);
+}
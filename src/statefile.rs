@@ -0,0 +1,85 @@
+//! Crash-safe reading and writing of state files.
+//!
+//! We write state files by writing a temporary file, flushing it to
+//! stable storage, and renaming it over the original file, so that a
+//! crash leaves either the old or the new content in place. We also
+//! terminate the content with a trailer line, which allows us to
+//! detect and recover files that were only partially written. The
+//! temporary file is readable only by the user and we never follow a
+//! file or symbolic link someone else created in its place.
+
+use crate::model::{Error, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Line terminating a complete state file.
+const TRAILER: &str = "# end\n";
+
+/// Atomically replaces the content of the given file.
+pub fn write(path: &Path, content: &str) -> Result<()> {
+    let temp = temp_path(path);
+    match write_and_rename(&temp, path, content) {
+        Err(err) => {
+            let _ = std::fs::remove_file(&temp); // don't leave garbage around
+            Err(Error::new(&format!("{}: {}", path.display(), err)))
+        }
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Helper function for write.
+fn write_and_rename(temp: &Path, path: &Path, content: &str) -> std::io::Result<()> {
+    let _ = std::fs::remove_file(temp); // leftover of a crash
+    let mut filep = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(temp)?;
+    filep.write_all(content.as_bytes())?;
+    filep.write_all(TRAILER.as_bytes())?;
+    filep.sync_all()?;
+    std::fs::rename(temp, path)?;
+    if let Some(parent) = path.parent() {
+        // note: flushing the directory makes the rename durable
+        let dir = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Reads the content of the given file. If a crash left a temporary file
+/// behind, we remove it. If the file lacks the trailer, because it was only
+/// partially written, we warn the user and return its complete lines.
+pub fn read(path: &Path) -> Result<String> {
+    let _ = std::fs::remove_file(temp_path(path)); // leftover of a crash
+    let mut content = match std::fs::read_to_string(path) {
+        Err(err) => return Err(Error::new(&format!("{}: {}", path.display(), err))),
+        Ok(content) => content,
+    };
+    if content.ends_with(TRAILER) {
+        content.truncate(content.len() - TRAILER.len());
+        return Ok(content);
+    }
+    eprintln!(
+        "xv6sh: warning: {}: recovering partially written file",
+        path.display()
+    );
+    match content.rfind('\n') {
+        None => content.clear(),
+        Some(index) => content.truncate(index + 1),
+    }
+    Ok(content)
+}
+
+/// Returns the path of the temporary file for the given path.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
@@ -0,0 +1,173 @@
+//! Supervises background commands.
+//!
+//! The `supervise` builtin runs a command in background and restarts it
+//! whenever it exits with a non-zero status, waiting with exponential
+//! backoff between restarts and giving up after a maximum number of
+//! restarts. A thread per supervised command waits for the command and
+//! restarts it, so that restarts happen even while the shell is waiting
+//! for input. The `jobs` builtin shows the state of each command.
+
+use crate::model::{CommandFactory, Error, Result};
+use crate::process;
+use crate::watchdog::{Target, Tracker};
+use std::fmt;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The default maximum number of restarts.
+pub const DEFAULT_MAX_RESTARTS: usize = 5;
+
+/// The delay before the first restart, which doubles after each restart.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum delay between restarts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A supervised command.
+pub struct Supervisor {
+    /// The job number shown by `jobs`.
+    pub id: usize,
+
+    /// The command line of the command.
+    pub command: String,
+
+    state: Arc<Mutex<State>>,
+}
+
+/// The state of a supervised command.
+struct State {
+    status: Status,
+    restarts: usize,
+    max_restarts: usize,
+}
+
+/// What the supervised command is doing.
+enum Status {
+    /// The command is running with the given pid.
+    Running(u32),
+
+    /// The command exited with the given status and we're going to
+    /// restart it after the given delay.
+    Restarting(i32, Duration),
+
+    /// The command exited successfully.
+    Done,
+
+    /// The command exited with the given status too many times.
+    GaveUp(i32),
+
+    /// We could not spawn the command.
+    Failed(String),
+}
+
+/// Starts supervising the command created by the given function, which
+/// we call again on each restart. Returns an error if we cannot spawn
+/// the command in the first place.
+pub fn start(
+    id: usize,
+    command: String,
+    max_restarts: usize,
+    create: CommandFactory,
+) -> Result<Supervisor> {
+    let child = spawn(&create)?;
+    let state = Arc::new(Mutex::new(State {
+        status: Status::Running(child.id()),
+        restarts: 0,
+        max_restarts: max_restarts,
+    }));
+    let shared = Arc::clone(&state);
+    std::thread::spawn(move || supervise(child, create, shared));
+    Ok(Supervisor {
+        id: id,
+        command: command,
+        state: state,
+    })
+}
+
+impl Supervisor {
+    /// Tells whether we're still supervising the command.
+    pub fn active(self: &Self) -> bool {
+        matches!(
+            self.state.lock().unwrap().status,
+            Status::Running(_) | Status::Restarting(_, _)
+        )
+    }
+
+    /// Returns a function tracking the command across restarts.
+    pub fn tracker(self: &Self) -> Tracker {
+        let state = Arc::clone(&self.state);
+        Box::new(move || match state.lock().unwrap().status {
+            Status::Running(pid) => Target::Running(pid),
+            Status::Restarting(_, _) => Target::Paused,
+            _ => Target::Gone,
+        })
+    }
+}
+
+impl fmt::Display for Supervisor {
+    fn fmt(self: &Self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        let status = match &state.status {
+            Status::Running(pid) => format!("running (pid {})", pid),
+            Status::Restarting(code, delay) => {
+                format!("exited with {}, restarting in {}s", code, delay.as_secs())
+            }
+            Status::Done => String::from("done"),
+            Status::GaveUp(code) => format!("exited with {}, gave up", code),
+            Status::Failed(reason) => format!("failed: {}", reason),
+        };
+        write!(
+            f,
+            "[{}] {} (restarts {}/{}) {}",
+            self.id, status, state.restarts, state.max_restarts, self.command
+        )
+    }
+}
+
+/// Waits for the command and restarts it until it succeeds or we
+/// reach the maximum number of restarts.
+fn supervise(mut child: std::process::Child, create: CommandFactory, state: Arc<Mutex<State>>) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let code = match process::wait_detached(&mut child) {
+            Err(_) => 1,
+            Ok(status) => process::exit_code(status),
+        };
+        {
+            let mut state = state.lock().unwrap();
+            if code == 0 {
+                state.status = Status::Done;
+                return;
+            }
+            if state.restarts >= state.max_restarts {
+                state.status = Status::GaveUp(code);
+                return;
+            }
+            state.status = Status::Restarting(code, backoff);
+        }
+        std::thread::sleep(backoff);
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+        let mut state = state.lock().unwrap();
+        state.restarts += 1;
+        match spawn(&create) {
+            Err(err) => {
+                state.status = Status::Failed(err.to_string());
+                return;
+            }
+            Ok(next) => {
+                state.status = Status::Running(next.id());
+                child = next;
+            }
+        }
+    }
+}
+
+/// Creates and spawns the command.
+fn spawn(create: &dyn Fn() -> Result<Command>) -> Result<std::process::Child> {
+    let mut cmd = create()?;
+    match process::spawn_detached(&mut cmd) {
+        Err(err) => Err(Error::new(&err.to_string())),
+        Ok(child) => Ok(child),
+    }
+}
@@ -0,0 +1,168 @@
+//! Runs the test scripts inside a directory.
+//!
+//! The `--test DIR` flag runs each `*_test.sh` file inside `DIR`, in
+//! alphabetical order, in its own instance of the shell, whose working
+//! directory is a new temporary directory that we remove afterwards, and
+//! whose standard input is `/dev/null`. A script passes when it exits with
+//! zero (e.g., because all its `assert` commands succeeded). We capture
+//! the output of each script and show it only when the script fails. With
+//! `--tap`, we write the results using the Test Anything Protocol. The
+//! shell does not support functions yet, hence each script is a test.
+
+use crate::model::{Error, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Instant;
+
+/// The suffix of the names of the test scripts.
+const SUFFIX: &str = "_test.sh";
+
+/// The outcome of a test script.
+struct Outcome {
+    /// The name of the script.
+    name: String,
+
+    /// Whether the script exited with zero.
+    passed: bool,
+
+    /// The exit status of the script, or -1 if it was killed.
+    status: i32,
+
+    /// The standard output and error of the script.
+    output: String,
+
+    /// How long the script took, in seconds.
+    seconds: f64,
+}
+
+/// Runs the test scripts inside the given directory, writing the results
+/// to the standard output, and returns whether all of them passed.
+pub fn run(dir: &str, tap: bool) -> Result<bool> {
+    let scripts = discover(dir)?;
+    let exe = match std::env::current_exe() {
+        Err(err) => return Err(Error::new(&err.to_string())),
+        Ok(exe) => exe,
+    };
+    if tap {
+        println!("TAP version 13");
+        println!("1..{}", scripts.len());
+    }
+    let mut failed = 0;
+    for (index, script) in scripts.iter().enumerate() {
+        let outcome = run_one(&exe, script, index)?;
+        if !outcome.passed {
+            failed += 1;
+        }
+        match tap {
+            true => report_tap(&outcome, index + 1),
+            false => report(&outcome),
+        }
+    }
+    let summary = format!("{} passed, {} failed", scripts.len() - failed, failed);
+    match tap {
+        true => println!("# {}", summary),
+        false => println!("{}", summary),
+    }
+    Ok(failed == 0)
+}
+
+/// Returns the test scripts inside the given directory, sorted by name.
+fn discover(dir: &str) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Err(err) => return Err(Error::new(&format!("{}: {}", dir, err))),
+        Ok(entries) => entries,
+    };
+    let mut scripts = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(SUFFIX) && path.is_file() {
+            scripts.push(path);
+        }
+    }
+    scripts.sort();
+    // note: the scripts run elsewhere, hence they need absolute paths
+    let absolute = |path: PathBuf| match std::fs::canonicalize(&path) {
+        Err(_) => path,
+        Ok(absolute) => absolute,
+    };
+    Ok(scripts.into_iter().map(absolute).collect())
+}
+
+/// Runs a test script inside a new temporary directory.
+fn run_one(exe: &Path, script: &Path, index: usize) -> Result<Outcome> {
+    let name = script.file_name().unwrap_or_default();
+    let name = name.to_string_lossy().into_owned();
+    let tmpdir = std::env::temp_dir().join(format!("xv6sh-test-{}-{}", std::process::id(), index));
+    if let Err(err) = std::fs::create_dir(&tmpdir) {
+        return Err(Error::new(&format!("{}: {}", tmpdir.display(), err)));
+    }
+    let started = Instant::now();
+    let result = capture(exe, script, &tmpdir);
+    let seconds = started.elapsed().as_secs_f64();
+    let _ = std::fs::remove_dir_all(&tmpdir);
+    let (status, output) = match result {
+        Err(err) => return Err(Error::new(&format!("{}: {}", name, err))),
+        Ok(result) => result,
+    };
+    Ok(Outcome {
+        name: name,
+        passed: status.success(),
+        status: status.code().unwrap_or(-1),
+        output: String::from_utf8_lossy(&output).into_owned(),
+        seconds: seconds,
+    })
+}
+
+/// Runs a test script inside the given directory, returning its exit
+/// status and its output. We send the standard output and error to the
+/// same pipe, so that the output is in the order the script wrote it.
+fn capture(exe: &Path, script: &Path, dir: &Path) -> std::io::Result<(ExitStatus, Vec<u8>)> {
+    let (mut reader, writer) = os_pipe::pipe()?;
+    let mut child = Command::new(exe)
+        .arg(script)
+        .current_dir(dir)
+        .env("PWD", dir)
+        .stdin(Stdio::null())
+        .stdout(writer.try_clone()?)
+        .stderr(writer)
+        .spawn()?;
+    // note: we have dropped the Command and its copies of the writer,
+    // hence reading ends when the script and its children exit
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output)?;
+    let status = child.wait()?;
+    Ok((status, output))
+}
+
+/// Writes the outcome of a test script for humans.
+fn report(outcome: &Outcome) {
+    match outcome.passed {
+        true => println!("PASS {} ({:.2}s)", outcome.name, outcome.seconds),
+        false => {
+            println!(
+                "FAIL {} (status {}, {:.2}s)",
+                outcome.name, outcome.status, outcome.seconds
+            );
+            for line in outcome.output.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+}
+
+/// Writes the outcome of a test script using TAP, where the output of
+/// the failed scripts becomes diagnostic lines.
+fn report_tap(outcome: &Outcome, number: usize) {
+    match outcome.passed {
+        true => println!("ok {} - {}", number, outcome.name),
+        false => {
+            println!("not ok {} - {}", number, outcome.name);
+            println!("# exit status {}", outcome.status);
+            for line in outcome.output.lines() {
+                println!("# {}", line);
+            }
+        }
+    }
+}
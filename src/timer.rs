@@ -0,0 +1,218 @@
+//! Runs background commands later or periodically.
+//!
+//! The `after DURATION CMD...` builtin runs a command in background once
+//! the given duration has elapsed, while `every DURATION CMD...` runs it
+//! each time the duration elapses. A single timer thread, which we start
+//! when scheduling the first timer, sleeps until the next timer is due and
+//! spawns its command, so that timers fire even while the shell is waiting
+//! for input. The `jobs -t` builtin lists the pending timers and `cancel`
+//! removes them.
+
+use crate::model::{CommandFactory, Error, Result};
+use crate::process;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The timers scheduled by the shell.
+pub struct Scheduler {
+    shared: Arc<(Mutex<Vec<Timer>>, Condvar)>,
+    started: bool,
+}
+
+/// A pending timer.
+pub struct Timer {
+    /// The job number shown by `jobs -t`.
+    pub id: usize,
+
+    /// The command line of the command.
+    pub command: String,
+
+    /// The period, for timers created by `every`.
+    period: Option<Duration>,
+
+    /// When the timer is due next.
+    deadline: Instant,
+
+    /// How many times we have spawned the command.
+    runs: usize,
+
+    create: CommandFactory,
+}
+
+/// Parses a duration consisting of a number followed by an optional
+/// unit: `s` for seconds (the default), `m` for minutes, `h` for hours
+/// and `d` for days (e.g., `30s`, `10m`).
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let invalid = || Error::new(&format!("invalid duration: {}", value));
+    let (number, multiplier) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 3600),
+        Some('d') => (&value[..value.len() - 1], 86400),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(count) if count > 0 => match count.checked_mul(multiplier) {
+            None => Err(invalid()),
+            Some(secs) => Ok(Duration::from_secs(secs)),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Formats a duration using the largest units (e.g., `1h30m`).
+fn format_duration(duration: Duration) -> String {
+    let mut secs = duration.as_secs();
+    let mut output = String::new();
+    for (unit, size) in [("d", 86400), ("h", 3600), ("m", 60)] {
+        if secs >= size {
+            output.push_str(&format!("{}{}", secs / size, unit));
+            secs %= size;
+        }
+    }
+    if secs > 0 || output.len() == 0 {
+        output.push_str(&format!("{}s", secs));
+    }
+    output
+}
+
+impl Scheduler {
+    /// Creates a new scheduler without timers.
+    pub fn new() -> Scheduler {
+        Scheduler {
+            shared: Arc::new((Mutex::new(Vec::new()), Condvar::new())),
+            started: false,
+        }
+    }
+
+    /// Schedules the command created by the given function to run after
+    /// the given delay and then, if periodic, each time the delay elapses.
+    pub fn schedule(
+        self: &mut Self,
+        id: usize,
+        command: String,
+        delay: Duration,
+        periodic: bool,
+        create: CommandFactory,
+    ) {
+        let (timers, cvar) = &*self.shared;
+        timers.lock().unwrap().push(Timer {
+            id: id,
+            command: command,
+            period: if periodic { Some(delay) } else { None },
+            deadline: Instant::now() + delay,
+            runs: 0,
+            create: create,
+        });
+        cvar.notify_one(); // the new timer may be the next one due
+        if !self.started {
+            let shared = Arc::clone(&self.shared);
+            std::thread::spawn(move || run(shared));
+            self.started = true;
+        }
+    }
+
+    /// Cancels the timer with the given job number.
+    pub fn cancel(self: &Self, id: usize) -> Result<()> {
+        let (timers, cvar) = &*self.shared;
+        let mut timers = timers.lock().unwrap();
+        match timers.iter().position(|timer| timer.id == id) {
+            None => Err(Error::new(&format!("cancel: %{}: no such timer", id))),
+            Some(index) => {
+                timers.remove(index);
+                cvar.notify_one();
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the largest job number of the pending timers, if any.
+    pub fn last_id(self: &Self) -> Option<usize> {
+        let (timers, _) = &*self.shared;
+        timers.lock().unwrap().iter().map(|timer| timer.id).max()
+    }
+
+    /// Prints the pending timers ordered by deadline.
+    pub fn list(self: &Self) {
+        let (timers, _) = &*self.shared;
+        let timers = timers.lock().unwrap();
+        let mut sorted: Vec<&Timer> = timers.iter().collect();
+        sorted.sort_by_key(|timer| timer.deadline);
+        for timer in sorted {
+            println!("{}", timer);
+        }
+    }
+}
+
+impl fmt::Display for Timer {
+    fn fmt(self: &Self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        // note: round up so that we never show a due timer as 0s
+        let remaining = Duration::from_secs(remaining.as_secs() + 1);
+        match self.period {
+            None => write!(
+                f,
+                "[{}] after, due in {} {}",
+                self.id,
+                format_duration(remaining),
+                self.command
+            ),
+            Some(period) => write!(
+                f,
+                "[{}] every {}, due in {} (runs {}) {}",
+                self.id,
+                format_duration(period),
+                format_duration(remaining),
+                self.runs,
+                self.command
+            ),
+        }
+    }
+}
+
+/// Body of the timer thread, which spawns the commands of the timers
+/// that are due and then sleeps until the next deadline.
+fn run(shared: Arc<(Mutex<Vec<Timer>>, Condvar)>) {
+    let (timers, cvar) = &*shared;
+    let mut timers = timers.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        for timer in timers.iter_mut() {
+            if timer.deadline > now {
+                continue;
+            }
+            timer.runs += 1;
+            if let Some(period) = timer.period {
+                timer.deadline += period;
+                if timer.deadline <= now {
+                    timer.deadline = now + period; // skip missed runs
+                }
+            }
+            if let Err(err) = spawn(&timer.create) {
+                eprintln!("xv6sh: [{}] {}: {}", timer.id, timer.command, err);
+            }
+        }
+        timers.retain(|timer| timer.period.is_some() || timer.runs == 0);
+        timers = match timers.iter().map(|timer| timer.deadline).min() {
+            None => cvar.wait(timers).unwrap(),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(now);
+                cvar.wait_timeout(timers, timeout).unwrap().0
+            }
+        };
+    }
+}
+
+/// Creates and spawns the command, waiting for it in another thread,
+/// so that it does not become a zombie.
+fn spawn(create: &CommandFactory) -> Result<()> {
+    let mut cmd = create()?;
+    match process::spawn_detached(&mut cmd) {
+        Err(err) => Err(Error::new(&err.to_string())),
+        Ok(mut child) => {
+            std::thread::spawn(move || process::wait_detached(&mut child));
+            Ok(())
+        }
+    }
+}
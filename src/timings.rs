@@ -0,0 +1,102 @@
+//! Measures how long the shell takes to start the commands.
+//!
+//! With `--timings`, after each external command or pipeline, the shell
+//! writes to the standard error how long it took to prepare the commands
+//! (i.e., expanding the words and creating the pipes and redirections),
+//! how long it took from spawning the first process to spawning the last
+//! one, after which the whole pipeline is running and the sink may write
+//! its first output, and how long the pipeline took overall. We prepare
+//! all the processes of a pipeline before spawning any of them, so that
+//! they start back to back.
+//!
+//! We also write how long the stages of the front-end (scanning, parsing
+//! and planning) take and, when built with `--features alloc-stats` (see
+//! allocstats.rs), the peak memory that each stage allocated on top of
+//! what was already allocated, and how many allocations it performed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Whether we're measuring the commands.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Starts writing how long each command takes.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Runs the given stage of the front-end (e.g., `scan`) and writes how
+/// long it took and, with alloc-stats, how much memory it needed.
+pub fn stage<T>(name: &str, run: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return run();
+    }
+    #[cfg(feature = "alloc-stats")]
+    let baseline = crate::allocstats::reset();
+    let start = Instant::now();
+    let result = run();
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+    #[cfg(feature = "alloc-stats")]
+    {
+        let (peak, count) = crate::allocstats::peak();
+        eprintln!(
+            "xv6sh: timings: {} {:.3} ms, peak {} bytes ({} allocations)",
+            name,
+            elapsed,
+            peak.saturating_sub(baseline),
+            count
+        );
+    }
+    #[cfg(not(feature = "alloc-stats"))]
+    eprintln!("xv6sh: timings: {} {:.3} ms", name, elapsed);
+    result
+}
+
+/// The times at which the phases of a command started.
+pub struct Measure {
+    start: Instant,
+    spawning: Option<Instant>,
+    running: Option<Instant>,
+}
+
+/// Starts measuring a command, which we're about to prepare.
+pub fn start() -> Measure {
+    Measure {
+        start: Instant::now(),
+        spawning: None,
+        running: None,
+    }
+}
+
+impl Measure {
+    /// Records that we're about to spawn the first process.
+    pub fn spawning(self: &mut Self) {
+        self.spawning = Some(Instant::now());
+    }
+
+    /// Records that we have spawned all the processes.
+    pub fn running(self: &mut Self) {
+        self.running = Some(Instant::now());
+    }
+
+    /// Writes the timings of the given command, if we're measuring.
+    pub fn report(self: &Self, command: &str, processes: usize) {
+        if !ENABLED.load(Ordering::SeqCst) {
+            return;
+        }
+        let now = Instant::now();
+        let spawning = self.spawning.unwrap_or(now);
+        let running = self.running.unwrap_or(now);
+        let ms = |from: Instant, to: Instant| (to - from).as_secs_f64() * 1000.0;
+        let plural = if processes == 1 { "" } else { "es" };
+        eprintln!(
+            "xv6sh: timings: prepare {:.3} ms, spawn {:.3} ms ({} process{}), total {:.3} ms: {}",
+            ms(self.start, spawning),
+            ms(spawning, running),
+            processes,
+            plural,
+            ms(self.start, now),
+            command
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Writes a transcript of the interactive session.
+//!
+//! The `transcript start FILE` builtin appends to FILE, prefixing each line
+//! with the seconds elapsed since the UNIX epoch, the prompts and the lines
+//! the user types, as well as the errors printed by the shell. The
+//! `transcript stop` builtin stops writing the transcript.
+//!
+//! While writing the transcript, the shell runs the commands that would
+//! write to its terminal inside a pseudo-terminal (see pty.rs), whose
+//! output it forwards to the terminal and records line by line. The
+//! output of the builtins, which the shell writes itself, and of the
+//! commands whose standard output is redirected is not included.
+
+use crate::model::{Error, Result};
+use once_cell::sync::Lazy;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The file where we're writing the transcript, if any.
+static TRANSCRIPT: Lazy<Mutex<Option<File>>> = Lazy::new(|| Mutex::new(None));
+
+/// Starts writing the transcript to the given file.
+pub fn start(filename: &str) -> Result<()> {
+    let filep = match OpenOptions::new().create(true).append(true).open(filename) {
+        Err(err) => return Err(Error::new(&format!("{}: {}", filename, err))),
+        Ok(filep) => filep,
+    };
+    *TRANSCRIPT.lock().unwrap() = Some(filep);
+    write("# transcript started");
+    Ok(())
+}
+
+/// Stops writing the transcript.
+pub fn stop() -> Result<()> {
+    write("# transcript stopped");
+    match TRANSCRIPT.lock().unwrap().take() {
+        None => Err(Error::new("transcript: not started")),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Tells whether we're writing the transcript.
+pub fn active() -> bool {
+    TRANSCRIPT.lock().unwrap().is_some()
+}
+
+/// Records the output of a command, which may arrive in chunks
+/// splitting the lines, into the transcript.
+pub struct Output {
+    line: Vec<u8>,
+}
+
+impl Output {
+    /// Creates an empty output.
+    pub fn new() -> Output {
+        Output { line: Vec::new() }
+    }
+
+    /// Records the complete lines, keeping the last one if incomplete.
+    pub fn write(self: &mut Self, buffer: &[u8]) {
+        for byte in buffer.iter() {
+            if *byte != b'\n' {
+                self.line.push(*byte);
+                continue;
+            }
+            write(&String::from_utf8_lossy(&self.line));
+            self.line.clear();
+        }
+    }
+}
+
+impl Drop for Output {
+    /// Records the last line, even if incomplete.
+    fn drop(&mut self) {
+        if self.line.len() > 0 {
+            write(&String::from_utf8_lossy(&self.line));
+        }
+    }
+}
+
+/// Writes a line to the transcript, if we're writing one.
+pub fn write(line: &str) {
+    if let Some(filep) = TRANSCRIPT.lock().unwrap().as_mut() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // note: ignore errors, the session is more important than the transcript
+        let _ = writeln!(
+            filep,
+            "[{}.{:03}] {}",
+            now.as_secs(),
+            now.subsec_millis(),
+            line
+        );
+    }
+}
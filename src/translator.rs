@@ -1,14 +1,21 @@
 //! Translates the syntax tree into an executable syntax tree
 //! that the interpreter will then interpret.
 
-use crate::model::{Error, Result};
+use crate::model::{Assignment, Condition, DupRedir, Error, Redirect, Result, Word};
 use crate::parser::{
-    Command, CompleteCommand, InputRedir, OutputRedir, Pipeline, SimpleCommand, Subshell,
+    BraceGroup, Command, CompleteCommand, ConditionalCommand, IfClause, Pipeline, RedirectList,
+    SimpleCommand,
 };
-use crate::serializer;
+use crate::{serializer, validator, xtrace};
+use once_cell::sync::Lazy;
 use std::collections::VecDeque;
 
-/// Contains a list of commands to run serially.
+/// Contains a list of commands to run serially. This is the execution
+/// plan produced by the translator. The interpreter consumes the plan
+/// one CompoundSerialCommand at a time (see Interpreter::step), so you
+/// can inspect the plan, drive its execution step by step, and inject a
+/// custom ProcessSpawner into the interpreter to observe or fake the
+/// processes it spawns.
 #[derive(Debug)]
 pub struct ListOfCommands {
     pub pipelines: VecDeque<CompoundSerialCommand>,
@@ -19,15 +26,28 @@ pub struct ListOfCommands {
 pub enum CompoundSerialCommand {
     SingleCommand(SingleCommand),
     PipelinedCommands(PipelinedCommands),
+    GroupCommand(GroupCommand),
+    IfCommand(IfCommand),
+    TestCommand(TestCommand),
 }
 
 /// A single, standalone command.
 #[derive(Debug)]
 pub struct SingleCommand {
-    pub arguments: VecDeque<String>,
-    pub input: Option<InputRedir>,
-    pub output: Option<OutputRedir>,
+    /// The variables to add to the command's environment.
+    pub assignments: VecDeque<Assignment>,
+
+    pub arguments: VecDeque<Word>,
+    pub redirs: VecDeque<Redirect>,
+
+    /// Copied from the sync flag of the parser's Pipeline.
     pub sync: bool,
+
+    /// Copied from the negated flag of the parser's Pipeline.
+    pub negated: bool,
+
+    /// The offset in chars of the pipeline inside the input.
+    pub offset: usize,
 }
 
 /// A pipeline consisting of a SourceCommand, zero or more
@@ -37,32 +57,113 @@ pub struct PipelinedCommands {
     pub source: SourceCommand,
     pub filters: VecDeque<FilterCommand>,
     pub sink: SinkCommand,
+
+    /// Copied from the sync flag of the parser's Pipeline.
     pub sync: bool,
+
+    /// Copied from the negated flag of the parser's Pipeline.
+    pub negated: bool,
+
+    /// The offset in chars of the pipeline inside the input.
+    pub offset: usize,
+}
+
+/// A group of commands that the shell executes itself, applying the
+/// redirections to the whole group, because it's a standalone, synchronous
+/// pipeline. Otherwise, we execute the brace group inside a subshell.
+#[derive(Debug)]
+pub struct GroupCommand {
+    pub body: ListOfCommands,
+    pub redirs: VecDeque<Redirect>,
+
+    /// Copied from the negated flag of the parser's Pipeline.
+    pub negated: bool,
+
+    /// The offset in chars of the pipeline inside the input.
+    pub offset: usize,
+}
+
+/// A conditional command that the shell evaluates itself, because it's
+/// a standalone, synchronous pipeline without redirections. Otherwise,
+/// we execute the if clause inside a subshell.
+#[derive(Debug)]
+pub struct IfCommand {
+    /// The condition and the body of each `if` and `elif` branch.
+    pub branches: VecDeque<(ListOfCommands, ListOfCommands)>,
+
+    /// The commands following `else`, if any.
+    pub otherwise: Option<ListOfCommands>,
+
+    /// Copied from the negated flag of the parser's Pipeline.
+    pub negated: bool,
+
+    /// The offset in chars of the pipeline inside the input.
+    pub offset: usize,
+}
+
+/// A `[[ ... ]]` conditional command that the shell evaluates itself,
+/// because it's a standalone, synchronous pipeline without redirections.
+/// Otherwise, we evaluate the expression inside a subshell.
+#[derive(Debug)]
+pub struct TestCommand {
+    pub condition: Condition,
+
+    /// Copied from the negated flag of the parser's Pipeline.
+    pub negated: bool,
+
+    /// The offset in chars of the pipeline inside the input.
+    pub offset: usize,
 }
 
 /// The source command of a pipeline.
 #[derive(Debug)]
 pub struct SourceCommand {
-    pub arguments: VecDeque<String>,
-    pub input: Option<InputRedir>,
+    /// The variables to add to the command's environment.
+    pub assignments: VecDeque<Assignment>,
+
+    pub arguments: VecDeque<Word>,
+    pub redirs: VecDeque<Redirect>,
 }
 
 /// A filter command in the middle of a pipeline.
 #[derive(Debug)]
 pub struct FilterCommand {
-    pub arguments: VecDeque<String>,
+    /// The variables to add to the command's environment.
+    pub assignments: VecDeque<Assignment>,
+
+    pub arguments: VecDeque<Word>,
+    pub redirs: VecDeque<Redirect>,
 }
 
 /// The sink command of a pipeline.
 #[derive(Debug)]
 pub struct SinkCommand {
-    pub arguments: VecDeque<String>,
-    pub output: Option<OutputRedir>,
+    /// The variables to add to the command's environment.
+    pub assignments: VecDeque<Assignment>,
+
+    pub arguments: VecDeque<Word>,
+    pub redirs: VecDeque<Redirect>,
 }
 
-/// Translates the syntax tree to make it interpretable.
-pub fn translate(cc: CompleteCommand, verbose: bool) -> Result<ListOfCommands> {
-    let translator = Translator::new(verbose);
+/// Translates the syntax tree to make it interpretable. We validate the
+/// syntax tree first and fail on the first error. When tracing, we also
+/// write the warnings to the trace. The verbose (i.e., tracing) and
+/// noclobber flags are passed along to subshells.
+pub fn translate(
+    cc: CompleteCommand,
+    trace: Option<&xtrace::Trace>,
+    noclobber: bool,
+) -> Result<ListOfCommands> {
+    let report = validator::validate(&cc, false);
+    if let Some(issue) = report.errors().next() {
+        return Err(issue.to_error());
+    }
+    if let Some(trace) = trace {
+        for issue in report.warnings() {
+            trace.write(&format!("xv6sh: warning: {}", issue.to_error()));
+        }
+    }
+    let translator = Translator::new(trace.is_some(), noclobber);
     translator.complete_command(cc)
 }
 
@@ -77,16 +178,70 @@ impl ListOfCommands {
             pipelines: VecDeque::<_>::new(),
         }
     }
+
+    /// Returns the arguments of each command in execution order.
+    pub fn commands(self: &Self) -> Vec<&VecDeque<Word>> {
+        let mut output = Vec::<&VecDeque<Word>>::new();
+        for csc in self.pipelines.iter() {
+            match csc {
+                CompoundSerialCommand::SingleCommand(sc) => output.push(&sc.arguments),
+                CompoundSerialCommand::PipelinedCommands(pc) => {
+                    output.push(&pc.source.arguments);
+                    for filter in pc.filters.iter() {
+                        output.push(&filter.arguments);
+                    }
+                    output.push(&pc.sink.arguments);
+                }
+                CompoundSerialCommand::GroupCommand(gc) => output.extend(gc.body.commands()),
+                CompoundSerialCommand::IfCommand(ic) => {
+                    for (condition, body) in ic.branches.iter() {
+                        output.extend(condition.commands());
+                        output.extend(body.commands());
+                    }
+                    if let Some(otherwise) = &ic.otherwise {
+                        output.extend(otherwise.commands());
+                    }
+                }
+                CompoundSerialCommand::TestCommand(_) => (),
+            }
+        }
+        output
+    }
+
+    /// Estimates the number of processes that executing the plan spawns
+    /// directly. This is an upper bound, because builtin commands run in
+    /// the shell itself, and ignores the processes spawned by subshells.
+    pub fn processes(self: &Self) -> usize {
+        self.commands()
+            .iter()
+            .filter(|arguments| arguments.len() > 0)
+            .count()
+    }
+}
+
+impl CompoundSerialCommand {
+    /// Returns the offset in chars of the pipeline inside the input.
+    pub fn offset(self: &Self) -> usize {
+        match self {
+            CompoundSerialCommand::SingleCommand(sc) => sc.offset,
+            CompoundSerialCommand::PipelinedCommands(pc) => pc.offset,
+            CompoundSerialCommand::GroupCommand(gc) => gc.offset,
+            CompoundSerialCommand::IfCommand(ic) => ic.offset,
+            CompoundSerialCommand::TestCommand(tc) => tc.offset,
+        }
+    }
 }
 
 impl SingleCommand {
     /// Creates a new single command.
     pub fn new() -> SingleCommand {
         SingleCommand {
+            assignments: VecDeque::<_>::new(),
             arguments: VecDeque::<_>::new(),
-            input: None,
-            output: None,
+            redirs: VecDeque::<_>::new(),
             sync: false,
+            negated: false,
+            offset: 0,
         }
     }
 }
@@ -99,6 +254,8 @@ impl PipelinedCommands {
             filters: VecDeque::<_>::new(),
             sink: SinkCommand::new(),
             sync: false,
+            negated: false,
+            offset: 0,
         }
     }
 }
@@ -107,8 +264,9 @@ impl SourceCommand {
     /// Creates a new source command.
     pub fn new() -> SourceCommand {
         SourceCommand {
+            assignments: VecDeque::<_>::new(),
             arguments: VecDeque::<_>::new(),
-            input: None,
+            redirs: VecDeque::<_>::new(),
         }
     }
 }
@@ -117,7 +275,9 @@ impl FilterCommand {
     /// Creates a new filter command.
     pub fn new() -> FilterCommand {
         FilterCommand {
+            assignments: VecDeque::<_>::new(),
             arguments: VecDeque::<_>::new(),
+            redirs: VecDeque::<_>::new(),
         }
     }
 }
@@ -126,12 +286,31 @@ impl SinkCommand {
     /// Creates a new sink command.
     pub fn new() -> SinkCommand {
         SinkCommand {
+            assignments: VecDeque::<_>::new(),
             arguments: VecDeque::<_>::new(),
-            output: None,
+            redirs: VecDeque::<_>::new(),
         }
     }
 }
 
+/// Tells whether the shell runs the commands of the given pipeline itself,
+/// rather than inside a subshell: this happens for a standalone, synchronous
+/// brace group and for a standalone, synchronous if clause or conditional
+/// command without redirections. Otherwise, the pipeline is a single unit
+/// of execution.
+pub fn runs_in_shell(pipeline: &Pipeline) -> bool {
+    match (
+        pipeline.sync,
+        pipeline.commands.len(),
+        pipeline.commands.front(),
+    ) {
+        (true, 1, Some(Command::If(ic))) => ic.redirs.redirs.len() == 0,
+        (true, 1, Some(Command::Group(_))) => true,
+        (true, 1, Some(Command::Conditional(cc))) => cc.redirs.redirs.len() == 0,
+        _ => false,
+    }
+}
+
 //
 // Translator implementation
 //
@@ -139,12 +318,16 @@ impl SinkCommand {
 /// The translator itself.
 struct Translator {
     verbose: bool,
+    noclobber: bool,
 }
 
 impl Translator {
     /// creates a new translator
-    fn new(verbose: bool) -> Translator {
-        Translator { verbose: verbose }
+    fn new(verbose: bool, noclobber: bool) -> Translator {
+        Translator {
+            verbose: verbose,
+            noclobber: noclobber,
+        }
     }
 
     /// visits each pipeline inside the complete command.
@@ -169,8 +352,22 @@ impl Translator {
     /// visits each command inside the pipeline.
     fn pipeline(self: &Self, input: Pipeline) -> Result<CompoundSerialCommand> {
         let mut intermediate = VecDeque::<SimpleCommand>::new();
-        let sync = input.sync;
+        let (sync, negated, offset) = (input.sync, input.negated, input.offset());
+        let in_shell = runs_in_shell(&input);
         let mut input = input.commands;
+        match input.pop_front() {
+            Some(Command::If(ic)) if in_shell => {
+                return self.if_command(ic, negated, offset);
+            }
+            Some(Command::Group(bg)) if in_shell => {
+                return self.group_command(bg, negated, offset);
+            }
+            Some(Command::Conditional(cc)) if in_shell => {
+                return self.test_command(cc, negated, offset);
+            }
+            Some(cmd) => input.push_front(cmd),
+            None => (),
+        }
         loop {
             match input.pop_front() {
                 None => break,
@@ -185,9 +382,9 @@ impl Translator {
         }
         if intermediate.len() == 1 {
             let f = intermediate.pop_front().unwrap(); // cannot fail
-            return self.single_command(f, sync);
+            return self.single_command(f, sync, negated, offset);
         }
-        self.pipelined_commands(intermediate, sync)
+        self.pipelined_commands(intermediate, sync, negated, offset)
     }
 
     /// produces a single command instance
@@ -195,22 +392,16 @@ impl Translator {
         self: &Self,
         input: SimpleCommand,
         sync: bool,
+        negated: bool,
+        offset: usize,
     ) -> Result<CompoundSerialCommand> {
         let mut output = SingleCommand::new();
+        output.offset = offset;
+        output.assignments = input.assignments;
         output.arguments = input.arguments;
         output.sync = sync;
-        if input.redirs.input.len() > 1 {
-            return Err(Error::new("more than one input redirection"));
-        }
-        if input.redirs.input.len() == 1 {
-            output.input = Some(input.redirs.input[0].clone());
-        }
-        if input.redirs.output.len() > 1 {
-            return Err(Error::new("more than one output redirection"));
-        }
-        if input.redirs.output.len() == 1 {
-            output.output = Some(input.redirs.output[0].clone());
-        }
+        output.negated = negated;
+        output.redirs = Self::redirs(input.redirs);
         Ok(CompoundSerialCommand::SingleCommand(output))
     }
 
@@ -219,9 +410,13 @@ impl Translator {
         self: &Self,
         mut input: VecDeque<SimpleCommand>,
         sync: bool,
+        negated: bool,
+        offset: usize,
     ) -> Result<CompoundSerialCommand> {
         let mut output = PipelinedCommands::new();
+        output.offset = offset;
         output.sync = sync;
+        output.negated = negated;
         output.source = self.new_source(&mut input)?;
         output.filters = self.new_filters(&mut input)?;
         output.sink = self.new_sink(&mut input)?;
@@ -234,16 +429,9 @@ impl Translator {
         match input.pop_front() {
             None => Err(Error::new("unexpected empty deque")),
             Some(item) => {
+                output.assignments = item.assignments;
                 output.arguments = item.arguments;
-                if item.redirs.input.len() > 1 {
-                    return Err(Error::new("more than one input redirection"));
-                }
-                if item.redirs.input.len() == 1 {
-                    output.input = Some(item.redirs.input[0].clone());
-                }
-                if item.redirs.output.len() > 0 {
-                    return Err(Error::new("output redirection for pipeline source"));
-                }
+                output.redirs = Self::redirs(item.redirs);
                 Ok(output)
             }
         }
@@ -259,13 +447,9 @@ impl Translator {
             // note: the last element is the sink
             let e = input.pop_front().unwrap(); // cannot fail
             let mut filter = FilterCommand::new();
+            filter.assignments = e.assignments;
             filter.arguments = e.arguments;
-            if e.redirs.input.len() > 0 {
-                return Err(Error::new("input redirection for pipeline filter"));
-            }
-            if e.redirs.output.len() > 0 {
-                return Err(Error::new("output redirection for pipeline filter"));
-            }
+            filter.redirs = Self::redirs(e.redirs);
             output.push_back(filter);
         }
         Ok(output)
@@ -277,54 +461,191 @@ impl Translator {
         match input.pop_front() {
             None => Err(Error::new("unexpected empty deque")),
             Some(item) => {
+                output.assignments = item.assignments;
                 output.arguments = item.arguments;
-                if item.redirs.input.len() > 0 {
-                    return Err(Error::new("input redirection for pipeline sink"));
-                }
-                if item.redirs.output.len() > 1 {
-                    return Err(Error::new("more than one output redirection"));
-                }
-                if item.redirs.output.len() == 1 {
-                    output.output = Some(item.redirs.output[0].clone());
-                }
+                output.redirs = Self::redirs(item.redirs);
                 Ok(output)
             }
         }
     }
 
+    /// Helper to prepare the redirections of a command for execution.
+    fn redirs(input: RedirectList) -> VecDeque<Redirect> {
+        Self::desugar(input.redirs)
+    }
+
+    /// Helper to rewrite `&> file` as `> file 2>&1`.
+    fn desugar(input: VecDeque<Redirect>) -> VecDeque<Redirect> {
+        let mut output = VecDeque::<Redirect>::new();
+        for redir in input {
+            match redir {
+                Redirect::Combined(redir) => {
+                    output.push_back(Redirect::Output(redir));
+                    output.push_back(Redirect::Duplicate(DupRedir { fd: 2, target: 1 }));
+                }
+                _ => output.push_back(redir),
+            }
+        }
+        output
+    }
+
+    /// produces a group command executed by the shell itself
+    fn group_command(
+        self: &Self,
+        input: BraceGroup,
+        negated: bool,
+        offset: usize,
+    ) -> Result<CompoundSerialCommand> {
+        Ok(CompoundSerialCommand::GroupCommand(GroupCommand {
+            body: self.complete_command(input.complete_command)?,
+            redirs: Self::redirs(input.redirs),
+            negated: negated,
+            offset: offset,
+        }))
+    }
+
+    /// produces an if command evaluated by the shell itself
+    fn if_command(
+        self: &Self,
+        input: IfClause,
+        negated: bool,
+        offset: usize,
+    ) -> Result<CompoundSerialCommand> {
+        let mut output = IfCommand {
+            branches: VecDeque::<_>::new(),
+            otherwise: None,
+            negated: negated,
+            offset: offset,
+        };
+        for branch in input.branches {
+            let condition = self.complete_command(branch.condition)?;
+            let body = self.complete_command(branch.body)?;
+            output.branches.push_back((condition, body));
+        }
+        if let Some(otherwise) = input.otherwise {
+            output.otherwise = Some(self.complete_command(otherwise)?);
+        }
+        Ok(CompoundSerialCommand::IfCommand(output))
+    }
+
+    /// produces a conditional command evaluated by the shell itself
+    fn test_command(
+        self: &Self,
+        input: ConditionalCommand,
+        negated: bool,
+        offset: usize,
+    ) -> Result<CompoundSerialCommand> {
+        Ok(CompoundSerialCommand::TestCommand(TestCommand {
+            condition: input.condition,
+            negated: negated,
+            offset: offset,
+        }))
+    }
+
     /// visits a specific command
     fn command(self: &Self, input: Command) -> Result<SimpleCommand> {
         match input {
             Command::SimpleCommand(cmd) => Ok(cmd),
-            Command::Subshell(ss) => self.subshell(ss),
+            Command::Subshell(ss) => self.subshell(ss.complete_command, ss.redirs, ss.offset),
+            Command::Group(mut bg) => {
+                // note: the redirections apply to the whole subshell
+                let redirs = std::mem::replace(&mut bg.redirs, RedirectList::new());
+                let offset = bg.offset;
+                let mut pipeline = Pipeline::new();
+                pipeline.sync = true;
+                pipeline.commands.push_back(Command::Group(bg));
+                let mut cc = CompleteCommand::new();
+                cc.pipelines.push_back(pipeline);
+                self.subshell(cc, redirs, offset)
+            }
+            Command::If(mut ic) => {
+                // note: the redirections apply to the whole subshell
+                let redirs = std::mem::replace(&mut ic.redirs, RedirectList::new());
+                let offset = ic.offset;
+                let mut pipeline = Pipeline::new();
+                pipeline.sync = true;
+                pipeline.commands.push_back(Command::If(ic));
+                let mut cc = CompleteCommand::new();
+                cc.pipelines.push_back(pipeline);
+                self.subshell(cc, redirs, offset)
+            }
+            Command::Conditional(mut cond) => {
+                // note: the redirections apply to the whole subshell
+                let redirs = std::mem::replace(&mut cond.redirs, RedirectList::new());
+                let offset = cond.offset;
+                let mut pipeline = Pipeline::new();
+                pipeline.sync = true;
+                pipeline.commands.push_back(Command::Conditional(cond));
+                let mut cc = CompleteCommand::new();
+                cc.pipelines.push_back(pipeline);
+                self.subshell(cc, redirs, offset)
+            }
         }
     }
 
-    /// visits a subshell
-    fn subshell(self: &Self, input: Subshell) -> Result<SimpleCommand> {
+    /// produces a simple command running the complete command in a subshell
+    fn subshell(
+        self: &Self,
+        cc: CompleteCommand,
+        redirs: RedirectList,
+        offset: usize,
+    ) -> Result<SimpleCommand> {
         let mut scmd = SimpleCommand {
+            assignments: VecDeque::<_>::new(),
             arguments: VecDeque::<_>::new(),
-            redirs: input.redirs,
+            redirs: redirs,
+            offset: offset,
         };
-        let exe = Self::get_current_exe()?;
-        scmd.arguments.push_back(exe);
+        let exe = current_exe()?;
+        scmd.arguments.push_back(Word::new(&exe));
         if self.verbose {
-            scmd.arguments.push_back(String::from("-x"))
+            scmd.arguments.push_back(Word::new("-x"))
         }
-        scmd.arguments.push_back(String::from("-c"));
-        let serialized = serializer::serialize(input.complete_command)?;
-        scmd.arguments.push_back(serialized);
+        if self.noclobber {
+            scmd.arguments.push_back(Word::new("-C"))
+        }
+        // note: the subshell inherits `$?`, `$$` and the positional
+        // parameters, which the interpreter expands before spawning it
+        scmd.arguments.push_back(Word::new("--status"));
+        scmd.arguments.push_back(Self::parameter("$?"));
+        scmd.arguments.push_back(Word::new("--pid"));
+        scmd.arguments.push_back(Self::parameter("$$"));
+        scmd.arguments.push_back(Word::new("-c"));
+        let serialized = serializer::serialize(&cc)?;
+        scmd.arguments.push_back(Word::new(&serialized));
+        scmd.arguments.push_back(Word::new("--"));
+        scmd.arguments.push_back(Self::parameter("$0"));
+        scmd.arguments.push_back(Self::parameter("$@"));
         Ok(scmd)
     }
 
-    /// Helper function to obtain the current exe.
-    fn get_current_exe() -> Result<String> {
-        match std::env::current_exe() {
-            Err(err) => Err(Error::new(&err.to_string())),
+    /// Helper function to create a quoted word expanding the given
+    /// parameter (e.g., `"$?"`).
+    fn parameter(value: &str) -> Word {
+        Word {
+            value: String::from(value),
+            quoted: true,
+            expansions: vec![0],
+            splits: Vec::new(),
+            globs: Vec::new(),
+        }
+    }
+}
+
+/// Returns the path of the current exe, which we use to run subshells
+/// and command substitutions. We look it up just once and only when we
+/// need it (see startup.rs).
+pub fn current_exe() -> Result<String> {
+    static CURRENT_EXE: Lazy<std::result::Result<String, String>> =
+        Lazy::new(|| match std::env::current_exe() {
+            Err(err) => Err(err.to_string()),
             Ok(pb) => match pb.to_str() {
-                None => Err(Error::new("unicode decode error")),
+                None => Err(String::from("unicode decode error")),
                 Some(path) => Ok(String::from(path)),
             },
-        }
+        });
+    match &*CURRENT_EXE {
+        Err(reason) => Err(Error::new(reason)),
+        Ok(path) => Ok(path.clone()),
     }
 }
@@ -0,0 +1,151 @@
+//! Checks whether a newer version of the shell is available.
+//!
+//! `xv6sh --check-update` fetches the latest release from the URL inside
+//! `$XV6SH_UPDATE_URL` or, if unset, from the GitHub releases of the
+//! project, and prints upgrade instructions when the release is newer than
+//! the running shell. The URL must return a JSON object whose `tag_name`
+//! string is the version of the release (e.g., `"tag_name":"v0.2.0"`),
+//! like GitHub does. We use curl to fetch the URL, with a short timeout.
+//!
+//! When `XV6SH_UPDATE_HINT=1`, the interactive shell also checks, in the
+//! background and at most once a day, whether an update is available and,
+//! if so, prints a hint before the next prompt. Nothing is ever checked
+//! otherwise, and setting `XV6SH_NO_UPDATE_CHECK=1` disables both checks,
+//! so that the shell never contacts the network. The hardened mode also
+//! disables the background check, which would write a file.
+
+use crate::hardened;
+use crate::model::{Error, Result};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+/// The URL we query unless `$XV6SH_UPDATE_URL` is set.
+const DEFAULT_URL: &str = "https://api.github.com/repos/bassosimone/xv6sh/releases/latest";
+
+/// The repository from which to install a newer version.
+const REPOSITORY: &str = "https://github.com/bassosimone/xv6sh";
+
+/// How long we wait for the server.
+const TIMEOUT_SECONDS: &str = "5";
+
+/// How often the interactive shell checks for updates when opted in.
+const HINT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Tells whether the user disabled the update checks.
+fn disabled() -> bool {
+    matches!(std::env::var("XV6SH_NO_UPDATE_CHECK"), Ok(value) if value.len() > 0 && value != "0")
+}
+
+/// Returns the instructions to upgrade to the latest release, if newer
+/// than the running shell, or None if the shell is up to date.
+pub fn check() -> Result<Option<String>> {
+    if disabled() {
+        return Err(Error::new(
+            "update checks disabled by XV6SH_NO_UPDATE_CHECK",
+        ));
+    }
+    let url = match std::env::var("XV6SH_UPDATE_URL") {
+        Ok(url) if url.len() > 0 => url,
+        _ => String::from(DEFAULT_URL),
+    };
+    let output = match Command::new("curl")
+        .args(["-fsSL", "--max-time", TIMEOUT_SECONDS, &url])
+        .output()
+    {
+        Err(err) => return Err(Error::new(&format!("curl: {}", err))),
+        Ok(output) => output,
+    };
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::new(&format!("{}: {}", url, reason.trim())));
+    }
+    let tag = tag_name(&String::from_utf8_lossy(&output.stdout))?;
+    let current = env!("CARGO_PKG_VERSION");
+    match (version(&tag), version(current)) {
+        (Some(latest), Some(running)) if latest > running => Ok(Some(format!(
+            "xv6sh {} is available (you have {}); to upgrade, run:\n\n    cargo install --locked --git {} --tag {}\n",
+            tag.trim_start_matches('v'),
+            current,
+            REPOSITORY,
+            tag
+        ))),
+        (Some(_), _) => Ok(None),
+        (None, _) => Err(Error::new(&format!("{}: invalid version", tag))),
+    }
+}
+
+/// Extracts the `tag_name` string from the JSON returned by the server.
+fn tag_name(reply: &str) -> Result<String> {
+    let missing = || Error::new("invalid reply: missing tag_name");
+    let start = reply.find("\"tag_name\"").ok_or_else(missing)? + "\"tag_name\"".len();
+    let rest = reply[start..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?;
+    let rest = rest.trim_start().strip_prefix('"').ok_or_else(missing)?;
+    // note: version tags do not contain escaped chars
+    match rest.find(['"', '\\']) {
+        Some(end) if rest[end..].starts_with('"') => Ok(String::from(&rest[..end])),
+        _ => Err(missing()),
+    }
+}
+
+/// Parses a version such as `v1.2.3` into its numeric components.
+fn version(tag: &str) -> Option<Vec<u64>> {
+    let tag = tag.trim_start_matches('v');
+    let tag = tag.split(['-', '+']).next()?; // ignore pre-release and build
+    tag.split('.')
+        .map(|part| part.parse::<u64>().ok())
+        .collect()
+}
+
+/// Checks for updates in the background on behalf of the interactive
+/// shell, when the user opted in (see the module documentation).
+pub struct Hint {
+    rx: mpsc::Receiver<String>,
+}
+
+impl Hint {
+    /// Starts checking in the background if the user opted in and
+    /// we did not check during the last day.
+    pub fn start() -> Option<Hint> {
+        if disabled() || hardened::enabled() || std::env::var("XV6SH_UPDATE_HINT").ok()? != "1" {
+            return None;
+        }
+        let stamp = stamp_path()?;
+        if let Ok(modified) = std::fs::metadata(&stamp).and_then(|meta| meta.modified()) {
+            match SystemTime::now().duration_since(modified) {
+                Ok(elapsed) if elapsed < HINT_INTERVAL => return None,
+                _ => (),
+            }
+        }
+        // note: we record the check before performing it, so that the
+        // shell does not check again on each start when offline
+        if let Some(parent) = stamp.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _ = std::fs::write(&stamp, "");
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            if let Ok(Some(message)) = check() {
+                let _ = tx.send(message);
+            }
+        });
+        Some(Hint { rx: rx })
+    }
+
+    /// Returns the hint, if the check completed and found an update.
+    pub fn poll(self: &Self) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// Returns the file whose modification time is that of the last check.
+fn stamp_path() -> Option<PathBuf> {
+    match std::env::var("HOME") {
+        Ok(home) if home.len() > 0 => Some(PathBuf::from(home).join(".xv6sh/update-check")),
+        _ => None,
+    }
+}
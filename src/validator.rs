@@ -0,0 +1,267 @@
+//! Validates the syntax tree before translating it.
+//!
+//! The validation collects all the issues it finds rather than stopping
+//! at the first one. Errors prevent the translator from translating the
+//! syntax tree, while warnings point at suspicious constructs that the
+//! shell nonetheless knows how to execute.
+
+use crate::model::{Error, Redirect};
+use crate::parser::{
+    BraceGroup, CompleteCommand, ConditionalCommand, IfClause, Pipeline, Program, RedirectList,
+    SimpleCommand, Subshell,
+};
+use crate::visitor::{self, Visitor};
+use std::fmt;
+
+/// The severity of an issue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// An issue found while validating.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub reason: String,
+
+    /// The offset in chars of the offending command inside the input.
+    pub offset: usize,
+}
+
+/// Contains all the issues found while validating.
+#[derive(Debug)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+}
+
+/// Validates the given complete command. When subshells is true, we also
+/// validate the complete commands inside subshells, which otherwise are
+/// validated by the subshell itself once it runs.
+pub fn validate(cc: &CompleteCommand, subshells: bool) -> Report {
+    let mut validator = Validator::new(subshells);
+    validator.visit_complete_command(cc);
+    validator.report
+}
+
+/// Validates the given program, including the subshells.
+pub fn validate_program(program: &Program) -> Report {
+    let mut validator = Validator::new(true);
+    validator.visit_program(program);
+    validator.report
+}
+
+//
+// Implementation of public types.
+//
+
+impl fmt::Display for Severity {
+    fn fmt(self: &Self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl Issue {
+    /// Converts the issue to an error pointing at the offending command.
+    pub fn to_error(self: &Self) -> Error {
+        Error::with_offset(&self.reason, self.offset)
+    }
+}
+
+impl Report {
+    /// Returns all the errors.
+    pub fn errors(self: &Self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    /// Returns all the warnings.
+    pub fn warnings(self: &Self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Warning)
+    }
+}
+
+//
+// Validator implementation.
+//
+
+/// The validator itself.
+struct Validator {
+    report: Report,
+    subshells: bool,
+
+    /// The role of the command we're visiting inside a pipeline (if any)
+    /// and whether its standard input and output are pipes.
+    role: Option<&'static str>,
+    piped_input: bool,
+    piped_output: bool,
+}
+
+impl Visitor for Validator {
+    /// visits each command inside the pipeline, knowing that the first
+    /// command is the source, the last command is the sink and all the
+    /// other commands in the middle are filters.
+    fn visit_pipeline(self: &mut Self, pipeline: &Pipeline) {
+        let count = pipeline.commands.len();
+        for (index, command) in pipeline.commands.iter().enumerate() {
+            let (first, last) = (index == 0, index + 1 == count);
+            self.role = match (first, last) {
+                (true, true) => None,
+                (true, false) => Some("source"),
+                (false, false) => Some("filter"),
+                (false, true) => Some("sink"),
+            };
+            self.piped_input = !first;
+            self.piped_output = !last;
+            self.visit_command(command);
+        }
+    }
+
+    /// visits a simple command.
+    fn visit_simple_command(self: &mut Self, scmd: &SimpleCommand) {
+        if scmd.arguments.len() == 0 && self.role.is_some() {
+            self.error("empty command in pipeline", scmd.offset);
+        } else if scmd.arguments.len() == 0 && scmd.redirs.redirs.len() > 0 {
+            self.warning("redirections without a command are ignored", scmd.offset);
+        }
+        self.redirs(&scmd.redirs, scmd.offset);
+    }
+
+    /// visits a subshell.
+    fn visit_subshell(self: &mut Self, ss: &Subshell) {
+        // note: validate the redirections before visiting the complete
+        // command, which overwrites the role of the current command
+        self.redirs(&ss.redirs, ss.offset);
+        if self.subshells {
+            self.visit_complete_command(&ss.complete_command);
+        }
+    }
+
+    /// visits a brace group, whose commands we always validate because
+    /// the translator translates them along with the brace group.
+    fn visit_brace_group(self: &mut Self, bg: &BraceGroup) {
+        // note: as for subshells, validate the redirections first
+        self.redirs(&bg.redirs, bg.offset);
+        visitor::walk_brace_group(self, bg);
+    }
+
+    /// visits an if clause, whose commands we always validate because
+    /// the translator translates them along with the if clause.
+    fn visit_if_clause(self: &mut Self, ic: &IfClause) {
+        // note: as for subshells, validate the redirections first
+        self.redirs(&ic.redirs, ic.offset);
+        visitor::walk_if_clause(self, ic);
+    }
+
+    /// visits a conditional command.
+    fn visit_conditional(self: &mut Self, cc: &ConditionalCommand) {
+        self.redirs(&cc.redirs, cc.offset);
+    }
+}
+
+impl Validator {
+    /// creates a new validator instance.
+    fn new(subshells: bool) -> Validator {
+        Validator {
+            report: Report { issues: Vec::new() },
+            subshells: subshells,
+            role: None,
+            piped_input: false,
+            piped_output: false,
+        }
+    }
+
+    /// validates the redirections of the command we're visiting.
+    fn redirs(self: &mut Self, redirs: &RedirectList, offset: usize) {
+        let mut inputs = 0;
+        let mut outputs = Vec::<i32>::new();
+        for (index, redir) in redirs.redirs.iter().enumerate() {
+            let fds = match redir {
+                Redirect::Input(_) => {
+                    inputs += 1;
+                    vec![0]
+                }
+                Redirect::Output(output) => vec![output.fd],
+                Redirect::Duplicate(dup) => {
+                    if dup.fd == dup.target {
+                        self.warning(
+                            &format!("useless redirection {}>&{}", dup.fd, dup.target),
+                            offset,
+                        );
+                    } else if Self::redirected_after(redirs, index, dup.target) {
+                        self.warning(
+                            &format!(
+                                "{}>&{} duplicates {} before {} is redirected",
+                                dup.fd, dup.target, dup.target, dup.target
+                            ),
+                            offset,
+                        );
+                    }
+                    vec![dup.fd]
+                }
+                Redirect::Close(fd) => vec![*fd],
+                Redirect::Combined(_) => vec![1, 2],
+            };
+            for fd in fds {
+                match redir {
+                    Redirect::Output(_) | Redirect::Combined(_) if outputs.contains(&fd) => {
+                        self.error("more than one output redirection", offset);
+                    }
+                    Redirect::Output(_) | Redirect::Combined(_) => outputs.push(fd),
+                    _ => (),
+                }
+                if let (0, true, Some(role)) = (fd, self.piped_input, self.role) {
+                    self.error(&format!("input redirection for pipeline {}", role), offset);
+                }
+                if let (1, true, Some(role)) = (fd, self.piped_output, self.role) {
+                    self.error(&format!("output redirection for pipeline {}", role), offset);
+                }
+            }
+        }
+        if inputs > 1 {
+            self.error("more than one input redirection", offset);
+        }
+    }
+
+    /// tells whether a redirection following the one at the given index
+    /// redirects the given file descriptor.
+    fn redirected_after(redirs: &RedirectList, index: usize, fd: i32) -> bool {
+        redirs
+            .redirs
+            .iter()
+            .skip(index + 1)
+            .any(|redir| match redir {
+                Redirect::Input(_) => fd == 0,
+                Redirect::Output(output) => output.fd == fd,
+                Redirect::Duplicate(dup) => dup.fd == fd,
+                Redirect::Close(closed) => *closed == fd,
+                Redirect::Combined(_) => fd == 1 || fd == 2,
+            })
+    }
+
+    /// records an error.
+    fn error(self: &mut Self, reason: &str, offset: usize) {
+        self.issue(Severity::Error, reason, offset);
+    }
+
+    /// records a warning.
+    fn warning(self: &mut Self, reason: &str, offset: usize) {
+        self.issue(Severity::Warning, reason, offset);
+    }
+
+    /// records an issue.
+    fn issue(self: &mut Self, severity: Severity, reason: &str, offset: usize) {
+        self.report.issues.push(Issue {
+            severity: severity,
+            reason: String::from(reason),
+            offset: offset,
+        });
+    }
+}
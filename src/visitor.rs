@@ -0,0 +1,169 @@
+//! Visitor over the parse tree.
+//!
+//! Implement the Visitor trait and override the methods for the nodes
+//! you care about. The default implementations call the corresponding
+//! walk function, which visits the children of the node, so you only
+//! need to call the walk function yourself when overriding a method
+//! and still wanting to visit the children.
+
+use crate::model::{Condition, Redirect, Word};
+use crate::parser::{
+    BraceGroup, Command, CompleteCommand, ConditionalCommand, IfClause, Pipeline, Program,
+    SimpleCommand, Subshell,
+};
+
+/// Visits the nodes of the parse tree.
+pub trait Visitor {
+    /// visits a program.
+    fn visit_program(self: &mut Self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    /// visits a complete command.
+    fn visit_complete_command(self: &mut Self, cc: &CompleteCommand) {
+        walk_complete_command(self, cc);
+    }
+
+    /// visits a pipeline.
+    fn visit_pipeline(self: &mut Self, pipeline: &Pipeline) {
+        walk_pipeline(self, pipeline);
+    }
+
+    /// visits a command.
+    fn visit_command(self: &mut Self, command: &Command) {
+        walk_command(self, command);
+    }
+
+    /// visits a simple command.
+    fn visit_simple_command(self: &mut Self, scmd: &SimpleCommand) {
+        walk_simple_command(self, scmd);
+    }
+
+    /// visits a subshell.
+    fn visit_subshell(self: &mut Self, ss: &Subshell) {
+        walk_subshell(self, ss);
+    }
+
+    /// visits a brace group.
+    fn visit_brace_group(self: &mut Self, bg: &BraceGroup) {
+        walk_brace_group(self, bg);
+    }
+
+    /// visits an if clause.
+    fn visit_if_clause(self: &mut Self, ic: &IfClause) {
+        walk_if_clause(self, ic);
+    }
+
+    /// visits a conditional command.
+    fn visit_conditional(self: &mut Self, cc: &ConditionalCommand) {
+        walk_conditional(self, cc);
+    }
+
+    /// visits a word (i.e., a command or an argument).
+    fn visit_word(self: &mut Self, _word: &Word) {}
+
+    /// visits a redirection.
+    fn visit_redirect(self: &mut Self, _redir: &Redirect) {}
+}
+
+/// Visits each complete command inside the program.
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for cc in program.commands.iter() {
+        visitor.visit_complete_command(cc);
+    }
+}
+
+/// Visits each pipeline inside the complete command.
+pub fn walk_complete_command<V: Visitor + ?Sized>(visitor: &mut V, cc: &CompleteCommand) {
+    for pipeline in cc.pipelines.iter() {
+        visitor.visit_pipeline(pipeline);
+    }
+}
+
+/// Visits each command inside the pipeline.
+pub fn walk_pipeline<V: Visitor + ?Sized>(visitor: &mut V, pipeline: &Pipeline) {
+    for command in pipeline.commands.iter() {
+        visitor.visit_command(command);
+    }
+}
+
+/// Visits the specific kind of command.
+pub fn walk_command<V: Visitor + ?Sized>(visitor: &mut V, command: &Command) {
+    match command {
+        Command::SimpleCommand(scmd) => visitor.visit_simple_command(scmd),
+        Command::Subshell(ss) => visitor.visit_subshell(ss),
+        Command::Group(bg) => visitor.visit_brace_group(bg),
+        Command::If(ic) => visitor.visit_if_clause(ic),
+        Command::Conditional(cc) => visitor.visit_conditional(cc),
+    }
+}
+
+/// Visits the values of the assignments, the words and then the
+/// redirections of the simple command.
+pub fn walk_simple_command<V: Visitor + ?Sized>(visitor: &mut V, scmd: &SimpleCommand) {
+    for assignment in scmd.assignments.iter() {
+        visitor.visit_word(&assignment.value);
+    }
+    for word in scmd.arguments.iter() {
+        visitor.visit_word(word);
+    }
+    for redir in scmd.redirs.redirs.iter() {
+        visitor.visit_redirect(redir);
+    }
+}
+
+/// Visits the complete command and then the redirections of the subshell.
+pub fn walk_subshell<V: Visitor + ?Sized>(visitor: &mut V, ss: &Subshell) {
+    visitor.visit_complete_command(&ss.complete_command);
+    for redir in ss.redirs.redirs.iter() {
+        visitor.visit_redirect(redir);
+    }
+}
+
+/// Visits the complete command and then the redirections of the brace group.
+pub fn walk_brace_group<V: Visitor + ?Sized>(visitor: &mut V, bg: &BraceGroup) {
+    visitor.visit_complete_command(&bg.complete_command);
+    for redir in bg.redirs.redirs.iter() {
+        visitor.visit_redirect(redir);
+    }
+}
+
+/// Visits the condition and the body of each branch, the commands
+/// following `else`, and then the redirections of the if clause.
+pub fn walk_if_clause<V: Visitor + ?Sized>(visitor: &mut V, ic: &IfClause) {
+    for branch in ic.branches.iter() {
+        visitor.visit_complete_command(&branch.condition);
+        visitor.visit_complete_command(&branch.body);
+    }
+    if let Some(otherwise) = &ic.otherwise {
+        visitor.visit_complete_command(otherwise);
+    }
+    for redir in ic.redirs.redirs.iter() {
+        visitor.visit_redirect(redir);
+    }
+}
+
+/// Visits the words of the expression and then the redirections of the
+/// conditional command.
+pub fn walk_conditional<V: Visitor + ?Sized>(visitor: &mut V, cc: &ConditionalCommand) {
+    walk_condition(visitor, &cc.condition);
+    for redir in cc.redirs.redirs.iter() {
+        visitor.visit_redirect(redir);
+    }
+}
+
+/// Helper for walk_conditional.
+fn walk_condition<V: Visitor + ?Sized>(visitor: &mut V, condition: &Condition) {
+    match condition {
+        Condition::Word(word) | Condition::Unary(_, word) => visitor.visit_word(word),
+        Condition::Binary(left, _, right) => {
+            visitor.visit_word(left);
+            visitor.visit_word(right);
+        }
+        Condition::Not(inner) => walk_condition(visitor, inner),
+        Condition::And(left, right) | Condition::Or(left, right) => {
+            walk_condition(visitor, left);
+            walk_condition(visitor, right);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Watches the CPU and memory usage of background jobs.
+//!
+//! The `jobs --limit JOB cpu=PERCENT mem=SIZE [signal=NAME]` builtin attaches
+//! a watchdog to a supervised command (`%N`) or to a background process
+//! (its pid). A single sampler thread, which we start when attaching the
+//! first watchdog, reads `/proc/PID/stat` and `/proc/PID/statm` once per
+//! second and, when the job exceeds a threshold, prints a notification
+//! or sends the configured signal. A watchdog fires once each time the
+//! job goes over a threshold and ends when the job ends.
+
+use crate::model::{Error, Result};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often we sample the usage of the jobs.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// The signals that `signal=NAME` accepts, besides their numbers.
+const SIGNALS: [(&str, libc::c_int); 9] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("STOP", libc::SIGSTOP),
+    ("CONT", libc::SIGCONT),
+];
+
+/// What a job is doing, according to the function tracking it.
+pub enum Target {
+    /// The job is running with the given pid.
+    Running(u32),
+
+    /// The job is not running now but may run again (e.g., a supervised
+    /// command that is about to restart).
+    Paused,
+
+    /// The job has ended.
+    Gone,
+}
+
+/// A function telling what the watched job is doing.
+pub type Tracker = Box<dyn Fn() -> Target + Send>;
+
+/// The thresholds of a watchdog and what to do when the job exceeds them.
+pub struct Limits {
+    /// The maximum CPU usage, in percent of one CPU.
+    pub cpu: Option<f64>,
+
+    /// The maximum resident memory, in bytes.
+    pub mem: Option<u64>,
+
+    /// The signal to send, or None to print a notification.
+    pub signal: Option<libc::c_int>,
+}
+
+/// The watchdogs attached by the shell.
+pub struct Watchdogs {
+    shared: Arc<Mutex<Vec<Watchdog>>>,
+    started: bool,
+}
+
+/// A watchdog attached to a job.
+struct Watchdog {
+    /// The job as the user named it (e.g., `%1`).
+    job: String,
+
+    limits: Limits,
+    track: Tracker,
+
+    /// The pid, CPU time in clock ticks and time of the last sample.
+    last: Option<(u32, u64, Instant)>,
+
+    /// Whether the job is over a threshold, so that we fire once.
+    tripped: bool,
+}
+
+/// A usage sample of a process.
+struct Usage {
+    /// The CPU time in clock ticks.
+    ticks: u64,
+
+    /// The resident memory in bytes.
+    rss: u64,
+}
+
+impl Limits {
+    /// Parses the `cpu=PERCENT`, `mem=SIZE` and `signal=NAME` arguments,
+    /// where `SIZE` is a number of bytes optionally followed by `K`, `M`
+    /// or `G` (e.g., `cpu=80%`, `mem=1G`, `signal=TERM`).
+    pub fn parse(args: &[String]) -> Result<Limits> {
+        let mut limits = Limits {
+            cpu: None,
+            mem: None,
+            signal: None,
+        };
+        for arg in args {
+            let invalid = || Error::new(&format!("jobs: invalid limit: {}", arg));
+            match arg.split_once('=') {
+                Some(("cpu", value)) => {
+                    let value = value.strip_suffix('%').unwrap_or(value);
+                    limits.cpu = match value.parse::<f64>() {
+                        Ok(percent) if percent.is_finite() && percent > 0.0 => Some(percent),
+                        _ => return Err(invalid()),
+                    };
+                }
+                Some(("mem", value)) => {
+                    limits.mem = match parse_size(value) {
+                        None => return Err(invalid()),
+                        Some(size) => Some(size),
+                    };
+                }
+                Some(("signal", value)) => {
+                    limits.signal = match parse_signal(value) {
+                        None => return Err(invalid()),
+                        Some(signal) => Some(signal),
+                    };
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if limits.cpu.is_none() && limits.mem.is_none() {
+            return Err(Error::new("jobs: --limit needs cpu=PERCENT or mem=SIZE"));
+        }
+        Ok(limits)
+    }
+}
+
+/// Parses a size in bytes optionally followed by `K`, `M` or `G`.
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, multiplier) = match value.chars().last() {
+        Some('K' | 'k') => (&value[..value.len() - 1], 1 << 10),
+        Some('M' | 'm') => (&value[..value.len() - 1], 1 << 20),
+        Some('G' | 'g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(count) if count > 0 => count.checked_mul(multiplier),
+        _ => None,
+    }
+}
+
+/// Parses a signal name (e.g., `TERM` or `SIGTERM`) or number.
+fn parse_signal(value: &str) -> Option<libc::c_int> {
+    if let Ok(number) = value.parse::<libc::c_int>() {
+        return Some(number).filter(|number| *number > 0 && *number < 65);
+    }
+    let name = value.strip_prefix("SIG").unwrap_or(value);
+    SIGNALS
+        .iter()
+        .find(|(other, _)| *other == name)
+        .map(|(_, signal)| *signal)
+}
+
+/// Returns the name of the given signal, if we know it.
+fn signal_name(signal: libc::c_int) -> String {
+    match SIGNALS.iter().find(|(_, other)| *other == signal) {
+        Some((name, _)) => format!("SIG{}", name),
+        None => signal.to_string(),
+    }
+}
+
+impl fmt::Display for Limits {
+    fn fmt(self: &Self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(cpu) = self.cpu {
+            parts.push(format!("cpu={}%", cpu));
+        }
+        if let Some(mem) = self.mem {
+            parts.push(format!("mem={}", mem));
+        }
+        match self.signal {
+            Some(signal) => parts.push(format!("signal={}", signal_name(signal))),
+            None => parts.push(String::from("notify")),
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl Watchdogs {
+    /// Creates a new set without watchdogs.
+    pub fn new() -> Watchdogs {
+        Watchdogs {
+            shared: Arc::new(Mutex::new(Vec::new())),
+            started: false,
+        }
+    }
+
+    /// Attaches a watchdog to the job tracked by the given function,
+    /// replacing the one already attached to the same job, if any.
+    pub fn attach(self: &mut Self, job: String, limits: Limits, track: Tracker) {
+        let mut watchdogs = self.shared.lock().unwrap();
+        watchdogs.retain(|watchdog| watchdog.job != job);
+        watchdogs.push(Watchdog {
+            job: job,
+            limits: limits,
+            track: track,
+            last: None,
+            tripped: false,
+        });
+        if !self.started {
+            let shared = Arc::clone(&self.shared);
+            std::thread::spawn(move || run(shared));
+            self.started = true;
+        }
+    }
+
+    /// Detaches the watchdog from the given job.
+    pub fn detach(self: &Self, job: &str) -> Result<()> {
+        let mut watchdogs = self.shared.lock().unwrap();
+        match watchdogs.iter().position(|watchdog| watchdog.job == job) {
+            None => Err(Error::new(&format!("jobs: {}: no such watchdog", job))),
+            Some(index) => {
+                watchdogs.remove(index);
+                Ok(())
+            }
+        }
+    }
+
+    /// Prints the watchdogs.
+    pub fn list(self: &Self) {
+        for watchdog in self.shared.lock().unwrap().iter() {
+            println!("[{}] limit {}", watchdog.job, watchdog.limits);
+        }
+    }
+}
+
+/// Body of the sampler thread, which samples the usage of the watched
+/// jobs and fires the watchdogs of the jobs exceeding their thresholds.
+fn run(shared: Arc<Mutex<Vec<Watchdog>>>) {
+    loop {
+        std::thread::sleep(INTERVAL);
+        let mut watchdogs = shared.lock().unwrap();
+        watchdogs.retain_mut(|watchdog| match (watchdog.track)() {
+            Target::Gone => false,
+            Target::Paused => {
+                watchdog.last = None;
+                true
+            }
+            Target::Running(pid) => {
+                sample(watchdog, pid);
+                true
+            }
+        });
+    }
+}
+
+/// Samples the usage of the given process and fires the watchdog when
+/// the process goes over one of the thresholds.
+fn sample(watchdog: &mut Watchdog, pid: u32) {
+    let usage = match usage(pid) {
+        None => return, // the process is exiting
+        Some(usage) => usage,
+    };
+    let now = Instant::now();
+    // note: we need two samples of the same process to compute the CPU
+    let cpu = match watchdog.last {
+        Some((last, ticks, when)) if last == pid => {
+            let hertz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+            let seconds = usage.ticks.saturating_sub(ticks) as f64 / hertz;
+            let elapsed = now.duration_since(when).as_secs_f64().max(f64::EPSILON);
+            Some(100.0 * seconds / elapsed)
+        }
+        _ => None,
+    };
+    watchdog.last = Some((pid, usage.ticks, now));
+    let mut reasons = Vec::new();
+    if let (Some(cpu), Some(limit)) = (cpu, watchdog.limits.cpu) {
+        if cpu > limit {
+            reasons.push(format!("cpu {:.0}% > {}%", cpu, limit));
+        }
+    }
+    if let Some(limit) = watchdog.limits.mem {
+        if usage.rss > limit {
+            reasons.push(format!("mem {} > {}", usage.rss, limit));
+        }
+    }
+    let tripped = reasons.len() > 0;
+    if !tripped || watchdog.tripped {
+        watchdog.tripped = tripped;
+        return;
+    }
+    watchdog.tripped = true;
+    let reasons = reasons.join(", ");
+    match watchdog.limits.signal {
+        None => eprintln!("xv6sh: [{}] pid {}: {}", watchdog.job, pid, reasons),
+        Some(signal) => {
+            unsafe { libc::kill(pid as libc::pid_t, signal) };
+            eprintln!(
+                "xv6sh: [{}] pid {}: {}, sent {}",
+                watchdog.job,
+                pid,
+                reasons,
+                signal_name(signal)
+            );
+        }
+    }
+}
+
+/// Reads the usage of the given process from `/proc`, returning None if
+/// the process does not exist or is a zombie.
+fn usage(pid: u32) -> Option<Usage> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // note: the command name may contain spaces, hence we split after it
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    if fields.first() == Some(&"Z") {
+        return None;
+    }
+    // note: utime and stime are the 14th and 15th fields of the file
+    let utime = fields.get(11)?.parse::<u64>().ok()?;
+    let stime = fields.get(12)?.parse::<u64>().ok()?;
+    let statm = std::fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+    Some(Usage {
+        ticks: utime + stime,
+        rss: pages * page,
+    })
+}
+
+/// Returns a function tracking a background process by pid.
+pub fn track_pid(pid: u32) -> Tracker {
+    Box::new(move || match usage(pid) {
+        None => Target::Gone,
+        Some(_) => Target::Running(pid),
+    })
+}
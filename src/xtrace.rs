@@ -0,0 +1,103 @@
+//! Writes the trace output of the `-x` flag.
+//!
+//! By default, the trace goes to the standard error. With the
+//! `--xtrace-fd` and `--xtrace-file` flags, the trace goes to the given
+//! file descriptor or file instead, which the interpreter owns through a
+//! Trace. We move the descriptor above the numbers that redirections use
+//! in practice and keep it close-on-exec, so that commands do not inherit
+//! it and redirections such as `exec 5>out` cannot capture the trace.
+//! Subshells, which are new instances of the shell, write their trace
+//! to the same place, hence we pass them the descriptor explicitly
+//! through `--xtrace-fd`.
+
+use crate::fdtable;
+use crate::model::{Error, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// The lowest number we use for the trace descriptor.
+const MINIMUM: i32 = 100;
+
+/// Where the trace output goes.
+pub struct Trace {
+    /// The trace file, or None for the standard error.
+    file: Option<File>,
+}
+
+impl Trace {
+    /// Creates a trace writing to the standard error.
+    pub fn new() -> Trace {
+        Trace { file: None }
+    }
+
+    /// Creates a trace writing to the given fd, which the shell takes
+    /// over, unless it's one of the standard descriptors.
+    pub fn from_fd(fd: &str) -> Result<Trace> {
+        let fd = match fd.parse::<i32>() {
+            Ok(fd) if fd >= 0 => fd,
+            _ => return Err(Error::new(&format!("invalid xtrace fd: {}", fd))),
+        };
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+            let err = std::io::Error::last_os_error();
+            return Err(Error::new(&format!("xtrace fd {}: {}", fd, err)));
+        }
+        let trace = Self::raise(fd)?;
+        if fd > 2 {
+            // note: closing the original, so that commands don't inherit it
+            drop(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+        Ok(trace)
+    }
+
+    /// Creates a trace appending to the given file.
+    pub fn from_file(filename: &str) -> Result<Trace> {
+        match OpenOptions::new().create(true).append(true).open(filename) {
+            Err(err) => Err(Error::new(&format!("{}: {}", filename, err))),
+            Ok(filep) => Self::raise(filep.as_raw_fd()),
+        }
+    }
+
+    /// Creates a trace writing to a close-on-exec copy of the given
+    /// fd numbered at least MINIMUM.
+    fn raise(fd: i32) -> Result<Trace> {
+        match fdtable::dup_above(fd, MINIMUM) {
+            Err(err) => Err(Error::new(&format!("xtrace fd {}: {}", fd, err))),
+            Ok(fd) => Ok(Trace {
+                file: Some(File::from(fd)),
+            }),
+        }
+    }
+
+    /// Writes a line of trace output.
+    pub fn write(self: &Self, line: &str) {
+        match &self.file {
+            None => eprintln!("{}", line),
+            Some(filep) => {
+                let mut filep: &File = filep;
+                if writeln!(filep, "{}", line).is_err() {
+                    eprintln!("{}", line); // fallback to stderr
+                }
+            }
+        }
+    }
+
+    /// Configures the given command, which runs a new instance of the
+    /// shell, to write its trace to the same place.
+    pub fn inherit(self: &Self, cmd: &mut Command) {
+        if let Some(filep) = &self.file {
+            let fd = filep.as_raw_fd();
+            cmd.arg("--xtrace-fd").arg(fd.to_string());
+            unsafe {
+                cmd.pre_exec(move || {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+}
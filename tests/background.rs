@@ -0,0 +1,111 @@
+//! Checks that a trailing `&` runs every shape of pipeline in background,
+//! i.e., that the translator threads the sync flag through each of them.
+//!
+//! The command reads from a fifo that the test controls, hence it cannot
+//! finish before the test writes into the fifo, and the order of the output
+//! tells whether the shell waited for it, without depending on timing.
+
+mod common;
+
+use common::workdir;
+use std::ffi::CString;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// How long we wait before unblocking a shell that should not block,
+/// which only matters when the test fails.
+const DEADLINE: Duration = Duration::from_secs(30);
+
+/// Creates a fifo with the given name inside the directory.
+fn gate(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    let cpath = CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) }, 0);
+    path
+}
+
+/// Opens the fifo and writes into it, which unblocks the command.
+fn open_gate(path: &Path) {
+    let mut fifo = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    fifo.write_all(b"gated\n").unwrap();
+}
+
+/// Runs the line followed by `echo shell`.
+fn spawn(dir: &Path, line: &str) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_xv6sh"))
+        .arg("-c")
+        .arg(format!("{}\necho shell", line))
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+#[test]
+fn trailing_ampersand_backgrounds_every_shape() {
+    let dir = workdir("background");
+    // note: the shell does not support `&&` lists yet, hence `a && b &`
+    // runs `a` in background and then `b` in background
+    let shapes = [
+        "cat {} &",
+        "true | cat {} &",
+        "(cat {}) &",
+        "true && cat {} &",
+    ];
+    for (index, shape) in shapes.iter().enumerate() {
+        let path = gate(&dir, &format!("gate{}", index));
+        let line = shape.replace("{}", &path.to_string_lossy());
+        let mut child = spawn(&dir, &line);
+        // note: if the shell waits for the command, we unblock it once the
+        // deadline expires, so that the test fails rather than hanging,
+        // while a nonblocking open fails once the command has read
+        let unblock = path.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(DEADLINE);
+            let options = std::fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&unblock);
+            if let Ok(mut fifo) = options {
+                let _ = fifo.write_all(b"gated\n");
+            }
+        });
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut first = String::new();
+        stdout.read_line(&mut first).unwrap();
+        assert_eq!(first, "shell\n", "{}: did not run in background", line);
+        open_gate(&path);
+        let mut rest = String::new();
+        stdout.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "gated\n", "{}: did not run", line);
+        assert!(child.wait().unwrap().success(), "{}", line);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn no_ampersand_waits_for_every_shape() {
+    let dir = workdir("foreground");
+    let shapes = ["cat {}", "true | cat {}", "(cat {})"];
+    for (index, shape) in shapes.iter().enumerate() {
+        let path = gate(&dir, &format!("gate{}", index));
+        let line = shape.replace("{}", &path.to_string_lossy());
+        let child = spawn(&dir, &line);
+        open_gate(&path);
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}: {}", line, output.status);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "gated\nshell\n",
+            "{}: did not run in foreground",
+            line
+        );
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,94 @@
+//! Checks that binary data flows unchanged through pipelines, through
+//! redirections and through the pseudo-terminals of `pty`.
+
+mod common;
+
+use common::workdir;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The sizes of the blobs we send, including the empty one and a blob
+/// larger than the pipe buffer.
+const SIZES: [usize; 4] = [0, 1, 4096, 200_000];
+
+/// Returns pseudo-random bytes, which are the same for the same seed.
+fn blob(seed: u64, size: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e3779b97f4a7c15) | 1;
+    let mut output = Vec::with_capacity(size);
+    while output.len() < size {
+        // note: xorshift64, which is good enough to cover all the bytes
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        output.push((state >> 24) as u8);
+    }
+    output
+}
+
+/// Runs the commands with the shell inside the directory, writing the
+/// input to its standard input, and returns its standard output.
+fn shell(dir: &Path, commands: &str, input: Vec<u8>) -> Vec<u8> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_xv6sh"))
+        .arg("-c")
+        .arg(commands)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    // note: writing from another thread, since the input may not fit
+    // into the pipe buffer while we are not reading the output yet
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success(), "{}: {}", commands, output.status);
+    output.stdout
+}
+
+#[test]
+fn pipelines_are_binary_safe() {
+    let dir = workdir("pipelines");
+    for (seed, size) in SIZES.iter().enumerate() {
+        let data = blob(seed as u64, *size);
+        let output = shell(&dir, "cat | cat | cat", data.clone());
+        assert!(output == data, "cat | cat | cat: {} bytes changed", size);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn redirections_are_binary_safe() {
+    let dir = workdir("redirections");
+    for (seed, size) in SIZES.iter().enumerate() {
+        let data = blob(seed as u64, *size);
+        std::fs::write(dir.join("blob"), &data).unwrap();
+        shell(
+            &dir,
+            "cat < blob > out; cat < blob | cat | cat >> piped",
+            Vec::new(),
+        );
+        assert!(std::fs::read(dir.join("out")).unwrap() == data);
+        assert!(std::fs::read(dir.join("piped")).unwrap() == data);
+        std::fs::remove_file(dir.join("piped")).unwrap();
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pty_is_binary_safe() {
+    let dir = workdir("pty");
+    for (seed, size) in SIZES.iter().enumerate() {
+        // note: a terminal limits each line of input to 4095 bytes
+        let mut data = blob(seed as u64, *size);
+        for index in (1000..data.len()).step_by(1000) {
+            data[index] = b'\n';
+        }
+        data.push(b'\n');
+        std::fs::write(dir.join("blob"), &data).unwrap();
+        let output = shell(&dir, "pty cat < blob", Vec::new());
+        assert!(output == data, "pty cat < blob: {} bytes changed", size);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
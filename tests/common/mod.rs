@@ -0,0 +1,11 @@
+//! Helpers shared by the integration tests.
+
+use std::path::PathBuf;
+
+/// Returns a new empty directory for the given test.
+pub fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("xv6sh-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
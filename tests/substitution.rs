@@ -0,0 +1,93 @@
+//! Checks what command substitutions do with the output of the command:
+//! stripping the trailing newlines unless `set -o keepnewlines`, and
+//! rejecting the output that arguments cannot contain unchanged.
+
+mod common;
+
+use common::workdir;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Writes the data into the file, then runs the commands with the shell
+/// inside the directory and returns what it did.
+fn shell(dir: &Path, data: &[u8], commands: &str) -> Output {
+    std::fs::write(dir.join("data"), data).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_xv6sh"))
+        .arg("-c")
+        .arg(commands)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/// Runs the commands, which must succeed, and returns the standard output.
+fn stdout(dir: &Path, data: &[u8], commands: &str) -> String {
+    let output = shell(dir, data, commands);
+    assert!(output.status.success(), "{}: {}", commands, output.status);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Runs the commands, which must fail, and returns the standard error.
+fn stderr(dir: &Path, data: &[u8], commands: &str) -> String {
+    let output = shell(dir, data, commands);
+    assert!(!output.status.success(), "{}: succeeded", commands);
+    assert_eq!(output.stdout, b"", "{}: ran the command anyway", commands);
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn trailing_newlines_are_stripped() {
+    let dir = workdir("strip");
+    let cases: [(&[u8], &str); 4] = [
+        (b"", "[]\n"),
+        (b"a", "[a]\n"),
+        (b"a\n\n\n", "[a]\n"),
+        (b"\na\n\nb\n", "[\na\n\nb]\n"),
+    ];
+    for (data, expect) in cases.iter() {
+        let output = stdout(&dir, data, "echo \"[$(cat data)]\"");
+        assert_eq!(output, *expect, "{:?}", data);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn keepnewlines_keeps_trailing_newlines() {
+    let dir = workdir("keep");
+    let commands = "set -o keepnewlines\necho \"[$(cat data)]\"";
+    let output = stdout(&dir, b"a\n\n", commands);
+    assert_eq!(output, "[a\n\n]\n");
+    let commands = "set -o keepnewlines\nset +o keepnewlines\necho \"[$(cat data)]\"";
+    let output = stdout(&dir, b"a\n\n", commands);
+    assert_eq!(output, "[a]\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn nul_bytes_are_rejected() {
+    let dir = workdir("nul");
+    for data in [&b"a\0b"[..], b"\0", b"a\n\0\n"] {
+        let error = stderr(&dir, data, "echo \"$(cat data)\"");
+        assert!(
+            error.contains("contains NUL bytes"),
+            "{:?}: {}",
+            data,
+            error
+        );
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn invalid_utf8_is_rejected() {
+    let dir = workdir("utf8");
+    for data in [&b"\xff"[..], b"a\xc3", b"\xc3\x28\n", b"\xed\xa0\x80"] {
+        let error = stderr(&dir, data, "echo \"$(cat data)\"");
+        assert!(error.contains("not valid UTF-8"), "{:?}: {}", data, error);
+    }
+    // note: valid multibyte sequences pass through unchanged
+    let output = stdout(&dir, "caf\u{e9} \u{1f41a}\n".as_bytes(), "echo $(cat data)");
+    assert_eq!(output, "caf\u{e9} \u{1f41a}\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}